@@ -17,18 +17,29 @@ export let curUsername: Writable<string|null> = writable(null);
 export let curUserId: Writable<string|null> = writable(null);
 export let curUserIsAdmin: Writable<boolean> = writable(false);
 export let curUserPic: Writable<string|null> = writable(null);
+export let curUserEmailNotifications: Writable<string|null> = writable(null);  // null = server doesn't send e-mails
 
 export let allComments: Writable<IndentedComment[]> = writable([]);
+export let commentImportPreview: Writable<Proto3.client.ServerToClientCmd_CommentImportPreview|null> = writable(null);
+export let searchResults: Writable<Proto3.client.ServerToClientCmd_SearchResults|null> = writable(null);
 
 export let curSubtitle: Writable<Proto3.Subtitle|null> = writable(null);
 export let subtitleEditingId: Writable<string|null> = writable(null);
 
 export let userMessages: Writable<Proto3.UserMessage[]> = writable([]);
+export let userMessagesListInfo: Writable<Proto3.client.ServerToClientCmd_ShowMessages_ListInfo|null> = writable(null);
+export let userMessagePrefs: Writable<Proto3.UserMessagePref[]> = writable([]);
+export let userApiTokens: Writable<Proto3.ApiToken[]> = writable([]);
+export let userSessions: Writable<Proto3.ActiveSession[]> = writable([]);
+export let userShareLinks: Writable<Proto3.ShareLink[]> = writable([]);
+export let curMediaFileGrants: Writable<Proto3.MediaFileGrant[]> = writable([]);  // Access grants of the open media file (owner only)
+export let curGuestLink: Writable<Proto3.ShareLink | null> = writable(null);  // Set if we are a guest on a share link
 export let latestProgressReports: Writable<MediaProgressReport[]> = writable([]);
 
 export let connectionErrors: Writable<string[]> = writable([]);
 
 export let collabId: Writable<string|null> = writable(null);
+export let collabParticipants: Writable<Proto3.UserInfo[]> = writable([]);
 export let userMenuItems: Writable<UserMenuItem[]> = writable([]);
 export let selectedTiles: Writable<{[key: string]: VideoListDefItem}> = writable({});
 export let serverDefinedActions: Writable<{ [key: string]: Proto3.ActionDef }> = writable({});
@@ -0,0 +1,11 @@
+DROP TRIGGER IF EXISTS subtitle_cues_fts_delete;
+DROP TRIGGER IF EXISTS comments_fts_delete;
+DROP TRIGGER IF EXISTS comments_fts_update;
+DROP TRIGGER IF EXISTS comments_fts_insert;
+DROP TRIGGER IF EXISTS media_files_fts_delete;
+DROP TRIGGER IF EXISTS media_files_fts_update;
+DROP TRIGGER IF EXISTS media_files_fts_insert;
+
+DROP TABLE IF EXISTS subtitle_cues_fts;
+DROP TABLE IF EXISTS comments_fts;
+DROP TABLE IF EXISTS media_files_fts;
@@ -0,0 +1,73 @@
+-- Full-text search indexes (SQLite FTS5).
+-- Media files and comments are kept in sync by triggers. Subtitle cues live in files on disk,
+-- so the server indexes them itself, but cues of deleted subtitles are removed here.
+
+CREATE VIRTUAL TABLE media_files_fts USING fts5(
+    media_file_id UNINDEXED,
+    title,
+    orig_filename,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE comments_fts USING fts5(
+    comment_id UNINDEXED,
+    media_file_id UNINDEXED,
+    comment,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE subtitle_cues_fts USING fts5(
+    subtitle_id UNINDEXED,
+    media_file_id UNINDEXED,
+    start_sec UNINDEXED,    -- Cue start time, not including subtitle time_offset
+    cue_text,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Media files
+
+CREATE TRIGGER media_files_fts_insert AFTER INSERT ON media_files BEGIN
+    INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+        VALUES (NEW.id, COALESCE(NEW.title, ''), COALESCE(NEW.orig_filename, ''));
+END;
+
+CREATE TRIGGER media_files_fts_update AFTER UPDATE OF id, title, orig_filename ON media_files BEGIN
+    DELETE FROM media_files_fts WHERE media_file_id = OLD.id;
+    INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+        VALUES (NEW.id, COALESCE(NEW.title, ''), COALESCE(NEW.orig_filename, ''));
+END;
+
+CREATE TRIGGER media_files_fts_delete AFTER DELETE ON media_files BEGIN
+    DELETE FROM media_files_fts WHERE media_file_id = OLD.id;
+END;
+
+-- Comments
+
+CREATE TRIGGER comments_fts_insert AFTER INSERT ON comments BEGIN
+    INSERT INTO comments_fts (comment_id, media_file_id, comment)
+        VALUES (NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER comments_fts_update AFTER UPDATE OF id, media_file_id, comment ON comments BEGIN
+    DELETE FROM comments_fts WHERE comment_id = OLD.id;
+    INSERT INTO comments_fts (comment_id, media_file_id, comment)
+        VALUES (NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER comments_fts_delete AFTER DELETE ON comments BEGIN
+    DELETE FROM comments_fts WHERE comment_id = OLD.id;
+END;
+
+-- Subtitles
+
+CREATE TRIGGER subtitle_cues_fts_delete AFTER DELETE ON subtitles BEGIN
+    DELETE FROM subtitle_cues_fts WHERE subtitle_id = OLD.id;
+END;
+
+-- Index existing data
+
+INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+    SELECT id, COALESCE(title, ''), COALESCE(orig_filename, '') FROM media_files;
+
+INSERT INTO comments_fts (comment_id, media_file_id, comment)
+    SELECT id, media_file_id, comment FROM comments;
@@ -0,0 +1,2 @@
+-- Vector annotations (JSON) for comments, in addition to / instead of raster drawings
+ALTER TABLE comments ADD COLUMN annotation TEXT NULL;
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS ix_comment_revisions_comment_id;
+DROP TABLE IF EXISTS comment_revisions;
+
+ALTER TABLE comments DROP COLUMN deleted;
@@ -0,0 +1,18 @@
+-- Step 1: Soft delete for comments (keeps reply threads intact)
+
+ALTER TABLE comments ADD COLUMN deleted DATETIME DEFAULT NULL;
+
+
+-- Step 2: Revision history. Each row stores the comment contents as they were BEFORE the action.
+
+CREATE TABLE IF NOT EXISTS "comment_revisions" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    comment_id INTEGER NOT NULL REFERENCES comments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    user_id VARCHAR(255),       -- Who made the change. Retain even if user is deleted, hence no foreign key constraint
+    action VARCHAR NOT NULL,    -- 'edit', 'delete' or 'restore'
+    comment TEXT NOT NULL,
+    annotation TEXT
+);
+
+CREATE INDEX ix_comment_revisions_comment_id ON comment_revisions (comment_id);
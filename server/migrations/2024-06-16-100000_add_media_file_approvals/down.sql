@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS ix_media_file_approvals_user_id;
+DROP TABLE IF EXISTS media_file_approvals;
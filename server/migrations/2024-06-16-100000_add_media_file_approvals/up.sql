@@ -0,0 +1,13 @@
+-- Per-reviewer approval / sign-off state of media files
+
+CREATE TABLE IF NOT EXISTS "media_file_approvals" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255) NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    state VARCHAR NOT NULL,     -- 'pending', 'approved' or 'changes_requested'
+    note TEXT,
+    updated DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    UNIQUE (media_file_id, user_id)
+);
+
+CREATE INDEX ix_media_file_approvals_user_id ON media_file_approvals (user_id);
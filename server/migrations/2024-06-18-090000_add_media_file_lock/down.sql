@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS ix_media_file_lock_log_media_file_id;
+DROP TABLE IF EXISTS media_file_lock_log;
+ALTER TABLE media_files DROP COLUMN locked_by;
+ALTER TABLE media_files DROP COLUMN locked;
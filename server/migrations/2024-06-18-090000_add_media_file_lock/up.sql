@@ -0,0 +1,16 @@
+-- Locked (finalized) media files can't be commented, renamed etc. by non-admins
+
+ALTER TABLE media_files ADD COLUMN locked DATETIME DEFAULT NULL;
+ALTER TABLE media_files ADD COLUMN locked_by VARCHAR(255) DEFAULT NULL;
+
+-- Audit trail of locking / unlocking
+
+CREATE TABLE IF NOT EXISTS "media_file_lock_log" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255),       -- Who (un)locked. Retain even if user is deleted, hence no foreign key constraint
+    locked BOOLEAN NOT NULL,    -- true = locked, false = unlocked
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL
+);
+
+CREATE INDEX ix_media_file_lock_log_media_file_id ON media_file_lock_log (media_file_id);
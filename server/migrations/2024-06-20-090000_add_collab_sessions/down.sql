@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS collab_chat_messages;
+DROP TABLE IF EXISTS collab_session_participants;
+DROP TABLE IF EXISTS collab_sessions;
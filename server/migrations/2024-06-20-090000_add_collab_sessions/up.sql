@@ -0,0 +1,31 @@
+-- Records of collaborative viewing sessions, for organizers and auditing
+
+CREATE TABLE IF NOT EXISTS "collab_sessions" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    collab_id VARCHAR(255) NOT NULL,    -- Client-chosen session id. Can be reused later, hence not unique.
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    started DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    ended DATETIME DEFAULT NULL         -- NULL = still active
+);
+
+CREATE INDEX ix_collab_sessions_media_file_id ON collab_sessions (media_file_id);
+
+CREATE TABLE IF NOT EXISTS "collab_session_participants" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    collab_session_id INTEGER NOT NULL REFERENCES collab_sessions(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255) NOT NULL,      -- Retain even if user is deleted, hence no foreign key constraint
+    joined DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,   -- First join
+    UNIQUE (collab_session_id, user_id)
+);
+
+-- Chat messages that participants chose to save into the session transcript
+
+CREATE TABLE IF NOT EXISTS "collab_chat_messages" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    collab_session_id INTEGER NOT NULL REFERENCES collab_sessions(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255) NOT NULL,
+    message TEXT NOT NULL,
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL
+);
+
+CREATE INDEX ix_collab_chat_messages_collab_session_id ON collab_chat_messages (collab_session_id);
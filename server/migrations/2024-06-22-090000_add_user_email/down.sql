@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN email_digest_sent;
+ALTER TABLE users DROP COLUMN email_notifications;
+ALTER TABLE users DROP COLUMN email;
@@ -0,0 +1,5 @@
+-- E-mail notifications. Address is set by Organizer (or directly in DB), opt-out level by the user.
+
+ALTER TABLE users ADD COLUMN email VARCHAR(255) DEFAULT NULL;
+ALTER TABLE users ADD COLUMN email_notifications VARCHAR(16) NOT NULL DEFAULT 'all';    -- 'all', 'immediate', 'digest' or 'none'
+ALTER TABLE users ADD COLUMN email_digest_sent DATETIME DEFAULT NULL;                   -- Comments after this are included in the next digest
@@ -0,0 +1,17 @@
+-- Outgoing webhook calls. Works both as a retry queue and as a delivery log.
+
+CREATE TABLE IF NOT EXISTS "webhook_deliveries" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    url VARCHAR NOT NULL,
+    event VARCHAR(64) NOT NULL,             -- e.g. "comment.added"
+    payload TEXT NOT NULL,                  -- JSON body, exactly as sent
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    status VARCHAR(16) DEFAULT 'pending' NOT NULL CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts INTEGER DEFAULT 0 NOT NULL,
+    next_attempt DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    last_attempt DATETIME DEFAULT NULL,
+    response_code INTEGER DEFAULT NULL,     -- HTTP status of the last attempt, if any
+    last_error TEXT DEFAULT NULL
+);
+
+CREATE INDEX ix_webhook_deliveries_status_next_attempt ON webhook_deliveries (status, next_attempt);
@@ -0,0 +1,11 @@
+-- Per-user preferences for user message (notification) types.
+-- No row = defaults (pushed to client, persisted when the server would normally do so).
+
+CREATE TABLE IF NOT EXISTS "user_message_prefs" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id VARCHAR(255) NOT NULL,
+    event_name VARCHAR NOT NULL,    -- Same as messages.event_name, e.g. 'progress'
+    persist BOOLEAN NOT NULL DEFAULT TRUE,
+    push BOOLEAN NOT NULL DEFAULT TRUE,
+    UNIQUE (user_id, event_name)
+);
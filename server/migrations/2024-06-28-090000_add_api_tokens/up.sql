@@ -0,0 +1,14 @@
+-- Personal API tokens for scripts and CI.
+-- Only a SHA-256 hash of the token is stored.
+
+CREATE TABLE IF NOT EXISTS "api_tokens" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id VARCHAR(255) NOT NULL,
+    name VARCHAR NOT NULL,
+    token_hash VARCHAR NOT NULL UNIQUE,     -- Hex SHA-256 of the token
+    scopes VARCHAR NOT NULL,                -- Comma-separated, e.g. 'upload,comments:read'
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires TIMESTAMP,                      -- NULL = never
+    last_used TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS api_tokens_user_id ON api_tokens (user_id);
@@ -0,0 +1,13 @@
+-- Time-limited review links for guests without an account
+
+CREATE TABLE IF NOT EXISTS "share_links" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    link_key VARCHAR NOT NULL UNIQUE,       -- Random secret in the link URL
+    user_id VARCHAR(255) NOT NULL,          -- Creator
+    media_file_ids VARCHAR NOT NULL,        -- Comma-separated
+    can_comment BOOLEAN NOT NULL DEFAULT FALSE,
+    password_hash VARCHAR,                  -- NULL = no password
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS share_links_user_id ON share_links (user_id);
@@ -0,0 +1,18 @@
+-- Per-media file access grants (built-in ACL, used when Organizer doesn't decide).
+-- Files with no grants are open to all users. Once a file has grants,
+-- only its owner, admins and the grantees can access it.
+
+CREATE TABLE IF NOT EXISTS "media_file_grants" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255),               -- Either this...
+    group_name VARCHAR(255),            -- ...or this
+    role VARCHAR(32) NOT NULL,          -- 'viewer', 'commenter' or 'editor'
+    granted_by VARCHAR(255),
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    CHECK ((user_id IS NULL) <> (group_name IS NULL))
+);
+
+CREATE INDEX ix_media_file_grants_media_file_id ON media_file_grants (media_file_id);
+CREATE INDEX ix_media_file_grants_user_id ON media_file_grants (user_id);
+CREATE INDEX ix_media_file_grants_group_name ON media_file_grants (group_name);
@@ -0,0 +1,99 @@
+-- Rebuild full-text indexes so that triggers can find rows to delete without a full table scan.
+-- FTS5 can only look up indexed columns (through MATCH) and rowids, so comments now use
+-- the comment id as rowid, and media file / subtitle ids are indexed. Searches are
+-- limited to the text columns with column filters.
+-- Subtitle cues are reindexed by the server on startup.
+
+DROP TRIGGER IF EXISTS subtitle_cues_fts_delete;
+DROP TRIGGER IF EXISTS comments_fts_delete;
+DROP TRIGGER IF EXISTS comments_fts_update;
+DROP TRIGGER IF EXISTS comments_fts_insert;
+DROP TRIGGER IF EXISTS media_files_fts_delete;
+DROP TRIGGER IF EXISTS media_files_fts_update;
+DROP TRIGGER IF EXISTS media_files_fts_insert;
+
+DROP TABLE IF EXISTS subtitle_cues_fts;
+DROP TABLE IF EXISTS comments_fts;
+DROP TABLE IF EXISTS media_files_fts;
+
+CREATE VIRTUAL TABLE media_files_fts USING fts5(
+    media_file_id,
+    title,
+    orig_filename,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE comments_fts USING fts5(   -- rowid = comments.id
+    media_file_id UNINDEXED,
+    comment,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE subtitle_cues_fts USING fts5(
+    subtitle_id,
+    media_file_id UNINDEXED,
+    start_sec UNINDEXED,    -- Cue start time, not including subtitle time_offset
+    cue_text,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Media files. The "<table> = <query>" form is a MATCH that is allowed in triggers. It finds candidates
+-- through the index, and the equality check drops false positives (tokenizer folds case and punctuation).
+
+CREATE TRIGGER media_files_fts_insert AFTER INSERT ON media_files BEGIN
+    INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+        VALUES (NEW.id, COALESCE(NEW.title, ''), COALESCE(NEW.orig_filename, ''));
+END;
+
+CREATE TRIGGER media_files_fts_update AFTER UPDATE OF id, title, orig_filename ON media_files BEGIN
+    DELETE FROM media_files_fts
+        WHERE media_files_fts = 'media_file_id : "' || replace(OLD.id, '"', '""') || '"' AND media_file_id = OLD.id;
+    INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+        VALUES (NEW.id, COALESCE(NEW.title, ''), COALESCE(NEW.orig_filename, ''));
+END;
+
+CREATE TRIGGER media_files_fts_delete AFTER DELETE ON media_files BEGIN
+    DELETE FROM media_files_fts
+        WHERE media_files_fts = 'media_file_id : "' || replace(OLD.id, '"', '""') || '"' AND media_file_id = OLD.id;
+END;
+
+-- Comments
+
+CREATE TRIGGER comments_fts_insert AFTER INSERT ON comments BEGIN
+    INSERT INTO comments_fts (rowid, media_file_id, comment)
+        VALUES (NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER comments_fts_update AFTER UPDATE OF id, media_file_id, comment ON comments BEGIN
+    DELETE FROM comments_fts WHERE rowid = OLD.id;
+    INSERT INTO comments_fts (rowid, media_file_id, comment)
+        VALUES (NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER comments_fts_delete AFTER DELETE ON comments BEGIN
+    DELETE FROM comments_fts WHERE rowid = OLD.id;
+END;
+
+-- Subtitles. Cues of a subtitle whose file (or media file) changes are stale, so they are
+-- removed here, and the server reindexes the subtitle after updating it.
+
+CREATE TRIGGER subtitle_cues_fts_update AFTER UPDATE OF id, media_file_id, filename, orig_filename ON subtitles
+    WHEN OLD.id IS NOT NEW.id OR OLD.media_file_id IS NOT NEW.media_file_id
+        OR OLD.filename IS NOT NEW.filename OR OLD.orig_filename IS NOT NEW.orig_filename
+BEGIN
+    DELETE FROM subtitle_cues_fts
+        WHERE subtitle_cues_fts = 'subtitle_id : "' || OLD.id || '"' AND subtitle_id = OLD.id;
+END;
+
+CREATE TRIGGER subtitle_cues_fts_delete AFTER DELETE ON subtitles BEGIN
+    DELETE FROM subtitle_cues_fts
+        WHERE subtitle_cues_fts = 'subtitle_id : "' || OLD.id || '"' AND subtitle_id = OLD.id;
+END;
+
+-- Index existing data
+
+INSERT INTO media_files_fts (media_file_id, title, orig_filename)
+    SELECT id, COALESCE(title, ''), COALESCE(orig_filename, '') FROM media_files;
+
+INSERT INTO comments_fts (rowid, media_file_id, comment)
+    SELECT id, media_file_id, comment FROM comments;
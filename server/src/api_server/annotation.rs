@@ -0,0 +1,244 @@
+//! Vector annotations for comments.
+//!
+//! Annotations are JSON documents with freehand strokes, lines, arrows, rectangles,
+//! ellipses and text. Coordinates are normalized to the frame (0.0 - 1.0, origin at top left),
+//! so they scale to any playback resolution. Line widths and text sizes are relative to frame height.
+//!
+//! Example:
+//! ```json
+//! {"version": 1, "aspect": 1.778, "items": [
+//!     {"type": "stroke", "color": "red", "width": 0.01, "points": [[0.1, 0.1], [0.2, 0.15]]},
+//!     {"type": "arrow", "color": "#00ff00", "width": 0.005, "from": [0.5, 0.5], "to": [0.6, 0.4]},
+//!     {"type": "text", "color": "yellow", "x": 0.3, "y": 0.8, "size": 0.05, "text": "Fix this"}
+//! ]}
+//! ```
+
+use std::sync::{Arc, OnceLock};
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+type Res<T> = anyhow::Result<T>;
+
+pub const ANNOTATION_VERSION: u32 = 1;
+const DEFAULT_ASPECT: f64 = 16.0 / 9.0;
+
+const MAX_ITEMS: usize = 1000;
+const MAX_POINTS: usize = 10000;
+const MAX_TEXT_LEN: usize = 500;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub version: u32,
+    /// Width / height of the frame the annotation was drawn on (keeps circles round when rendering)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect: Option<f64>,
+    pub items: Vec<Shape>,
+}
+
+type Point = [f64; 2];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Shape {
+    Stroke { color: String, width: f64, points: Vec<Point> },
+    Line { color: String, width: f64, from: Point, to: Point },
+    Arrow { color: String, width: f64, from: Point, to: Point },
+    Rect { color: String, width: f64, x: f64, y: f64, w: f64, h: f64, #[serde(default)] fill: bool },
+    Ellipse { color: String, width: f64, x: f64, y: f64, w: f64, h: f64, #[serde(default)] fill: bool },
+    Text { color: String, x: f64, y: f64, size: f64, text: String },
+}
+
+
+fn check_color(c: &str) -> Res<()> {
+    let is_hex = c.len() > 1 && c.len() <= 9 && c.starts_with('#') && c[1..].chars().all(|c| c.is_ascii_hexdigit());
+    let is_name = !c.is_empty() && c.len() <= 20 && c.chars().all(|c| c.is_ascii_lowercase());
+    if is_hex || is_name { Ok(()) } else { bail!("Bad color '{}'", c) }
+}
+
+fn check_coord(v: f64) -> Res<()> {
+    // Allow some overshoot, as strokes may go past the frame edges
+    if v.is_finite() && (-1.0..=2.0).contains(&v) { Ok(()) } else { bail!("Coordinate out of range: {}", v) }
+}
+
+fn check_size(v: f64, what: &str) -> Res<()> {
+    if v.is_finite() && v > 0.0 && v <= 1.0 { Ok(()) } else { bail!("Bad {}: {}", what, v) }
+}
+
+impl Annotation {
+
+    /// Parse and validate annotation JSON.
+    pub fn parse(json: &str) -> Res<Self> {
+        let a: Annotation = serde_json::from_str(json).context("Invalid annotation JSON")?;
+        a.validate()?;
+        Ok(a)
+    }
+
+    /// Serialize into compact JSON (as stored in the database).
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Annotation serialization failed")
+    }
+
+    pub fn validate(&self) -> Res<()> {
+        if self.version != ANNOTATION_VERSION { bail!("Unsupported annotation version {}", self.version); }
+        if let Some(a) = self.aspect {
+            if !(a.is_finite() && (0.1..=10.0).contains(&a)) { bail!("Bad aspect ratio: {}", a); }
+        }
+        if self.items.len() > MAX_ITEMS { bail!("Too many annotation items ({} > {})", self.items.len(), MAX_ITEMS); }
+
+        for it in &self.items {
+            match it {
+                Shape::Stroke { color, width, points } => {
+                    check_color(color)?;
+                    check_size(*width, "line width")?;
+                    if points.is_empty() || points.len() > MAX_POINTS { bail!("Stroke must have 1 - {} points", MAX_POINTS); }
+                    for p in points { check_coord(p[0])?; check_coord(p[1])?; }
+                },
+                Shape::Line { color, width, from, to } | Shape::Arrow { color, width, from, to } => {
+                    check_color(color)?;
+                    check_size(*width, "line width")?;
+                    for v in from.iter().chain(to.iter()) { check_coord(*v)?; }
+                },
+                Shape::Rect { color, width, x, y, w, h, .. } | Shape::Ellipse { color, width, x, y, w, h, .. } => {
+                    check_color(color)?;
+                    check_size(*width, "line width")?;
+                    for v in [x, y, w, h] { check_coord(*v)?; }
+                },
+                Shape::Text { color, x, y, size, text } => {
+                    check_color(color)?;
+                    check_size(*size, "text size")?;
+                    check_coord(*x)?; check_coord(*y)?;
+                    if text.chars().count() > MAX_TEXT_LEN { bail!("Annotation text too long"); }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Pixel height for given width, based on the aspect ratio the annotation was drawn on.
+    pub fn height_for_width(&self, width: u32) -> u32 {
+        ((width as f64) / self.aspect.unwrap_or(DEFAULT_ASPECT)).round().max(1.0) as u32
+    }
+
+    /// Render as SVG document of given pixel size (transparent background).
+    pub fn to_svg(&self, width: u32, height: u32) -> String
+    {
+        let (w, h) = (width as f64, height as f64);
+        let px = |p: &Point| (p[0] * w, p[1] * h);
+        let stroke = |color: &str, width: f64| format!(
+            r#"stroke="{}" stroke-width="{:.2}" stroke-linecap="round" stroke-linejoin="round""#, xml_escape(color), width * h);
+
+        let mut svg = format!(r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#);
+        svg.push('\n');
+
+        for it in &self.items {
+            let el = match it {
+                Shape::Stroke { color, width, points } => {
+                    if points.len() == 1 {
+                        let (x, y) = px(&points[0]);
+                        format!(r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="{}"/>"#, x, y, width * h / 2.0, xml_escape(color))
+                    } else {
+                        let pts = points.iter().map(|p| { let (x, y) = px(p); format!("{:.2},{:.2}", x, y) }).collect::<Vec<_>>().join(" ");
+                        format!(r#"<polyline points="{}" fill="none" {}/>"#, pts, stroke(color, *width))
+                    }
+                },
+                Shape::Line { color, width, from, to } => {
+                    let ((x1, y1), (x2, y2)) = (px(from), px(to));
+                    format!(r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" {}/>"#, x1, y1, x2, y2, stroke(color, *width))
+                },
+                Shape::Arrow { color, width, from, to } => {
+                    let ((x1, y1), (x2, y2)) = (px(from), px(to));
+                    let head_len = (width * h * 4.0).max(h * 0.02);
+                    let angle = (y2 - y1).atan2(x2 - x1);
+                    let head = |da: f64| (x2 - head_len * (angle + da).cos(), y2 - head_len * (angle + da).sin());
+                    let ((hx1, hy1), (hx2, hy2)) = (head(0.45), head(-0.45));
+                    format!(r#"<path d="M{:.2},{:.2} L{:.2},{:.2} M{:.2},{:.2} L{:.2},{:.2} L{:.2},{:.2}" fill="none" {}/>"#,
+                        x1, y1, x2, y2, hx1, hy1, x2, y2, hx2, hy2, stroke(color, *width))
+                },
+                Shape::Rect { color, width, x, y, w: rw, h: rh, fill } => {
+                    let fill = if *fill { xml_escape(color) } else { "none".into() };
+                    format!(r#"<rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" {}/>"#,
+                        x * w, y * h, rw * w, rh * h, fill, stroke(color, *width))
+                },
+                Shape::Ellipse { color, width, x, y, w: rw, h: rh, fill } => {
+                    let fill = if *fill { xml_escape(color) } else { "none".into() };
+                    format!(r#"<ellipse cx="{:.2}" cy="{:.2}" rx="{:.2}" ry="{:.2}" fill="{}" {}/>"#,
+                        (x + rw / 2.0) * w, (y + rh / 2.0) * h, (rw / 2.0).abs() * w, (rh / 2.0).abs() * h, fill, stroke(color, *width))
+                },
+                Shape::Text { color, x, y, size, text } => {
+                    format!(r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="{:.2}" fill="{}">{}</text>"#,
+                        x * w, y * h, size * h, xml_escape(color), xml_escape(text))
+                },
+            };
+            svg.push_str(&el);
+            svg.push('\n');
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Render into a PNG image (transparent background) of given width.
+    /// Height is derived from the aspect ratio.
+    pub fn render_png(&self, width: u32) -> Res<Vec<u8>>
+    {
+        use resvg::{usvg, tiny_skia};
+
+        // Loading system fonts is slow, so only do it once
+        static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+        let fonts = FONTS.get_or_init(|| {
+            let mut db = usvg::fontdb::Database::new();
+            db.load_system_fonts();
+            Arc::new(db)
+        });
+
+        let height = self.height_for_width(width);
+        let opt = usvg::Options { fontdb: fonts.clone(), ..Default::default() };
+        let tree = usvg::Tree::from_str(&self.to_svg(width, height), &opt).context("Failed to parse annotation SVG")?;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(anyhow!("Bad annotation image size {}x{}", width, height))?;
+        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+        pixmap.encode_png().context("Failed to encode annotation PNG")
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+const TEST_ANNOTATION: &str = r##"{"version": 1, "aspect": 2.0, "items": [
+    {"type": "stroke", "color": "red", "width": 0.01, "points": [[0.1, 0.1], [0.2, 0.15], [0.3, 0.1]]},
+    {"type": "arrow", "color": "#00ff00", "width": 0.005, "from": [0.5, 0.5], "to": [0.6, 0.4]},
+    {"type": "ellipse", "color": "blue", "width": 0.01, "x": 0.7, "y": 0.1, "w": 0.2, "h": 0.2, "fill": true},
+    {"type": "text", "color": "yellow", "x": 0.3, "y": 0.8, "size": 0.05, "text": "Fix <this>"}
+]}"##;
+
+#[test]
+fn test_annotation_parse_and_validate()
+{
+    let a = Annotation::parse(TEST_ANNOTATION).unwrap();
+    assert_eq!(a.items.len(), 4);
+    assert_eq!(Annotation::parse(&a.to_json()).unwrap(), a);
+
+    assert!(Annotation::parse(r#"{"version": 2, "items": []}"#).is_err());
+    assert!(Annotation::parse(r#"{"version": 1, "items": [{"type": "blob"}]}"#).is_err());
+    assert!(Annotation::parse(r#"{"version": 1, "items": [{"type": "line", "color": "red", "width": 0.01, "from": [0, 0], "to": [5, 0]}]}"#).is_err());
+    assert!(Annotation::parse(r#"{"version": 1, "items": [{"type": "text", "color": "red;x", "x": 0, "y": 0, "size": 0.1, "text": "a"}]}"#).is_err());
+    assert!(Annotation::parse(r#"{"version": 1, "items": [{"type": "stroke", "color": "red", "width": 0.01, "points": []}]}"#).is_err());
+}
+
+#[test]
+fn test_annotation_render()
+{
+    let a = Annotation::parse(TEST_ANNOTATION).unwrap();
+    assert_eq!(a.height_for_width(640), 320);
+
+    let svg = a.to_svg(640, 320);
+    assert!(svg.contains("<polyline points=\"64.00,32.00 128.00,48.00 192.00,32.00\""));
+    assert!(svg.contains("Fix &lt;this&gt;"));
+
+    let png = a.render_png(640).unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+}
@@ -0,0 +1,120 @@
+//! Personal API tokens for scripts and CI.
+//!
+//! Tokens are sent as `Authorization: Bearer clapshot_<hex>`, and accepted instead of
+//! browser authentication (reverse proxy headers or JWT). Only a SHA-256 hash of the
+//! token is stored, so it can't be shown again after creation.
+//!
+//! Token sessions are limited to the commands allowed by the token's scopes.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::Cmd;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::database::{models, PooledConnection};
+
+type Res<T> = anyhow::Result<T>;
+
+/// Prefix that tells API tokens apart from JWTs in the `Authorization` header
+pub const TOKEN_PREFIX: &str = "clapshot_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiTokenScope {
+    Upload,
+    CommentsRead,
+    CommentsWrite,
+}
+
+impl ApiTokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiTokenScope::Upload => "upload",
+            ApiTokenScope::CommentsRead => "comments:read",
+            ApiTokenScope::CommentsWrite => "comments:write",
+        }
+    }
+}
+
+impl FromStr for ApiTokenScope {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Res<Self> {
+        match s.trim() {
+            "upload" => Ok(ApiTokenScope::Upload),
+            "comments:read" => Ok(ApiTokenScope::CommentsRead),
+            "comments:write" => Ok(ApiTokenScope::CommentsWrite),
+            _ => bail!("Unknown API token scope '{}'. Valid ones: upload, comments:read, comments:write", s),
+        }
+    }
+}
+
+/// Parse and deduplicate a scope list. At least one scope is required.
+pub fn parse_scopes<S: AsRef<str>>(scopes: &[S]) -> Res<Vec<ApiTokenScope>>
+{
+    let mut res = Vec::new();
+    for s in scopes.iter().map(|s| s.as_ref()).filter(|s| !s.trim().is_empty()) {
+        let sc = ApiTokenScope::from_str(s)?;
+        if !res.contains(&sc) { res.push(sc); }
+    }
+    if res.is_empty() { bail!("API token needs at least one scope"); }
+    Ok(res)
+}
+
+/// New random token. Returns (token, hash of token).
+pub fn generate_token() -> (String, String)
+{
+    let mut buf = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut buf);
+    let token = format!("{}{}", TOKEN_PREFIX, hex::encode(buf));
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+pub fn hash_token(token: &str) -> String
+{
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+impl models::ApiToken {
+    pub fn scope_list(&self) -> Vec<ApiTokenScope> {
+        self.scopes.split(',').filter_map(|s| ApiTokenScope::from_str(s).ok()).collect()
+    }
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.expires.map(|e| e <= now).unwrap_or(false)
+    }
+}
+
+/// Look up a token, check that it's not expired, and record its use.
+pub fn authenticate(conn: &mut PooledConnection, token: &str) -> Res<models::ApiToken>
+{
+    let now = chrono::Utc::now().naive_utc();
+    let t = models::ApiToken::get_by_hash(conn, &hash_token(token))?
+        .ok_or_else(|| anyhow!("Unknown API token"))?;
+    if t.is_expired(now) {
+        bail!("API token '{}' of user '{}' has expired", t.name, t.user_id);
+    }
+    models::ApiToken::set_last_used(conn, t.id, now)?;
+    Ok(t)
+}
+
+/// Scope that an API token session needs for given command.
+/// None = not allowed with API tokens at all (e.g. managing tokens, deleting media).
+pub fn required_scope(cmd: &Cmd) -> Option<ApiTokenScope>
+{
+    match cmd {
+        Cmd::OpenNavigationPage(_) |
+        Cmd::OpenMediaFile(_) |
+        Cmd::GetCommentHistory(_) |
+        Cmd::ExportReviewReport(_) |
+        Cmd::Search(_) |
+        Cmd::ListMyMessages(_) => Some(ApiTokenScope::CommentsRead),
+
+        Cmd::AddComment(_) |
+        Cmd::EditComment(_) |
+        Cmd::DelComment(_) |
+        Cmd::ImportComments(_) => Some(ApiTokenScope::CommentsWrite),
+
+        _ => None,
+    }
+}
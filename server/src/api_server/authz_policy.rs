@@ -0,0 +1,297 @@
+//! Declarative authorization policy, as a Rhai script.
+//!
+//! Lets admins express simple rules ("contractors can't delete, only project leads can rename")
+//! without writing an Organizer. The script must define `authorize(req)`, which is called for
+//! every authz check before asking Organizer. It returns:
+//!
+//!  - `true` to allow,
+//!  - `false` or a string (message for the user) to deny,
+//!  - nothing (`()`) to leave the decision to Organizer / server defaults.
+//!
+//! The file is reloaded when it changes. If the new version doesn't compile, the old one is kept.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context};
+use lib_clapshot_grpc::proto;
+use parking_lot::{Mutex, RwLock};
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde::{Deserialize, Serialize};
+
+use crate::database::models;
+use super::user_session::AuthzTopic;
+
+type Res<T> = anyhow::Result<T>;
+
+const RELOAD_CHECK_INTERVAL_SECS: u64 = 2;
+const MAX_OPERATIONS: u64 = 100_000;    // Stop runaway scripts
+
+#[derive(Debug, Clone)]
+pub struct AuthzPolicyConfig {
+    pub path: PathBuf,
+    pub dry_run: bool,      // Only log decisions, don't enforce them
+}
+
+/// Media file info for policy scripts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyMediaFile {
+    pub id: String,
+    pub owner: String,
+    pub media_type: String,
+    pub title: String,
+}
+
+/// Comment info for policy scripts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyComment {
+    pub id: String,
+    pub author: String,
+    pub media_file_id: String,
+}
+
+/// What a policy script gets as `req`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyRequest {
+    pub topic: String,          // "media_file", "comment" or "other"
+    pub op: String,             // Lowercase op name, e.g. "view", "rename", "delete", "upload_media_file"
+    pub user_id: String,
+    pub user_name: String,
+    pub is_admin: bool,
+    pub groups: Vec<String>,
+    pub cookies: HashMap<String, String>,
+    pub media_file: Option<PolicyMediaFile>,    // Set for media file and comment ops
+    pub comment: Option<PolicyComment>,         // Set for comment ops
+    pub subject: Option<String>,                // Set for some other ops, e.g. collab id
+}
+
+impl From<&models::MediaFile> for PolicyMediaFile {
+    fn from(mf: &models::MediaFile) -> Self {
+        PolicyMediaFile {
+            id: mf.id.clone(),
+            owner: mf.user_id.clone(),
+            media_type: mf.media_type.clone().unwrap_or_default(),
+            title: mf.title.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl PolicyRequest {
+    /// Build request from session and authz topic.
+    /// `comment_media_file` is the media file of a comment, for comment ops.
+    pub fn new(ses: &proto::org::UserSessionData, op: &AuthzTopic, comment_media_file: Option<&models::MediaFile>) -> Self
+    {
+        let mut req = PolicyRequest {
+            user_id: ses.user.as_ref().map(|u| u.id.clone()).unwrap_or_default(),
+            user_name: ses.user.as_ref().map(|u| u.name.clone()).unwrap_or_default(),
+            is_admin: ses.is_admin,
+            groups: ses.groups.clone(),
+            cookies: ses.cookies.clone(),
+            ..Default::default()
+        };
+        match op {
+            AuthzTopic::MediaFile(mf, op) => {
+                req.topic = "media_file".into();
+                req.op = op.as_str_name().to_lowercase();
+                req.media_file = Some((*mf).into());
+            },
+            AuthzTopic::Comment(c, op) => {
+                req.topic = "comment".into();
+                req.op = op.as_str_name().to_lowercase();
+                req.media_file = comment_media_file.map(|mf| mf.into());
+                req.comment = Some(PolicyComment {
+                    id: c.id.to_string(),
+                    author: c.user_id.clone().unwrap_or_default(),
+                    media_file_id: c.media_file_id.clone(),
+                });
+            },
+            AuthzTopic::Other(subj, op) => {
+                req.topic = "other".into();
+                req.op = op.as_str_name().to_lowercase();
+                req.subject = subj.map(String::from);
+            },
+        }
+        req
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyDecision {
+    Allow,
+    Deny(Option<String>),
+    NoDecision,
+}
+
+impl PolicyDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PolicyDecision::Allow => "allow",
+            PolicyDecision::Deny(_) => "deny",
+            PolicyDecision::NoDecision => "none",
+        }
+    }
+}
+
+pub struct AuthzPolicy {
+    cfg: AuthzPolicyConfig,
+    engine: Engine,
+    ast: RwLock<Arc<AST>>,
+    mtime: Mutex<Option<SystemTime>>,
+}
+
+impl AuthzPolicy {
+
+    pub fn load(cfg: AuthzPolicyConfig) -> Res<Self>
+    {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|s| tracing::info!(msg=s, "Authz policy print"));
+        engine.on_debug(|s, _src, pos| tracing::debug!(msg=s, pos=%pos, "Authz policy debug"));
+
+        let mtime = file_mtime(&cfg.path);
+        let ast = compile(&engine, &cfg.path)?;
+        Ok(AuthzPolicy { cfg, engine, ast: RwLock::new(Arc::new(ast)), mtime: Mutex::new(mtime) })
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.cfg.dry_run
+    }
+
+    /// Recompile if the file has changed. Returns true if reloaded.
+    pub fn reload_if_changed(&self) -> Res<bool>
+    {
+        let mtime = file_mtime(&self.cfg.path);
+        if mtime == *self.mtime.lock() {
+            return Ok(false);
+        }
+        *self.mtime.lock() = mtime;     // Don't retry a broken file until it changes again
+        let ast = compile(&self.engine, &self.cfg.path)?;
+        *self.ast.write() = Arc::new(ast);
+        Ok(true)
+    }
+
+    /// Watch policy file for changes until terminate flag is set
+    pub fn run_reload_loop(&self, terminate_flag: Arc<AtomicBool>)
+    {
+        let _span = tracing::info_span!("AUTHZ_POLICY").entered();
+        let mut elapsed = 0;
+        while !terminate_flag.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= RELOAD_CHECK_INTERVAL_SECS {
+                elapsed = 0;
+                match self.reload_if_changed() {
+                    Ok(true) => tracing::info!(path=?self.cfg.path, "Reloaded authz policy."),
+                    Ok(false) => {},
+                    Err(e) => tracing::error!(details=%e, "Failed to reload authz policy, keeping old one"),
+                }
+            }
+        }
+    }
+
+    /// Run `authorize(req)` from the policy script
+    pub fn evaluate(&self, req: &PolicyRequest) -> Res<PolicyDecision>
+    {
+        let ast = self.ast.read().clone();
+        let arg = rhai::serde::to_dynamic(req).map_err(|e| anyhow!("Failed to convert request for policy: {}", e))?;
+        let res: Dynamic = self.engine.call_fn(&mut Scope::new(), &ast, "authorize", (arg,))
+            .map_err(|e| anyhow!("Authz policy failed: {}", e))?;
+
+        if res.is_unit() {
+            Ok(PolicyDecision::NoDecision)
+        } else if let Ok(b) = res.as_bool() {
+            Ok(if b { PolicyDecision::Allow } else { PolicyDecision::Deny(None) })
+        } else if res.is_string() {
+            Ok(PolicyDecision::Deny(Some(res.into_string().unwrap_or_default())))
+        } else {
+            bail!("Authz policy returned '{}', expected bool, string or nothing", res.type_name());
+        }
+    }
+
+    /// Run test cases from a JSON file, print results, and return number of failures.
+    ///
+    /// File is a list of `{"name": "...", "request": {<PolicyRequest>}, "expect": "allow" | "deny" | "none"}`.
+    pub fn run_test_file(&self, path: &Path) -> Res<usize>
+    {
+        #[derive(Deserialize)]
+        struct TestCase { name: String, request: PolicyRequest, expect: String }
+
+        let txt = std::fs::read_to_string(path).with_context(|| format!("Failed to read policy test file {:?}", path))?;
+        let cases: Vec<TestCase> = serde_json::from_str(&txt).with_context(|| format!("Failed to parse policy test file {:?}", path))?;
+        let mut failures = 0;
+        for tc in &cases {
+            let (ok, got) = match self.evaluate(&tc.request) {
+                Ok(d) => (d.as_str() == tc.expect, format!("{:?}", d)),
+                Err(e) => (false, format!("error: {}", e)),
+            };
+            println!("{}  {}  (expected {}, got {})", if ok { "PASS" } else { "FAIL" }, tc.name, tc.expect, got);
+            if !ok { failures += 1; }
+        }
+        println!("{} passed, {} failed", cases.len() - failures, failures);
+        Ok(failures)
+    }
+}
+
+fn file_mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn compile(engine: &Engine, path: &Path) -> Res<AST>
+{
+    let script = std::fs::read_to_string(path).with_context(|| format!("Failed to read authz policy {:?}", path))?;
+    let ast = engine.compile(&script).map_err(|e| anyhow!("Failed to compile authz policy {:?}: {}", path, e))?;
+    if !ast.iter_functions().any(|f| f.name == "authorize" && f.params.len() == 1) {
+        bail!("Authz policy {:?} doesn't define `fn authorize(req)`", path);
+    }
+    Ok(ast)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authz_policy_eval_and_reload() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("policy.rhai");
+        std::fs::write(&path, r#"
+            fn authorize(req) {
+                if req.is_admin { return; }
+                if "contractors" in req.groups && req.op == "delete" { return "Contractors can't delete"; }
+                if req.op == "rename" { return req.media_file.owner == req.user_id && "leads" in req.groups; }
+            }"#).unwrap();
+        let policy = AuthzPolicy::load(AuthzPolicyConfig { path: path.clone(), dry_run: false }).unwrap();
+
+        let mut req = PolicyRequest {
+            topic: "media_file".into(), op: "delete".into(), user_id: "bob".into(), groups: vec!["contractors".into()],
+            media_file: Some(PolicyMediaFile { owner: "bob".into(), ..Default::default() }), ..Default::default() };
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::Deny(Some("Contractors can't delete".into())));
+        req.op = "rename".into();
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::Deny(None));
+        req.groups.push("leads".into());
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::Allow);
+        req.op = "view".into();
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::NoDecision);
+
+        // Broken update keeps the old policy
+        std::fs::write(&path, "fn authorize(req) { true ").unwrap();
+        *policy.mtime.lock() = None;
+        assert!(policy.reload_if_changed().is_err());
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::NoDecision);
+
+        std::fs::write(&path, "fn authorize(req) { false }").unwrap();
+        *policy.mtime.lock() = None;
+        assert!(policy.reload_if_changed().unwrap());
+        assert_eq!(policy.evaluate(&req).unwrap(), PolicyDecision::Deny(None));
+
+        std::fs::write(&path, "fn something_else() { }").unwrap();
+        assert!(AuthzPolicy::load(AuthzPolicyConfig { path, dry_run: false }).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Comment export as CSV or CMX 3600 style EDL, for spreadsheets and NLEs.
+//! Both can be read back by the comment importer.
+
+use crate::database::models;
+
+type Res<T> = anyhow::Result<T>;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Edl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Edl => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Export comments (deleted ones are skipped) in the given format
+pub fn export_comments(format: ExportFormat, media: &models::MediaFile, comments: &[models::Comment]) -> Res<String>
+{
+    let comments = comments.iter().filter(|c| c.deleted.is_none());
+    match format {
+        ExportFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(vec![]);
+            wtr.write_record(["id", "parent_id", "timecode", "author", "comment", "created"])?;
+            for c in comments {
+                wtr.write_record([
+                    c.id.to_string(),
+                    c.parent_id.map(|p| p.to_string()).unwrap_or_default(),
+                    c.timecode.clone().unwrap_or_default(),
+                    c.username_ifnull.clone(),
+                    c.comment.clone(),
+                    c.created.and_utc().to_rfc3339(),
+                ])?;
+            }
+            Ok(String::from_utf8(wtr.into_inner()?)?)
+        },
+        ExportFormat::Edl => {
+            // One zero length event per timecoded comment, with the text in `* COMMENT:` lines
+            let mut res = format!("TITLE: {}\nFCM: NON-DROP FRAME\n\n", media.title.as_deref().unwrap_or(&media.id));
+            for (i, c) in comments.filter(|c| c.timecode.as_deref().is_some_and(|tc| !tc.is_empty())).enumerate() {
+                let tc = c.timecode.as_deref().unwrap_or_default();
+                res.push_str(&format!("{:03}  AX       V     C        {} {} {} {}\n", i + 1, tc, tc, tc, tc));
+                res.push_str(&format!("* FROM CLIP NAME: {}\n", c.username_ifnull));
+                for line in c.comment.lines().filter(|l| !l.trim().is_empty()) {
+                    res.push_str(&format!("* COMMENT: {}\n", line.trim()));
+                }
+                res.push('\n');
+            }
+            Ok(res)
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::comment_import::{parse_comment_file, test_media, ImportFormat, TimecodeMapper};
+
+    #[test]
+    fn test_export_round_trip() {
+        let media = test_media("25", 120.0, None);
+        let cmt = |id, tc: Option<&str>, text: &str, deleted: bool| models::Comment {
+            id, media_file_id: media.id.clone(), parent_id: None, created: chrono::NaiveDateTime::default(), edited: None,
+            user_id: Some("alice".into()), username_ifnull: "Alice".into(), comment: text.into(), timecode: tc.map(String::from),
+            drawing: None, subtitle_id: None, subtitle_filename_ifnull: None, annotation: None,
+            deleted: deleted.then(chrono::NaiveDateTime::default) };
+        let comments = vec![
+            cmt(1, Some("00:00:01:05"), "Too dark, \"fix\" it", false),
+            cmt(2, Some("00:00:02:00"), "Deleted", true),
+            cmt(3, None, "Line one\nline two", false),
+        ];
+        let mapper = TimecodeMapper::for_media_file(&media, None).unwrap();
+
+        let csv = export_comments(ExportFormat::Csv, &media, &comments).unwrap();
+        let res = parse_comment_file(ImportFormat::Csv, &csv, &media, &mapper, "bob", "Bob").unwrap();
+        assert_eq!(res.comments.len(), 1);     // Comment without timecode is rejected by importer
+        assert_eq!(res.comments[0].comment, "Too dark, \"fix\" it");
+        assert_eq!(res.comments[0].username_ifnull, "Alice");
+        assert_eq!(res.comments[0].timecode.as_deref(), Some("00:00:01:05"));
+
+        let edl = export_comments(ExportFormat::Edl, &media, &comments).unwrap();
+        let res = parse_comment_file(ImportFormat::Edl, &edl, &media, &mapper, "bob", "Bob").unwrap();
+        assert!(res.rejected.is_empty());
+        assert_eq!(res.comments.len(), 1);
+        assert_eq!(res.comments[0].comment, "Too dark, \"fix\" it");
+        assert_eq!(res.comments[0].timecode.as_deref(), Some("00:00:01:05"));
+    }
+}
@@ -0,0 +1,489 @@
+//! Parsers for importing comments / markers from review files made in other tools.
+//!
+//! Supported formats (guessed from file extension):
+//!  - CSV: header row with (at least) a timecode and a comment column, or bare `timecode,comment[,author]` rows
+//!  - EDL: CMX3600 style; record-in timecodes of events, plus `* LOC:` locators
+//!  - SRT / WebVTT: cue start times and texts
+//!
+//! Timecodes are mapped to media file relative SMPTE timecodes (HH:MM:SS:FF)
+//! using the media file's FPS and start timecode.
+
+use anyhow::{anyhow, bail};
+use crate::database::models;
+
+type Res<T> = anyhow::Result<T>;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Edl,
+    Srt,
+}
+
+impl ImportFormat {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let ext = std::path::Path::new(file_name).extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "csv" | "tsv" => Some(Self::Csv),
+            "edl" => Some(Self::Edl),
+            "srt" | "vtt" => Some(Self::Srt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedLine {
+    pub line_num: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportResult {
+    pub comments: Vec<models::CommentInsert>,
+    pub rejected: Vec<RejectedLine>,
+}
+
+/// Converts between file timecodes and media file relative SMPTE timecodes.
+#[derive(Debug, Clone)]
+pub struct TimecodeMapper {
+    fps: f64,
+    start_frame: i64,
+    total_frames: Option<i64>,
+}
+
+impl TimecodeMapper {
+
+    /// Make a mapper for given media file.
+    ///
+    /// # Arguments
+    /// * `media` - Media file to map timecodes against
+    /// * `start_timecode` - SMPTE timecode of the first frame. If None, read from media metadata (or 00:00:00:00 if not found there).
+    pub fn for_media_file(media: &models::MediaFile, start_timecode: Option<&str>) -> Res<Self> {
+        let fps = media.fps.as_ref().and_then(|f| f.parse::<f64>().ok())
+            .filter(|f| *f > 0.0)
+            .ok_or(anyhow!("Media file has no valid frame rate. Cannot map timecodes."))?;
+
+        let mut mapper = Self {
+            fps,
+            start_frame: 0,
+            total_frames: media.duration.map(|d| (d as f64 * fps).round() as i64),
+        };
+        let start_tc = match start_timecode.map(str::trim).filter(|s| !s.is_empty()) {
+            Some(tc) => Some(tc.to_string()),
+            None => media.raw_metadata_all.as_deref().and_then(start_timecode_from_metadata),
+        };
+        if let Some(tc) = start_tc {
+            mapper.start_frame = mapper.smpte_to_frames(&tc).ok_or(anyhow!("Bad start timecode '{}'", tc))?;
+        }
+        Ok(mapper)
+    }
+
+    /// Parse SMPTE timecode (HH:MM:SS:FF, also ';' or '.' before frames) into absolute frame number.
+    fn smpte_to_frames(&self, tc: &str) -> Option<i64> {
+        let parts = tc.trim().split([':', ';', '.']).map(|p| p.parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+        match parts[..] {
+            [h, m, s, f] if m < 60 && s < 60 && (f as f64) < self.fps.ceil() =>
+                Some(((h * 3600 + m * 60 + s) as f64 * self.fps).round() as i64 + f),
+            _ => None,
+        }
+    }
+
+    /// Parse clock time (HH:MM:SS.mmm, MM:SS.mmm, HH:MM:SS,mmm or plain seconds) into seconds.
+    fn clock_to_seconds(time: &str) -> Option<f64> {
+        let time = time.trim().replace(',', ".");
+        let mut secs = 0.0;
+        for p in time.split(':') {
+            let v = p.parse::<f64>().ok().filter(|v| *v >= 0.0)?;
+            secs = secs * 60.0 + v;
+        }
+        Some(secs)
+    }
+
+    /// Format media relative frame number as SMPTE timecode, the same way as the client does.
+    fn frames_to_smpte(&self, frame: i64) -> String {
+        let frame = frame as f64;
+        let hours = (frame / (self.fps * 3600.0)).floor();
+        let minutes = (frame / (self.fps * 60.0)).floor() % 60.0;
+        let seconds = (frame / self.fps).floor() % 60.0;
+        let frames = (frame % self.fps).floor();
+        format!("{:02}:{:02}:{:02}:{:02}", hours, minutes, seconds, frames)
+    }
+
+    /// Map a frame number from the imported file's timeline into media relative SMPTE timecode.
+    fn map_frame(&self, abs_frame: i64, file_has_start_tc: bool) -> Result<String, String> {
+        let frame = if file_has_start_tc { abs_frame - self.start_frame } else { abs_frame };
+        if frame < 0 {
+            return Err("Timecode is before start of media".into());
+        }
+        if let Some(total) = self.total_frames {
+            if frame > total {
+                return Err("Timecode is past end of media".into());
+            }
+        }
+        Ok(self.frames_to_smpte(frame))
+    }
+
+    /// Map SMPTE timecode from an edit timeline (EDL record timecodes etc.) into media relative SMPTE.
+    pub fn map_smpte(&self, tc: &str) -> Result<String, String> {
+        let abs = self.smpte_to_frames(tc).ok_or(format!("Invalid SMPTE timecode '{}'", tc.trim()))?;
+        self.map_frame(abs, true)
+    }
+
+    /// Map clock time from start of media (subtitles etc.) into media relative SMPTE.
+    pub fn map_clock(&self, time: &str) -> Result<String, String> {
+        let secs = Self::clock_to_seconds(time).ok_or(format!("Invalid time '{}'", time.trim()))?;
+        self.map_frame((secs * self.fps).floor() as i64, false)
+    }
+
+    /// Map either an SMPTE timecode or clock time (as found in CSV files).
+    pub fn map_any(&self, time: &str) -> Result<String, String> {
+        if time.split([':', ';']).count() == 4 || time.contains(';') {
+            self.map_smpte(time)
+        } else {
+            self.map_clock(time)
+        }
+    }
+}
+
+/// Look for first frame timecode in mediainfo JSON ("TimeCode_FirstFrame" in any track).
+fn start_timecode_from_metadata(raw_metadata_all: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(raw_metadata_all).ok()?;
+    json["media"]["track"].as_array()?.iter()
+        .find_map(|t| t["TimeCode_FirstFrame"].as_str().map(|s| s.to_string()))
+}
+
+
+/// Parse a review file into (unsaved) comments for given media file.
+///
+/// # Arguments
+/// * `format` - File format
+/// * `contents` - File contents as text
+/// * `media` - Media file to attach comments to
+/// * `mapper` - Timecode mapper for the media file
+/// * `user_id` - User ID of the importing user (stored as comment owner)
+/// * `source_author` - Name to attribute comments to, unless the file itself has an author column
+pub fn parse_comment_file(format: ImportFormat, contents: &str, media: &models::MediaFile, mapper: &TimecodeMapper, user_id: &str, source_author: &str) -> Res<ImportResult>
+{
+    let rows = match format {
+        ImportFormat::Csv => parse_csv(contents)?,
+        ImportFormat::Edl => parse_edl(contents),
+        ImportFormat::Srt => parse_srt(contents),
+    };
+
+    let mut res = ImportResult::default();
+    for row in rows {
+        let row = match row {
+            Ok(r) => r,
+            Err(rej) => { res.rejected.push(rej); continue; }
+        };
+        let tc = match row.time {
+            RowTime::Smpte(tc) => mapper.map_smpte(&tc),
+            RowTime::Clock(t) => mapper.map_clock(&t),
+            RowTime::Any(t) => mapper.map_any(&t),
+        };
+        match tc {
+            Ok(tc) => res.comments.push(models::CommentInsert {
+                media_file_id: media.id.clone(),
+                parent_id: None,
+                user_id: Some(user_id.to_string()),
+                username_ifnull: row.author.filter(|a| !a.trim().is_empty()).unwrap_or(source_author.to_string()),
+                comment: row.text,
+                timecode: Some(tc),
+                drawing: None,
+                subtitle_id: None,
+                subtitle_filename_ifnull: None,
+                annotation: None,
+            }),
+            Err(reason) => res.rejected.push(RejectedLine { line_num: row.line_num, line: row.line, reason }),
+        }
+    }
+    Ok(res)
+}
+
+
+// ---------------------------------------------------------------------
+// Format specific parsers
+// ---------------------------------------------------------------------
+
+enum RowTime {
+    Smpte(String),
+    Clock(String),
+    Any(String),
+}
+
+struct Row {
+    line_num: usize,
+    line: String,
+    time: RowTime,
+    text: String,
+    author: Option<String>,
+}
+
+type RowRes = Result<Row, RejectedLine>;
+
+fn reject(line_num: usize, line: &str, reason: &str) -> RowRes {
+    Err(RejectedLine { line_num, line: line.to_string(), reason: reason.to_string() })
+}
+
+
+fn parse_csv(contents: &str) -> Res<Vec<RowRes>>
+{
+    let first_line = contents.lines().next().unwrap_or_default();
+    let delim = if first_line.matches('\t').count() > first_line.matches(',').count() { b'\t' }
+        else if first_line.matches(';').count() > first_line.matches(',').count() { b';' }
+        else { b',' };
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delim)
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+
+    fn find_col(header: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+        names.iter().find_map(|n| header.iter().position(|h| h.to_lowercase() == *n))
+    }
+
+    let mut records = rdr.records().peekable();
+    let (mut tc_col, mut text_col, mut author_col) = (0, 1, Some(2));
+
+    // Use column names from header row, if there is one
+    if let Some(Ok(header)) = records.peek() {
+        if let Some(tc) = find_col(header, &["timecode", "tc", "time", "start", "in", "record in", "source in"]) {
+            text_col = find_col(header, &["comment", "description", "note", "notes", "text", "marker name", "name"])
+                .ok_or(anyhow!("CSV header has no comment column"))?;
+            tc_col = tc;
+            author_col = find_col(header, &["author", "user", "username", "reviewer"]);
+            records.next();
+        }
+    }
+
+    let mut rows = vec![];
+    for rec in records {
+        let rec = match rec {
+            Ok(r) => r,
+            Err(e) => {
+                let line_num = e.position().map(|p| p.line() as usize).unwrap_or_default();
+                rows.push(reject(line_num, contents.lines().nth(line_num.saturating_sub(1)).unwrap_or_default(), &format!("CSV error: {}", e)));
+                continue;
+            }
+        };
+        let line_num = rec.position().map(|p| p.line() as usize).unwrap_or_default();
+        let line = rec.iter().collect::<Vec<_>>().join(&(delim as char).to_string());
+        if rec.iter().all(|f| f.is_empty()) {
+            continue;
+        }
+        let (Some(tc), Some(text)) = (rec.get(tc_col), rec.get(text_col)) else {
+            rows.push(reject(line_num, &line, "Missing timecode or comment column"));
+            continue;
+        };
+        if text.is_empty() {
+            rows.push(reject(line_num, &line, "Empty comment"));
+            continue;
+        }
+        rows.push(Ok(Row {
+            line_num, line: line.clone(),
+            time: RowTime::Any(tc.to_string()),
+            text: text.to_string(),
+            author: author_col.and_then(|c| rec.get(c)).map(|a| a.to_string()),
+        }));
+    }
+    Ok(rows)
+}
+
+
+fn parse_edl(contents: &str) -> Vec<RowRes>
+{
+    let event_re = regex::Regex::new(r"^(\d+)\s+.*?(\d{2}:\d{2}:\d{2}[:;]\d{2})\s+(\d{2}:\d{2}:\d{2}[:;]\d{2})\s+(\d{2}:\d{2}:\d{2}[:;]\d{2})\s+(\d{2}:\d{2}:\d{2}[:;]\d{2})\s*$").unwrap();
+    let loc_re = regex::Regex::new(r"^\*\s*LOC:\s*(\d{2}:\d{2}:\d{2}[:;]\d{2})\s+(\S+)?\s*(.*)$").unwrap();
+
+    struct Event { line_num: usize, line: String, num: String, record_in: String, clip_name: Option<String>, comments: Vec<String> }
+    fn finish(ev: Option<Event>, rows: &mut Vec<RowRes>) {
+        if let Some(ev) = ev {
+            let text = if !ev.comments.is_empty() { ev.comments.join("\n") }
+                else { format!("EDL ({})", ev.clip_name.unwrap_or(ev.num)) };
+            rows.push(Ok(Row { line_num: ev.line_num, line: ev.line, time: RowTime::Smpte(ev.record_in), text, author: None }));
+        }
+    }
+
+    let mut rows = vec![];
+    let mut cur: Option<Event> = None;
+    for (i, line) in contents.lines().enumerate() {
+        let line_num = i + 1;
+        let trimmed = line.trim();
+        if let Some(caps) = event_re.captures(trimmed) {
+            finish(cur.take(), &mut rows);
+            cur = Some(Event { line_num, line: line.to_string(), num: caps[1].to_string(), record_in: caps[4].to_string(), clip_name: None, comments: vec![] });
+        } else if let Some(caps) = loc_re.captures(trimmed) {
+            let text = caps.get(3).map(|m| m.as_str().trim()).filter(|t| !t.is_empty())
+                .unwrap_or("Locator").to_string();
+            rows.push(Ok(Row { line_num, line: line.to_string(), time: RowTime::Smpte(caps[1].to_string()), text, author: None }));
+        } else if let Some(name) = trimmed.strip_prefix("* FROM CLIP NAME:") {
+            if let Some(ev) = cur.as_mut() { ev.clip_name = Some(name.trim().to_string()); }
+        } else if let Some(cmt) = trimmed.strip_prefix("* COMMENT:") {
+            if let Some(ev) = cur.as_mut() { ev.comments.push(cmt.trim().to_string()); }
+        } else if trimmed.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+            // Looks like an event line, but didn't parse
+            rows.push(reject(line_num, line, "Unrecognized EDL event line"));
+        }
+    }
+    finish(cur, &mut rows);
+    rows
+}
+
+
+fn parse_srt(contents: &str) -> Vec<RowRes>
+{
+    let tag_re = regex::Regex::new(r"<[^>]*>").unwrap();
+    let mut rows = vec![];
+
+    // Split into blocks separated by empty lines, remembering line numbers
+    let mut blocks: Vec<Vec<(usize, &str)>> = vec![vec![]];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            if !blocks.last().unwrap().is_empty() { blocks.push(vec![]); }
+        } else {
+            blocks.last_mut().unwrap().push((i + 1, line));
+        }
+    }
+
+    for block in blocks.into_iter().filter(|b| !b.is_empty()) {
+        let (first_num, first_line) = block[0];
+        if first_line.starts_with("WEBVTT") || first_line.starts_with("NOTE") || first_line.starts_with("STYLE") || first_line.starts_with("REGION") {
+            continue;
+        }
+        let Some(timing_idx) = block.iter().position(|(_, l)| l.contains("-->")) else {
+            rows.push(reject(first_num, first_line, "No timing line ('-->') in cue"));
+            continue;
+        };
+        let (line_num, timing) = block[timing_idx];
+        let start = timing.split("-->").next().unwrap_or_default().trim();
+        let text = block[timing_idx + 1..].iter()
+            .map(|(_, l)| tag_re.replace_all(l, "").trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if text.is_empty() {
+            rows.push(reject(line_num, timing, "Empty cue text"));
+            continue;
+        }
+        rows.push(Ok(Row { line_num, line: timing.to_string(), time: RowTime::Clock(start.to_string()), text, author: None }));
+    }
+    rows
+}
+
+
+/// Decode imported file contents (UTF-8, with or without BOM) into text.
+pub fn decode_contents(bytes: &[u8]) -> Res<String> {
+    let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Ok(s.to_string()),
+        Err(e) => bail!("File is not valid UTF-8 text: {}", e),
+    }
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+pub(crate) fn test_media(fps: &str, duration: f32, metadata: Option<&str>) -> models::MediaFile {
+    models::MediaFile {
+        id: "HASH0".into(),
+        user_id: "user.num1".into(),
+        media_type: Some("video".into()),
+        added_time: chrono::NaiveDateTime::default(),
+        recompression_done: None,
+        thumbs_done: None,
+        has_thumbnail: None,
+        thumb_sheet_cols: None,
+        thumb_sheet_rows: None,
+        orig_filename: None,
+        title: None,
+        total_frames: None,
+        duration: Some(duration),
+        fps: Some(fps.into()),
+        raw_metadata_all: metadata.map(|s| s.to_string()),
+        default_subtitle_id: None,
+        locked: None,
+        locked_by: None,
+    }
+}
+
+#[test]
+fn test_timecode_mapping()
+{
+    let media = test_media("25", 120.0, None);
+    let m = TimecodeMapper::for_media_file(&media, Some("01:00:00:00")).unwrap();
+    assert_eq!(m.map_smpte("01:00:05:10"), Ok("00:00:05:10".to_string()));
+    assert!(m.map_smpte("00:59:59:24").unwrap_err().contains("before start"));
+    assert!(m.map_smpte("01:05:00:00").unwrap_err().contains("past end"));
+    assert!(m.map_smpte("01:00:05:30").is_err());   // frame number >= fps
+    assert_eq!(m.map_clock("00:00:01,500"), Ok("00:00:01:12".to_string()));
+    assert_eq!(m.map_any("90.2"), Ok("00:01:30:05".to_string()));
+    assert_eq!(m.map_any("01:00:01:00"), Ok("00:00:01:00".to_string()));
+
+    // Start timecode from mediainfo JSON
+    let media = test_media("24", 60.0, Some(r#"{"media": {"track": [{"@type": "General"}, {"@type": "Other", "TimeCode_FirstFrame": "10:00:00:00"}]}}"#));
+    let m = TimecodeMapper::for_media_file(&media, None).unwrap();
+    assert_eq!(m.map_smpte("10:00:00:01"), Ok("00:00:00:01".to_string()));
+
+    // No usable fps
+    assert!(TimecodeMapper::for_media_file(&test_media("0", 60.0, None), None).is_err());
+}
+
+#[test]
+fn test_import_edl()
+{
+    let edl = std::fs::read_to_string("src/tests/assets/red-lettuce.edl").unwrap();
+    let media = test_media("24", 10.0, None);
+    let m = TimecodeMapper::for_media_file(&media, None).unwrap();
+    let res = parse_comment_file(ImportFormat::Edl, &edl, &media, &m, "user.num1", "Editor").unwrap();
+    assert!(res.comments.len() >= 3);
+    assert_eq!(res.comments[0].comment, "Preparing lettuce with scissors");
+    assert_eq!(res.comments[0].timecode, Some("00:00:00:00".into()));
+    assert_eq!(res.comments[1].timecode, Some("00:00:02:17".into()));
+    assert!(res.comments.iter().all(|c| c.username_ifnull == "Editor" && c.user_id == Some("user.num1".into())));
+}
+
+#[test]
+fn test_import_csv()
+{
+    let csv = "Timecode,Comment,Author\n00:00:01:00,First note,Alice\n00:00:02:00,\"Second, with comma\",\nnot-a-time,Bad row,Bob\n00:00:03:00\n";
+    let media = test_media("25", 60.0, None);
+    let m = TimecodeMapper::for_media_file(&media, None).unwrap();
+    let res = parse_comment_file(ImportFormat::Csv, csv, &media, &m, "user.num1", "Importer").unwrap();
+    assert_eq!(res.comments.len(), 2);
+    assert_eq!(res.comments[0].username_ifnull, "Alice");
+    assert_eq!(res.comments[1].comment, "Second, with comma");
+    assert_eq!(res.comments[1].username_ifnull, "Importer");
+    assert_eq!(res.rejected.len(), 2);
+    assert_eq!(res.rejected[0].line_num, 4);
+    assert_eq!(res.rejected[1].line_num, 5);
+
+    // Headerless
+    let res = parse_comment_file(ImportFormat::Csv, "1.0,Note without header\n", &media, &m, "user.num1", "Importer").unwrap();
+    assert_eq!(res.comments.len(), 1);
+    assert_eq!(res.comments[0].timecode, Some("00:00:01:00".into()));
+}
+
+#[test]
+fn test_import_srt()
+{
+    let srt = std::fs::read_to_string("src/tests/assets/Apollo11_countdown.en.srt").unwrap();
+    let media = test_media("30", 600.0, None);
+    let m = TimecodeMapper::for_media_file(&media, None).unwrap();
+    let res = parse_comment_file(ImportFormat::Srt, &srt, &media, &m, "user.num1", "Subs").unwrap();
+    assert!(!res.comments.is_empty());
+    assert!(res.rejected.is_empty());
+
+    let vtt = "WEBVTT\n\n00:01.000 --> 00:02.000\n<i>Hello</i>\n\nbroken cue\n\n00:03.000 --> 00:04.000\n";
+    let res = parse_comment_file(ImportFormat::Srt, vtt, &media, &m, "user.num1", "Subs").unwrap();
+    assert_eq!(res.comments.len(), 1);
+    assert_eq!(res.comments[0].comment, "Hello");
+    assert_eq!(res.comments[0].timecode, Some("00:00:01:00".into()));
+    assert_eq!(res.rejected.len(), 2);
+}
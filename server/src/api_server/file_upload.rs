@@ -7,7 +7,9 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crate::video_pipeline::IncomingFile;
-use super::parse_auth_headers;
+use super::authenticate_request_blocking;
+use super::api_tokens::ApiTokenScope;
+use super::rate_limit::RateClass;
 use super::server_state::ServerState;
 use super::user_session::{org_authz_with_default, AuthzTopic, AuthzError};
 
@@ -22,6 +24,7 @@ use proto::org::authz_user_action_request as authz_req;
 /// * `upload_done` - Channel to submit the uploaded file path to further processing
 /// * `mime` - Parsed mime options from the request
 /// * `hdrs` - Authentication headers to be used for identifying the uploader
+/// * `peer` - Address of the connecting peer, for trusted proxy check
 /// * `server` - Server state (for organizer connection)
 /// * `body` - The request body (stream)
 pub async fn handle_multipart_upload(
@@ -29,11 +32,27 @@ pub async fn handle_multipart_upload(
     upload_done: crossbeam_channel::Sender<IncomingFile>,
     mime: mime::Mime,
     hdrs: HeaderMap,
+    peer: Option<std::net::SocketAddr>,
     server: ServerState,
     body: impl warp::Stream<Item = Result<impl bytes::Buf, warp::Error>> + Unpin)
         -> Result<warp::reply::WithStatus<String>, Infallible>
 {
-    let (user_id, user_name, is_admin, cookies) = parse_auth_headers(&hdrs, &server.default_user);
+    let super::AuthenticatedUser { user_id, user_name, is_admin, cookies, groups, token_scopes, .. } = match authenticate_request_blocking(&hdrs, peer, &server).await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::info!(details=%e, "Refused upload: authentication failed.");
+            return Ok(warp::reply::with_status("Unauthorized".into(), warp::http::StatusCode::UNAUTHORIZED));
+        }
+    };
+    let client = super::ClientInfo::from_request(&hdrs, peer, &server);
+    if server.rate_limiter.banned_for(&user_id, client.remote_addr.as_deref()).is_some() || !server.rate_limiter.check(RateClass::Upload, &user_id, client.remote_addr.as_deref()) {
+        tracing::info!(user=user_id, addr=?client.remote_addr, "Refused upload: rate limit exceeded.");
+        return Ok(warp::reply::with_status("Too many uploads, try again later".into(), warp::http::StatusCode::TOO_MANY_REQUESTS));
+    }
+    if token_scopes.is_some_and(|s| !s.contains(&ApiTokenScope::Upload)) {
+        tracing::info!(user=user_id, "Refused upload: API token lacks 'upload' scope.");
+        return Ok(warp::reply::with_status("Forbidden: API token lacks 'upload' scope".into(), warp::http::StatusCode::FORBIDDEN));
+    }
 
     // Check from organizer if user is allowed to upload.
     // Allow by default if organizer is not configured or doesn't care.
@@ -51,7 +70,9 @@ pub async fn handle_multipart_upload(
                 sid: "<upload--not-set>".to_string(),
                 user: Some(proto::UserInfo { id: user_id.clone(), name: user_name.clone() }),
                 is_admin,
-                cookies: cookies.clone()
+                cookies: cookies.clone(),
+                guest_link: None,
+                groups,
             };
 
             match org_authz_with_default(&org_session, "upload media file", true, &server, &Some(organizer),
@@ -0,0 +1,244 @@
+//! Built-in authentication with JWT bearer tokens.
+//!
+//! When enabled, user identity comes from a signed token instead of
+//! `X-Remote-User-*` headers. The token is read from `Authorization: Bearer <token>`
+//! or a cookie (browsers can't set headers on WebSocket connections).
+//!
+//! Signing keys are loaded from a JWKS file, or from the `jwks_uri` of an
+//! OpenID Connect issuer (`<issuer>/.well-known/openid-configuration`),
+//! and reloaded hourly to follow key rotation. Tokens signed with an unknown
+//! key id trigger an early reload (at most once a minute).
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use parking_lot::{Mutex, RwLock};
+use warp::http::HeaderMap;
+
+type Res<T> = anyhow::Result<T>;
+
+const KEY_REFRESH_INTERVAL_SECS: u64 = 60 * 60;
+const MIN_KEY_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct JwtAuthConfig {
+    pub jwks_file: Option<PathBuf>,     // Either this...
+    pub issuer: Option<String>,         // ...or this. If both are given, keys come from the file, and `iss` is still checked.
+    pub audience: Option<String>,       // Required `aud`, if given
+    pub cookie_name: String,
+    pub user_id_claim: String,          // Claim names can be dotted paths into nested objects, e.g. `realm_access.roles`
+    pub user_name_claim: String,
+    pub admin_claim: Option<String>,
+    pub admin_value: String,            // User is admin if admin claim is `true`, equals this, or is a list containing this
+    pub groups_claim: Option<String>,
+}
+
+impl Default for JwtAuthConfig {
+    fn default() -> Self {
+        JwtAuthConfig {
+            jwks_file: None,
+            issuer: None,
+            audience: None,
+            cookie_name: "clapshot_jwt".into(),
+            user_id_claim: "sub".into(),
+            user_name_claim: "name".into(),
+            admin_claim: None,
+            admin_value: "admin".into(),
+            groups_claim: None,
+        }
+    }
+}
+
+/// User identity from a validated token
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtUser {
+    pub user_id: String,
+    pub user_name: String,
+    pub is_admin: bool,
+    pub groups: Vec<String>,
+}
+
+pub struct JwtAuth {
+    cfg: JwtAuthConfig,
+    keys: RwLock<JwkSet>,
+    last_refetch: Mutex<Option<Instant>>,   // Last reload triggered by an unknown key id
+}
+
+impl JwtAuth {
+
+    /// Load signing keys. Blocks on network if keys come from an issuer.
+    pub fn new(cfg: JwtAuthConfig) -> Res<Self>
+    {
+        if cfg.jwks_file.is_none() && cfg.issuer.is_none() {
+            bail!("JWT authentication needs a JWKS file or an issuer");
+        }
+        let keys = load_keys(&cfg)?;
+        if keys.keys.is_empty() {
+            bail!("No keys found in JWKS");
+        }
+        Ok(JwtAuth { cfg, keys: RwLock::new(keys), last_refetch: Mutex::new(None) })
+    }
+
+    /// Reload signing keys from file or issuer. Keeps the old keys if the new set is empty.
+    pub fn refresh_keys(&self) -> Res<()>
+    {
+        let keys = load_keys(&self.cfg)?;
+        if keys.keys.is_empty() {
+            bail!("No keys found in JWKS");
+        }
+        *self.keys.write() = keys;
+        Ok(())
+    }
+
+    /// Reload keys early for a token with an unknown key id, unless that was already done recently.
+    /// Returns true if keys were reloaded.
+    fn refetch_for_unknown_kid(&self, kid: &str) -> bool
+    {
+        {
+            let mut last = self.last_refetch.lock();
+            if last.is_some_and(|t| t.elapsed() < MIN_KEY_REFETCH_INTERVAL) {
+                return false;
+            }
+            *last = Some(Instant::now());
+        }
+        match self.refresh_keys() {
+            Ok(_) => { tracing::info!(kid, "Reloaded JWT signing keys for an unknown key id"); true },
+            Err(e) => { tracing::error!(kid, details=%e, "Failed to reload JWT signing keys, keeping old ones"); false },
+        }
+    }
+
+    /// Periodically refresh keys until terminate flag is set
+    pub fn run_key_refresh_loop(&self, terminate_flag: Arc<AtomicBool>)
+    {
+        let _span = tracing::info_span!("JWT_KEYS").entered();
+        let mut elapsed = 0;
+        while !terminate_flag.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= KEY_REFRESH_INTERVAL_SECS {
+                elapsed = 0;
+                match self.refresh_keys() {
+                    Ok(_) => tracing::debug!("Refreshed JWT signing keys"),
+                    Err(e) => tracing::error!(details=%e, "Failed to refresh JWT signing keys, keeping old ones"),
+                }
+            }
+        }
+    }
+
+    /// Find token in `Authorization` header or the configured cookie
+    pub fn token_from_headers(&self, hdrs: &HeaderMap) -> Option<String>
+    {
+        let bearer = hdrs.get(warp::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer ").or_else(|| v.strip_prefix("bearer ")))
+            .map(|t| t.trim().to_string());
+        bearer.or_else(|| {
+            hdrs.get_all(warp::http::header::COOKIE).iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(';'))
+                .filter_map(|c| c.trim().split_once('='))
+                .find(|(k, _)| *k == self.cfg.cookie_name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    /// Verify token signature and standard claims, and map claims to a user
+    pub fn validate(&self, token: &str) -> Res<JwtUser>
+    {
+        let header = jsonwebtoken::decode_header(token).context("Malformed token")?;
+        if let Some(kid) = &header.kid {
+            if self.keys.read().find(kid).is_none() {
+                self.refetch_for_unknown_kid(kid);
+            }
+        }
+        let key = {
+            let keys = self.keys.read();
+            let jwk = match &header.kid {
+                Some(kid) => keys.find(kid).ok_or_else(|| anyhow!("Unknown key id '{}'", kid))?,
+                None if keys.keys.len() == 1 => &keys.keys[0],
+                None => bail!("Token has no key id, and JWKS has several keys"),
+            };
+            // Don't let the token choose a different algorithm than the key is for
+            if let Some(ka) = &jwk.common.key_algorithm {
+                if Algorithm::from_str(&ka.to_string()).ok() != Some(header.alg) {
+                    bail!("Token algorithm {:?} doesn't match key algorithm {}", header.alg, ka);
+                }
+            }
+            DecodingKey::from_jwk(jwk).context("Unusable key in JWKS")?
+        };
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(iss) = &self.cfg.issuer {
+            validation.set_issuer(&[iss]);
+        }
+        match &self.cfg.audience {
+            Some(aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation)
+            .context("Invalid token")?.claims;
+
+        let user_id = match get_claim(&claims, &self.cfg.user_id_claim) {
+            Some(serde_json::Value::String(s)) if !s.is_empty() => s.clone(),
+            Some(serde_json::Value::Number(n)) => n.to_string(),
+            _ => bail!("Token has no '{}' claim", self.cfg.user_id_claim),
+        };
+        let user_name = get_claim(&claims, &self.cfg.user_name_claim)
+            .and_then(|v| v.as_str()).filter(|s| !s.is_empty())
+            .map(String::from).unwrap_or_else(|| user_id.clone());
+        let is_admin = match self.cfg.admin_claim.as_deref().and_then(|c| get_claim(&claims, c)) {
+            Some(serde_json::Value::Bool(b)) => *b,
+            Some(serde_json::Value::String(s)) => s == &self.cfg.admin_value,
+            Some(serde_json::Value::Array(a)) => a.iter().any(|v| v.as_str() == Some(&self.cfg.admin_value)),
+            _ => false,
+        };
+        let groups = match self.cfg.groups_claim.as_deref().and_then(|c| get_claim(&claims, c)) {
+            Some(serde_json::Value::Array(a)) => a.iter().filter_map(|v| v.as_str()).map(String::from).collect(),
+            Some(serde_json::Value::String(s)) => s.split(',').map(|g| g.trim()).filter(|g| !g.is_empty()).map(String::from).collect(),
+            _ => vec![],
+        };
+        Ok(JwtUser { user_id, user_name, is_admin, groups })
+    }
+
+    /// Find and validate token from request headers
+    pub fn authenticate(&self, hdrs: &HeaderMap) -> Res<JwtUser>
+    {
+        let token = self.token_from_headers(hdrs).ok_or_else(|| anyhow!("No token in request"))?;
+        self.validate(&token)
+    }
+}
+
+/// Get claim by name. Falls back to a dotted path into nested objects,
+/// as some IdPs (e.g. Keycloak) put roles in those.
+fn get_claim<'a>(claims: &'a serde_json::Value, name: &str) -> Option<&'a serde_json::Value>
+{
+    claims.get(name).or_else(|| name.split('.').try_fold(claims, |v, k| v.get(k)))
+}
+
+fn load_keys(cfg: &JwtAuthConfig) -> Res<JwkSet>
+{
+    if let Some(path) = &cfg.jwks_file {
+        let txt = std::fs::read_to_string(path).with_context(|| format!("Failed to read JWKS file {:?}", path))?;
+        return serde_json::from_str(&txt).with_context(|| format!("Failed to parse JWKS file {:?}", path));
+    }
+    let issuer = cfg.issuer.as_deref().ok_or_else(|| anyhow!("No JWKS file or issuer"))?;
+    let http = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(15))
+        .user_agent(format!("{}/{}", crate::PKG_NAME, crate::PKG_VERSION))
+        .build()?;
+
+    let discovery_url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+    let discovery: serde_json::Value = serde_json::from_str(&http.get(&discovery_url).send()?.error_for_status()?.text()?)
+        .with_context(|| format!("Invalid OpenID configuration at {}", discovery_url))?;
+    let jwks_uri = discovery.get("jwks_uri").and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("No 'jwks_uri' in {}", discovery_url))?;
+
+    serde_json::from_str(&http.get(jwks_uri).send()?.error_for_status()?.text()?)
+        .with_context(|| format!("Invalid JWKS at {}", jwks_uri))
+}
@@ -0,0 +1,181 @@
+//! Built-in per-media file access grants.
+//!
+//! When no Organizer makes the authz decision, the server's defaults apply: anyone can
+//! view and comment on any media file, and only the owner or an admin can edit it.
+//! Grants give users or groups a role on specific media files. Once a file has any
+//! grants, it's restricted to its owner, admins, grantees (and share link guests).
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use lib_clapshot_grpc::proto;
+use proto::org::authz_user_action_request as authz_req;
+
+use crate::database::{models, DBPaging, DbBasicQuery, DbQueryByMediaFile, PooledConnection};
+use super::user_session::AuthzTopic;
+
+type Res<T> = anyhow::Result<T>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GrantRole {
+    Viewer,
+    Commenter,      // View + comment + approve
+    Editor,         // Also rename, edit subtitles and delete others' comments
+}
+
+impl GrantRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GrantRole::Viewer => "viewer",
+            GrantRole::Commenter => "commenter",
+            GrantRole::Editor => "editor",
+        }
+    }
+
+    pub fn to_proto3(self) -> proto::media_file_grant::Role {
+        match self {
+            GrantRole::Viewer => proto::media_file_grant::Role::Viewer,
+            GrantRole::Commenter => proto::media_file_grant::Role::Commenter,
+            GrantRole::Editor => proto::media_file_grant::Role::Editor,
+        }
+    }
+
+    pub fn from_proto3(r: proto::media_file_grant::Role) -> Self {
+        match r {
+            proto::media_file_grant::Role::Viewer => GrantRole::Viewer,
+            proto::media_file_grant::Role::Commenter => GrantRole::Commenter,
+            proto::media_file_grant::Role::Editor => GrantRole::Editor,
+        }
+    }
+}
+
+impl FromStr for GrantRole {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "viewer" => Ok(GrantRole::Viewer),
+            "commenter" => Ok(GrantRole::Commenter),
+            "editor" => Ok(GrantRole::Editor),
+            _ => bail!("Unknown media file grant role '{}'", s),
+        }
+    }
+}
+
+impl models::MediaFileGrant {
+    pub fn grant_role(&self) -> Option<GrantRole> {
+        GrantRole::from_str(&self.role).ok()
+    }
+
+    pub fn applies_to(&self, user_id: &str, groups: &[String]) -> bool {
+        self.user_id.as_deref() == Some(user_id) ||
+            self.group_name.as_ref().is_some_and(|g| groups.contains(g))
+    }
+}
+
+/// Best role given grants give to a user, directly or through groups
+pub fn effective_role(grants: &[models::MediaFileGrant], user_id: &str, groups: &[String]) -> Option<GrantRole>
+{
+    grants.iter()
+        .filter(|g| g.applies_to(user_id, groups))
+        .filter_map(|g| g.grant_role())
+        .max()
+}
+
+/// Role needed for an operation on a restricted media file.
+/// None = only owner and admins.
+///
+/// # Arguments
+/// * `is_own_comment` - For comment ops, if the user wrote the comment
+fn required_role(op: &AuthzTopic, is_own_comment: bool) -> Option<GrantRole>
+{
+    use authz_req::media_file_op::Op as MOp;
+    use authz_req::comment_op::Op as COp;
+    match op {
+        AuthzTopic::MediaFile(_, op) => match op {
+            MOp::View => Some(GrantRole::Viewer),
+            MOp::Comment | MOp::Approve => Some(GrantRole::Commenter),
+            MOp::Rename | MOp::Edit => Some(GrantRole::Editor),
+            MOp::Delete | MOp::Lock => None,
+        },
+        AuthzTopic::Comment(_, op) => match (op, is_own_comment) {
+            (COp::Edit, true) | (COp::Delete, true) => Some(GrantRole::Commenter),
+            (COp::Delete, false) => Some(GrantRole::Editor),
+            (COp::Edit, false) => None,
+        },
+        AuthzTopic::Other(..) => None,
+    }
+}
+
+/// Adjust the server's default authz decision by media file grants.
+/// Unrestricted files (no grants), owners and admins get the plain default.
+pub fn apply_grants_to_default(conn: &mut PooledConnection, ses: &proto::org::UserSessionData, op: &AuthzTopic, default: bool) -> Res<bool>
+{
+    let user_id = ses.user.as_ref().map(|u| u.id.as_str()).unwrap_or_default();
+    let (media_file, is_own_comment) = match op {
+        AuthzTopic::MediaFile(mf, _) => ((*mf).clone(), false),
+        AuthzTopic::Comment(c, _) => (models::MediaFile::get(conn, &c.media_file_id)?, c.user_id.as_deref() == Some(user_id)),
+        AuthzTopic::Other(..) => return Ok(default),
+    };
+    if ses.is_admin || media_file.user_id == user_id {
+        return Ok(default);
+    }
+    if ses.guest_link.as_ref().is_some_and(|l| guest_link_allows(l, &media_file.id)) {
+        return Ok(default);     // Share link already limits what guests can do
+    }
+    let grants = models::MediaFileGrant::get_by_media_file(conn, &media_file.id, DBPaging::default())?;
+    if grants.is_empty() {
+        return Ok(default);
+    }
+    let role = effective_role(&grants, user_id, &ses.groups);
+    Ok(required_role(op, is_own_comment).is_some_and(|req| role.is_some_and(|r| r >= req)))
+}
+
+/// True if the (proto) share link covers the given media file. Whole IDs only, never substrings.
+fn guest_link_allows(link: &proto::ShareLink, media_file_id: &str) -> bool
+{
+    link.media_file_ids.iter().any(|id| id == media_file_id)
+}
+
+/// IDs of media files shared with the user (directly or through groups), excluding their own.
+pub fn media_files_shared_with(conn: &mut PooledConnection, user_id: &str, groups: &[String]) -> Res<Vec<String>>
+{
+    let mut ids: Vec<String> = vec![];
+    for g in models::MediaFileGrant::get_for_principal(conn, user_id, groups)? {
+        if !ids.contains(&g.media_file_id) { ids.push(g.media_file_id); }
+    }
+    let mfs = models::MediaFile::get_many(conn, &ids)?;
+    Ok(mfs.into_iter().filter(|m| m.user_id != user_id).map(|m| m.id).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(user_id: Option<&str>, group: Option<&str>, role: &str) -> models::MediaFileGrant {
+        models::MediaFileGrant {
+            id: 1, media_file_id: "mf".into(), user_id: user_id.map(String::from), group_name: group.map(String::from),
+            role: role.into(), granted_by: None, created: chrono::Utc::now().naive_utc()
+        }
+    }
+
+    #[test]
+    fn test_effective_role() {
+        let grants = vec![grant(Some("bob"), None, "viewer"), grant(None, Some("editors"), "editor"), grant(None, Some("reviewers"), "commenter")];
+        assert_eq!(effective_role(&grants, "bob", &[]), Some(GrantRole::Viewer));
+        assert_eq!(effective_role(&grants, "bob", &["reviewers".into()]), Some(GrantRole::Commenter));
+        assert_eq!(effective_role(&grants, "alice", &["reviewers".into(), "editors".into()]), Some(GrantRole::Editor));
+        assert_eq!(effective_role(&grants, "alice", &["bob".into()]), None);
+    }
+
+    #[test]
+    fn test_guest_link_allows() {
+        let link = proto::ShareLink { media_file_ids: vec!["abc123".into(), "xyz".into()], ..Default::default() };
+        assert!(guest_link_allows(&link, "abc123"));
+        assert!(guest_link_allows(&link, "xyz"));
+        assert!(!guest_link_allows(&link, "abc"));
+        assert!(!guest_link_allows(&link, "abc1234"));
+        assert!(!guest_link_allows(&link, "abc123,xyz"));
+        assert!(!guest_link_allows(&link, ""));
+    }
+}
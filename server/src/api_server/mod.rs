@@ -8,6 +8,7 @@ use lib_clapshot_grpc::proto;
 use lib_clapshot_grpc::proto::org::OnStartUserSessionResponse;
 use tracing::debug;
 use warp::Filter;
+use warp::Reply;
 use core::panic;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -30,6 +31,19 @@ pub mod user_session;
 pub mod ws_handers;
 use ws_handers::msg_dispatch;
 
+pub mod comment_import;
+pub mod comment_export;
+pub mod review_report;
+pub mod annotation;
+pub mod jwt_auth;
+pub mod trusted_proxy;
+pub mod api_tokens;
+pub mod share_links;
+pub mod media_grants;
+pub mod authz_policy;
+pub mod rate_limit;
+mod ws_resume;
+
 #[macro_use]
 #[cfg(test)]
 pub mod test_utils;
@@ -38,6 +52,9 @@ pub mod test_utils;
 pub mod tests;
 mod file_upload;
 use file_upload::handle_multipart_upload;
+mod rest_api;
+use rest_api::handle_rest_request;
+use rate_limit::RateClass;
 use crate::api_server::user_session::AuthzTopic;
 use crate::api_server::user_session::org_authz;
 use crate::client_cmd;
@@ -53,10 +70,9 @@ use crate::PKG_VERSION;
 use self::user_session::UserSession;
 
 type Res<T> = anyhow::Result<T>;
-type WsMsgSender = tokio::sync::mpsc::UnboundedSender<Message>;
+type WsMsgSender = tokio::sync::mpsc::UnboundedSender<WsMsg>;
 type SenderList = Vec<WsMsgSender>;
 type SenderListMap = Arc<RwLock<HashMap<String, SenderList>>>;
-type StringToStringMap = Arc<RwLock<HashMap<String, String>>>;
 type SessionMap = Arc<RwLock<HashMap<String, UserSession>>>;
 
 pub enum SendTo<'a> {
@@ -79,6 +95,7 @@ pub struct UserMessage {
     pub media_file_id: Option<String>,
     pub subtitle_id: Option<i32>,
     pub progress: Option<f32>,
+    pub webhook_event: Option<crate::webhooks::WebhookEvent>,   // Also notify webhooks about this
 }
 
 fn abbrv(msg: &str) -> String {
@@ -86,17 +103,38 @@ fn abbrv(msg: &str) -> String {
 }
 
 
+type WsSink = futures_util::stream::SplitSink<warp::ws::WebSocket, Message>;
+
+/// Websocket close code for sessions closed by the server (e.g. disconnected by user or admin).
+/// Clients should not reconnect or try to resume after it.
+pub const WS_CLOSE_DISCONNECTED: u16 = 4001;
+
+fn server_close_message() -> Message {
+    Message::close_with(WS_CLOSE_DISCONNECTED, "Session disconnected by server")
+}
+
+/// Send a message to client in given framing. If a replay buffer is given, a copy is kept there.
+async fn send_to_client(ws_tx: &mut WsSink, framing: WsFraming, replay: Option<&mut ws_resume::ReplayBuffer>, msg: WsMsg) -> Result<(), warp::Error>
+{
+    let out = framing.encode(&msg);
+    if let Some(replay) = replay {
+        replay.push(msg);
+    }
+    ws_tx.send(out).await
+}
+
 /// User has connected to our WebSocket endpoint.
 /// This function will run (potentially forever) for each individual user that connects.
 async fn handle_ws_session(
         ws: warp::ws::WebSocket,
         sid: String,
-        user_id: String,
-        username: String,
-        is_admin: bool,
-        cookies: HashMap<String, String>,
+        auth: AuthenticatedUser,
+        client: ClientInfo,
+        mut framing: WsFraming,
         server: ServerState)
 {
+    let session_auth = auth.clone();     // For checking that resuming connections have the same privileges
+    let AuthenticatedUser { user_id, user_name: username, is_admin, cookies, groups, token_scopes, api_token, guest_link } = auth;
     let (msgq_tx, mut msgq_rx) = tokio::sync::mpsc::unbounded_channel();
 
     let user = match server.db.conn().and_then(|mut conn|
@@ -120,6 +158,15 @@ async fn handle_ws_session(
         media_session_guard: None,
         collab_session_guard: None,
         organizer: None,
+        token_scopes,
+        api_token,
+        guest_link: guest_link.clone(),
+        groups: groups.clone(),
+        remote_addr: client.remote_addr,
+        user_agent: client.user_agent,
+        connected: chrono::Utc::now().naive_utc(),
+        rate_limit_violations: 0,
+        parked: false,
         org_session: proto::org::UserSessionData {
             sid: sid.clone(),
             user: Some(proto::UserInfo {
@@ -128,21 +175,37 @@ async fn handle_ws_session(
             }),
             is_admin,
             cookies,
+            guest_link: guest_link.as_ref().map(|l| l.to_proto3(None)),
+            groups,
         }
     };
 
     let _user_session_guard = Some(server.register_user_session(&sid, &user_id, ses.clone()));
     let (mut ws_tx, mut ws_rx) = ws.split();
 
-    // Let the client know user's id and name
-    if let Err(e) = server.emit_cmd(
-        client_cmd!(Welcome, {
-            user: Some(proto::UserInfo { id: user_id, name: username }),
+    // Keep a copy of sent messages, to replay them if the client reconnects after a dropped connection
+    let resumable = !server.ws_resume_grace.is_zero();
+    let resume_token = resumable.then(share_links::generate_key);
+    let (takeover_tx, mut takeover_rx) = tokio::sync::mpsc::unbounded_channel::<ws_resume::Takeover>();
+    let _resumable_guard = resume_token.as_ref().map(|t| server.register_resumable_session(t, &user_id, &sid, takeover_tx.clone()));
+    let mut replay = ws_resume::ReplayBuffer::default();
+    let mut parked: Option<(tokio::time::Instant, u64)> = None;    // (deadline, messages sent before connection was lost)
+
+    // Let the client know user's id and name. Welcome is sent directly (not queued), and isn't counted for replay.
+    let welcome = |resumed: bool| server_state::cmd_to_ws_msg(client_cmd!(Welcome, {
+            user: Some(proto::UserInfo { id: user_id.clone(), name: username.clone() }),
             is_admin: is_admin,
             server_version: PKG_VERSION.to_string(),
-        }),
-        SendTo::MsgSender(&ses.sender)
-    ) {
+            email_notifications: server.email.as_ref().map(|_| user.email_notifications.clone()),
+            guest_link: guest_link.as_ref().map(|l| l.to_proto3(None)),
+            resume_token: resume_token.clone(),
+            resumed: resumed,
+        }));
+    let welcome_res = match welcome(false) {
+        Ok(msg) => send_to_client(&mut ws_tx, framing, None, msg).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = welcome_res {
         tracing::error!(details=%e, "Error sending welcome message. Closing session.");
         return;
     }
@@ -200,104 +263,150 @@ async fn handle_ws_session(
 
     loop
     {
+        let mut conn_lost = false;
+
         tokio::select!
         {
-            // Termination flag set? Exit.
+            // Termination flag set, or resume grace period over? Exit.
             _ = sleep(Duration::from_millis(100)) => {
                 if server.terminate_flag.load(Relaxed) {
                     tracing::debug!("Termination flag set. Closing session.");
                     break;
-             }},
+                }
+                if parked.is_some_and(|(deadline, _)| tokio::time::Instant::now() > deadline) {
+                    tracing::info!("Client didn't reconnect in time. Closing session.");
+                    break;
+                }
+            },
 
-            // Message in queue? Send to client.
+            // Message in queue? Send to client (or just keep it for replay, if disconnected).
             Some(msg) = msgq_rx.recv() => {
-                tracing::debug!(msg = abbrv(msg.to_str().unwrap_or("<msg.to_str() failed>")), "Sending message to client.");
-                if let Err(e) = ws_tx.send(msg).await {
-                    tracing::error!(details=%e, "Error sending message - closing session.");
+                tracing::debug!(msg = abbrv(msg.json().unwrap_or("<close>")), "Sending message to client.");
+                if matches!(msg, WsMsg::Close) {
+                    if parked.is_none() { ws_tx.send(server_close_message()).await.ok(); }
+                    tracing::debug!("Session closed by server.");
                     break;
                 }
+                if let Some((_, sent_before)) = parked {
+                    replay.push(msg);
+                    if replay.lost_after(sent_before) {
+                        tracing::info!("Too many messages for a disconnected session to replay. Closing session.");
+                        break;
+                    }
+                } else if let Err(e) = send_to_client(&mut ws_tx, framing, resumable.then_some(&mut replay), msg).await {
+                    tracing::debug!(details=%e, "Error sending message - connection lost.");
+                    conn_lost = true;
+                }
+            },
+
+            // New connection taking over this session?
+            Some(t) = takeover_rx.recv() => {
+                if !ws_resume::same_privileges(&t.auth, &session_auth) {
+                    tracing::info!("Refused to resume session: new connection has different privileges.");
+                    t.reject.send(t.ws).ok();
+                    if parked.is_some() { break; }
+                    continue;
+                }
+                let Some(missed) = replay.since(t.received) else {
+                    tracing::info!(received=t.received, sent=replay.sent(), "Refused to resume session: missed messages no longer available.");
+                    t.reject.send(t.ws).ok();
+                    if parked.is_some() { break; }  // Client starts a new session instead
+                    continue;
+                };
+                tracing::info!(replayed=missed.len(), "Resuming session on new connection.");
+                if parked.is_none() {
+                    ws_tx.close().await.ok();   // Old connection might still seem open, e.g. after a laptop sleep
+                }
+                (ws_tx, ws_rx) = t.ws.split();
+                framing = t.framing;
+                parked = None;
+                ses.remote_addr = t.client.remote_addr;
+                ses.user_agent = t.client.user_agent;
+                if let Some(mut s) = server.get_session_write(&sid) {
+                    s.remote_addr = ses.remote_addr.clone();
+                    s.user_agent = ses.user_agent.clone();
+                    s.parked = false;
+                }
+                match welcome(true) {
+                    Ok(w) => { conn_lost = send_to_client(&mut ws_tx, framing, None, w).await.is_err(); },
+                    Err(e) => { tracing::error!(details=%e, "Error serializing welcome message. Closing session."); break; }
+                }
+                for m in missed {
+                    if conn_lost { break; }
+                    conn_lost = send_to_client(&mut ws_tx, framing, None, m).await.is_err();
+                }
             },
 
             // Message from client? Handle it.
-            Some(msg) = ws_rx.next() => {
+            msg = ws_rx.next(), if parked.is_none() => {
+                // Send an error to client, in session's framing
+                let error_msg = |msg: String| server_state::cmd_to_ws_msg(client_cmd!(Error, { msg: msg }));
+
                 match msg {
-                    Err(e) => {
-                        tracing::debug!(details=%e, "Error receiving message - closing session.");
-                        break;
+                    None => {
+                        tracing::debug!("Websocket stream ended - connection lost.");
+                        conn_lost = true;
                     },
-                    Ok(msg) => {
-                        if msg.is_text() {
-
-                            fn parse_msg(msg: &Message) -> Res<(String, serde_json::Value, HashMap<String, String>)> {
-                                let msg_str = msg.to_str().unwrap_or("!!msg was supposed to .is_text()!!");
-
-                                let mut json: serde_json::Value = serde_json::from_str(msg_str)?;
-                                let mut cookies = HashMap::new();
-
-                                if let serde_json::Value::Object(map) = &mut json {
-                                    if let Some(cookies_json) = map.get("cookies") {
-                                        if let Some(cookies_json) = cookies_json.as_object() {
-                                            for (k, v) in cookies_json {
-                                                if let Some(v) = v.as_str() {
-                                                    cookies.insert(k.clone(), v.to_string());
-                                                }}}
-                                        map.remove("cookies");
-                                        assert!(json.get("cookies").is_none());
-                                    }
-                                } else {
-                                    bail!("JSON message was not a dict.");
-                                }
-                                let cmd_str = json.as_object().unwrap().keys().next().ok_or(anyhow!("JSON message had no command."))?.clone();
-                                Ok((cmd_str, json, cookies))
+                    Some(Err(e)) => {
+                        tracing::debug!(details=%e, "Error receiving message - connection lost.");
+                        conn_lost = true;
+                    },
+                    Some(Ok(msg)) => {
+                        if msg.is_text() || msg.is_binary() {
+                            if msg.is_text() {
+                                tracing::debug!("Msg from client. Raw text: {}", abbrv(msg.to_str().unwrap_or("<msg.to_str() failed>")));
                             }
 
-                            tracing::debug!("Msg from client. Raw text: {}", abbrv(msg.to_str().unwrap_or("<msg.to_str() failed>")));
-
-                            let (cmd_str, json) = match parse_msg(&msg) {
-                                Ok((cmd, json, cookies)) => {
+                            let (cmd_str, parsed) = match parse_client_msg(&msg) {
+                                Ok((cmd, parsed, cookies)) => {
                                     ses.org_session.cookies = cookies;
-                                    (cmd, json)
+                                    (cmd, parsed)
                                 },
                                 Err(e) => {
-                                    tracing::warn!(details=%e, "Error parsing JSON message. Closing session.");
+                                    tracing::warn!(details=%e, "Error parsing message. Closing session.");
                                     #[cfg(not(test))] {
                                         sleep(Duration::from_secs(5)).await;
                                     }
-                                    let err_msg = proto::client::server_to_client_cmd::Error { msg: format!("Invalid message, bye -- {}", e) };
-                                    let json_txt = serde_json::to_string(&err_msg).expect("Error serializing error message");
-                                    ws_tx.send(Message::text(json_txt)).await.ok();
+                                    if let Ok(m) = error_msg(format!("Invalid message, bye -- {}", e)) {
+                                        send_to_client(&mut ws_tx, framing, None, m).await.ok();
+                                    }
                                     break;
                                 }
                             };
                             tracing::debug!(cmd=%cmd_str, "Msg from client");
 
-                            match serde_json::from_value::<proto::client::ClientToServerCmd>(json.clone()) {
+                            match parsed {
                                 Ok(req) => {
                                     match msg_dispatch(&req, &mut ses, &server, ).await {
                                         Ok(true) => {},             // Continues serving
                                         Ok(false) => { break; }     // Session closed
                                         Err(e) => {
                                             if let Some(e) = e.downcast_ref::<SessionClose>() {
-                                                if !matches!(e, SessionClose::Logout) { tracing::debug!("[{}] Closing session: {:?}", sid, e); }
+                                                if !matches!(e, SessionClose::Logout) {
+                                                    tracing::debug!("[{}] Closing session: {:?}", sid, e);
+                                                    if let Ok(msg) = error_msg(e.to_string()) {
+                                                        send_to_client(&mut ws_tx, framing, None, msg).await.ok();
+                                                    }
+                                                }
                                                 break;
-                                            } else if let Some(e) = e.downcast_ref::<tokio::sync::mpsc::error::SendError<Message>>() {
+                                            } else if let Some(e) = e.downcast_ref::<tokio::sync::mpsc::error::SendError<WsMsg>>() {
                                                 tracing::error!("[{}] Error sending message. Closing session. -- {}", sid, e);
                                                 break;
                                             } else {
                                                 let answ = format!("Error handling command '{}'.", cmd_str);
                                                 tracing::warn!("[{}] {}: {}", sid, answ, e);
-                                                let err_msg = proto::client::server_to_client_cmd::Error { msg: answ };
-                                                let json_txt = serde_json::to_string(&err_msg).expect("Error serializing error message");
-                                                if ws_tx.send(Message::text(json_txt)).await.is_err() { break; };
+                                                if let Ok(m) = error_msg(answ) {
+                                                    conn_lost = send_to_client(&mut ws_tx, framing, resumable.then_some(&mut replay), m).await.is_err();
+                                                }
                                             }
                                         }
                                     };
                                 },
                                 Err(e) => {
                                     tracing::warn!(details=%e, "Invalid command from client: {:?}", cmd_str);
-                                    let err_msg = proto::client::server_to_client_cmd::Error { msg: format!("Invalid command from client: {:?}", e) };
-                                    let json_txt = serde_json::to_string(&err_msg).expect("Error serializing error message");
-                                    ws_tx.send(Message::text(json_txt)).await.ok();
+                                    if let Ok(m) = error_msg(format!("Invalid command from client: {}", e)) {
+                                        conn_lost = send_to_client(&mut ws_tx, framing, resumable.then_some(&mut replay), m).await.is_err();
+                                    }
                                 }
                             };
                         } else if msg.is_close() {
@@ -318,9 +427,45 @@ async fn handle_ws_session(
                 }
             }
         }
+
+        // Connection dropped without a close frame? Keep the session for a while, for the client to resume it.
+        if conn_lost {
+            if !resumable {
+                tracing::debug!("Connection lost. Closing session.");
+                break;
+            }
+            tracing::info!(grace_secs=server.ws_resume_grace.as_secs(), "Connection lost. Keeping session for resume.");
+            parked = Some((tokio::time::Instant::now() + server.ws_resume_grace, replay.sent()));
+            if let Some(mut s) = server.get_session_write(&sid) { s.parked = true; }
+        }
+    }
+
+    if let Err(e) = ws_handers::leave_collab_and_notify(&mut ses, &server) {
+        tracing::warn!(details=%e, "Error notifying collab participants of session end.");
     }
 }
 
+const HDR_USER_ID: [&str; 3] = ["X-Remote-User-Id", "X_Remote_User_Id", "HTTP_X_REMOTE_USER_ID"];
+const HDR_USER_NAME: [&str; 3] = ["X-Remote-User-Name", "X_Remote_User_Name", "HTTP_X_REMOTE_USER_NAME"];
+const HDR_USER_IS_ADMIN: [&str; 3] = ["X-Remote-User-Is-Admin", "X_Remote_User_Is_Admin", "HTTP_X_REMOTE_USER_IS_ADMIN"];
+const HDR_USER_GROUPS: [&str; 3] = ["X-Remote-User-Groups", "X_Remote_User_Groups", "HTTP_X_REMOTE_USER_GROUPS"];
+const HDR_CLAPSHOT_COOKIES: [&str; 3] = ["X-Clapshot-Cookies", "X_Clapshot_Cookies", "HTTP_X_CLAPSHOT_COOKIES"];
+const HDR_PROXY_SECRET: [&str; 3] = ["X-Clapshot-Proxy-Secret", "X_Clapshot_Proxy_Secret", "HTTP_X_CLAPSHOT_PROXY_SECRET"];
+const HDR_PROXY_TIMESTAMP: [&str; 3] = ["X-Clapshot-Proxy-Timestamp", "X_Clapshot_Proxy_Timestamp", "HTTP_X_CLAPSHOT_PROXY_TIMESTAMP"];
+const HDR_PROXY_SIGNATURE: [&str; 3] = ["X-Clapshot-Proxy-Signature", "X_Clapshot_Proxy_Signature", "HTTP_X_CLAPSHOT_PROXY_SIGNATURE"];
+const HDR_FORWARDED_FOR: [&str; 3] = ["X-Forwarded-For", "X_Forwarded_For", "HTTP_X_FORWARDED_FOR"];
+
+fn try_get_first_named_hdr<T>(hdrs: &HeaderMap, names: T) -> Option<String>
+    where T: IntoIterator<Item=&'static str> {
+    for n in names {
+        if let Some(val) = hdrs.get(n).or(hdrs.get(n.to_lowercase())) {
+            match val.to_str() {
+                Ok(s) => return Some(s.into()),
+                Err(e) => tracing::warn!(details=%e, "Error parsing header '{}'.", n),
+    }}}
+    None
+}
+
 /// Extract user id, name and clapshot_cookies from HTTP headers (set by nginx)
 /// If any of the headers are missing, default values are used:
 /// - If X-Remote-User-Id is missing, `default_user_id` is used.
@@ -335,33 +480,28 @@ async fn handle_ws_session(
 /// * Returns: (user_id: String, user_name: String, is_admin: bool, clapshot_cookies: HashMap<String, String>)
 fn parse_auth_headers(hdrs: &HeaderMap, default_user_id: &str) -> (String, String, bool, HashMap<String, String>)
 {
-    fn try_get_first_named_hdr<T>(hdrs: &HeaderMap, names: T) -> Option<String>
-        where T: IntoIterator<Item=&'static str> {
-        for n in names {
-            if let Some(val) = hdrs.get(n).or(hdrs.get(n.to_lowercase())) {
-                match val.to_str() {
-                    Ok(s) => return Some(s.into()),
-                    Err(e) => tracing::warn!(details=%e, "Error parsing header '{}'.", n),
-        }}}
-        None
-    }
-
-    let user_id = match try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Id", "X_Remote_User_Id", "HTTP_X_REMOTE_USER_ID"]) {
+    let user_id = match try_get_first_named_hdr(hdrs, HDR_USER_ID) {
         Some(id) => id,
         None => {
             tracing::warn!("Missing X-Remote-User-Id in HTTP headers. Using '{}' instead.", default_user_id);
             default_user_id.into()
         }};
-    let user_name = try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Name", "X_Remote_User_Name", "HTTP_X_REMOTE_USER_NAME"])
+    let user_name = try_get_first_named_hdr(hdrs, HDR_USER_NAME)
         .unwrap_or_else(|| user_id.clone());
 
-    let cookies_str = try_get_first_named_hdr(&hdrs, vec!["X-Clapshot-Cookies", "X_Clapshot_Cookies", "HTTP_X_CLAPSHOT_COOKIES"])
-        .unwrap_or_else(|| "{}".into());
-
-    let is_admin: bool = try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Is-Admin", "X_Remote_User_Is_Admin", "HTTP_X_REMOTE_USER_IS_ADMIN"])
+    let is_admin: bool = try_get_first_named_hdr(hdrs, HDR_USER_IS_ADMIN)
         .map(|s| s.to_lowercase() == "true" || s == "1").unwrap_or(user_id == "admin");
 
-    let app_cookies = match cookies_str.parse::<serde_json::Value>() {
+    (user_id, user_name, is_admin, parse_clapshot_cookies(hdrs))
+}
+
+/// Parse `X-Clapshot-Cookies` header (JSON dict), or return an empty map.
+fn parse_clapshot_cookies(hdrs: &HeaderMap) -> HashMap<String, String>
+{
+    let cookies_str = try_get_first_named_hdr(hdrs, HDR_CLAPSHOT_COOKIES)
+        .unwrap_or_else(|| "{}".into());
+
+    match cookies_str.parse::<serde_json::Value>() {
         Ok(c) => {
             match c.as_object() {
                 Some(c) => c.iter().map(|(k, v)| (k.clone(), v.as_str().unwrap_or("<!ERROR: NON-STRING COOKIE VALUE!>").to_string())).collect(),
@@ -375,9 +515,304 @@ fn parse_auth_headers(hdrs: &HeaderMap, default_user_id: &str) -> (String, Strin
             tracing::error!("Error parsing 'clapshot_cookies' JSON: {}", e);
             HashMap::new()
         }
+    }
+}
+
+/// Comma-separated group list from `X-Remote-User-Groups`
+fn parse_groups(s: &str) -> Vec<String>
+{
+    s.split(',').map(|g| g.trim()).filter(|g| !g.is_empty()).map(String::from).collect()
+}
+
+/// Identity of a connecting user, from whichever authentication method was used
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuthenticatedUser {
+    pub user_id: String,
+    pub user_name: String,
+    pub is_admin: bool,
+    pub cookies: HashMap<String, String>,
+    pub groups: Vec<String>,
+    pub token_scopes: Option<Vec<api_tokens::ApiTokenScope>>,   // Set only for API token authenticated requests
+    pub api_token: Option<models::ApiToken>,                    // Same
+    pub guest_link: Option<models::ShareLink>,                  // Set only for share link guests
+}
+
+/// Where a websocket connection comes from, for session listings
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientInfo {
+    pub remote_addr: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    /// Client address is the first `X-Forwarded-For` entry if the peer is in configured trusted proxy networks,
+    /// otherwise the peer address. Used for session listings, per-IP rate limits and bans, not for access control.
+    pub(crate) fn from_request(hdrs: &HeaderMap, peer: Option<std::net::SocketAddr>, server: &ServerState) -> Self {
+        let forwarded = server.trusted_proxy.as_ref().is_some_and(|tp| tp.is_trusted_network_peer(peer.map(|p| p.ip())))
+            .then(|| try_get_first_named_hdr(hdrs, HDR_FORWARDED_FOR)).flatten()
+            .and_then(|v| v.split(',').next().map(|s| s.trim().to_string()))
+            .filter(|s| !s.is_empty());
+        ClientInfo {
+            remote_addr: forwarded.or_else(|| peer.map(|p| p.ip().to_string())),
+            user_agent: hdrs.get(warp::http::header::USER_AGENT).and_then(|v| v.to_str().ok()).map(String::from),
+        }
+    }
+}
+
+/// Websocket subprotocols (`Sec-WebSocket-Protocol`). Without either, JSON text frames are used.
+pub(crate) const WS_PROTOCOL_PROTOBUF: &str = "clapshot.protobuf";
+pub(crate) const WS_PROTOCOL_JSON: &str = "clapshot.json";
+
+/// Encoding of `ClientToServerCmd` / `ServerToClientCmd` messages on a websocket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum WsFraming {
+    #[default]
+    Json,           // pbjson text frames
+    Protobuf,       // Binary protobuf frames
+}
+
+impl WsFraming {
+    /// Pick framing by the first supported subprotocol the client offers.
+    /// Returns it, and the protocol name to echo back (if any was chosen).
+    pub(crate) fn negotiate(hdrs: &HeaderMap) -> (WsFraming, Option<&'static str>) {
+        let offered = hdrs.get_all(warp::http::header::SEC_WEBSOCKET_PROTOCOL).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim);
+        for p in offered {
+            match p {
+                WS_PROTOCOL_PROTOBUF => return (WsFraming::Protobuf, Some(WS_PROTOCOL_PROTOBUF)),
+                WS_PROTOCOL_JSON => return (WsFraming::Json, Some(WS_PROTOCOL_JSON)),
+                _ => {},
+            }
+        }
+        (WsFraming::Json, None)
+    }
+
+    /// Websocket message for a queued message, in this framing
+    fn encode(&self, msg: &WsMsg) -> Message {
+        match (self, msg) {
+            (_, WsMsg::Close) => server_close_message(),
+            (WsFraming::Json, WsMsg::Cmd(c)) => Message::text(c.json.clone()),
+            (WsFraming::Protobuf, WsMsg::Cmd(c)) => Message::binary(c.protobuf.get_or_init(|| match with_binary_drawings(&c.cmd) {
+                Some(cmd) => prost::Message::encode_to_vec(&cmd),
+                None => prost::Message::encode_to_vec(&c.cmd),
+            }).clone()),
+        }
+    }
+}
+
+/// Message queued for a session: a command, or a request to close the connection.
+/// Commands are serialized to JSON when queued, and to binary protobuf when first sent to a session
+/// using that framing. Clones (one per recipient) share the encodings.
+#[derive(Clone)]
+pub enum WsMsg {
+    Cmd(Arc<QueuedCmd>),
+    Close,
+}
+
+pub struct QueuedCmd {
+    cmd: proto::client::ServerToClientCmd,
+    json: String,
+    protobuf: std::sync::OnceLock<Vec<u8>>,
+}
+
+impl WsMsg {
+    pub(crate) fn from_cmd(cmd: proto::client::ServerToClientCmd) -> Res<Self> {
+        let json = serde_json::to_string(&cmd)?;
+        Ok(WsMsg::Cmd(Arc::new(QueuedCmd { cmd, json, protobuf: Default::default() })))
+    }
+
+    /// Command as JSON text, None for close
+    pub(crate) fn json(&self) -> Option<&str> {
+        match self {
+            WsMsg::Cmd(c) => Some(&c.json),
+            WsMsg::Close => None,
+        }
+    }
+}
+
+/// Data URI for a WebP drawing, as sent to JSON clients
+pub(crate) fn webp_data_uri(data: &[u8]) -> String {
+    use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD};
+    format!("data:image/webp;base64,{}", STANDARD_NO_PAD.encode(data))
+}
+
+/// Copy of the command with data URI drawings moved to raw bytes fields, for binary clients.
+/// None if the command has no drawings.
+fn with_binary_drawings(cmd: &proto::client::ServerToClientCmd) -> Option<proto::client::ServerToClientCmd>
+{
+    use proto::client::server_to_client_cmd::Cmd;
+    use base64::{Engine as _, engine::{GeneralPurpose, GeneralPurposeConfig, DecodePaddingMode}, alphabet};
+    const B64: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent));
+
+    let has_drawing = |d: &Option<String>| d.as_ref().is_some_and(|d| !d.is_empty());
+    let needed = match &cmd.cmd {
+        Some(Cmd::AddComments(a)) => a.comments.iter().any(|c| has_drawing(&c.drawing)),
+        Some(Cmd::CommentHistory(h)) => h.comment.as_ref().is_some_and(|c| has_drawing(&c.drawing)),
+        Some(Cmd::CollabEvent(e)) => has_drawing(&e.drawing),
+        _ => false,
     };
+    if !needed {
+        return None;
+    }
+    let to_bytes = |drawing: &mut Option<String>, webp: &mut Option<Vec<u8>>| {
+        if let Some(data) = drawing.as_deref().and_then(|d| d.strip_prefix("data:image/webp;base64,")).and_then(|b| B64.decode(b).ok()) {
+            *webp = Some(data);
+            *drawing = None;
+        }
+    };
+    let mut cmd = cmd.clone();
+    match &mut cmd.cmd {
+        Some(Cmd::AddComments(a)) => a.comments.iter_mut().for_each(|c| to_bytes(&mut c.drawing, &mut c.drawing_webp)),
+        Some(Cmd::CommentHistory(h)) => h.comment.iter_mut().for_each(|c| to_bytes(&mut c.drawing, &mut c.drawing_webp)),
+        Some(Cmd::CollabEvent(e)) => to_bytes(&mut e.drawing, &mut e.drawing_webp),
+        _ => {},
+    }
+    Some(cmd)
+}
+
+/// Command name, command (or error if it's invalid), and cookies
+type ParsedClientMsg = (String, Result<proto::client::ClientToServerCmd, String>, HashMap<String, String>);
 
-    (user_id, user_name, is_admin, app_cookies)
+/// Name of a client command, as in JSON messages
+fn client_cmd_name(cmd: &proto::client::client_to_server_cmd::Cmd) -> &'static str
+{
+    use proto::client::client_to_server_cmd::Cmd;
+    match cmd {
+        Cmd::OpenNavigationPage(_) => "openNavigationPage",
+        Cmd::OpenMediaFile(_) => "openMediaFile",
+        Cmd::DelMediaFile(_) => "delMediaFile",
+        Cmd::RenameMediaFile(_) => "renameMediaFile",
+        Cmd::AddComment(_) => "addComment",
+        Cmd::EditComment(_) => "editComment",
+        Cmd::DelComment(_) => "delComment",
+        Cmd::AddSubtitle(_) => "addSubtitle",
+        Cmd::EditSubtitleInfo(_) => "editSubtitleInfo",
+        Cmd::DelSubtitle(_) => "delSubtitle",
+        Cmd::ListMyMessages(_) => "listMyMessages",
+        Cmd::JoinCollab(_) => "joinCollab",
+        Cmd::LeaveCollab(_) => "leaveCollab",
+        Cmd::CollabReport(_) => "collabReport",
+        Cmd::OrganizerCmd(_) => "organizerCmd",
+        Cmd::MoveToFolder(_) => "moveToFolder",
+        Cmd::ReorderItems(_) => "reorderItems",
+        Cmd::Logout(_) => "logout",
+        Cmd::ImportComments(_) => "importComments",
+        Cmd::ExportReviewReport(_) => "exportReviewReport",
+        Cmd::Search(_) => "search",
+        Cmd::GetCommentHistory(_) => "getCommentHistory",
+        Cmd::RestoreComment(_) => "restoreComment",
+        Cmd::SetMediaFileApproval(_) => "setMediaFileApproval",
+        Cmd::SetMediaFileLocked(_) => "setMediaFileLocked",
+        Cmd::CollabSetPresenter(_) => "collabSetPresenter",
+        Cmd::CollabRequestControl(_) => "collabRequestControl",
+        Cmd::CollabChat(_) => "collabChat",
+        Cmd::SetEmailNotifications(_) => "setEmailNotifications",
+        Cmd::SetMessagesSeen(_) => "setMessagesSeen",
+        Cmd::DelMessages(_) => "delMessages",
+        Cmd::GetMessagePrefs(_) => "getMessagePrefs",
+        Cmd::SetMessagePrefs(_) => "setMessagePrefs",
+        Cmd::CreateApiToken(_) => "createApiToken",
+        Cmd::ListApiTokens(_) => "listApiTokens",
+        Cmd::RevokeApiToken(_) => "revokeApiToken",
+        Cmd::CreateShareLink(_) => "createShareLink",
+        Cmd::ListShareLinks(_) => "listShareLinks",
+        Cmd::RevokeShareLink(_) => "revokeShareLink",
+        Cmd::GrantMediaFileAccess(_) => "grantMediaFileAccess",
+        Cmd::ListMediaFileGrants(_) => "listMediaFileGrants",
+        Cmd::RevokeMediaFileAccess(_) => "revokeMediaFileAccess",
+        Cmd::ListSessions(_) => "listSessions",
+        Cmd::DisconnectSession(_) => "disconnectSession",
+    }
+}
+
+/// Parse a client message, either JSON text or binary protobuf.
+/// Error means the message is unreadable.
+fn parse_client_msg(msg: &Message) -> Res<ParsedClientMsg>
+{
+    if msg.is_binary() {
+        let mut req = <proto::client::ClientToServerCmd as prost::Message>::decode(msg.as_bytes())?;
+        let cookies = std::mem::take(&mut req.cookies);
+        let cmd_str = req.cmd.as_ref().map_or("", client_cmd_name).to_string();
+        return Ok((cmd_str, Ok(req), cookies));
+    }
+
+    let msg_str = msg.to_str().map_err(|_| anyhow!("Message was neither text nor binary."))?;
+    let mut json: serde_json::Value = serde_json::from_str(msg_str)?;
+    let mut cookies = HashMap::new();
+
+    if let serde_json::Value::Object(map) = &mut json {
+        if let Some(cookies_json) = map.get("cookies") {
+            if let Some(cookies_json) = cookies_json.as_object() {
+                for (k, v) in cookies_json {
+                    if let Some(v) = v.as_str() {
+                        cookies.insert(k.clone(), v.to_string());
+                    }}}
+            map.remove("cookies");
+            assert!(json.get("cookies").is_none());
+        }
+    } else {
+        bail!("JSON message was not a dict.");
+    }
+    let cmd_str = json.as_object().unwrap().keys().next().ok_or(anyhow!("JSON message had no command."))?.clone();
+    let req = serde_json::from_value::<proto::client::ClientToServerCmd>(json).map_err(|e| format!("{:?}", e));
+    Ok((cmd_str, req, cookies))
+}
+
+/// Identify the user of a HTTP request.
+/// Personal API tokens (`Authorization: Bearer clapshot_...`) are accepted in all modes.
+/// With JWT authentication enabled, identity comes from a validated token only, and
+/// requests without a valid one are refused. Otherwise, it is read from reverse proxy headers,
+/// and if trusted proxies are configured, requests with auth headers from other peers are refused.
+fn authenticate_request(hdrs: &HeaderMap, peer: Option<std::net::SocketAddr>, server: &ServerState) -> Res<AuthenticatedUser>
+{
+    let bearer = hdrs.get(warp::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer ").or_else(|| v.strip_prefix("bearer ")))
+        .map(|t| t.trim());
+    if let Some(token) = bearer.filter(|t| t.starts_with(api_tokens::TOKEN_PREFIX)) {
+        let t = api_tokens::authenticate(&mut server.db.conn()?, token)?;
+        let user = models::User::get_or_create(&mut server.db.conn()?, &t.user_id, None)?;
+        tracing::debug!(user=t.user_id, token=t.name, "Authenticated with API token.");
+        return Ok(AuthenticatedUser { user_id: user.id, user_name: user.name, token_scopes: Some(t.scope_list()), api_token: Some(t), ..Default::default() });
+    }
+    if let Some(jwt) = &server.jwt_auth {
+        let u = jwt.authenticate(hdrs)?;
+        return Ok(AuthenticatedUser { user_id: u.user_id, user_name: u.user_name, is_admin: u.is_admin, cookies: parse_clapshot_cookies(hdrs), groups: u.groups, ..Default::default() });
+    }
+    if let Some(tp) = server.trusted_proxy.as_ref().filter(|tp| tp.is_enabled()) {
+        let ph = trusted_proxy::ProxyHeaders {
+            user_id: try_get_first_named_hdr(hdrs, HDR_USER_ID),
+            user_name: try_get_first_named_hdr(hdrs, HDR_USER_NAME),
+            is_admin: try_get_first_named_hdr(hdrs, HDR_USER_IS_ADMIN),
+            groups: try_get_first_named_hdr(hdrs, HDR_USER_GROUPS),
+            cookies: try_get_first_named_hdr(hdrs, HDR_CLAPSHOT_COOKIES),
+            secret: try_get_first_named_hdr(hdrs, HDR_PROXY_SECRET),
+            timestamp: try_get_first_named_hdr(hdrs, HDR_PROXY_TIMESTAMP),
+            signature: try_get_first_named_hdr(hdrs, HDR_PROXY_SIGNATURE),
+        };
+        let peer_ip = peer.map(|a| a.ip());
+        match tp.verify(peer_ip, &ph, chrono::Utc::now().timestamp()) {
+            Ok(_) => tracing::debug!(peer=?peer_ip, "Auth headers from trusted proxy accepted."),
+            Err(e) if ph.has_identity() => {
+                tracing::warn!(peer=?peer_ip, user=?ph.user_id, reason=%e, "Rejected auth headers from untrusted peer.");
+                bail!("untrusted auth headers: {}", e);
+            },
+            Err(e) => tracing::info!(peer=?peer_ip, reason=%e, "Untrusted peer without auth headers. Using default user."),
+        }
+    }
+    let (user_id, user_name, is_admin, cookies) = parse_auth_headers(hdrs, &server.default_user);
+    let groups = try_get_first_named_hdr(hdrs, HDR_USER_GROUPS).map(|g| parse_groups(&g)).unwrap_or_default();
+    Ok(AuthenticatedUser { user_id, user_name, is_admin, cookies, groups, ..Default::default() })
+}
+
+/// Run `authenticate_request` on a blocking thread, as it may query the database
+/// or fetch JWT signing keys over HTTP.
+async fn authenticate_request_blocking(hdrs: &HeaderMap, peer: Option<std::net::SocketAddr>, server: &ServerState) -> Res<AuthenticatedUser>
+{
+    let (hdrs, server) = (hdrs.clone(), server.clone());
+    tokio::task::spawn_blocking(move || authenticate_request(&hdrs, peer, &server)).await?
 }
 
 /// Handle HTTP requests, read authentication headers and dispatch to WebSocket handler.
@@ -390,7 +825,6 @@ async fn run_api_server_async(
     grpc_server_bind: Option<GrpcBindAddr>,
     port: u16)
 {
-    let session_counter = Arc::new(RwLock::new(0u64));
     let server_state_cln1 = server_state.clone();
     let server_state_cln2 = server_state.clone();
     let server_state_cln3 = server_state.clone();
@@ -445,43 +879,119 @@ async fn run_api_server_async(
         .and(warp::any().map(move || upload_results_tx.clone()))
         .and(warp::header::<mime::Mime>("content-type"))
         .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || server_state_cln3.clone()))
         .and(warp::body::stream())
         .and_then(handle_multipart_upload);
 
+    let server_state_cln5 = server_state.clone();
+    let rt_rest = warp::path("api").and(warp::path("v1"))
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::any().map(move || server_state_cln5.clone()))
+        .and(warp::body::stream())
+        .and_then(handle_rest_request);
+
+    // Lets the client know if it should ask guest for a password before connecting
+    let server_state_cln4 = server_state.clone();
+    let rt_share_info = warp::path("api").and(warp::path("share_info"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |query: HashMap<String, String>| {
+            let server = server_state_cln4.clone();
+            async move {
+                let link = tokio::task::spawn_blocking(move || query.get(share_links::QUERY_SHARE_KEY).and_then(|key|
+                    server.db.conn().ok().and_then(|mut conn| models::ShareLink::get_by_key(&mut conn, key).ok().flatten())))
+                    .await.ok().flatten();
+                Ok::<_, warp::Rejection>(match link.filter(|l| !l.is_expired(chrono::Utc::now().naive_utc())) {
+                    Some(l) => warp::reply::json(&serde_json::json!({ "password_required": l.password_hash.is_some() })).into_response(),
+                    None => warp::reply::with_status("Unknown or expired share link", warp::http::StatusCode::NOT_FOUND).into_response(),
+                })
+            }
+        });
+
     let rt_videos = warp::path("videos").and(
         warp::fs::dir(server_state_cln1.media_files_dir.clone())
             .with(warp::log("videos")));
 
     let rt_api_ws = warp::path("api").and(warp::path("ws"))
         .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::query::<HashMap<String, String>>())
         .and(warp::ws())
-        .map (move|hdrs: HeaderMap, ws: warp::ws::Ws| {
-
-            // Get user ID and username (from reverse proxy)
-            let (user_id, user_name, is_admin, app_cookies) = parse_auth_headers(&hdrs, &server_state.default_user);
-
-            // Increment session counter
-            let sid = {
-                let mut counter = session_counter.write();
-                *counter += 1;
-                (*counter).to_string()
+        .and(warp::any().map(move || server_state.clone()))
+        .and_then(|hdrs: HeaderMap, peer: Option<std::net::SocketAddr>, query: HashMap<String, String>, ws: warp::ws::Ws, server_state: ServerState| async move {
+
+            let client = ClientInfo::from_request(&hdrs, peer, &server_state);
+
+            // Get user ID and username (from share link, API token, JWT or reverse proxy).
+            // Guest password hashing and DB queries are blocking, so keep them off the async workers.
+            let auth_res = if let Some(key) = query.get(share_links::QUERY_SHARE_KEY) {
+                // Throttle password guessing per link and IP, before doing the (slow) hashing
+                let link_scope = format!("share:{}", key);
+                let ip = client.remote_addr.clone();
+                if server_state.rate_limiter.is_exhausted(RateClass::AuthFailure, &link_scope, ip.as_deref()) {
+                    tracing::info!(addr=?ip, "Refused guest connection: too many failed attempts.");
+                    return Ok::<_, warp::Rejection>(warp::reply::with_status("Too many failed attempts, try again later", warp::http::StatusCode::TOO_MANY_REQUESTS).into_response());
+                }
+                let (server, query) = (server_state.clone(), query.clone());
+                let res = tokio::task::spawn_blocking(move || {
+                    let mut conn = server.db.conn()?;
+                    share_links::authenticate_guest(&mut conn, &query)
+                }).await.map_err(anyhow::Error::from).and_then(|r| r);
+                if res.is_err() {
+                    server_state.rate_limiter.check(RateClass::AuthFailure, &link_scope, ip.as_deref());
+                }
+                res.map(|(user_id, user_name, link)| AuthenticatedUser { user_id, user_name, guest_link: Some(link), ..Default::default() })
+            } else {
+                authenticate_request_blocking(&hdrs, peer, &server_state).await
             };
+            let auth = match auth_res {
+                Ok(u) => u,
+                Err(e) => {
+                    tracing::info!(details=%e, "Refused websocket connection: authentication failed.");
+                    return Ok(warp::reply::with_status("Unauthorized", warp::http::StatusCode::UNAUTHORIZED).into_response());
+                }
+            };
+
+            if let Some(secs) = server_state.rate_limiter.banned_for(&auth.user_id, client.remote_addr.as_deref()) {
+                tracing::info!(user=auth.user_id, addr=?client.remote_addr, "Refused websocket connection: temporarily banned for rate limit violations.");
+                return Ok(warp::reply::with_status(format!("Too many requests, try again in {} seconds", secs), warp::http::StatusCode::TOO_MANY_REQUESTS).into_response());
+            }
 
-            let server_state = server_state.clone();
-            let is_admin = is_admin.clone();
-            ws.on_upgrade(move |ws| async move {
+            // Random session id, as it's passed around to Organizer and other users (e.g. collab participants)
+            let sid = uuid::Uuid::new_v4().to_string();
+            let (framing, protocol) = WsFraming::negotiate(&hdrs);
+            let ws = ws.max_message_size(server_state.rate_limiter.max_ws_message_bytes());
+            let resume = query.get(ws_resume::QUERY_RESUME_TOKEN).cloned().map(|token|
+                (token, query.get(ws_resume::QUERY_RECEIVED_COUNT).and_then(|n| n.parse::<u64>().ok()).unwrap_or(0)));
+
+            let mut resp = ws.on_upgrade(move |ws| async move {
+                // Reconnecting client? Let the old session take over the connection, if it still can.
+                let ws = match resume {
+                    Some((token, received)) => match ws_resume::try_resume(&server_state, &token, &auth, ws, framing, client.clone(), received).await {
+                        Some(ws) => ws,
+                        None => return,
+                    },
+                    None => ws,
+                };
                 // Diesel SQLite calls are blocking, so run a thread per user session
                 // even though we're using async/await
                 tokio::task::spawn_blocking(move || {
-                    let _span = tracing::info_span!("ws_session", sid=%sid, user=%user_id).entered();
-                    block_on(handle_ws_session(ws, sid, user_id, user_name, is_admin, app_cookies, server_state));
+                    let _span = tracing::info_span!("ws_session", sid=%sid, user=%auth.user_id).entered();
+                    block_on(handle_ws_session(ws, sid, auth, client, framing, server_state));
                 }).await.unwrap_or_else(|e| {
                     tracing::error!(details=%e, "Error joining handle_ws_session thread."); });
-            })
+            }).into_response();
+            if let Some(p) = protocol {
+                resp.headers_mut().insert(warp::http::header::SEC_WEBSOCKET_PROTOCOL, warp::http::HeaderValue::from_static(p));
+            }
+            Ok(resp)
         });
 
-    let routes = rt_health.or(rt_api_ws).or(rt_upload).or(rt_videos)
+    let routes = rt_health.or(rt_api_ws).or(rt_upload).or(rt_rest).or(rt_share_info).or(rt_videos)
         .with(warp::log("api_server"));
 
 
@@ -491,8 +1001,8 @@ async fn run_api_server_async(
         .collect();
     tracing::info!("Allowed CORS origins: {:?}", cors_origins);
 
-    let cors_methods = ["GET", "POST", "HEAD", "OPTIONS"];
-    let cors_headers = ["x-file-name", "x-clapshot-cookies", "content-type", "upgrade", "sec-websocket-protocol", "sec-websocket-version"];
+    let cors_methods = ["GET", "POST", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+    let cors_headers = ["x-file-name", "x-clapshot-cookies", "content-type", "authorization", "upgrade", "sec-websocket-protocol", "sec-websocket-version"];
 
     let routes = if cors_origins.contains(&"*") {
         tracing::warn!(concat!(
@@ -529,6 +1039,22 @@ async fn run_api_server_async(
             while let Ok(m) = user_msg_rx.try_recv() {
                 let topic_str = proto_msg_type_to_event_name(m.topic);
 
+                if let Some(evt) = m.webhook_event {
+                    let media_file = m.media_file_id.as_deref().and_then(|id| server_state.db.conn().map_err(anyhow::Error::from)
+                        .and_then(|mut conn| crate::webhooks::media_file_json_by_id(&mut conn, id, &server_state.url_base))
+                        .map_err(|e| tracing::error!(media_file=id, details=%e, "Failed to get media file for webhook."))
+                        .ok());
+                    let mut data = serde_json::json!({ "media_file": media_file });
+                    if evt == crate::webhooks::WebhookEvent::MediaFileTranscodeFailed {
+                        data["details"] = serde_json::json!(m.details.clone().unwrap_or(m.msg.clone()));
+                    }
+                    server_state.emit_webhook(evt, m.user_id.as_deref(), data);
+                }
+
+                if let Some(vid) = &m.media_file_id {
+                    server_state.update_media_file_progress(vid, m.topic, &m.msg, m.progress);
+                }
+
                 let msg_insert = models::MessageInsert  {
                     event_name: topic_str.into(),
                     user_id: m.user_id.clone().unwrap_or("".into()),
@@ -545,7 +1071,7 @@ async fn run_api_server_async(
                 // Message to all watchers of a media file
                 if let Some(vid) = m.media_file_id {
                     if let Err(_) = server_state.emit_cmd(
-                        client_cmd!(ShowMessages, { msgs: vec![proto_msg.clone()] }),
+                        client_cmd!(ShowMessages, { msgs: vec![proto_msg.clone()], list_info: None }),
                         SendTo::MediaFileId(&vid)
                     ) {
                         tracing::error!(media_file=vid, "Failed to send notification to media file watchers.");
@@ -553,17 +1079,21 @@ async fn run_api_server_async(
                 };
 
                 // Message to a single user
-                // Save it to the database, marking it as seen if sending it to the user succeeds
+                // Save it to the database, marking it as seen if sending it to the user succeeds.
+                // User's message preferences can suppress either.
                 if let Some(user_id) = m.user_id {
+                    let (pref_persist, pref_push) = server_state.user_message_prefs(&user_id, topic_str);
                     let mut user_was_online = false;
-                    match server_state.emit_cmd(
-                        client_cmd!(ShowMessages, { msgs: vec![proto_msg.clone()] }),
-                        SendTo::UserId(&user_id))
-                    {
-                        Ok(session_cnt) => { user_was_online = session_cnt>0 },
-                        Err(e) => tracing::error!(user=user_id, details=%e, "Failed to send user notification."),
+                    if pref_push {
+                        match server_state.emit_cmd(
+                            client_cmd!(ShowMessages, { msgs: vec![proto_msg.clone()], list_info: None }),
+                            SendTo::UserId(&user_id))
+                        {
+                            Ok(_) => { user_was_online = server_state.count_live_user_sessions(&user_id) > 0 },
+                            Err(e) => tracing::error!(user=user_id, details=%e, "Failed to send user notification."),
+                        }
                     }
-                    if !(matches!(m.topic, UserMessageTopic::Progress | UserMessageTopic::MediaFileAdded | UserMessageTopic::MediaFileUpdated)) {
+                    if pref_persist && !(matches!(m.topic, UserMessageTopic::Progress | UserMessageTopic::MediaFileAdded | UserMessageTopic::MediaFileUpdated)) {
                         let msg = models::MessageInsert {
                             seen: msg_insert.seen || user_was_online,
                             ..msg_insert
@@ -0,0 +1,338 @@
+//! Rate limiting for websocket commands and uploads.
+//!
+//! Each command class (comments, collab reports, organizer commands, uploads) has
+//! token buckets per user and per client IP. Commands over the limit are refused with
+//! an error. Sessions that keep going get disconnected, and the user (and their IP)
+//! is refused to reconnect for a while, with the ban doubling on every repeat.
+//! Failed share link logins are counted per link and IP, to slow down password guessing.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::Cmd;
+use parking_lot::Mutex;
+
+type Res<T> = anyhow::Result<T>;
+
+const BAN_BASE: Duration = Duration::from_secs(60);
+const BAN_MAX: Duration = Duration::from_secs(60 * 60);
+const KICK_MEMORY: Duration = Duration::from_secs(6 * 60 * 60);    // Forget disconnects after this long
+const PRUNE_THRESHOLD: usize = 10_000;                              // Drop idle buckets when there are more than this
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateClass {
+    Comment,
+    CollabReport,
+    OrganizerCmd,
+    Upload,
+    AuthFailure,    // Failed guest logins. "User" scope is the share link.
+}
+
+impl RateClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateClass::Comment => "comment",
+            RateClass::CollabReport => "collab_report",
+            RateClass::OrganizerCmd => "organizer_cmd",
+            RateClass::Upload => "upload",
+            RateClass::AuthFailure => "auth_failure",
+        }
+    }
+
+    /// Rate limited class of a client command, if any
+    pub fn of_cmd(cmd: &Cmd) -> Option<RateClass> {
+        match cmd {
+            Cmd::AddComment(_) | Cmd::EditComment(_) | Cmd::ImportComments(_) => Some(RateClass::Comment),
+            Cmd::CollabReport(_) | Cmd::CollabChat(_) => Some(RateClass::CollabReport),
+            Cmd::OrganizerCmd(_) | Cmd::MoveToFolder(_) | Cmd::ReorderItems(_) => Some(RateClass::OrganizerCmd),
+            Cmd::AddSubtitle(_) => Some(RateClass::Upload),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for RateClass {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "comment" => Ok(RateClass::Comment),
+            "collab_report" => Ok(RateClass::CollabReport),
+            "organizer_cmd" => Ok(RateClass::OrganizerCmd),
+            "upload" => Ok(RateClass::Upload),
+            "auth_failure" => Ok(RateClass::AuthFailure),
+            _ => bail!("Unknown rate limit class '{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateScope {
+    User,
+    Ip,
+}
+
+/// Allow `count` events per `per`, in bursts of up to `count`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub limits: HashMap<(RateClass, RateScope), Rate>,  // Missing = unlimited
+    pub max_drawing_bytes: usize,                       // Base64 encoded size
+    pub max_subtitle_bytes: usize,                      // Base64 encoded size
+    pub violations_before_disconnect: u32,              // Per session. 0 = never disconnect.
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        use RateClass::*;
+        use RateScope::*;
+        let r = |count, secs| Rate { count, per: Duration::from_secs(secs) };
+        RateLimitConfig {
+            limits: HashMap::from([
+                ((Comment, User), r(60, 60)),
+                ((Comment, Ip), r(300, 60)),
+                ((CollabReport, User), r(100, 10)),     // Scrubbing the timeline sends a lot of these
+                ((CollabReport, Ip), r(500, 10)),
+                ((OrganizerCmd, User), r(120, 60)),
+                ((OrganizerCmd, Ip), r(600, 60)),
+                ((Upload, User), r(60, 3600)),
+                ((Upload, Ip), r(300, 3600)),
+                ((AuthFailure, User), r(10, 600)),
+                ((AuthFailure, Ip), r(20, 600)),
+            ]),
+            max_drawing_bytes: 4 * 1024 * 1024,
+            max_subtitle_bytes: 2 * 1024 * 1024,
+            violations_before_disconnect: 20,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Override a limit from a `CLASS:SCOPE=COUNT/SECONDS` (or `CLASS:SCOPE=off`) spec,
+    /// e.g. `comment:user=30/60`.
+    pub fn set_from_spec(&mut self, spec: &str) -> Res<()>
+    {
+        let err = || anyhow!("Invalid rate limit '{}', expected e.g. 'comment:user=30/60' or 'upload:ip=off'", spec);
+        let (key, val) = spec.split_once('=').ok_or_else(err)?;
+        let (class, scope) = key.trim().split_once(':').ok_or_else(err)?;
+        let class = RateClass::from_str(class.trim())?;
+        let scope = match scope.trim() {
+            "user" => RateScope::User,
+            "ip" => RateScope::Ip,
+            _ => return Err(err()),
+        };
+        match val.trim() {
+            "off" => { self.limits.remove(&(class, scope)); },
+            v => {
+                let (count, secs) = v.split_once('/').ok_or_else(err)?;
+                let count: u32 = count.trim().parse().map_err(|_| err())?;
+                let secs: u64 = secs.trim().parse().map_err(|_| err())?;
+                if count == 0 || secs == 0 { return Err(err()); }
+                self.limits.insert((class, scope), Rate { count, per: Duration::from_secs(secs) });
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refill and try to take a token
+    fn take(&mut self, rate: &Rate, now: Instant) -> bool {
+        let refill = now.duration_since(self.updated).as_secs_f64() * rate.count as f64 / rate.per.as_secs_f64();
+        self.tokens = (self.tokens + refill).min(rate.count as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn has_token(&self, rate: &Rate, now: Instant) -> bool {
+        let refill = now.duration_since(self.updated).as_secs_f64() * rate.count as f64 / rate.per.as_secs_f64();
+        self.tokens + refill >= 1.0
+    }
+
+    fn is_full(&self, rate: &Rate, now: Instant) -> bool {
+        let refill = now.duration_since(self.updated).as_secs_f64() * rate.count as f64 / rate.per.as_secs_f64();
+        self.tokens + refill >= rate.count as f64
+    }
+}
+
+struct Kicks {
+    count: u32,
+    last: Instant,
+    banned_until: Instant,
+}
+
+pub struct RateLimiter {
+    pub cfg: RateLimitConfig,
+    buckets: Mutex<HashMap<(RateClass, RateScope, String), Bucket>>,
+    kicks: Mutex<HashMap<(RateScope, String), Kicks>>,    // user id / IP -> disconnects for rate limit violations
+}
+
+impl RateLimiter {
+
+    pub fn new(cfg: RateLimitConfig) -> Self {
+        RateLimiter { cfg, buckets: Mutex::new(HashMap::new()), kicks: Mutex::new(HashMap::new()) }
+    }
+
+    /// Take a token from user's and IP's buckets for the class.
+    /// Returns false if either is empty (then neither is consumed).
+    pub fn check(&self, class: RateClass, user_id: &str, ip: Option<&str>) -> bool
+    {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|(c, s, _), b| self.cfg.limits.get(&(*c, *s)).is_some_and(|r| !b.is_full(r, now)));
+        }
+        let keys = [(RateScope::User, Some(user_id)), (RateScope::Ip, ip)];
+        let mut taken = vec![];
+        for (scope, key) in keys {
+            let (Some(rate), Some(key)) = (self.cfg.limits.get(&(class, scope)), key) else { continue };
+            let b = buckets.entry((class, scope, key.to_string()))
+                .or_insert_with(|| Bucket { tokens: rate.count as f64, updated: now });
+            if !b.take(rate, now) {
+                for k in taken {
+                    if let Some(b) = buckets.get_mut(&k) { b.tokens += 1.0; }
+                }
+                return false;
+            }
+            taken.push((class, scope, key.to_string()));
+        }
+        true
+    }
+
+    /// True if user's or IP's bucket for the class is empty. Doesn't take a token.
+    pub fn is_exhausted(&self, class: RateClass, user_id: &str, ip: Option<&str>) -> bool
+    {
+        let now = Instant::now();
+        let buckets = self.buckets.lock();
+        [(RateScope::User, Some(user_id)), (RateScope::Ip, ip)].into_iter().any(|(scope, key)| {
+            let (Some(rate), Some(key)) = (self.cfg.limits.get(&(class, scope)), key) else { return false };
+            buckets.get(&(class, scope, key.to_string())).is_some_and(|b| !b.has_token(rate, now))
+        })
+    }
+
+    /// Error message if a drawing or subtitle in the command is too large
+    pub fn check_sizes(&self, cmd: &Cmd) -> Option<String>
+    {
+        // Limits are for base64 encoded sizes, also for raw bytes from binary clients
+        let base64_len = |b: Option<&[u8]>| b.map_or(0, |b| b.len().div_ceil(3) * 4);
+        let too_big = |what: &str, len: usize, max: usize| (len > max)
+            .then(|| format!("{} too large ({} KiB, max {} KiB)", what, len / 1024, max / 1024));
+        match cmd {
+            Cmd::AddComment(c) => too_big("Drawing", c.drawing.as_ref().map_or(0, |d| d.len()) + base64_len(c.drawing_webp.as_deref()), self.cfg.max_drawing_bytes),
+            Cmd::CollabReport(c) => too_big("Drawing", c.drawing.as_ref().map_or(0, |d| d.len()) + base64_len(c.drawing_webp.as_deref()), self.cfg.max_drawing_bytes),
+            Cmd::AddSubtitle(s) => too_big("Subtitle file", s.contents_base64.len() + base64_len(Some(&s.contents)), self.cfg.max_subtitle_bytes),
+            _ => None,
+        }
+    }
+
+    /// Max size of a websocket message, so that the largest allowed drawing or subtitle fits in it
+    pub fn max_ws_message_bytes(&self) -> usize {
+        self.cfg.max_drawing_bytes.max(self.cfg.max_subtitle_bytes) + 256 * 1024
+    }
+
+    /// Record that a session was disconnected for rate limit violations,
+    /// and ban the user and client IP from reconnecting for a while.
+    /// IP ban keeps users that can get new ids (share link guests) out. Returns ban length.
+    pub fn kick(&self, user_id: &str, ip: Option<&str>) -> Duration
+    {
+        let now = Instant::now();
+        let mut kicks = self.kicks.lock();
+        kicks.retain(|_, k| now.duration_since(k.last) < KICK_MEMORY);
+        let keys = [(RateScope::User, Some(user_id)), (RateScope::Ip, ip)];
+        let mut ban = Duration::ZERO;
+        for (scope, key) in keys {
+            let Some(key) = key else { continue };
+            let k = kicks.entry((scope, key.to_string())).or_insert(Kicks { count: 0, last: now, banned_until: now });
+            k.count += 1;
+            k.last = now;
+            ban = ban.max(BAN_BASE.saturating_mul(1 << (k.count - 1).min(16)).min(BAN_MAX));
+        }
+        for (scope, key) in keys {
+            if let Some(k) = key.and_then(|key| kicks.get_mut(&(scope, key.to_string()))) {
+                k.banned_until = now + ban;
+            }
+        }
+        ban
+    }
+
+    /// Seconds until user can connect again, if either the user or their IP is banned
+    pub fn banned_for(&self, user_id: &str, ip: Option<&str>) -> Option<u64> {
+        let now = Instant::now();
+        let kicks = self.kicks.lock();
+        [(RateScope::User, Some(user_id)), (RateScope::Ip, ip)].into_iter()
+            .filter_map(|(scope, key)| kicks.get(&(scope, key?.to_string())))
+            .filter(|k| k.banned_until > now)
+            .map(|k| (k.banned_until - now).as_secs().max(1))
+            .max()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut cfg = RateLimitConfig::default();
+        cfg.set_from_spec("comment:user=3/60").unwrap();
+        cfg.set_from_spec("comment:ip=4/60").unwrap();
+        cfg.set_from_spec("upload:ip=off").unwrap();
+        assert!(cfg.set_from_spec("comment:host=1/1").is_err());
+        assert!(cfg.set_from_spec("comment:user=0/60").is_err());
+        assert!(!cfg.limits.contains_key(&(RateClass::Upload, RateScope::Ip)));
+        let rl = RateLimiter::new(cfg);
+
+        // Per user
+        for _ in 0..3 { assert!(rl.check(RateClass::Comment, "alice", Some("10.0.0.1"))); }
+        assert!(!rl.check(RateClass::Comment, "alice", Some("10.0.0.1")));
+        assert!(rl.check(RateClass::CollabReport, "alice", Some("10.0.0.1")));
+
+        // Per IP. Refused attempt doesn't consume user's tokens.
+        assert!(rl.check(RateClass::Comment, "bob", Some("10.0.0.1")));
+        assert!(!rl.check(RateClass::Comment, "bob", Some("10.0.0.1")));
+        assert!(rl.check(RateClass::Comment, "bob", Some("10.0.0.2")));
+        assert!(rl.check(RateClass::Comment, "bob", None));
+        assert!(!rl.check(RateClass::Comment, "bob", None));
+
+        // Escalating bans
+        assert_eq!(rl.banned_for("alice", None), None);
+        assert_eq!(rl.kick("alice", Some("10.0.0.1")), BAN_BASE);
+        assert!(rl.banned_for("alice", None).is_some());
+        assert_eq!(rl.kick("alice", None), BAN_BASE * 2);
+        assert_eq!(rl.kick("alice", None), BAN_BASE * 4);
+        for _ in 0..20 { rl.kick("alice", None); }
+        assert_eq!(rl.kick("alice", None), BAN_MAX);
+
+        // IP is banned too, so new user ids from there don't help
+        assert!(rl.banned_for("guest.1.abc", Some("10.0.0.1")).is_some());
+        assert!(rl.banned_for("guest.1.abc", Some("10.0.0.2")).is_none());
+        assert_eq!(rl.kick("guest.1.abc", Some("10.0.0.1")), BAN_BASE * 2);
+
+        // Exhaustion check doesn't consume tokens
+        let af = RateLimiter::new(RateLimitConfig::default());
+        for _ in 0..9 { af.check(RateClass::AuthFailure, "share:abc", Some("10.0.0.3")); }
+        assert!(!af.is_exhausted(RateClass::AuthFailure, "share:abc", Some("10.0.0.3")));
+        assert!(!af.is_exhausted(RateClass::AuthFailure, "share:abc", Some("10.0.0.3")));
+        af.check(RateClass::AuthFailure, "share:abc", Some("10.0.0.4"));
+        assert!(af.is_exhausted(RateClass::AuthFailure, "share:abc", None));
+        assert!(!af.is_exhausted(RateClass::AuthFailure, "share:other", Some("10.0.0.3")));
+        assert!(!af.is_exhausted(RateClass::Comment, "share:abc", None));
+    }
+}
@@ -0,0 +1,660 @@
+//! Stateless REST/JSON API (`/api/v1/...`) for scripts and integrations.
+//!
+//! Covers the common tasks that otherwise need the websocket protocol: listing and
+//! reading media files, reading and adding comments, renaming, deleting, polling
+//! processing status and reading user messages. Requests are authenticated like uploads
+//! (reverse proxy headers, JWT or API token), and go through the same authz checks
+//! (`org_authz_with_default`) as the corresponding websocket commands.
+//!
+//! Objects are serialized as protobuf JSON, the same as in the websocket API.
+//! All routes are listed in `ENDPOINTS`, which is also used to generate the
+//! OpenAPI description served at `/api/v1/openapi.json`.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use bytes::Buf;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::reply::Response;
+use warp::Reply;
+
+use lib_clapshot_grpc::proto;
+use proto::org::authz_user_action_request as authz_req;
+
+use crate::database::error::DBError;
+use crate::database::{models, DBPaging, DbBasicQuery, DbQueryByMediaFile, DbQueryByUser};
+use crate::grpc::grpc_client::OrganizerConnection;
+use super::api_tokens::ApiTokenScope;
+use super::comment_export::{export_comments, ExportFormat};
+use super::rate_limit::RateClass;
+use super::server_state::ServerState;
+use super::user_session::{org_authz_with_default, AuthzTopic};
+use super::{authenticate_request_blocking, AuthenticatedUser, ClientInfo};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 1000;
+const MAX_JSON_BODY_BYTES: usize = 64 * 1024;     // For bodies without drawings
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    OpenApi,
+    ListMediaFiles,
+    GetMediaFile,
+    RenameMediaFile,
+    DeleteMediaFile,
+    GetMediaFileStatus,
+    GetComments,
+    AddComment,
+    ListMessages,
+}
+
+/// Query parameter: (name, OpenAPI type, description)
+type Param = (&'static str, &'static str, &'static str);
+
+const PAGING: [Param; 2] = [
+    ("page", "integer", "Page number, starting from 0"),
+    ("page_size", "integer", "Items per page (default 50, max 1000)"),
+];
+
+struct Endpoint {
+    route: Route,
+    method: Method,
+    path: &'static str,                     // Relative to /api/v1, with `{id}` for path parameters
+    summary: &'static str,
+    scopes: &'static [ApiTokenScope],       // API token needs one of these. Empty = not allowed with tokens.
+    query: &'static [Param],
+    body: Option<&'static str>,             // Request schema name
+    response: (StatusCode, Option<&'static str>),
+}
+
+const ENDPOINTS: &[Endpoint] = &[
+    Endpoint { route: Route::OpenApi, method: Method::GET, path: "openapi.json",
+        summary: "This OpenAPI description", scopes: &[], query: &[], body: None, response: (StatusCode::OK, None) },
+    Endpoint { route: Route::ListMediaFiles, method: Method::GET, path: "media_files",
+        summary: "List your media files, newest first",
+        scopes: &[ApiTokenScope::CommentsRead],
+        query: &[PAGING[0], PAGING[1], ("shared", "boolean", "List files shared with you instead of your own")],
+        body: None, response: (StatusCode::OK, Some("MediaFileList")) },
+    Endpoint { route: Route::GetMediaFile, method: Method::GET, path: "media_files/{id}",
+        summary: "Get a media file", scopes: &[ApiTokenScope::CommentsRead], query: &[],
+        body: None, response: (StatusCode::OK, Some("MediaFile")) },
+    Endpoint { route: Route::RenameMediaFile, method: Method::PATCH, path: "media_files/{id}",
+        summary: "Rename a media file", scopes: &[], query: &[],
+        body: Some("RenameMediaFile"), response: (StatusCode::OK, Some("MediaFile")) },
+    Endpoint { route: Route::DeleteMediaFile, method: Method::DELETE, path: "media_files/{id}",
+        summary: "Delete (trash) a media file", scopes: &[], query: &[],
+        body: None, response: (StatusCode::NO_CONTENT, None) },
+    Endpoint { route: Route::GetMediaFileStatus, method: Method::GET, path: "media_files/{id}/status",
+        summary: "Processing (transcoding / thumbnailing) status of an uploaded media file",
+        scopes: &[ApiTokenScope::Upload, ApiTokenScope::CommentsRead], query: &[],
+        body: None, response: (StatusCode::OK, Some("MediaFileStatus")) },
+    Endpoint { route: Route::GetComments, method: Method::GET, path: "media_files/{id}/comments",
+        summary: "Get comments of a media file, as JSON or exported as CSV, EDL or HTML review report",
+        scopes: &[ApiTokenScope::CommentsRead],
+        query: &[("format", "string", "One of 'json' (default), 'csv', 'edl' or 'html'")],
+        body: None, response: (StatusCode::OK, Some("CommentList")) },
+    Endpoint { route: Route::AddComment, method: Method::POST, path: "media_files/{id}/comments",
+        summary: "Add a comment to a media file", scopes: &[ApiTokenScope::CommentsWrite], query: &[],
+        body: Some("NewComment"), response: (StatusCode::CREATED, Some("Comment")) },
+    Endpoint { route: Route::ListMessages, method: Method::GET, path: "messages",
+        summary: "List your notifications, newest first",
+        scopes: &[ApiTokenScope::CommentsRead],
+        query: &[PAGING[0], PAGING[1],
+            ("unseen_only", "boolean", "Only list unseen messages"),
+            ("mark_seen", "boolean", "Mark listed messages as seen (default false)")],
+        body: None, response: (StatusCode::OK, Some("MessageList")) },
+];
+
+/// Match a request path against an endpoint path pattern. Returns path parameters.
+fn match_path(pattern: &str, path: &str) -> Option<Vec<String>> {
+    let (pat, segs): (Vec<&str>, Vec<&str>) = (pattern.split('/').collect(), path.trim_end_matches('/').split('/').collect());
+    if pat.len() != segs.len() { return None; }
+    let mut args = vec![];
+    for (p, s) in pat.iter().zip(segs.iter()) {
+        if p.starts_with('{') {
+            if s.is_empty() { return None; }
+            args.push(urlencoding::decode(s).ok()?.into_owned());
+        } else if p != s {
+            return None;
+        }
+    }
+    Some(args)
+}
+
+
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn new(status: StatusCode, msg: impl Into<String>) -> Self {
+        ApiError(status, msg.into())
+    }
+    fn into_response(self) -> Response {
+        json_reply(self.0, &json!({ "error": self.1 }))
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        tracing::error!(details=%e, "REST API request failed.");
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+    }
+}
+
+impl From<DBError> for ApiError {
+    fn from(e: DBError) -> Self {
+        match e {
+            DBError::NotFound() => ApiError::new(StatusCode::NOT_FOUND, "Not found"),
+            e => anyhow::Error::from(e).into(),
+        }
+    }
+}
+
+type ApiRes = Result<Response, ApiError>;
+
+fn json_reply<T: serde::Serialize>(status: StatusCode, v: &T) -> Response {
+    warp::reply::with_status(warp::reply::json(v), status).into_response()
+}
+
+fn text_reply(body: String, content_type: &str) -> Response {
+    warp::reply::with_header(body, "content-type", content_type).into_response()
+}
+
+
+/// Warp handler for everything under `/api/v1/`
+///
+/// # Arguments
+/// * `method` - HTTP method
+/// * `tail` - Request path after `/api/v1/`
+/// * `hdrs` - Headers, for authentication
+/// * `peer` - Address of the connecting peer, for trusted proxy check and rate limits
+/// * `query` - Parsed query string
+/// * `server` - Server state
+/// * `body` - The request body (stream)
+pub async fn handle_rest_request(
+    method: Method,
+    tail: warp::path::Tail,
+    hdrs: HeaderMap,
+    peer: Option<std::net::SocketAddr>,
+    query: HashMap<String, String>,
+    server: ServerState,
+    body: impl warp::Stream<Item = Result<impl Buf, warp::Error>> + Unpin)
+        -> Result<Response, Infallible>
+{
+    let found = ENDPOINTS.iter().find_map(|ep| (ep.method == method).then(|| match_path(ep.path, tail.as_str())).flatten().map(|args| (ep, args)));
+    let (ep, args) = match found {
+        Some(f) => f,
+        None if ENDPOINTS.iter().any(|ep| match_path(ep.path, tail.as_str()).is_some()) =>
+            return Ok(ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed").into_response()),
+        None => return Ok(ApiError::new(StatusCode::NOT_FOUND, "No such endpoint").into_response()),
+    };
+    if ep.route == Route::OpenApi {
+        return Ok(json_reply(StatusCode::OK, &openapi_spec(&server.url_base)));
+    }
+
+    let auth = match authenticate_request_blocking(&hdrs, peer, &server).await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::info!(details=%e, "Refused REST request: authentication failed.");
+            return Ok(ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized").into_response());
+        }
+    };
+    let client = ClientInfo::from_request(&hdrs, peer, &server);
+    if let Some(secs) = server.rate_limiter.banned_for(&auth.user_id, client.remote_addr.as_deref()) {
+        return Ok(ApiError::new(StatusCode::TOO_MANY_REQUESTS, format!("Too many requests, try again in {} seconds", secs)).into_response());
+    }
+    if let Some(scopes) = &auth.token_scopes {
+        if !ep.scopes.iter().any(|s| scopes.contains(s)) {
+            tracing::info!(user=auth.user_id, path=tail.as_str(), "Refused REST request: API token lacks scope.");
+            let msg = match ep.scopes {
+                [] => "Forbidden: not allowed with API tokens".to_string(),
+                s => format!("Forbidden: API token lacks '{}' scope", s.iter().map(|s| s.as_str()).collect::<Vec<_>>().join("' or '")),
+            };
+            return Ok(ApiError::new(StatusCode::FORBIDDEN, msg).into_response());
+        }
+    }
+
+    let res = async {
+        let req = RestRequest::new(auth, client, query, server).await?;
+        let id = args.first().map(String::as_str).unwrap_or_default();
+        match ep.route {
+            Route::OpenApi => unreachable!(),
+            Route::ListMediaFiles => req.list_media_files().await,
+            Route::GetMediaFile => req.get_media_file(id).await,
+            Route::RenameMediaFile => req.rename_media_file(id, &read_body(body, MAX_JSON_BODY_BYTES).await?).await,
+            Route::DeleteMediaFile => req.delete_media_file(id).await,
+            Route::GetMediaFileStatus => req.get_media_file_status(id).await,
+            Route::GetComments => req.get_comments(id).await,
+            Route::AddComment => {
+                let max = req.server.rate_limiter.max_ws_message_bytes();
+                req.add_comment(id, &read_body(body, max).await?).await
+            },
+            Route::ListMessages => req.list_messages().await,
+        }
+    }.await;
+    Ok(res.unwrap_or_else(|e| e.into_response()))
+}
+
+async fn read_body(mut body: impl warp::Stream<Item = Result<impl Buf, warp::Error>> + Unpin, max_bytes: usize) -> Result<Vec<u8>, ApiError>
+{
+    let mut res = vec![];
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("Failed to read request body: {}", e)))?;
+        while chunk.has_remaining() {
+            let b = chunk.chunk();
+            res.extend_from_slice(b);
+            let n = b.len();
+            chunk.advance(n);
+        }
+        if res.len() > max_bytes {
+            return Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"));
+        }
+    }
+    Ok(res)
+}
+
+fn parse_json_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid request body: {}", e)))
+}
+
+
+/// Authenticated request, with a transient Organizer session for authz checks
+struct RestRequest {
+    auth: AuthenticatedUser,
+    client: ClientInfo,
+    query: HashMap<String, String>,
+    server: ServerState,
+    organizer: Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>,
+    org_session: proto::org::UserSessionData,
+}
+
+impl RestRequest {
+
+    async fn new(auth: AuthenticatedUser, client: ClientInfo, query: HashMap<String, String>, server: ServerState) -> Result<Self, ApiError>
+    {
+        let organizer = match &server.organizer_uri {
+            Some(uri) if server.organizer_has_connected.load(std::sync::atomic::Ordering::Relaxed) =>
+                match crate::grpc::grpc_client::connect(uri.clone()).await {
+                    Ok(c) => Some(Arc::new(tokio::sync::Mutex::new(c))),
+                    Err(e) => {
+                        tracing::error!("Failed to connect to organizer: {}", e);
+                        return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal error: failed to connect to organizer"));
+                    }
+                },
+            _ => None,
+        };
+        let org_session = proto::org::UserSessionData {
+            sid: "<rest-api--not-set>".to_string(),
+            user: Some(proto::UserInfo { id: auth.user_id.clone(), name: auth.user_name.clone() }),
+            is_admin: auth.is_admin,
+            cookies: auth.cookies.clone(),
+            guest_link: None,
+            groups: auth.groups.clone(),
+        };
+        Ok(RestRequest { auth, client, query, server, organizer, org_session })
+    }
+
+    async fn authz(&self, desc: &str, default: bool, op: AuthzTopic<'_>) -> Result<(), ApiError> {
+        org_authz_with_default(&self.org_session, desc, false, &self.server, &self.organizer, default, op).await
+            .map_err(|_| ApiError::new(StatusCode::FORBIDDEN, format!("Permission denied: {}", desc)))
+    }
+
+    fn media_file(&self, id: &str) -> Result<models::MediaFile, ApiError> {
+        match models::MediaFile::get(&mut self.server.db.conn()?, &id.to_string()) {
+            Err(DBError::NotFound()) => Err(ApiError::new(StatusCode::NOT_FOUND, "No such media file")),
+            res => Ok(res?),
+        }
+    }
+
+    fn reject_if_locked(&self, v: &models::MediaFile, action: &str) -> Result<(), ApiError> {
+        match v.locked.is_some() && !self.auth.is_admin {
+            true => Err(ApiError::new(StatusCode::CONFLICT, format!("Cannot {}: media file is locked", action))),
+            false => Ok(()),
+        }
+    }
+
+    fn media_file_json(&self, v: &models::MediaFile) -> Result<proto::MediaFile, ApiError> {
+        let conn = &mut self.server.db.conn()?;
+        Ok(v.to_proto3(&self.server.url_base, v.get_subtitles(conn)?, v.get_approvals(conn)?))
+    }
+
+    fn query_u32(&self, name: &str, default: u32) -> Result<u32, ApiError> {
+        self.query.get(name).map(|v| v.parse().map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid '{}'", name))))
+            .transpose().map(|v| v.unwrap_or(default))
+    }
+
+    fn query_bool(&self, name: &str) -> Result<bool, ApiError> {
+        match self.query.get(name).map(String::as_str) {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("") | Some("true") | Some("1") => Ok(true),
+            _ => Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid '{}', expected true or false", name))),
+        }
+    }
+
+    fn paging(&self) -> Result<DBPaging, ApiError> {
+        let page_size = self.query_u32("page_size", DEFAULT_PAGE_SIZE)?.clamp(1, MAX_PAGE_SIZE);
+        Ok(DBPaging { page_num: self.query_u32("page", 0)?, page_size: std::num::NonZeroU32::new(page_size).unwrap() })
+    }
+
+    // ---------------------------------------------------------------------
+    // Endpoint handlers
+    // ---------------------------------------------------------------------
+
+    async fn list_media_files(&self) -> ApiRes {
+        let pg = self.paging()?;
+        let mfs = {
+            let conn = &mut self.server.db.conn()?;
+            if self.query_bool("shared")? {
+                let ids = super::media_grants::media_files_shared_with(conn, &self.auth.user_id, &self.auth.groups)?;
+                models::MediaFile::get_many_paged(conn, &ids, pg)?
+            } else {
+                models::MediaFile::get_by_user(conn, &self.auth.user_id, pg)?
+            }
+        };
+        let mut res = vec![];
+        for v in mfs {
+            let default_perm = true;    // same as opening the file
+            if self.authz("view media file", default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await.is_ok() {
+                res.push(self.media_file_json(&v)?);
+            }
+        }
+        Ok(json_reply(StatusCode::OK, &json!({ "media_files": res })))
+    }
+
+    async fn get_media_file(&self, id: &str) -> ApiRes {
+        let v = self.media_file(id)?;
+        self.authz("open media file", true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+        Ok(json_reply(StatusCode::OK, &self.media_file_json(&v)?))
+    }
+
+    async fn rename_media_file(&self, id: &str, body: &[u8]) -> ApiRes {
+        #[derive(Deserialize)]
+        struct Rename { title: String }
+        let data: Rename = parse_json_body(body)?;
+
+        let v = self.media_file(id)?;
+        let default_perm = self.auth.user_id == v.user_id || self.auth.is_admin;
+        self.authz("rename media file", default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Rename)).await?;
+        self.reject_if_locked(&v, "rename")?;
+        let new_name = super::ws_handers::validate_media_file_name(&data.title)
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+        models::MediaFile::rename(&mut self.server.db.conn()?, &v.id, new_name)?;
+        tracing::info!(media_file_id=v.id, user_id=self.auth.user_id, "Media file renamed through REST API.");
+        self.get_media_file(id).await
+    }
+
+    async fn delete_media_file(&self, id: &str) -> ApiRes {
+        let v = self.media_file(id)?;
+        let default_perm = self.auth.user_id == v.user_id || self.auth.is_admin;
+        self.authz("delete media file", default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Delete)).await?;
+        self.reject_if_locked(&v, "delete")?;
+        tracing::info!(media_file_id=v.id, user_id=self.auth.user_id, "Trashing media file through REST API.");
+        let (details, cleanup_errors) = super::ws_handers::trash_media_file(&v, Some(&self.auth.user_id), &self.server)?;
+        if cleanup_errors {
+            tracing::warn!(media_file_id=v.id, details, "Media file deleted, but cleanup had errors.");
+        }
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    async fn get_media_file_status(&self, id: &str) -> ApiRes {
+        let v = self.media_file(id)?;
+        self.authz("view media file", true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+        let progress = self.server.get_media_file_progress(&v.id);
+        let state = match &progress {
+            Some(p) if p.failed => "failed",
+            Some(_) => "processing",
+            None if v.thumbs_done.is_none() => "processing",
+            None => "ready",
+        };
+        Ok(json_reply(StatusCode::OK, &json!({
+            "media_file_id": v.id,
+            "state": state,
+            "transcoded": v.recompression_done.is_some(),
+            "thumbnails_done": v.thumbs_done.is_some(),
+            "progress": progress.as_ref().and_then(|p| p.progress),
+            "message": progress.map(|p| p.msg),
+        })))
+    }
+
+    async fn get_comments(&self, id: &str) -> ApiRes {
+        let v = self.media_file(id)?;
+        self.authz("read comments", true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+        let comments = models::Comment::visible_to_users(
+            models::Comment::get_by_media_file(&mut self.server.db.conn()?, &v.id, DBPaging::default())?);
+
+        match self.query.get("format").map(String::as_str).unwrap_or("json") {
+            "json" => {
+                let comments: Vec<proto::Comment> = comments.iter().map(|c| {
+                    let mut c = c.to_proto3();
+                    c.drawing = c.drawing.filter(|d| !d.is_empty())
+                        .map(|d| format!("{}/videos/{}/drawings/{}", self.server.url_base, v.id, urlencoding::encode(&d)));
+                    c
+                }).collect();
+                Ok(json_reply(StatusCode::OK, &json!({ "comments": comments })))
+            },
+            "csv" => Ok(text_reply(export_comments(ExportFormat::Csv, &v, &comments)?, ExportFormat::Csv.content_type())),
+            "edl" => Ok(text_reply(export_comments(ExportFormat::Edl, &v, &comments)?, ExportFormat::Edl.content_type())),
+            "html" => {
+                let media_dir = self.server.media_files_dir.join(&v.id);
+                let html = tokio::task::spawn_blocking(move || super::review_report::render_html(&v, &comments, &media_dir))
+                    .await.map_err(anyhow::Error::from)?;
+                Ok(text_reply(html, "text/html; charset=utf-8"))
+            },
+            f => Err(ApiError::new(StatusCode::BAD_REQUEST, format!("Unknown format '{}'", f))),
+        }
+    }
+
+    async fn add_comment(&self, id: &str, body: &[u8]) -> ApiRes {
+        #[derive(Deserialize)]
+        struct NewComment {
+            comment: String,
+            timecode: Option<String>,
+            parent_id: Option<String>,
+            drawing: Option<String>,
+            annotation: Option<String>,
+        }
+        let data: NewComment = parse_json_body(body)?;
+        let data = proto::client::client_to_server_cmd::AddComment {
+            media_file_id: id.to_string(),
+            comment: data.comment,
+            timecode: data.timecode,
+            parent_id: data.parent_id,
+            drawing: data.drawing,
+            drawing_webp: None,
+            subtitle_id: None,
+            annotation: data.annotation,
+        };
+
+        let limiter = &self.server.rate_limiter;
+        if let Some(msg) = limiter.check_sizes(&proto::client::client_to_server_cmd::Cmd::AddComment(data.clone())) {
+            return Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, msg));
+        }
+        if !limiter.check(RateClass::Comment, &self.auth.user_id, self.client.remote_addr.as_deref()) {
+            return Err(ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests, slow down"));
+        }
+
+        let v = self.media_file(id)?;
+        let default_perm = true;    // anyone can comment on any media file
+        self.authz("comment media file", default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Comment)).await?;
+        self.reject_if_locked(&v, "comment")?;
+        if let Some(pid) = &data.parent_id {
+            let parent = pid.parse::<i32>().ok().and_then(|pid| models::Comment::get(&mut self.server.db.conn().ok()?, &pid).ok());
+            if parent.is_none_or(|p| p.media_file_id != v.id) {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, "No such parent comment on this media file"));
+            }
+        }
+        let c = super::ws_handers::store_comment(&data, &v, &self.auth.user_id, &self.auth.user_name, &self.organizer, &self.server).await
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("{:#}", e)))?;
+        Ok(json_reply(StatusCode::CREATED, &c.to_proto3()))
+    }
+
+    async fn list_messages(&self) -> ApiRes {
+        let pg = self.paging()?;
+        let conn = &mut self.server.db.conn()?;
+        let msgs = models::Message::get_by_user_filtered(conn, &self.auth.user_id, self.query_bool("unseen_only")?, pg)?;
+        if self.query_bool("mark_seen")? {
+            let unseen_ids = msgs.iter().filter(|m| !m.seen).map(|m| m.id).collect::<Vec<_>>();
+            if !unseen_ids.is_empty() {
+                models::Message::set_seen_by_user(conn, &self.auth.user_id, Some(&unseen_ids), true)?;
+            }
+        }
+        let (total_count, unseen_count) = models::Message::count_by_user(conn, &self.auth.user_id)?;
+        Ok(json_reply(StatusCode::OK, &json!({
+            "messages": msgs.iter().map(|m| m.to_proto3()).collect::<Vec<_>>(),
+            "total_count": total_count,
+            "unseen_count": unseen_count,
+        })))
+    }
+}
+
+
+// ---------------------------------------------------------------------
+// OpenAPI description
+// ---------------------------------------------------------------------
+
+/// Schemas of request and response bodies. Protobuf objects only list their main fields.
+fn schemas() -> Value {
+    let obj = |props: Value, required: &[&str]| json!({ "type": "object", "properties": props, "required": required });
+    let list = |key: &str, item: &str| json!({ "type": "object", "properties": { key: { "type": "array", "items": { "$ref": format!("#/components/schemas/{}", item) } } } });
+    let s = |desc: &str| json!({ "type": "string", "description": desc });
+    let ts = json!({ "type": "string", "format": "date-time" });
+    json!({
+        "Error": obj(json!({ "error": s("Error message") }), &["error"]),
+        "MediaFile": obj(json!({
+            "id": s("Media file ID"), "title": s("Title"), "userId": s("Owner"),
+            "mediaType": s("'video', 'audio' or 'image'"), "addedTime": ts,
+            "duration": { "type": "object", "properties": { "duration": { "type": "number" }, "totalFrames": { "type": "integer" }, "fps": { "type": "string" } } },
+            "locked": ts, "lockedBy": s("Who locked (finalized) the file"),
+            "playbackUrl": s("URL of transcoded (or original) media"), "origUrl": s("URL of original file"),
+        }), &["id", "userId", "mediaType"]),
+        "MediaFileList": list("media_files", "MediaFile"),
+        "RenameMediaFile": obj(json!({ "title": s("New title") }), &["title"]),
+        "MediaFileStatus": obj(json!({
+            "media_file_id": s("Media file ID"),
+            "state": { "type": "string", "enum": ["processing", "ready", "failed"] },
+            "transcoded": { "type": "boolean" }, "thumbnails_done": { "type": "boolean" },
+            "progress": { "type": "number", "nullable": true, "description": "0-1, while transcoding" },
+            "message": { "type": "string", "nullable": true, "description": "Latest progress or error message" },
+        }), &["media_file_id", "state"]),
+        "Comment": obj(json!({
+            "id": s("Comment ID"), "mediaFileId": s("Media file ID"), "userId": s("Author ID"), "usernameIfnull": s("Author name"),
+            "comment": s("Text"), "timecode": s("SMPTE timecode, e.g. 00:00:01:05"), "parentId": s("Parent comment, for replies"),
+            "drawing": s("URL of drawing image"), "annotation": s("Vector annotation JSON"),
+            "created": ts, "edited": ts, "deleted": ts,
+        }), &["id", "mediaFileId", "comment"]),
+        "CommentList": list("comments", "Comment"),
+        "NewComment": obj(json!({
+            "comment": s("Text"), "timecode": s("SMPTE timecode, e.g. 00:00:01:05"), "parent_id": s("Parent comment, for replies"),
+            "drawing": s("Drawing as a data:image/webp URI"), "annotation": s("Vector annotation JSON"),
+        }), &["comment"]),
+        "UserMessage": obj(json!({
+            "id": s("Message ID"), "created": ts, "seen": { "type": "boolean" },
+            "type": { "type": "string", "enum": ["OK", "ERROR", "PROGRESS", "MEDIA_FILE_UPDATED", "MEDIA_FILE_ADDED"] },
+            "refs": { "type": "object", "properties": { "mediaFileId": { "type": "string" }, "commentId": { "type": "string" }, "subtitleId": { "type": "string" } } },
+            "message": s("Message"), "details": s("Details"),
+        }), &["message"]),
+        "MessageList": {
+            "type": "object",
+            "properties": {
+                "messages": { "type": "array", "items": { "$ref": "#/components/schemas/UserMessage" } },
+                "total_count": { "type": "integer" },
+                "unseen_count": { "type": "integer" },
+            }
+        },
+    })
+}
+
+/// OpenAPI 3 description generated from `ENDPOINTS`
+fn openapi_spec(url_base: &str) -> Value {
+    let schema_ref = |name: &str| json!({ "$ref": format!("#/components/schemas/{}", name) });
+    let err = |desc: &str| json!({ "description": desc, "content": { "application/json": { "schema": schema_ref("Error") } } });
+
+    let mut paths = serde_json::Map::new();
+    for ep in ENDPOINTS {
+        let mut params: Vec<Value> = ep.path.split('/').filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+            .collect();
+        params.extend(ep.query.iter().map(|(name, typ, desc)|
+            json!({ "name": name, "in": "query", "required": false, "description": desc, "schema": { "type": typ } })));
+
+        let (status, resp) = ep.response;
+        let mut responses = json!({
+            status.as_u16().to_string(): match resp {
+                Some(r) => json!({ "description": "Success", "content": { "application/json": { "schema": schema_ref(r) } } }),
+                None => json!({ "description": "Success" }),
+            }
+        });
+        let mut op = json!({
+            "operationId": format!("{:?}", ep.route).as_str().chars().enumerate()
+                .map(|(i, c)| if i == 0 { c.to_ascii_lowercase() } else { c }).collect::<String>(),
+            "summary": ep.summary,
+            "parameters": params,
+        });
+        if ep.route == Route::OpenApi {
+            op["security"] = json!([]);
+        } else {
+            responses["401"] = err("Not authenticated");
+            responses["403"] = err("Permission denied");
+            responses["429"] = err("Rate limited");
+            if ep.path.contains('{') { responses["404"] = err("No such media file"); }
+            let tokens = match ep.scopes {
+                [] => "Not allowed with API tokens.".to_string(),
+                s => format!("API tokens need scope: {}.", s.iter().map(|s| format!("`{}`", s.as_str())).collect::<Vec<_>>().join(" or ")),
+            };
+            op["description"] = json!(tokens);
+        }
+        if let Some(body) = ep.body {
+            op["requestBody"] = json!({ "required": true, "content": { "application/json": { "schema": schema_ref(body) } } });
+            responses["400"] = err("Invalid request");
+        }
+        op["responses"] = responses;
+
+        let path = paths.entry(format!("/{}", ep.path)).or_insert(json!({}));
+        path[ep.method.as_str().to_lowercase()] = op;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Clapshot REST API",
+            "version": crate::PKG_VERSION,
+            "description": "Authenticate with the same reverse proxy headers / JWT as the web UI, or a personal API token (`Authorization: Bearer clapshot_...`).",
+        },
+        "servers": [{ "url": format!("{}/api/v1", url_base) }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": { "apiToken": { "type": "http", "scheme": "bearer" } },
+        },
+        "security": [{ "apiToken": [] }],
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_path_and_spec() {
+        assert_eq!(match_path("media_files/{id}/comments", "media_files/abc/comments"), Some(vec!["abc".to_string()]));
+        assert_eq!(match_path("media_files/{id}", "media_files/a%20b/"), Some(vec!["a b".to_string()]));
+        assert_eq!(match_path("media_files/{id}", "media_files/"), None);
+        assert_eq!(match_path("media_files/{id}", "media_files/abc/comments"), None);
+        assert_eq!(match_path("messages", "messages"), Some(vec![]));
+
+        let spec = openapi_spec("http://localhost");
+        assert_eq!(spec["paths"]["/media_files/{id}"].as_object().unwrap().len(), 3);
+        assert!(spec["paths"]["/media_files/{id}/comments"]["post"]["requestBody"].is_object());
+
+        // All referenced schemas exist
+        let txt = spec.to_string();
+        for name in txt.split("#/components/schemas/").skip(1).map(|s| s.split('"').next().unwrap()) {
+            assert!(spec["components"]["schemas"][name].is_object(), "Missing schema {}", name);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Review report export: a self-contained HTML document (optionally rendered to PDF)
+//! listing all comments of a media file, with still frames and drawings.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{anyhow, bail, Context};
+use base64::{Engine as _, engine::general_purpose as Base64GP};
+
+use crate::database::models;
+
+type Res<T> = anyhow::Result<T>;
+
+const FRAME_WIDTH: u32 = 640;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Html,
+    Pdf,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+
+/// Convert SMPTE timecode (HH:MM:SS:FF) into seconds from start of media.
+fn timecode_to_seconds(tc: &str, fps: f64) -> Option<f64> {
+    let parts = tc.trim().split([':', ';']).map(|p| p.parse::<f64>().ok()).collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [h, m, s, f] if fps > 0.0 => Some(h * 3600.0 + m * 60.0 + s + f / fps),
+        [h, m, s] => Some(h * 3600.0 + m * 60.0 + s),
+        _ => None,
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            '\n' => res.push_str("<br/>"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Path of the file to grab stills from (transcoded one if available, otherwise original).
+fn source_media_path(media_dir: &Path, media: &models::MediaFile) -> Option<PathBuf> {
+    let transcoded = media_dir.join("video.mp4");
+    if media.recompression_done.is_some() && transcoded.exists() {
+        return Some(transcoded);
+    }
+    media.orig_filename.as_ref().map(|f| media_dir.join("orig").join(f)).filter(|p| p.exists())
+}
+
+/// ffmpeg filter graph for a frame grab: scale the frame, and overlay the drawing (second input) if any.
+/// Drawing is made on top of the player, so it's scaled to the size of the frame (`rw`/`rh` of scale2ref).
+fn frame_filter(with_drawing: bool) -> String
+{
+    let scale = format!("scale={FRAME_WIDTH}:-2");
+    if with_drawing {
+        format!("[0:v]{scale}[v];[1:v][v]scale2ref=w=rw:h=rh[d][v2];[v2][d]overlay=format=auto")
+    } else {
+        scale
+    }
+}
+
+/// Extract a still frame at given time with ffmpeg, and composite the drawing (if any) on top of it.
+/// Returns JPEG image data.
+fn grab_frame(src: &Path, seek_sec: Option<f64>, drawing: Option<&Path>) -> Res<Vec<u8>>
+{
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-nostats", "-hide_banner", "-loglevel", "error"]);
+    if let Some(t) = seek_sec {
+        cmd.arg("-ss").arg(format!("{:.3}", t));
+    }
+    cmd.arg("-i").arg(src);
+    match drawing {
+        Some(d) => { cmd.arg("-i").arg(d).args(["-filter_complex", &frame_filter(true)]); },
+        None => { cmd.args(["-vf", &frame_filter(false)]); }
+    }
+    cmd.args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "mjpeg", "-"]);
+
+    tracing::debug!(cmd=?cmd, "Invoking ffmpeg for report frame grab.");
+    let res = cmd.output().context("Failed to run ffmpeg")?;
+    if !res.status.success() || res.stdout.is_empty() {
+        bail!("ffmpeg frame grab failed: {}", String::from_utf8_lossy(&res.stderr).trim());
+    }
+    Ok(res.stdout)
+}
+
+
+/// Render vector annotation into a temporary PNG file, for compositing with ffmpeg.
+fn annotation_to_png_file(annotation_json: &str) -> Res<tempfile::NamedTempFile> {
+    let png = super::annotation::Annotation::parse(annotation_json)?.render_png(FRAME_WIDTH)?;
+    let mut f = tempfile::Builder::new().suffix(".png").tempfile().context("Failed to create temp file")?;
+    std::io::Write::write_all(&mut f, &png).context("Failed to write annotation PNG")?;
+    Ok(f)
+}
+
+
+/// Render review report as a self-contained HTML document.
+///
+/// # Arguments
+/// * `media` - Media file to report on
+/// * `comments` - All comments of the media file
+/// * `media_dir` - Directory of the media file (for stills and drawings)
+pub fn render_html(media: &models::MediaFile, comments: &[models::Comment], media_dir: &Path) -> String
+{
+    let title = media.title.clone().or(media.orig_filename.clone()).unwrap_or(media.id.clone());
+    let fps = media.fps.as_ref().and_then(|f| f.parse::<f64>().ok()).unwrap_or(0.0);
+    let src = if media.media_type.as_deref().map(|t| t.eq_ignore_ascii_case("audio")) == Some(true) { None }
+        else { source_media_path(media_dir, media) };
+    let is_image = media.media_type.as_deref().map(|t| t.eq_ignore_ascii_case("image")) == Some(true);
+
+    let mut html = String::new();
+    html.push_str(&format!(r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"/>
+<title>Review report - {t}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table.meta td {{ padding: 0.1em 1em 0.1em 0; vertical-align: top; }}
+.comment {{ border-top: 1px solid #ccc; padding: 0.8em 0; page-break-inside: avoid; }}
+.reply {{ margin-left: 2em; border-left: 3px solid #ddd; padding-left: 0.8em; margin-top: 0.5em; }}
+.hdr {{ color: #666; font-size: 0.9em; }}
+.tc {{ font-family: monospace; font-weight: bold; color: #000; }}
+.still {{ max-width: {FRAME_WIDTH}px; width: 100%; display: block; margin: 0.5em 0; }}
+.nostill {{ color: #a00; font-size: 0.8em; }}
+</style></head><body>
+<h1>{t}</h1>
+"#, t = html_escape(&title)));
+
+    // Metadata
+    let mut meta = vec![
+        ("Media ID", media.id.clone()),
+        ("Owner", media.user_id.clone()),
+        ("Type", media.media_type.clone().unwrap_or_default()),
+        ("Original file", media.orig_filename.clone().unwrap_or_default()),
+        ("Added", media.added_time.format("%Y-%m-%d %H:%M:%S").to_string()),
+    ];
+    if let Some(d) = media.duration { meta.push(("Duration", format!("{:.2} s", d))); }
+    if let Some(f) = &media.fps { meta.push(("FPS", f.clone())); }
+    if let Some(n) = media.total_frames { meta.push(("Frames", n.to_string())); }
+    meta.push(("Comments", comments.len().to_string()));
+    meta.push(("Report generated", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string()));
+
+    html.push_str("<table class=\"meta\">\n");
+    for (k, v) in meta {
+        html.push_str(&format!("<tr><td><b>{}</b></td><td>{}</td></tr>\n", k, html_escape(&v)));
+    }
+    html.push_str("</table>\n<h2>Comments</h2>\n");
+
+    // Top level comments in timecode order, replies under them in creation order
+    let mut top = comments.iter().filter(|c| c.parent_id.is_none()).collect::<Vec<_>>();
+    top.sort_by(|a, b| (&a.timecode, a.created).cmp(&(&b.timecode, b.created)));
+
+    fn render_comment(html: &mut String, c: &models::Comment, all: &[models::Comment], depth: usize, still: Option<String>) {
+        let cls = if depth == 0 { "comment" } else { "reply" };
+        html.push_str(&format!("<div class=\"{cls}\">\n<div class=\"hdr\">"));
+        if let Some(tc) = &c.timecode {
+            html.push_str(&format!("<span class=\"tc\">{}</span> &ndash; ", html_escape(tc)));
+        }
+        html.push_str(&format!("<b>{}</b>, {}{}</div>\n",
+            html_escape(&c.username_ifnull),
+            c.created.format("%Y-%m-%d %H:%M"),
+            if c.edited.is_some() { " (edited)" } else { "" }));
+        if let Some(s) = still { html.push_str(&s); }
+        if c.deleted.is_some() {
+            html.push_str("<p><i>(Comment deleted)</i></p>\n");
+        } else {
+            html.push_str(&format!("<p>{}</p>\n", html_escape(&c.comment)));
+        }
+
+        let mut replies = all.iter().filter(|r| r.parent_id == Some(c.id)).collect::<Vec<_>>();
+        replies.sort_by_key(|r| r.created);
+        for r in replies {
+            render_comment(html, r, all, depth + 1, None);
+        }
+        html.push_str("</div>\n");
+    }
+
+    for c in top {
+        let still = match (&src, &c.timecode) {
+            (Some(src), Some(tc)) => {
+                // Prefer vector annotation over raster drawing, if both are present
+                let annotation_png = c.annotation.as_deref().and_then(|a| match annotation_to_png_file(a) {
+                    Ok(f) => Some(f),
+                    Err(e) => {
+                        tracing::warn!(comment_id=c.id, details=%e, "Failed to render annotation for report.");
+                        None
+                    }
+                });
+                let drawing = match &annotation_png {
+                    Some(f) => Some(f.path().to_path_buf()),
+                    None => c.drawing.as_ref().filter(|d| !d.is_empty())
+                        .map(|d| media_dir.join("drawings").join(d))
+                        .filter(|p| p.exists()),
+                };
+                let seek = if is_image { None } else { timecode_to_seconds(tc, fps) };
+                match grab_frame(src, seek, drawing.as_deref()) {
+                    Ok(jpg) => Some(format!("<img class=\"still\" src=\"data:image/jpeg;base64,{}\"/>\n", Base64GP::STANDARD.encode(jpg))),
+                    Err(e) => {
+                        tracing::warn!(comment_id=c.id, details=%e, "Failed to extract still frame for report.");
+                        Some("<div class=\"nostill\">(Still frame not available)</div>\n".to_string())
+                    }
+                }
+            },
+            _ => None,
+        };
+        render_comment(&mut html, c, comments, 0, still);
+    }
+    if comments.is_empty() {
+        html.push_str("<p><i>No comments.</i></p>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+
+/// Render HTML file into PDF with `wkhtmltopdf`.
+fn html_to_pdf(html_path: &Path, pdf_path: &Path) -> Res<()> {
+    let mut cmd = Command::new("wkhtmltopdf");
+    cmd.args(["--quiet", "--encoding", "utf-8"]).arg(html_path).arg(pdf_path);
+    tracing::debug!(cmd=?cmd, "Invoking wkhtmltopdf.");
+    let res = cmd.output().map_err(|e| anyhow!("PDF rendering requires 'wkhtmltopdf' to be installed: {}", e))?;
+    if !res.status.success() {
+        bail!("wkhtmltopdf failed: {}", String::from_utf8_lossy(&res.stderr).trim());
+    }
+    Ok(())
+}
+
+
+/// Generate a review report file under `<media_dir>/reports/`.
+/// Returns the file name of the report (relative to the reports dir).
+///
+/// # Arguments
+/// * `media_dir` - Directory of the media file
+/// * `media` - Media file to report on
+/// * `comments` - All comments of the media file
+/// * `format` - Output format
+pub fn write_report(media_dir: &Path, media: &models::MediaFile, comments: &[models::Comment], format: ReportFormat) -> Res<String>
+{
+    let reports_dir = media_dir.join("reports");
+    std::fs::create_dir_all(&reports_dir).context("Failed to create reports dir")?;
+
+    let basename = format!("review-report-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+    let html_path = reports_dir.join(format!("{}.html", basename));
+    std::fs::write(&html_path, render_html(media, comments, media_dir)).context("Failed to write HTML report")?;
+
+    if format == ReportFormat::Pdf {
+        let pdf_path = reports_dir.join(format!("{}.pdf", basename));
+        html_to_pdf(&html_path, &pdf_path)?;
+    }
+    Ok(format!("{}.{}", basename, format.extension()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timecode_to_seconds() {
+        assert_eq!(timecode_to_seconds("00:01:02:12", 24.0), Some(62.5));
+        assert_eq!(timecode_to_seconds("01:00:00", 24.0), Some(3600.0));
+        assert_eq!(timecode_to_seconds("bad", 24.0), None);
+    }
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape("<b>\"a\" & 'b'</b>\nx"), "&lt;b&gt;&quot;a&quot; &amp; &#39;b&#39;&lt;/b&gt;<br/>x");
+    }
+
+    #[test]
+    fn test_frame_filter() {
+        assert_eq!(frame_filter(false), format!("scale={}:-2", FRAME_WIDTH));
+        // Drawing is scaled to the frame's size (rw/rh), not kept at its own (iw/ih)
+        let f = frame_filter(true);
+        assert!(f.contains("scale2ref=w=rw:h=rh[d][v2]"), "{}", f);
+        assert!(f.ends_with("[v2][d]overlay=format=auto"));
+    }
+}
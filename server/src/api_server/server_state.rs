@@ -8,15 +8,60 @@ use std::sync::atomic::AtomicBool;
 use tokio::sync::Mutex;
 use anyhow::anyhow;
 
-use base64::{Engine as _, engine::general_purpose as Base64GP};
 
 use super::user_session::OpaqueGuard;
-use super::{WsMsgSender, SenderList, SessionMap, SenderListMap, StringToStringMap, Res, UserSession, SendTo};
+use super::{WsMsgSender, SenderList, SessionMap, SenderListMap, Res, UserSession, SendTo};
 use crate::client_cmd;
 use crate::database::{DB, models, DbBasicQuery};
+use crate::grpc::db_models::proto_msg_type_to_event_name;
 use crate::grpc::grpc_client::OrganizerURI;
+use crate::email_notifier::EmailNotifier;
+use crate::webhooks::{WebhookEvent, Webhooks};
+use super::jwt_auth::JwtAuth;
+use super::authz_policy::AuthzPolicy;
+use super::rate_limit::{RateLimitConfig, RateLimiter};
+use super::trusted_proxy::TrustedProxyConfig;
+use super::ws_resume::{Takeover, TakeoverSender};
 use lib_clapshot_grpc::proto;
 
+/// Shared state of a collaborative viewing session, kept so that late joiners
+/// can be brought up to date without waiting for the next report.
+#[derive (Clone, Debug, Default)]
+pub struct CollabState {
+    pub media_file_id: String,
+    pub participants: Vec<(String, proto::UserInfo)>,   // (sid, user), in join order
+    pub presenter_id: Option<String>,                   // User id. If set, only the presenter's reports are relayed.
+    pub last_event: Option<proto::client::server_to_client_cmd::CollabEvent>,
+    pub db_session_id: Option<i32>,                     // Id of the models::CollabSession record
+}
+
+/// Latest processing pipeline status of a media file, for polling clients
+#[derive (Clone, Debug, Default)]
+pub struct MediaFileProgress {
+    pub msg: String,
+    pub progress: Option<f32>,
+    pub failed: bool,
+}
+
+type CollabStateMap = Arc<RwLock<HashMap<String, CollabState>>>;
+type ProgressMap = Arc<RwLock<HashMap<String, MediaFileProgress>>>;
+type ResumableMap = Arc<RwLock<HashMap<String, (String, String, TakeoverSender)>>>;    // resume token -> (user_id, sid, session)
+type ViewerMap = Arc<RwLock<HashMap<String, Vec<(String, proto::UserInfo)>>>>;  // media_file_id -> [(sid, user)]
+
+/// Make a queueable websocket message from a client command
+pub(crate) fn cmd_to_ws_msg(cmd: proto::client::server_to_client_cmd::Cmd) -> Res<super::WsMsg> {
+    super::WsMsg::from_cmd(proto::client::ServerToClientCmd { cmd: Some(cmd) })
+}
+
+/// Send a message to all senders of a media file except the given one
+fn send_to_other_viewers(senders: &SenderListMap, media_file_id: &str, except: &WsMsgSender, msg: &super::WsMsg) {
+    for s in senders.read().get(media_file_id).unwrap_or(&vec![]).iter().filter(|s| !s.same_channel(except)) {
+        if let Err(e) = s.send(msg.clone()) {
+            tracing::debug!(details=%e, "Failed to send viewer presence update");
+        }
+    }
+}
+
 /// Lists of all active connections and other server state vars
 #[derive (Clone)]
 pub struct ServerState {
@@ -27,12 +72,22 @@ pub struct ServerState {
     pub upload_dir: PathBuf,
     pub url_base: String,
     pub default_user: String,
+    pub email: Option<Arc<EmailNotifier>>,
+    pub webhooks: Option<Arc<Webhooks>>,
+    pub jwt_auth: Option<Arc<JwtAuth>>,     // If set, users are identified by JWT instead of proxy headers
+    pub trusted_proxy: Option<Arc<TrustedProxyConfig>>,
+    pub authz_policy: Option<Arc<AuthzPolicy>>,     // Evaluated before asking Organizer
+    pub rate_limiter: Arc<RateLimiter>,
+    pub ws_resume_grace: std::time::Duration,     // How long to keep sessions of dropped connections for resuming. Zero = disabled.
 
     sid_to_session: SessionMap,
     user_id_to_senders: SenderListMap,
     media_file_id_to_senders: SenderListMap,
+    media_file_viewers: ViewerMap,
     collab_id_to_senders: SenderListMap,
-    collab_id_to_media_file_id: StringToStringMap,
+    collab_states: CollabStateMap,
+    media_file_progress: ProgressMap,
+    resumable_sessions: ResumableMap,
 
     pub organizer_uri: Option<OrganizerURI>,
     pub organizer_has_connected: Arc<AtomicBool>,
@@ -49,6 +104,13 @@ impl ServerState {
         organizer_uri: Option<OrganizerURI>,
         grpc_srv_listening_flag: Arc<AtomicBool>,
         default_user: String,
+        email: Option<Arc<EmailNotifier>>,
+        webhooks: Option<Arc<Webhooks>>,
+        jwt_auth: Option<Arc<JwtAuth>>,
+        trusted_proxy: Option<Arc<TrustedProxyConfig>>,
+        authz_policy: Option<Arc<AuthzPolicy>>,
+        rate_limits: RateLimitConfig,
+        ws_resume_grace: std::time::Duration,
         terminate_flag: Arc<AtomicBool>) -> ServerState
     {
         ServerState {
@@ -59,11 +121,21 @@ impl ServerState {
             terminate_flag,
             url_base: url_base.to_string(),
             default_user,
+            email,
+            webhooks,
+            jwt_auth,
+            trusted_proxy,
+            authz_policy,
+            rate_limiter: Arc::new(RateLimiter::new(rate_limits)),
+            ws_resume_grace,
             sid_to_session: Arc::new(RwLock::new(HashMap::<String, UserSession>::new())),
             user_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
             media_file_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
+            media_file_viewers: Arc::new(RwLock::new(HashMap::new())),
             collab_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
-            collab_id_to_media_file_id: Arc::new(RwLock::new(HashMap::<String, String>::new())),
+            collab_states: Arc::new(RwLock::new(HashMap::<String, CollabState>::new())),
+            media_file_progress: Arc::new(RwLock::new(HashMap::new())),
+            resumable_sessions: Arc::new(RwLock::new(HashMap::new())),
             organizer_uri,
             organizer_has_connected: Arc::new(AtomicBool::new(false)),
             organizer_info: Arc::new(Mutex::new(None)),
@@ -96,9 +168,66 @@ impl ServerState {
         Arc::new(Mutex::new((guard1, guard2)))
     }
 
+    /// Make a session resumable with the given token (by the same user).
+    /// Returns a guard that will unregister it when dropped.
+    pub(crate) fn register_resumable_session(&self, token: &str, user_id: &str, sid: &str, takeover_tx: TakeoverSender) -> OpaqueGuard {
+        self.resumable_sessions.write().insert(token.to_string(), (user_id.to_string(), sid.to_string(), takeover_tx));
+
+        struct Guard { map: ResumableMap, token: String }
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.map.write().remove(&self.token);
+            }
+        }
+        Arc::new(Mutex::new(Guard { map: self.resumable_sessions.clone(), token: token.to_string() }))
+    }
+
+    /// Pass a new connection to the session with given resume token.
+    /// Gives the takeover back if there's no such session for the user.
+    pub(crate) fn take_over_session(&self, token: &str, user_id: &str, takeover: Takeover) -> Result<(), Box<Takeover>> {
+        match self.resumable_sessions.read().get(token) {
+            Some((uid, _, tx)) if uid == user_id => tx.send(takeover).map_err(|e| Box::new(e.0)),
+            _ => Err(Box::new(takeover)),
+        }
+    }
+
+    /// Ask sessions matching the predicate to close (client is sent a websocket close frame
+    /// telling it not to reconnect). Their resume tokens stop working right away.
+    /// Returns the number of sessions closed.
+    pub fn close_sessions_where(&self, pred: impl Fn(&UserSession) -> bool) -> u32 {
+        let mut closed = vec![];
+        for ses in self.sid_to_session.read().values().filter(|s| pred(s)) {
+            if ses.sender.send(super::WsMsg::Close).is_ok() { closed.push(ses.sid.clone()); }
+        }
+        if !closed.is_empty() {
+            self.resumable_sessions.write().retain(|_, (_, sid, _)| !closed.contains(sid));
+        }
+        closed.len() as u32
+    }
+
+    /// Info on connected sessions matching the predicate, oldest first.
+    pub fn list_sessions(&self, pred: impl Fn(&UserSession) -> bool) -> Vec<proto::ActiveSession> {
+        let open_media: HashMap<String, String> = self.media_file_viewers.read().iter()
+            .flat_map(|(mf_id, list)| list.iter().map(move |(sid, _)| (sid.clone(), mf_id.clone())))
+            .collect();
+        let map = self.sid_to_session.read();
+        let mut sessions: Vec<&UserSession> = map.values().filter(|s| pred(s)).collect();
+        sessions.sort_by_key(|s| s.connected);
+        sessions.into_iter().map(|s| proto::ActiveSession {
+            sid: s.sid.clone(),
+            user: Some(proto::UserInfo { id: s.user_id.clone(), name: s.user_name.clone() }),
+            is_admin: s.is_admin,
+            is_guest: s.guest_link.is_some(),
+            remote_addr: s.remote_addr.clone(),
+            user_agent: s.user_agent.clone(),
+            connected: Some(crate::grpc::datetime_to_proto3(&s.connected)),
+            media_file_id: open_media.get(&s.sid).cloned(),
+        }).collect()
+    }
+
     /// Send a message to a specific session.
     /// Returns the number of messages sent (0 or 1).
-    pub fn send_to_user_session(&self, sid: &str, msg: &super::Message) -> Res<u32> {
+    pub fn send_to_user_session(&self, sid: &str, msg: &super::WsMsg) -> Res<u32> {
         if let Some(session) = self.get_session(sid) {
             session.sender.send(msg.clone())?;
             Ok(1)
@@ -107,12 +236,20 @@ impl ServerState {
         }
     }
 
+    /// Queue webhook calls for an event, if webhooks are configured.
+    /// Errors are only logged, so that webhook problems don't fail the operation that caused the event.
+    pub fn emit_webhook(&self, event: WebhookEvent, user_id: Option<&str>, data: serde_json::Value) {
+        if let Some(wh) = &self.webhooks {
+            if let Err(e) = wh.emit(event, user_id, data) {
+                tracing::error!(event=event.as_str(), details=%e, "Failed to queue webhook calls");
+            }
+        }
+    }
+
     /// Send a client command to websocket of given recipient(s)
     pub fn emit_cmd(&self, cmd: proto::client::server_to_client_cmd::Cmd, send_to: SendTo) -> Res<u32>
     {
-        let cmd = proto::client::ServerToClientCmd { cmd: Some(cmd) };
-        let msg = serde_json::to_value(cmd)?;
-        let msg = warp::ws::Message::text(msg.to_string());
+        let msg = cmd_to_ws_msg(cmd)?;
         match send_to {
             SendTo::UserSession(sid) => { self.send_to_user_session(&sid, &msg) },
             SendTo::Collab(id) => { self.send_to_all_collab_users(&Some(id.into()), &msg) },
@@ -122,14 +259,37 @@ impl ServerState {
         }
     }
 
+    /// Get user's preferences for a message type, as (persist, push).
+    /// Defaults to true for both if nothing is stored (or lookup fails).
+    /// Errors are always pushed, so that users don't miss feedback on failed actions.
+    pub fn user_message_prefs(&self, user_id: &str, event_name: &str) -> (bool, bool) {
+        let pref = self.db.conn()
+            .and_then(|mut conn| models::UserMessagePref::get_for(&mut conn, user_id, event_name))
+            .map_err(|e| tracing::error!(user=user_id, details=%e, "Failed to get message prefs, using defaults."))
+            .ok().flatten();
+        match pref {
+            Some(p) => (p.persist, p.push || event_name == proto_msg_type_to_event_name(proto::user_message::Type::Error)),
+            None => (true, true),
+        }
+    }
+
     /// Send a user message to given recipients.
+    /// Recipient's (`msg.user_id`) message preferences can suppress pushing and/or persisting it.
+    /// Persisted message is marked seen only if it reached a live connection (not just a session waiting to be resumed).
     pub fn push_notify_message(&self, msg: &models::MessageInsert, send_to: SendTo, persist: bool) -> Res<()> {
-        let cmd = client_cmd!(ShowMessages, {msgs: vec![msg.to_proto3()]});
-        let send_res = self.emit_cmd(cmd, send_to);
+        let (pref_persist, pref_push) = if msg.user_id.is_empty() { (true, true) } else { self.user_message_prefs(&msg.user_id, &msg.event_name) };
+        let live_user = match &send_to { SendTo::UserId(uid) => Some(*uid), _ => None };
+        let send_res = if pref_push {
+            self.emit_cmd(client_cmd!(ShowMessages, {msgs: vec![msg.to_proto3()], list_info: None}), send_to)
+        } else { Ok(0) };
         if let Ok(sent_count) = send_res {
-            if persist {
+            if persist && pref_persist {
+                let delivered = match live_user {
+                    Some(uid) => sent_count > 0 && self.count_live_user_sessions(uid) > 0,
+                    None => sent_count > 0,
+                };
                 models::Message::insert(&mut self.db.conn()?, &models::MessageInsert {
-                    seen: msg.seen || sent_count > 0,
+                    seen: msg.seen || delivered,
                     ..msg.clone()
                 }).map_err(|e| anyhow!("Failed to persist msg: {}", e))?;
             }
@@ -137,10 +297,15 @@ impl ServerState {
         send_res.map(|_| ())
     }
 
+    /// Number of user's sessions with a live connection, i.e. not waiting for the client to resume them.
+    pub fn count_live_user_sessions(&self, user_id: &str) -> u32 {
+        self.sid_to_session.read().values().filter(|s| s.user_id == user_id && !s.parked).count() as u32
+    }
+
     /// Send a message to all sessions user_id has open.
     /// Bails out with error if any of the senders fail.
     /// Returns the number of messages sent.
-    pub fn send_to_all_user_sessions(&self, user_id: &str, msg: &super::Message) -> Res<u32> {
+    pub fn send_to_all_user_sessions(&self, user_id: &str, msg: &super::WsMsg) -> Res<u32> {
         let mut total_sent = 0u32;
         let map = self.user_id_to_senders.read();
         for sender in map.get(user_id).unwrap_or(&vec![]).iter() {
@@ -152,7 +317,7 @@ impl ServerState {
     /// Send a message to all sessions that are collaboratively viewing a media file.
     /// Bails out with error if any of the senders fail.
     /// Returns the number of messages sent.
-    pub fn send_to_all_collab_users(&self, collab_id: &Option<String>, msg: &super::Message) -> Res<u32> {
+    pub fn send_to_all_collab_users(&self, collab_id: &Option<String>, msg: &super::WsMsg) -> Res<u32> {
         let mut total_sent = 0u32;
         if let Some(collab_id) = collab_id {
             let map = self.collab_id_to_senders.read();
@@ -165,20 +330,86 @@ impl ServerState {
 
     /// Register a new sender (API connection) as a viewer for a media file.
     /// One file can have multiple viewers (including the same user, using different connections).
-    /// Returns a guard that will remove the sender when dropped.
+    /// Other viewers are notified when the session starts and stops viewing the file.
+    /// The stored guard unlinks the session when dropped (i.e. on session end or when another file is opened).
     pub fn link_session_to_media_file(&self, session_id: &str, media_file_id: &str) -> Res<()> {
         let mut map = self.sid_to_session.write();
         let ses = map.get_mut(session_id).ok_or_else(|| anyhow!("Session {} not found", session_id))?;
-        let grd: OpaqueGuard = self.add_sender_to_maplist(media_file_id, ses.sender.clone(), &self.media_file_id_to_senders);
-        ses.media_session_guard = Some(grd);
+        ses.media_session_guard = None;     // Leave previous file first, so "left" is sent before "joined"
+
+        let user = proto::UserInfo { id: ses.user_id.clone(), name: ses.user_name.clone() };
+        let sender_guard = self.add_sender_to_maplist(media_file_id, ses.sender.clone(), &self.media_file_id_to_senders);
+        self.media_file_viewers.write().entry(media_file_id.to_string()).or_default().push((session_id.to_string(), user.clone()));
+        send_to_other_viewers(&self.media_file_id_to_senders, media_file_id, &ses.sender, &cmd_to_ws_msg(
+            client_cmd!(MediaFileViewerChanged, { media_file_id: media_file_id.to_string(), user: Some(user.clone()), joined: true }))?);
+
+        struct Guard { viewers: ViewerMap, senders: SenderListMap, sender: WsMsgSender, media_file_id: String, sid: String, user: proto::UserInfo }
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                {
+                    let mut viewers = self.viewers.write();
+                    if let Some(list) = viewers.get_mut(&self.media_file_id) {
+                        list.retain(|(sid, _)| sid != &self.sid);
+                        if list.is_empty() { viewers.remove(&self.media_file_id); }
+                    }
+                }
+                match cmd_to_ws_msg(client_cmd!(MediaFileViewerChanged, { media_file_id: self.media_file_id.clone(), user: Some(self.user.clone()), joined: false })) {
+                    Ok(msg) => send_to_other_viewers(&self.senders, &self.media_file_id, &self.sender, &msg),
+                    Err(e) => tracing::error!(details=%e, "Failed to serialize viewer presence update"),
+                }
+            }
+        }
+        let viewer_guard = Guard {
+            viewers: self.media_file_viewers.clone(),
+            senders: self.media_file_id_to_senders.clone(),
+            sender: ses.sender.clone(),
+            media_file_id: media_file_id.to_string(),
+            sid: session_id.to_string(),
+            user,
+        };
+        // Drop order matters: viewer_guard notifies via the sender list, which sender_guard then cleans up
+        ses.media_session_guard = Some(Arc::new(Mutex::new((viewer_guard, sender_guard))));
         Ok(())
     }
 
-    /// Remove media file id mappings from all collabs that have no more viewers.
-    fn garbage_collect_collab_media_file_map(&self) {
-        let mut map = self.collab_id_to_media_file_id.write();
-        let senders = self.collab_id_to_senders.read();
-        map.retain(|collab_id, _| !senders.get(collab_id).unwrap_or(&vec![]).is_empty());
+    /// Stop tracking the session as a viewer of its current media file (if any).
+    pub fn unlink_session_from_media_file(&self, session_id: &str) {
+        if let Some(ses) = self.sid_to_session.write().get_mut(session_id) {
+            ses.media_session_guard = None;
+        }
+    }
+
+    /// Get current viewers of a media file, one entry per session, in order of opening.
+    pub fn get_media_file_viewers(&self, media_file_id: &str) -> Vec<proto::UserInfo> {
+        self.media_file_viewers.read().get(media_file_id)
+            .map(|l| l.iter().map(|(_sid, u)| u.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Track processing status of a media file from pipeline messages.
+    /// Progress reports are kept until processing finishes, failures until the next attempt.
+    pub fn update_media_file_progress(&self, media_file_id: &str, topic: super::UserMessageTopic, msg: &str, progress: Option<f32>) {
+        use super::UserMessageTopic as T;
+        let mut map = self.media_file_progress.write();
+        match topic {
+            T::Progress if progress.is_none_or(|p| p < 1.0) => {
+                map.insert(media_file_id.to_string(), MediaFileProgress { msg: msg.to_string(), progress, failed: false });
+            },
+            T::Error => {
+                map.insert(media_file_id.to_string(), MediaFileProgress { msg: msg.to_string(), progress: None, failed: true });
+            },
+            _ if progress.is_some_and(|p| p >= 1.0) => { map.remove(media_file_id); },
+            _ => {},
+        }
+    }
+
+    pub fn get_media_file_progress(&self, media_file_id: &str) -> Option<MediaFileProgress> {
+        self.media_file_progress.read().get(media_file_id).cloned()
+    }
+
+    /// Get ids of all media files that currently have viewers.
+    pub fn get_viewed_media_file_ids(&self) -> Vec<String> {
+        self.media_file_viewers.read().keys().cloned().collect()
     }
 
     pub fn sender_is_collab_participant(&self, collab_id: &str, sender: &WsMsgSender) -> bool {
@@ -186,25 +417,101 @@ impl ServerState {
         senders.get(collab_id).unwrap_or(&vec![]).iter().any(|s| s.same_channel(sender))
     }
 
-    pub fn link_session_to_collab(&self, collab_id: &str, media_file_id: &str, sender: WsMsgSender) -> Res<OpaqueGuard> {
-        // GC collab media file map. (This might not be the optimal way to do this but at least it
-        // will keep it from growing indefinitely.)
-        self.garbage_collect_collab_media_file_map();
+    /// Add a session to a collab, creating the collab if it doesn't exist yet.
+    /// Returns a guard that removes the session from the collab when dropped.
+    /// The collab state is discarded (and the session record marked as ended) when the last participant leaves.
+    pub fn link_session_to_collab(&self, collab_id: &str, media_file_id: &str, ses: &UserSession) -> Res<OpaqueGuard> {
+        // Update in-memory state under the lock, but do DB writes only after releasing it
+        let (is_new, db_session_id) = {
+            let mut map = self.collab_states.write();
+
+            // Only the first joiner (creator) of a collab gets to set the media file id.
+            let is_new = !map.contains_key(collab_id);
+            let state = map.entry(collab_id.to_string()).or_insert_with(|| CollabState {
+                media_file_id: media_file_id.to_string(),
+                ..Default::default()
+            });
+            if state.media_file_id != media_file_id {
+                return Err(anyhow!("Mismatching media file id for pre-existing collab"));
+            }
+            state.participants.push((ses.sid.clone(), proto::UserInfo { id: ses.user_id.clone(), name: ses.user_name.clone() }));
+            (is_new, state.db_session_id)
+        };
+
+        struct Guard { map: CollabStateMap, db: Arc<DB>, collab_id: String, sid: String }
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                let ended_db_id = {
+                    let mut map = self.map.write();
+                    let Some(state) = map.get_mut(&self.collab_id) else { return };
+                    state.participants.retain(|(sid, _)| sid != &self.sid);
+                    if state.participants.is_empty() {
+                        map.remove(&self.collab_id).and_then(|s| s.db_session_id)
+                    } else {
+                        // Presenter left (from all their sessions)? Return control to everyone.
+                        if let Some(p) = &state.presenter_id {
+                            if !state.participants.iter().any(|(_, u)| &u.id == p) { state.presenter_id = None; }
+                        }
+                        None
+                    }
+                };
+                if let Some(db_id) = ended_db_id {
+                    if let Err(e) = self.db.conn().and_then(|mut conn| models::CollabSession::set_ended(&mut conn, db_id)) {
+                        tracing::error!(details=%e, collab_id=self.collab_id, "Failed to mark collab session as ended");
+                    }
+                }
+            }
+        }
+        // Create the guard first, so that errors below undo the in-memory changes
+        let state_guard = Guard { map: self.collab_states.clone(), db: self.db.clone(), collab_id: collab_id.to_string(), sid: ses.sid.clone() };
+
+        let conn = &mut self.db.conn()?;
+        if is_new {
+            let rec = models::CollabSession::insert(conn, &models::CollabSessionInsert {
+                collab_id: collab_id.to_string(),
+                media_file_id: media_file_id.to_string(),
+            })?;
+            // Record everyone who joined before the DB id was known (including us)
+            let early_joiners = match self.collab_states.write().get_mut(collab_id) {
+                Some(state) => {
+                    state.db_session_id = Some(rec.id);
+                    state.participants.iter().map(|(_, u)| u.id.clone()).collect::<Vec<_>>()
+                },
+                None => vec![],
+            };
+            for uid in early_joiners {
+                models::CollabSessionParticipant::add(conn, rec.id, &uid)?;
+            }
+        } else if let Some(db_id) = db_session_id {
+            models::CollabSessionParticipant::add(conn, db_id, &ses.user_id)?;
+        }
+        let sender_guard = self.add_sender_to_maplist(collab_id, ses.sender.clone(), &self.collab_id_to_senders);
+        Ok(Arc::new(Mutex::new((sender_guard, state_guard))))
+    }
+
+    /// Get a copy of the current state of a collab, if it exists.
+    pub fn get_collab_state(&self, collab_id: &str) -> Option<CollabState> {
+        self.collab_states.read().get(collab_id).cloned()
+    }
+
+    /// Set or clear the presenter of a collab. Does nothing if the collab doesn't exist.
+    pub fn set_collab_presenter(&self, collab_id: &str, presenter_id: Option<String>) {
+        if let Some(state) = self.collab_states.write().get_mut(collab_id) {
+            state.presenter_id = presenter_id;
+        }
+    }
 
-        // Only the first joiner (creator) of a collab gets to set the media file is.
-        let mut map = self.collab_id_to_media_file_id.write();
-        if !map.contains_key(collab_id) {
-            map.insert(collab_id.to_string(), media_file_id.to_string());
-        } else if map.get(collab_id).unwrap() != media_file_id {
-            return Err(anyhow!("Mismatching media file id for pre-existing collab"));
+    /// Remember the latest playback state reported to a collab, for late joiners.
+    pub fn set_collab_last_event(&self, collab_id: &str, evt: proto::client::server_to_client_cmd::CollabEvent) {
+        if let Some(state) = self.collab_states.write().get_mut(collab_id) {
+            state.last_event = Some(evt);
         }
-        Ok(self.add_sender_to_maplist(collab_id, sender, &self.collab_id_to_senders))
     }
 
     /// Send a message to all sessions that are viewing a media file.
     /// Bails out with error if any of the senders fail.
     /// Returns the number of messages sent.
-    pub fn send_to_all_media_file_sessions(&self, media_file_id: &str, msg: &super::Message) -> Res<u32> {
+    pub fn send_to_all_media_file_sessions(&self, media_file_id: &str, msg: &super::WsMsg) -> Res<u32> {
         let mut total_sent = 0u32;
         let map = self.media_file_id_to_senders.read();
         for sender in map.get(media_file_id).unwrap_or(&vec![]).iter() {
@@ -252,7 +559,7 @@ impl ServerState {
                     let path = self.media_files_dir.join(&c.media_file_id).join("drawings").join(&drawing);
                     if path.exists() {
                         let data = tokio::fs::read(path).await?;
-                        *drawing = format!("data:image/webp;base64,{}", Base64GP::STANDARD_NO_PAD.encode(&data));
+                        *drawing = super::webp_data_uri(&data);
                     } else {
                         tracing::warn!("Drawing file not found for comment: {}", c.id);
                         c.comment += " [DRAWING NOT FOUND]";
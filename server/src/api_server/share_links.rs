@@ -0,0 +1,166 @@
+//! Guest review links.
+//!
+//! A share link gives people without an account time-limited access to one or more
+//! media files, either view only or view + comment. Guests connect to the websocket API
+//! with the link key in the URL query (`?share=<key>`), and get a synthetic user id
+//! that is stable per browser (`guest.<link id>.<hash of secret guest id>`). Otherwise they go through
+//! the same session and authz flow as regular users, with Organizer seeing the link in
+//! `UserSessionData.guest_link`.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::Cmd;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::database::{models, DbBasicQuery, PooledConnection};
+
+type Res<T> = anyhow::Result<T>;
+
+const MIN_GUEST_ID_LEN: usize = 16;     // Shorter (guessable) guest ids are replaced with random ones
+
+// Rounds are stored in the hash, so this can be raised later. Unoptimized test builds would be too slow with the full count.
+const PASSWORD_HASH_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 100_000 };
+
+/// URL query parameter names
+pub const QUERY_SHARE_KEY: &str = "share";
+pub const QUERY_GUEST_ID: &str = "guest_id";
+pub const QUERY_GUEST_NAME: &str = "guest_name";
+pub const QUERY_PASSWORD: &str = "share_password";
+
+pub fn generate_key() -> String
+{
+    let mut buf = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// Salted PBKDF2-HMAC-SHA256 hash: `pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>`
+pub fn hash_password(password: &str) -> String
+{
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let mut out = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PASSWORD_HASH_ROUNDS, &mut out);
+    format!("pbkdf2-sha256${}${}${}", PASSWORD_HASH_ROUNDS, hex::encode(salt), hex::encode(out))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool
+{
+    let parts: Vec<&str> = hash.split('$').collect();
+    let [_, rounds, salt, expected] = parts[..] else { return false; };
+    let (Ok(rounds), Ok(salt), Ok(expected)) = (rounds.parse::<u32>(), hex::decode(salt), hex::decode(expected)) else { return false; };
+    let mut out = vec![0u8; expected.len()];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, rounds, &mut out);
+    out.len() == expected.len() && out.iter().zip(&expected).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+impl models::ShareLink {
+
+    pub fn media_file_id_list(&self) -> Vec<String> {
+        self.media_file_ids.split(',').filter(|s| !s.is_empty()).map(String::from).collect()
+    }
+
+    pub fn allows_media_file(&self, media_file_id: &str) -> bool {
+        self.media_file_ids.split(',').any(|id| id == media_file_id)
+    }
+
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.expires <= now
+    }
+
+    /// Guest URL for the link
+    pub fn url(&self, url_base: &str) -> String {
+        format!("{}/?{}={}", url_base.trim_end_matches('/'), QUERY_SHARE_KEY, self.link_key)
+    }
+}
+
+/// Find a valid (unexpired) link by key, checking password if it has one.
+pub fn get_valid_link(conn: &mut PooledConnection, key: &str, password: Option<&str>) -> Res<models::ShareLink>
+{
+    let link = models::ShareLink::get_by_key(conn, key)?.ok_or_else(|| anyhow!("Unknown share link"))?;
+    if link.is_expired(chrono::Utc::now().naive_utc()) {
+        bail!("Share link {} has expired", link.id);
+    }
+    if let Some(hash) = &link.password_hash {
+        if !verify_password(password.unwrap_or_default(), hash) {
+            bail!("Wrong or missing password for share link {}", link.id);
+        }
+    }
+    Ok(link)
+}
+
+/// Authenticate a guest from websocket URL query parameters.
+///
+/// * Returns: (user_id, user_name, link)
+pub fn authenticate_guest(conn: &mut PooledConnection, query: &HashMap<String, String>) -> Res<(String, String, models::ShareLink)>
+{
+    let key = query.get(QUERY_SHARE_KEY).ok_or_else(|| anyhow!("No share link key"))?;
+    let link = get_valid_link(conn, key, query.get(QUERY_PASSWORD).map(|s| s.as_str()))?;
+
+    // Client keeps a random guest id, so returning guests can edit their own comments
+    let guest_id: String = query.get(QUERY_GUEST_ID).map(|s| s.chars().filter(|c| c.is_ascii_alphanumeric()).take(64).collect())
+        .filter(|s: &String| s.len() >= MIN_GUEST_ID_LEN)
+        .unwrap_or_else(generate_key);
+    let name = query.get(QUERY_GUEST_NAME).map(|s| s.trim()).filter(|s| !s.is_empty()).unwrap_or("Guest");
+    let name: String = name.chars().take(64).collect();
+    Ok((guest_user_id(&link, &guest_id), format!("{} (guest)", name), link))
+}
+
+/// Public user id of a guest. The guest id is a secret (anyone knowing it can act as the guest),
+/// and user ids are shown to other users, so only a hash of it is used.
+pub fn guest_user_id(link: &models::ShareLink, guest_id: &str) -> String
+{
+    let hash = Sha256::new().chain_update(link.link_key.as_bytes()).chain_update(b":").chain_update(guest_id.as_bytes()).finalize();
+    format!("guest.{}.{}", link.id, &hex::encode(hash)[..24])
+}
+
+/// Check if a guest is allowed to run given command.
+/// Guests can only view (and if allowed, comment) the linked media files,
+/// manage their own messages and take part in collaborative sessions on linked files.
+pub fn guest_allows(link: &models::ShareLink, cmd: &Cmd, conn: &mut PooledConnection) -> bool
+{
+    let comment_media_file = |conn: &mut PooledConnection, comment_id: &str| -> Option<String> {
+        let id = comment_id.parse::<i32>().ok()?;
+        models::Comment::get(conn, &id).ok().map(|c| c.media_file_id)
+    };
+    match cmd {
+        Cmd::OpenNavigationPage(_) |
+        Cmd::ListMyMessages(_) |
+        Cmd::SetMessagesSeen(_) |
+        Cmd::DelMessages(_) |
+        Cmd::GetMessagePrefs(_) |
+        Cmd::SetMessagePrefs(_) |
+        Cmd::LeaveCollab(_) |
+        Cmd::CollabReport(_) |
+        Cmd::CollabRequestControl(_) |
+        Cmd::CollabChat(_) |
+        Cmd::Logout(_) => true,
+
+        Cmd::OpenMediaFile(d) => link.allows_media_file(&d.media_file_id),
+        Cmd::JoinCollab(d) => link.allows_media_file(&d.media_file_id),
+        Cmd::AddComment(d) => link.can_comment && link.allows_media_file(&d.media_file_id),
+        Cmd::EditComment(d) => link.can_comment && comment_media_file(conn, &d.comment_id).is_some_and(|m| link.allows_media_file(&m)),
+        Cmd::DelComment(d) => link.can_comment && comment_media_file(conn, &d.comment_id).is_some_and(|m| link.allows_media_file(&m)),
+
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_link_password() {
+        let h = hash_password("hunter2");
+        assert!(h.starts_with("pbkdf2-sha256$"));
+        assert!(verify_password("hunter2", &h));
+        assert!(!verify_password("hunter3", &h));
+        assert!(!verify_password("", &h));
+        assert!(!verify_password("hunter2", "garbage"));
+        assert_ne!(h, hash_password("hunter2"));   // salted
+    }
+}
@@ -2,6 +2,7 @@
 //#![allow(unused_variables)]
 //#![allow(unused_imports)]
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::time::Duration;
@@ -22,6 +23,7 @@ macro_rules! send_server_cmd {
     ($ws:expr, $cmd_name:ident, $options:expr) => {{
         let cmd = proto::client::ClientToServerCmd {
             cmd: Some(proto::client::client_to_server_cmd::Cmd::$cmd_name($options)),
+            ..Default::default()
         };
         let json_cmd = serde_json::to_string(&cmd).expect("Failed to serialize ClientToServerCmd to JSON");
         crate::api_server::test_utils::write(&mut $ws, &json_cmd).await;
@@ -151,21 +153,47 @@ pub(crate) async fn write(ws: &mut WsClient, msg: &str) {
 }
 
 pub(crate) async fn connect_client_ws(ws_url: &str, user_id: &str) -> WsClient {
+    connect_client_ws_as(ws_url, user_id, false).await
+}
+
+/// Auth headers like the reverse proxy would send them
+pub(crate) fn user_auth_headers(user_id: &str, is_admin: bool) -> Vec<(&'static str, String)> {
+    let mut hdrs = vec![
+        ("HTTP_X_REMOTE_USER_ID", user_id.to_string()),
+        ("HTTP_X_REMOTE_USER_NAME", format!("Username for {}", user_id))];
+    if is_admin {
+        hdrs.push(("HTTP_X_REMOTE_USER_IS_ADMIN", "1".into()));
+    }
+    hdrs
+}
+
+pub(crate) async fn connect_client_ws_as(ws_url: &str, user_id: &str, is_admin: bool) -> WsClient {
+    connect_client_ws_with_headers(ws_url, &user_auth_headers(user_id, is_admin)).await
+}
+
+/// Open a websocket with given extra HTTP headers, without waiting for the welcome messages.
+pub(crate) async fn try_connect_ws(ws_url: &str, hdrs: &[(&str, String)]) -> Result<WsClient, tokio_tungstenite::tungstenite::Error> {
     use tokio_tungstenite::tungstenite::http;
     use tokio_tungstenite::connect_async;
 
-    let request = http::Request::builder()
+    let mut request = http::Request::builder()
         .uri(ws_url)
-        .header("Host", "127.0.0.1")
-        .header("HTTP_X_REMOTE_USER_ID", user_id)
-        .header("HTTP_X_REMOTE_USER_NAME", format!("Username for {}", user_id))
+        .header("Host", "127.0.0.1");
+    for (k, v) in hdrs {
+        request = request.header(*k, v);
+    }
+    let request = request
         .header("Connection", "Upgrade")
         .header("Upgrade", "websocket")
         .header("Sec-WebSocket-Version", "13")
         .header("Sec-WebSocket-Key", "1234567890")
         .body(()).unwrap();
 
-    let (mut ws, _) = connect_async(request).await.unwrap();
+    connect_async(request).await.map(|(ws, _)| ws)
+}
+
+pub(crate) async fn connect_client_ws_with_headers(ws_url: &str, hdrs: &[(&str, String)]) -> WsClient {
+    let mut ws = try_connect_ws(ws_url, hdrs).await.unwrap();
 
     tracing::info!("TEST: Client connected. Waiting for 'welcome'...");
     expect_client_cmd!(&mut ws, Welcome);
@@ -177,7 +205,7 @@ pub(crate) async fn connect_client_ws(ws_url: &str, user_id: &str) -> WsClient {
 }
 
 macro_rules! api_test {
-    ([$ws:ident, $state:ident] $($body:tt)*) => {
+    ([$ws:ident, $state:ident $(, email: $email_cfg:expr)? $(, webhooks: $webhook_cfg:expr)? $(, jwt: ($jwt_cfg:expr, $jwt_token:expr))? $(, trusted_proxy: ($tp_cfg:expr, $tp_hdrs:expr))? $(, authz_policy: $policy_cfg:expr)? $(, rate_limits: $rl_cfg:expr)? $(, ws_resume_grace: $grace:expr)?] $($body:tt)*) => {
         {
             let (db, data_dir, media_files, comments) = make_test_db();
 
@@ -191,6 +219,22 @@ macro_rules! api_test {
             let media_files_dir = data_dir.join("videos");
             let upload_dir = data_dir.join("upload");
 
+            let email_cfg: Option<crate::email_notifier::EmailConfig> = None $( .or($email_cfg) )?;
+            let email = email_cfg.map(|cfg| Arc::new(crate::email_notifier::EmailNotifier::new(&cfg, &url_base).unwrap()));
+            let webhook_cfg: Option<crate::webhooks::WebhookConfig> = None $( .or($webhook_cfg) )?;
+            let webhooks = webhook_cfg.map(|cfg| Arc::new(crate::webhooks::Webhooks::new(cfg, db.clone()).unwrap()));
+            let jwt_cfg: Option<crate::api_server::jwt_auth::JwtAuthConfig> = None $( .or($jwt_cfg) )?;
+            let jwt_auth = jwt_cfg.map(|cfg| Arc::new(crate::api_server::jwt_auth::JwtAuth::new(cfg).unwrap()));
+            let jwt_token: Option<String> = None $( .or(Some($jwt_token)) )?;
+            let tp_cfg: Option<crate::api_server::trusted_proxy::TrustedProxyConfig> = None $( .or($tp_cfg) )?;
+            let policy_cfg: Option<crate::api_server::authz_policy::AuthzPolicyConfig> = None $( .or($policy_cfg) )?;
+            let authz_policy = policy_cfg.map(|cfg| Arc::new(crate::api_server::authz_policy::AuthzPolicy::load(cfg).unwrap()));
+            let rate_limits: Option<crate::api_server::rate_limit::RateLimitConfig> = None $( .or(Some($rl_cfg)) )?;
+            let ws_resume_grace: std::time::Duration = std::time::Duration::ZERO $( + $grace )?;
+            #[allow(unused_mut)]
+            let mut connect_hdrs = crate::api_server::test_utils::user_auth_headers("user.num1", false);
+            $( connect_hdrs.extend($tp_hdrs); )?
+
             let server_state = ServerState::new( db.clone(),
                 &media_files_dir.clone(),
                 &upload_dir.clone(),
@@ -198,6 +242,13 @@ macro_rules! api_test {
                 None,
                 grpc_srv_listening_flag.clone(),
                 "anonymous".to_string(),
+                email,
+                webhooks,
+                jwt_auth,
+                tp_cfg.map(Arc::new),
+                authz_policy,
+                rate_limits.unwrap_or_default(),
+                ws_resume_grace,
                 terminate_flag.clone());
 
             let bind_addr: std::net::IpAddr = "127.0.0.1".parse().unwrap();
@@ -207,7 +258,10 @@ macro_rules! api_test {
             let tst = tokio::spawn(async move {
                 tracing::info!("TEST: Client connecting to {}", $state.ws_url);
                 #[allow(unused_mut)]
-                let mut $ws = connect_client_ws(&$state.ws_url, "user.num1").await;
+                let mut $ws = match &jwt_token {
+                    Some(t) => crate::api_server::test_utils::connect_client_ws_with_headers(&$state.ws_url, &[("Authorization", format!("Bearer {}", t))]).await,
+                    None => crate::api_server::test_utils::connect_client_ws_with_headers(&$state.ws_url, &connect_hdrs).await,
+                };
                 println!("TEST: Running the tests...");
                 { $($body)* }
                 println!("TEST: Test finished. Terminating...");
@@ -219,6 +273,95 @@ macro_rules! api_test {
     }
 }
 
+/// Start a minimal SMTP server that accepts all mail.
+///
+/// # Returns
+/// * E-mail config pointing to the sink
+/// * Receiver for raw messages (headers + body) that were sent
+pub(crate) fn start_smtp_sink() -> (crate::email_notifier::EmailConfig, crossbeam_channel::Receiver<String>)
+{
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind SMTP sink");
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                let mut out = stream.try_clone().unwrap();
+                let mut lines = BufReader::new(stream).lines();
+                let _ = out.write_all(b"220 sink ESMTP\r\n");
+                while let Some(Ok(line)) = lines.next() {
+                    let reply: &[u8] = match line.to_ascii_uppercase().split(' ').next().unwrap_or_default() {
+                        "EHLO" | "HELO" => b"250 sink\r\n",
+                        "DATA" => {
+                            let _ = out.write_all(b"354 go ahead\r\n");
+                            let data = lines.by_ref().map_while(Result::ok).take_while(|l| l != ".").collect::<Vec<_>>().join("\n");
+                            let _ = tx.send(data);
+                            b"250 queued\r\n"
+                        },
+                        "QUIT" => { let _ = out.write_all(b"221 bye\r\n"); break; },
+                        _ => b"250 ok\r\n",
+                    };
+                    let _ = out.write_all(reply);
+                }
+            });
+        }
+    });
+
+    let cfg = crate::email_notifier::EmailConfig {
+        smtp_url: format!("smtp://127.0.0.1:{}", port),
+        from: "Clapshot <clapshot@example.com>".into(),
+        template_dir: None,
+        digest_interval: crate::email_notifier::DigestInterval::Hourly,
+    };
+    (cfg, rx)
+}
+
+/// Start a minimal HTTP server that records requests.
+///
+/// # Arguments
+/// * `statuses` - HTTP status codes to respond with, in order. After these run out, responds 200.
+///
+/// # Returns
+/// * URL of the server
+/// * Receiver for (lowercase headers, body) of each request
+pub(crate) fn start_http_sink(statuses: Vec<u16>) -> (String, crossbeam_channel::Receiver<(HashMap<String, String>, String)>)
+{
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind HTTP sink");
+    let url = format!("http://127.0.0.1:{}/hook", listener.local_addr().unwrap().port());
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    std::thread::spawn(move || {
+        let mut statuses = statuses.into_iter();
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = HashMap::new();
+            let mut line = String::new();
+            reader.read_line(&mut line).ok();   // Request line
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() { break; }
+                if let Some((k, v)) = line.split_once(':') {
+                    headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+                }
+            }
+            let len = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).ok();
+
+            let status = statuses.next().unwrap_or(200);
+            let _ = write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+            let _ = tx.send((headers, String::from_utf8_lossy(&body).to_string()));
+        }
+    });
+    (url, rx)
+}
+
 /// Send an "open media file" message to the server
 ///
 /// # Arguments
@@ -243,6 +386,9 @@ pub(crate) async fn open_media_file(ws: &mut WsClient, vid: &str) -> proto::clie
             Some(proto::client::server_to_client_cmd::Cmd::AddComments(m)) => {
                 assert!(m.comments.iter().all(|c| c.media_file_id == vid));
             },
+            Some(proto::client::server_to_client_cmd::Cmd::MediaFileViewers(m)) => {
+                assert_eq!(m.media_file_id, vid);
+            },
             // Thumbnail generation can take a while, so ignore it if it happens to be in the queue
             Some(proto::client::server_to_client_cmd::Cmd::ShowMessages(m)) => {
                 assert!(m.msgs.iter().any(|m| m.message.contains("thumbnail")));
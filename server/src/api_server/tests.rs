@@ -16,10 +16,10 @@ use crate::api_server::server_state::ServerState;
 use crate::database::models::{self};
 use crate::database::tests::make_test_db;
 
-use crate::api_server::test_utils::{ApiTestState, expect_msg, expect_no_msg, write, open_media_file, connect_client_ws};
+use crate::api_server::test_utils::{ApiTestState, expect_msg, expect_no_msg, write, open_media_file, connect_client_ws, connect_client_ws_as, start_http_sink, start_smtp_sink};
 use crate::grpc::db_models::proto_msg_type_to_event_name;
 
-use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddComment, DelComment, DelMediaFile, EditComment, ListMyMessages, OpenNavigationPage, OpenMediaFile, RenameMediaFile};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddComment, CreateApiToken, CreateShareLink, DelComment, DelMediaFile, DisconnectSession, EditComment, EditSubtitleInfo, CollabChat, CollabReport, CollabRequestControl, CollabSetPresenter, ExportReviewReport, GetCommentHistory, ImportComments, DelMessages, GetMessagePrefs, GrantMediaFileAccess, JoinCollab, LeaveCollab, ListApiTokens, ListMediaFileGrants, ListMyMessages, ListSessions, ListShareLinks, OpenNavigationPage, OpenMediaFile, RenameMediaFile, RestoreComment, RevokeApiToken, RevokeMediaFileAccess, RevokeShareLink, Search, SetEmailNotifications, SetMediaFileApproval, SetMediaFileLocked, SetMessagePrefs, SetMessagesSeen};
 use std::convert::TryFrom;
 
 // ---------------------------------------------------------------------------------------------
@@ -45,7 +45,8 @@ async fn test_api_push_msg()
             media_file_id: None,
             topic: UserMessageTopic::Ok,
             subtitle_id: None,
-            progress: None
+            progress: None,
+            webhook_event: None,
         };
 
         ts.user_msg_tx.send(umsg.clone()).unwrap();
@@ -233,6 +234,670 @@ async fn test_api_add_plain_comment()
 }
 
 
+#[tokio::test]
+#[traced_test]
+async fn test_api_comment_annotation()
+{
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];
+        open_media_file(&mut ws, &media.id).await;
+
+        // Added annotation is stored in normalized form
+        let ann = r#"{ "version": 1, "items": [ {"type": "arrow", "color": "red", "width": 0.01, "from": [0.1, 0.1], "to": [0.5, 0.5]} ] }"#;
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: media.id.clone(), comment: "Look here".into(), annotation: Some(ann.into()), ..Default::default()});
+        let c = expect_client_cmd!(&mut ws, AddComments);
+        let ann_out = c.comments[0].annotation.clone().unwrap();
+        assert_eq!(ann_out, r#"{"version":1,"items":[{"type":"arrow","color":"red","width":0.01,"from":[0.1,0.1],"to":[0.5,0.5]}]}"#);
+        let cid = c.comments[0].id.clone();
+
+        // Invalid annotation is rejected
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: media.id.clone(), comment: "Bad".into(), annotation: Some(r#"{"version": 1, "items": [{"type": "nope"}]}"#.into()), ..Default::default()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Edit text only keeps annotation, empty annotation removes it
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: cid.clone(), new_comment: "Look here!".into(), new_annotation: None});
+        expect_client_cmd!(&mut ws, DelComment);
+        assert_eq!(expect_client_cmd!(&mut ws, AddComments).comments[0].annotation, Some(ann_out));
+
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: cid.clone(), new_comment: "Look here!".into(), new_annotation: Some("".into())});
+        expect_client_cmd!(&mut ws, DelComment);
+        assert_eq!(expect_client_cmd!(&mut ws, AddComments).comments[0].annotation, None);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_import_comments()
+{
+    api_test! {[ws, ts]
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        let media = &ts.media_files[4];     // fps 16, duration 400s
+        open_media_file(&mut ws, &media.id).await;
+
+        let csv = "timecode,comment\n00:00:01:00,First\n00:00:02:08,Second\n99:00:00:00,Too late\n";
+        let mk_cmd = |commit: bool| ImportComments {
+            media_file_id: media.id.clone(),
+            file_name: "notes.csv".into(),
+            contents_base64: STANDARD.encode(csv),
+            source_author: Some("Outside Reviewer".into()),
+            start_timecode: None,
+            commit,
+            contents: vec![] };
+
+        // Preview doesn't store anything
+        send_server_cmd!(ws, ImportComments, mk_cmd(false));
+        let p = expect_client_cmd!(&mut ws, CommentImportPreview);
+        assert_eq!(p.comments.len(), 2);
+        assert_eq!(p.comments[1].timecode, Some("00:00:02:08".into()));
+        assert_eq!(p.rejected.len(), 1);
+        assert_eq!(p.rejected[0].line_num, 4);
+        expect_no_msg(&mut ws).await;
+
+        // Commit
+        send_server_cmd!(ws, ImportComments, mk_cmd(true));
+        let c = expect_client_cmd!(&mut ws, AddComments);
+        assert_eq!(c.comments.len(), 2);
+        assert!(c.comments.iter().all(|c| c.username_ifnull == "Outside Reviewer" && c.user_id == Some("user.num1".into())));
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+
+        // Unknown format
+        send_server_cmd!(ws, ImportComments, ImportComments { file_name: "notes.doc".into(), ..mk_cmd(false) });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_media_file_lock()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];    // owned by user.num1
+        open_media_file(&mut ws, &mf.id).await;
+
+        // Owner can lock
+        send_server_cmd!(ws, SetMediaFileLocked, SetMediaFileLocked{media_file_id: mf.id.clone(), locked: true});
+        let m = expect_client_cmd!(&mut ws, MediaFileLockChanged);
+        assert!(m.locked.is_some());
+        assert_eq!(m.locked_by.as_deref(), Some("user.num1"));
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+
+        // Changes are rejected while locked
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Late comment".into(), ..Default::default()});
+        let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(m.message.contains("locked"));
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: ts.comments[0].id.to_string(), new_comment: "Changed".into(), new_annotation: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, DelComment, DelComment{comment_id: ts.comments[0].id.to_string()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, RenameMediaFile, RenameMediaFile{media_file_id: mf.id.clone(), new_name: "New name".into()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, DelMediaFile, DelMediaFile{media_file_id: mf.id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(models::MediaFile::get(&mut ts.db.conn().unwrap(), &mf.id).is_ok());
+        assert_eq!(models::Comment::get(&mut ts.db.conn().unwrap(), &ts.comments[0].id).unwrap().comment, ts.comments[0].comment);
+
+        // Only admins can unlock
+        send_server_cmd!(ws, SetMediaFileLocked, SetMediaFileLocked{media_file_id: mf.id.clone(), locked: false});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        let mut admin_ws = connect_client_ws_as(&ts.ws_url, "user.admin", true).await;
+        send_server_cmd!(admin_ws, SetMediaFileLocked, SetMediaFileLocked{media_file_id: mf.id.clone(), locked: false});
+        let m = expect_client_cmd!(&mut ws, MediaFileLockChanged);
+        assert!(m.locked.is_none());
+        expect_user_msg(&mut admin_ws, proto::user_message::Type::Ok).await;
+
+        send_server_cmd!(ws, RenameMediaFile, RenameMediaFile{media_file_id: mf.id.clone(), new_name: "New name".into()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_collab_presence_and_late_join()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        send_server_cmd!(ws, JoinCollab, JoinCollab{collab_id: "c1".into(), media_file_id: mf.id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        let p = expect_client_cmd!(&mut ws, CollabParticipants);
+        assert_eq!(p.collab_id, "c1");
+        assert_eq!(p.participants.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), vec!["user.num1"]);
+
+        send_server_cmd!(ws, CollabReport, CollabReport{paused: true, r#loop: true, seek_time_sec: 12.5, drawing: None, subtitle_id: Some("7".into()), drawing_webp: None});
+        let e = expect_client_cmd!(&mut ws, CollabEvent);
+        assert!(e.r#loop);
+
+        // Late joiner gets the participant list and the last reported state
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws2, JoinCollab, JoinCollab{collab_id: "c1".into(), media_file_id: mf.id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert_eq!(expect_client_cmd!(&mut ws, CollabParticipants).participants.len(), 2);
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        let p = expect_client_cmd!(&mut ws2, CollabParticipants);
+        assert_eq!(p.participants.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), vec!["user.num1", "user.num2"]);
+        let e = expect_client_cmd!(&mut ws2, CollabEvent);
+        assert!(e.paused && e.r#loop);
+        assert_eq!(e.seek_time_sec, 12.5);
+        assert_eq!(e.subtitle_id.as_deref(), Some("7"));
+        assert_eq!(e.from_user, "Username for user.num1");
+
+        // Leaving and disconnecting both update the list
+        send_server_cmd!(ws, LeaveCollab, LeaveCollab{});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        assert_eq!(expect_client_cmd!(&mut ws2, CollabParticipants).participants.len(), 1);
+
+        send_server_cmd!(ws, JoinCollab, JoinCollab{collab_id: "c1".into(), media_file_id: mf.id.clone()});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        assert_eq!(expect_client_cmd!(&mut ws2, CollabParticipants).participants.len(), 2);
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        expect_client_cmd!(&mut ws, CollabParticipants);
+        expect_client_cmd!(&mut ws, CollabEvent);
+
+        ws2.close(None).await.unwrap();
+        let p = expect_client_cmd!(&mut ws, CollabParticipants);
+        assert_eq!(p.participants.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), vec!["user.num1"]);
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_media_file_viewers()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        open_media_file(&mut ws, &mf.id).await;
+
+        // Opener gets the current viewer list, others get a join event
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws2, OpenMediaFile, OpenMediaFile{media_file_id: mf.id.clone()});
+        expect_client_cmd!(&mut ws2, OpenMediaFile);
+        expect_client_cmd!(&mut ws2, AddComments);
+        let v = expect_client_cmd!(&mut ws2, MediaFileViewers);
+        assert_eq!(v.media_file_id, mf.id);
+        assert_eq!(v.viewers.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), vec!["user.num1", "user.num2"]);
+
+        let e = expect_client_cmd!(&mut ws, MediaFileViewerChanged);
+        assert_eq!((e.media_file_id.as_str(), e.user.unwrap().id.as_str(), e.joined), (mf.id.as_str(), "user.num2", true));
+
+        // Going back to a page listing, opening another file and disconnecting all count as leaving
+        send_server_cmd!(ws2, OpenNavigationPage, OpenNavigationPage{page_id: None});
+        let e = expect_client_cmd!(&mut ws, MediaFileViewerChanged);
+        assert_eq!((e.user.unwrap().id.as_str(), e.joined), ("user.num2", false));
+
+        let mut ws3 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        open_media_file(&mut ws3, &mf.id).await;
+        assert!(expect_client_cmd!(&mut ws, MediaFileViewerChanged).joined);
+        open_media_file(&mut ws3, &ts.media_files[2].id).await;
+        assert!(!expect_client_cmd!(&mut ws, MediaFileViewerChanged).joined);
+
+        open_media_file(&mut ws3, &mf.id).await;
+        assert!(expect_client_cmd!(&mut ws, MediaFileViewerChanged).joined);
+        ws3.close(None).await.unwrap();
+        assert!(!expect_client_cmd!(&mut ws, MediaFileViewerChanged).joined);
+    }
+}
+
+
+/// Join two clients (one after another) to the same collab and consume the resulting notifications
+async fn join_collab_pair(ws: &mut crate::api_server::test_utils::WsClient, ws2: &mut crate::api_server::test_utils::WsClient, collab_id: &str, mf_id: &str)
+{
+    send_server_cmd!(*ws, JoinCollab, JoinCollab{collab_id: collab_id.into(), media_file_id: mf_id.into()});
+    expect_user_msg(ws, proto::user_message::Type::Ok).await;
+    expect_client_cmd!(&mut *ws, CollabParticipants);
+    send_server_cmd!(*ws2, JoinCollab, JoinCollab{collab_id: collab_id.into(), media_file_id: mf_id.into()});
+    for w in [ws, ws2] {
+        expect_user_msg(w, proto::user_message::Type::Ok).await;
+        assert_eq!(expect_client_cmd!(&mut *w, CollabParticipants).participants.len(), 2);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_collab_presenter_mode()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        join_collab_pair(&mut ws, &mut ws2, "c1", &mf.id).await;
+
+        send_server_cmd!(ws, CollabSetPresenter, CollabSetPresenter{user_id: Some("user.num1".into())});
+        for w in [&mut ws, &mut ws2] {
+            expect_user_msg(w, proto::user_message::Type::Ok).await;
+            assert_eq!(expect_client_cmd!(w, CollabParticipants).presenter_id.as_deref(), Some("user.num1"));
+        }
+
+        // Only the presenter's reports are relayed
+        let report = CollabReport{paused: false, r#loop: false, seek_time_sec: 3.0, drawing: None, subtitle_id: None, drawing_webp: None};
+        send_server_cmd!(ws2, CollabReport, report.clone());
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+        expect_no_msg(&mut ws).await;
+
+        send_server_cmd!(ws, CollabReport, report);
+        for w in [&mut ws, &mut ws2] {
+            assert_eq!(expect_client_cmd!(w, CollabEvent).presenter_id.as_deref(), Some("user.num1"));
+        }
+
+        // Others can ask for control but not take it
+        send_server_cmd!(ws2, CollabRequestControl, CollabRequestControl{});
+        for w in [&mut ws, &mut ws2] {
+            assert_eq!(expect_client_cmd!(w, CollabControlRequested).from_user.unwrap().id, "user.num2");
+        }
+        send_server_cmd!(ws2, CollabSetPresenter, CollabSetPresenter{user_id: Some("user.num2".into())});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+
+        // Presenter hands over
+        send_server_cmd!(ws, CollabSetPresenter, CollabSetPresenter{user_id: Some("user.num2".into())});
+        for w in [&mut ws, &mut ws2] {
+            expect_user_msg(w, proto::user_message::Type::Ok).await;
+            assert_eq!(expect_client_cmd!(w, CollabParticipants).presenter_id.as_deref(), Some("user.num2"));
+        }
+
+        // Presenter leaving ends presenter mode
+        send_server_cmd!(ws2, LeaveCollab, LeaveCollab{});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        let p = expect_client_cmd!(&mut ws, CollabParticipants);
+        assert_eq!(p.participants.len(), 1);
+        assert!(p.presenter_id.is_none());
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_collab_chat_and_session_record()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        join_collab_pair(&mut ws, &mut ws2, "c1", &mf.id).await;
+
+        // Ephemeral and saved messages are both relayed
+        send_server_cmd!(ws, CollabChat, CollabChat{message: " just chatting ".into(), save_to_transcript: false});
+        send_server_cmd!(ws2, CollabChat, CollabChat{message: "keep this".into(), save_to_transcript: true});
+        for w in [&mut ws, &mut ws2] {
+            let m = expect_client_cmd!(w, CollabChatMessage);
+            assert_eq!((m.message.as_str(), m.saved, m.from_user.unwrap().id.as_str()), ("just chatting", false, "user.num1"));
+            let m = expect_client_cmd!(w, CollabChatMessage);
+            assert_eq!((m.message.as_str(), m.saved, m.from_user.unwrap().id.as_str()), ("keep this", true, "user.num2"));
+        }
+
+        let conn = &mut ts.db.conn().unwrap();
+        let sessions = <models::CollabSession as crate::database::DbQueryByMediaFile>::get_by_media_file(conn, &mf.id, crate::database::DBPaging::default()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].ended.is_none());
+        let participants = models::CollabSessionParticipant::get_by_session(conn, sessions[0].id).unwrap();
+        assert_eq!(participants.iter().map(|p| p.user_id.as_str()).collect::<Vec<_>>(), vec!["user.num1", "user.num2"]);
+        let transcript = models::CollabChatMessage::get_by_session(conn, sessions[0].id).unwrap();
+        assert_eq!(transcript.iter().map(|m| m.message.as_str()).collect::<Vec<_>>(), vec!["keep this"]);
+
+        // Session record is closed when the last participant leaves
+        send_server_cmd!(ws, LeaveCollab, LeaveCollab{});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        expect_client_cmd!(&mut ws2, CollabParticipants);
+        assert!(models::CollabSession::get(conn, &sessions[0].id).unwrap().ended.is_none());
+        send_server_cmd!(ws2, LeaveCollab, LeaveCollab{});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        assert!(models::CollabSession::get(conn, &sessions[0].id).unwrap().ended.is_some());
+
+        send_server_cmd!(ws, CollabChat, CollabChat{message: "anyone?".into(), save_to_transcript: false});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_media_file_approval()
+{
+    use proto::media_file_approval::State;
+    api_test! {[ws, ts]
+        // Approve someone else's media file. Viewers get the new state.
+        let mf = &ts.media_files[1];
+        open_media_file(&mut ws, &mf.id).await;
+        send_server_cmd!(ws, SetMediaFileApproval, SetMediaFileApproval{media_file_id: mf.id.clone(), state: State::ChangesRequested.into(), note: Some(" Fix the color ".into())});
+        let m = expect_client_cmd!(&mut ws, MediaFileApprovals);
+        assert_eq!(m.media_file_id, mf.id);
+        assert_eq!(m.approvals.len(), 1);
+        assert_eq!(m.approvals[0].user_id, "user.num1");
+        assert_eq!(m.approvals[0].state(), State::ChangesRequested);
+        assert_eq!(m.approvals[0].note.as_deref(), Some("Fix the color"));
+
+        send_server_cmd!(ws, SetMediaFileApproval, SetMediaFileApproval{media_file_id: mf.id.clone(), state: State::Approved.into(), note: None});
+        let m = expect_client_cmd!(&mut ws, MediaFileApprovals);
+        assert_eq!(m.approvals.len(), 1);
+        assert_eq!(m.approvals[0].state(), State::Approved);
+
+        // Owner was notified (persistently)
+        use crate::database::{DBPaging, DbQueryByUser};
+        let msgs = models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num2", DBPaging::default()).unwrap();
+        assert!(msgs.iter().any(|m| m.message.contains("approved") && m.media_file_id.as_deref() == Some(mf.id.as_str())));
+
+        // Approvals are included in media file info
+        let v = open_media_file(&mut ws, &mf.id).await.media_file.unwrap();
+        assert_eq!(v.approvals.len(), 1);
+
+        // Bad media file
+        send_server_cmd!(ws, SetMediaFileApproval, SetMediaFileApproval{media_file_id: "non-existent".into(), state: State::Approved.into(), note: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_email_notifications()
+{
+    use proto::media_file_approval::State;
+    use std::time::Duration;
+    let (email_cfg, mails) = start_smtp_sink();
+
+    api_test! {[ws, ts, email: Some(email_cfg)]
+        models::User::set_email(&mut ts.db.conn().unwrap(), "user.num2", Some("num2@example.com")).unwrap();
+        let mf = &ts.media_files[1];    // owned by user.num2
+        open_media_file(&mut ws, &mf.id).await;
+
+        // Mention: persisted message and immediate e-mail. Unknown users are ignored.
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Thanks @user.num2 and @nobody".into(), ..Default::default()});
+        expect_client_cmd!(&mut ws, AddComments);
+        let mail = mails.recv_timeout(Duration::from_secs(5)).expect("No mention e-mail");
+        assert!(mail.contains("num2@example.com"));
+        assert!(mail.contains("Subject: Username for user.num1 mentioned you on 'test1.mp4'"));
+        assert!(mail.contains("Thanks @user.num2 and @nobody"));
+
+        use crate::database::{DBPaging, DbQueryByUser};
+        let msgs = models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num2", DBPaging::default()).unwrap();
+        assert!(msgs.iter().any(|m| m.message.contains("mentioned you") && m.media_file_id.as_deref() == Some(mf.id.as_str())));
+
+        // Approval of someone else's media file mails the owner
+        send_server_cmd!(ws, SetMediaFileApproval, SetMediaFileApproval{media_file_id: mf.id.clone(), state: State::Approved.into(), note: Some("Looks good".into())});
+        expect_client_cmd!(&mut ws, MediaFileApprovals);
+        let mail = mails.recv_timeout(Duration::from_secs(5)).expect("No approval e-mail");
+        assert!(mail.contains("Subject: 'test1.mp4': approved by Username for user.num1"));
+        assert!(mail.contains("Looks good"));
+
+        // Owner opted out of immediate mails
+        models::User::set_email_notifications(&mut ts.db.conn().unwrap(), "user.num2", "digest").unwrap();
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Ping @user.num2".into(), ..Default::default()});
+        expect_client_cmd!(&mut ws, AddComments);
+        assert!(mails.recv_timeout(Duration::from_millis(500)).is_err());
+
+        // Users can set their own preference
+        send_server_cmd!(ws, SetEmailNotifications, SetEmailNotifications{mode: "none".into()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert_eq!(models::User::get(&mut ts.db.conn().unwrap(), &"user.num1".into()).unwrap().email_notifications, "none");
+        send_server_cmd!(ws, SetEmailNotifications, SetEmailNotifications{mode: "sometimes".into()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+    }
+}
+
+#[test]
+#[traced_test]
+fn test_email_digest()
+{
+    use std::time::Duration;
+    let (email_cfg, mails) = start_smtp_sink();
+    let (db, _data_dir, media_files, _comments) = make_test_db();
+    let email = crate::email_notifier::EmailNotifier::new(&email_cfg, "https://clapshot.test").unwrap();
+    {
+        let conn = &mut db.conn().unwrap();
+        models::User::set_email(conn, "user.num1", Some("num1@example.com")).unwrap();
+        models::User::set_email(conn, "user.num2", Some("num2@example.com")).unwrap();
+        models::User::set_email_notifications(conn, "user.num2", "immediate").unwrap();
+        let u3 = models::User::get_or_create(conn, "user.num3", Some("User Number3")).unwrap();
+        models::User::set_email(conn, &u3.id, Some("broken@@address")).unwrap();
+        models::User::set_email_notifications(conn, &u3.id, "digest").unwrap();
+        let mf = media_files.iter().find(|m| m.user_id == "user.num2").unwrap().clone();
+        use crate::database::DbUpdate;
+        models::MediaFile::update_many(conn, &[models::MediaFile { user_id: u3.id.clone(), ..mf }]).unwrap();
+        let mf1 = media_files.iter().find(|m| m.user_id == "user.num1").unwrap();
+        models::Comment::insert(conn, &models::CommentInsert { media_file_id: mf1.id.clone(), parent_id: None, user_id: Some("user.num2".into()),
+            username_ifnull: "User Number2".into(), comment: "Literal {{url_base}}".into(), timecode: None, drawing: None,
+            subtitle_id: None, subtitle_filename_ifnull: None, annotation: None }).unwrap();
+    }
+
+    // user.num1 wants digests, and user.num2 has commented on their media.
+    // Broken address of user.num3 doesn't prevent that.
+    let now = chrono::Utc::now().naive_utc();
+    assert_eq!(email.send_due_digests(&db, now).unwrap(), 1);
+    let mail = mails.recv_timeout(Duration::from_secs(5)).expect("No digest e-mail");
+    assert!(mail.contains("num1@example.com"));
+    assert!(mail.contains("- User Number2 on '"));
+    assert!(mail.contains("Subject: 3 new comment(s) on your media"));
+    assert!(mail.contains("Literal {{url_base}}"));
+    assert!(mail.contains("https://clapshot.test/?vid=B1DE0"));
+
+    // Not due yet, and nothing new after that
+    assert_eq!(email.send_due_digests(&db, now + chrono::Duration::minutes(10)).unwrap(), 0);
+    assert_eq!(email.send_due_digests(&db, now + chrono::Duration::hours(2)).unwrap(), 0);
+    assert!(mails.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_webhook_events()
+{
+    use crate::webhooks::{WebhookConfig, WebhookEvent};
+    use crate::database::DBPaging;
+    let cfg = WebhookConfig { urls: vec!["http://127.0.0.1:9/a".into(), "http://127.0.0.1:9/b".into()], secret: "s3cret".into(), events: vec![] };
+
+    // Delivery loop is not running here, so calls just pile up in the DB
+    api_test! {[ws, ts, webhooks: Some(cfg)]
+        let queued = |db: &crate::database::DB| {
+            let mut d = models::WebhookDelivery::get_all(&mut db.conn().unwrap(), DBPaging::default()).unwrap();
+            d.sort_by_key(|d| d.id);
+            d.into_iter().filter(|d| d.url.ends_with("/a")).map(|d| (d.event, serde_json::from_str::<serde_json::Value>(&d.payload).unwrap())).collect::<Vec<_>>()
+        };
+        let mf = &ts.media_files[0];
+        open_media_file(&mut ws, &mf.id).await;
+
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Hooked".into(), ..Default::default()});
+        let c = expect_client_cmd!(&mut ws, AddComments).comments[0].clone();
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: c.id.clone(), new_comment: "Hooked again".into(), ..Default::default()});
+        expect_client_cmd!(&mut ws, DelComment);
+        expect_client_cmd!(&mut ws, AddComments);
+        send_server_cmd!(ws, DelComment, DelComment{comment_id: c.id.clone()});
+        expect_client_cmd!(&mut ws, DelComment);
+        send_server_cmd!(ws, SetMediaFileApproval, SetMediaFileApproval{media_file_id: mf.id.clone(), state: proto::media_file_approval::State::Approved.into(), note: None});
+        expect_client_cmd!(&mut ws, MediaFileApprovals);
+
+        let q = queued(&ts.db);
+        assert_eq!(q.iter().map(|(e, _)| e.as_str()).collect::<Vec<_>>(), vec!["comment.added", "comment.edited", "comment.deleted", "approval.changed"]);
+        assert!(q.iter().all(|(e, p)| p["event"] == e.as_str() && p["user_id"] == "user.num1"));
+        assert_eq!(q[0].1["comment"]["id"], c.id);
+        assert_eq!(q[1].1["comment"]["comment"], "Hooked again");
+        assert_eq!(q[3].1["approval"]["userId"], "user.num1");
+        assert_eq!(q[3].1["media_file_id"], mf.id);
+
+        // Media file added (pipeline message relay) and deleted
+        ts.user_msg_tx.send(UserMessage {
+            topic: UserMessageTopic::MediaFileAdded,
+            user_id: Some("user.num1".into()),
+            media_file_id: Some(ts.media_files[2].id.clone()),
+            webhook_event: Some(WebhookEvent::MediaFileAdded),
+            ..Default::default() }).unwrap();
+        expect_client_cmd!(&mut ws, ShowMessages);
+        send_server_cmd!(ws, DelMediaFile, DelMediaFile{media_file_id: ts.media_files[2].id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+
+        let q = queued(&ts.db);
+        assert_eq!(q[4].0, "media_file.added");
+        assert_eq!(q[4].1["media_file"]["id"], ts.media_files[2].id);
+        assert_eq!(q[5].0, "media_file.deleted");
+        assert_eq!(q[5].1["media_file"]["title"], "test2.mp4");
+
+        // One delivery per URL
+        assert_eq!(models::WebhookDelivery::get_all(&mut ts.db.conn().unwrap(), DBPaging::default()).unwrap().len(), 2 * q.len());
+    }
+}
+
+#[test]
+#[traced_test]
+fn test_webhook_delivery_retry_and_signature()
+{
+    use crate::webhooks::{WebhookConfig, WebhookEvent, Webhooks, sign_payload};
+    use crate::database::DBPaging;
+    use std::time::Duration;
+
+    let (db, _data_dir, _media_files, _comments) = make_test_db();
+    let (url, reqs) = start_http_sink(vec![500]);
+    let wh = Webhooks::new(WebhookConfig { urls: vec![url], secret: "s3cret".into(), events: vec![WebhookEvent::CommentAdded] }, db.clone()).unwrap();
+    let get = |id| models::WebhookDelivery::get(&mut db.conn().unwrap(), &id).unwrap();
+
+    // Not subscribed
+    wh.emit(WebhookEvent::CommentDeleted, None, serde_json::json!({})).unwrap();
+    assert!(models::WebhookDelivery::get_all(&mut db.conn().unwrap(), DBPaging::default()).unwrap().is_empty());
+
+    wh.emit(WebhookEvent::CommentAdded, Some("user.num1"), serde_json::json!({"comment": {"id": "123"}})).unwrap();
+    let id = models::WebhookDelivery::get_all(&mut db.conn().unwrap(), DBPaging::default()).unwrap()[0].id;
+
+    // First attempt fails -> retry scheduled
+    let now = chrono::Utc::now().naive_utc();
+    assert_eq!(wh.deliver_due(now).unwrap(), 0);
+    reqs.recv_timeout(Duration::from_secs(5)).expect("No webhook call");
+    let d = get(id);
+    assert_eq!((d.status.as_str(), d.attempts, d.response_code), ("pending", 1, Some(500)));
+    assert!(d.next_attempt > now);
+
+    // Not due yet
+    assert_eq!(wh.deliver_due(now).unwrap(), 0);
+    assert!(reqs.recv_timeout(Duration::from_millis(200)).is_err());
+
+    // Second attempt succeeds
+    assert_eq!(wh.deliver_due(d.next_attempt).unwrap(), 1);
+    let (headers, body) = reqs.recv_timeout(Duration::from_secs(5)).expect("No webhook retry");
+    assert_eq!(headers.get("x-clapshot-event").map(String::as_str), Some("comment.added"));
+    assert_eq!(headers.get("x-clapshot-delivery"), Some(&id.to_string()));
+    assert_eq!(headers.get("x-clapshot-signature"), Some(&sign_payload("s3cret", &body)));
+    let p: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!((p["event"].as_str(), p["user_id"].as_str(), p["comment"]["id"].as_str()), (Some("comment.added"), Some("user.num1"), Some("123")));
+    let d = get(id);
+    assert_eq!((d.status.as_str(), d.attempts, d.response_code, d.last_error), ("delivered", 2, Some(200), None));
+
+    // Unreachable endpoint -> give up after max attempts, with growing delays
+    let wh = Webhooks::new(WebhookConfig { urls: vec!["http://127.0.0.1:9/".into()], secret: "s3cret".into(), events: vec![] }, db.clone()).unwrap();
+    wh.emit(WebhookEvent::MediaFileDeleted, None, serde_json::json!({})).unwrap();
+    let mut t = chrono::Utc::now().naive_utc();
+    let mut delays = vec![];
+    loop {
+        wh.deliver_due(t).unwrap();
+        let d = models::WebhookDelivery::get_by_status(&mut db.conn().unwrap(), "pending", DBPaging::default()).unwrap();
+        let Some(d) = d.first() else { break };
+        delays.push((d.next_attempt - t).num_seconds());
+        t = d.next_attempt;
+    }
+    assert_eq!(delays, vec![10, 20, 40, 80, 160, 320, 640]);
+    let failed = models::WebhookDelivery::get_by_status(&mut db.conn().unwrap(), "failed", DBPaging::default()).unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].attempts, 8);
+    assert!(failed[0].last_error.is_some());
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_search()
+{
+    use crate::api_server::test_utils::read;
+    api_test! {[ws, ts]
+        // Only own media files are searched (no organizer)
+        send_server_cmd!(ws, Search, Search{query: "comment".into(), ..Default::default()});
+        let r = expect_client_cmd!(&mut ws, SearchResults);
+        assert_eq!(r.query, "comment");
+        let own = ts.media_files.iter().filter(|m| m.user_id == "user.num1").map(|m| m.id.clone()).collect::<Vec<_>>();
+        assert_eq!(r.hits.len(), ts.comments.iter().filter(|c| own.contains(&c.media_file_id)).count());
+        assert!(r.hits.iter().all(|h| h.kind() == proto::search_hit::Kind::Comment && own.contains(&h.media_file_id)));
+
+        // Title search, limited to one media file
+        let media = &ts.media_files[0];
+        send_server_cmd!(ws, Search, Search{query: "test0".into(), media_file_id: Some(media.id.clone()), ..Default::default()});
+        let r = expect_client_cmd!(&mut ws, SearchResults);
+        assert_eq!(r.hits.len(), 1);
+        assert_eq!(r.hits[0].kind(), proto::search_hit::Kind::MediaFile);
+        assert_eq!(r.hits[0].media_file_id, media.id);
+
+        // Searching within someone else's media file needs the same permission as global search
+        let other = ts.media_files.iter().find(|m| m.user_id == "user.num2").unwrap().clone();
+        send_server_cmd!(ws, Search, Search{query: "comment".into(), media_file_id: Some(other.id.clone()), ..Default::default()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        let grant = |uid: &str| GrantMediaFileAccess{ media_file_id: other.id.clone(), role: proto::media_file_grant::Role::Viewer.into(),
+            grantee: Some(proto::client::client_to_server_cmd::grant_media_file_access::Grantee::UserId(uid.into())) };
+        send_server_cmd!(ws2, GrantMediaFileAccess, grant("user.num3"));
+        expect_client_cmd!(&mut ws2, ShowMediaFileGrants);
+        send_server_cmd!(ws, Search, Search{query: "comment".into(), media_file_id: Some(other.id.clone()), ..Default::default()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Files shared with the user can be searched, and show up in global search
+        send_server_cmd!(ws2, GrantMediaFileAccess, grant("user.num1"));
+        expect_client_cmd!(&mut ws2, ShowMediaFileGrants);
+        send_server_cmd!(ws, Search, Search{query: "comment".into(), media_file_id: Some(other.id.clone()), ..Default::default()});
+        assert!(!expect_client_cmd!(&mut ws, SearchResults).hits.is_empty());
+        send_server_cmd!(ws, Search, Search{query: "comment".into(), ..Default::default()});
+        let hits = expect_client_cmd!(&mut ws, SearchResults).hits;
+        assert!(hits.iter().any(|h| h.media_file_id == other.id));
+        assert!(hits.iter().all(|h| h.media_file_id == other.id || own.contains(&h.media_file_id)));
+
+        // Pages are full, as hits are filtered before paging
+        let mut paged = vec![];
+        for page_num in 0.. {
+            send_server_cmd!(ws, Search, Search{query: "comment".into(), page_num, page_size: 2, ..Default::default()});
+            let page = expect_client_cmd!(&mut ws, SearchResults).hits;
+            assert!(page.len() == 2 || paged.len() + page.len() == hits.len());
+            if page.is_empty() { break; }
+            paged.extend(page);
+        }
+        assert_eq!(paged.len(), hits.len());
+
+        // Editing a subtitle reindexes its cues
+        let subs_dir = ts.media_files_dir.join(&media.id).join("subs");
+        std::fs::create_dir_all(&subs_dir).unwrap();
+        std::fs::write(subs_dir.join("sub.vtt"), "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello there\n").unwrap();
+        let sub = models::Subtitle::insert(&mut ts.db.conn().unwrap(), &models::SubtitleInsert {
+            media_file_id: media.id.clone(), title: "Sub".into(), language_code: "en".into(),
+            filename: Some("sub.vtt".into()), orig_filename: "sub.srt".into(), time_offset: 0.0 }).unwrap();
+        std::fs::write(subs_dir.join("sub.vtt"), "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nGeneral Kenobi\n").unwrap();
+        send_server_cmd!(ws, EditSubtitleInfo, EditSubtitleInfo{ id: sub.id.to_string(), title: Some("Edited".into()), ..Default::default() });
+        expect_client_cmd!(&mut ws, OpenMediaFile);
+        while read(&mut ws).await.is_some() {}     // Comments etc. of the reopened file
+        send_server_cmd!(ws, Search, Search{query: "kenobi".into(), ..Default::default()});
+        let hits = expect_client_cmd!(&mut ws, SearchResults).hits;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind(), proto::search_hit::Kind::Subtitle);
+        assert_eq!(hits[0].subtitle_id, Some(sub.id.to_string()));
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_export_review_report()
+{
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];
+        send_server_cmd!(ws, ExportReviewReport, ExportReviewReport{media_file_id: media.id.clone(), ..Default::default()});
+        let r = expect_client_cmd!(&mut ws, ReviewReportReady);
+        assert_eq!(r.media_file_id, media.id);
+        assert!(r.url.starts_with(&format!("{}/videos/{}/reports/", ts.url_base, media.id)));
+
+        // Report has all comments of the media file, replies included
+        let fname = r.url.rsplit('/').next().unwrap();
+        let html = std::fs::read_to_string(ts.media_files_dir.join(&media.id).join("reports").join(fname)).unwrap();
+        for c in ts.comments.iter().filter(|c| c.media_file_id == media.id) {
+            assert!(html.contains(&c.comment));
+        }
+        assert!(html.contains(&media.title.clone().unwrap()));
+
+        send_server_cmd!(ws, ExportReviewReport, ExportReviewReport{media_file_id: "bad_id".into(), ..Default::default()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+    }
+}
+
+
 #[tokio::test]
 #[traced_test]
 async fn test_api_comment_other_users_video()
@@ -324,17 +989,69 @@ async fn test_api_del_comment()
         let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
         assert!(m.message.contains("ermission"));
 
-        // Fail to delete comment[0] that has replies
+        // Delete comment[0] that has replies. It's replaced by a blank placeholder.
         send_server_cmd!(ws, DelComment, DelComment{comment_id: ts.comments[0].id.to_string()});
-        let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
-        assert!(m.details.unwrap().contains("repl"));
-
-        // Delete the last remaining reply comment[5]
-        models::Comment::delete(&mut ts.db.conn().unwrap(), &ts.comments[5].id).unwrap(); // Delete from db directly, to avoid user permission check
+        let m = expect_client_cmd!(&mut ws, DelComment);
+        assert_eq!(m.comment_id, ts.comments[0].id.to_string());
+        let m = expect_client_cmd!(&mut ws, AddComments);
+        assert_eq!(m.comments.len(), 1);
+        assert!(m.comments[0].deleted.is_some());
+        assert!(m.comments[0].comment.is_empty());
 
-        // Try again to delete comment id 1 that should now have no replies
+        // Deleted comments can't be edited or deleted again
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: ts.comments[0].id.to_string(), new_comment: "Zombie".into(), new_annotation: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
         send_server_cmd!(ws, DelComment, DelComment{comment_id: ts.comments[0].id.to_string()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Placeholder is sent on open, but remains in DB
+        open_media_file(&mut ws, &media.id).await;
+        assert!(models::Comment::get(&mut ts.db.conn().unwrap(), &ts.comments[6].id).unwrap().deleted.is_some());
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_comment_history_and_restore()
+{
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];
+        let com = &ts.comments[6];  // reply to comment[0], by user 1
+        open_media_file(&mut ws, &media.id).await;
+
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: com.id.to_string(), new_comment: "Edited".into(), new_annotation: None});
+        expect_client_cmd!(&mut ws, DelComment);
+        expect_client_cmd!(&mut ws, AddComments);
+        send_server_cmd!(ws, DelComment, DelComment{comment_id: com.id.to_string()});
         expect_client_cmd!(&mut ws, DelComment);
+
+        // History and restore are for admins only
+        send_server_cmd!(ws, GetCommentHistory, GetCommentHistory{comment_id: com.id.to_string()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, RestoreComment, RestoreComment{comment_id: com.id.to_string(), revision_id: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        let mut admin_ws = connect_client_ws_as(&ts.ws_url, "user.admin", true).await;
+        open_media_file(&mut admin_ws, &media.id).await;
+        assert!(expect_client_cmd!(&mut ws, MediaFileViewerChanged).joined);
+
+        send_server_cmd!(admin_ws, GetCommentHistory, GetCommentHistory{comment_id: com.id.to_string()});
+        let h = expect_client_cmd!(&mut admin_ws, CommentHistory);
+        assert_eq!(h.comment.unwrap().comment, "Edited");
+        assert_eq!(h.revisions.iter().map(|r| (r.action.as_str(), r.comment.as_str())).collect::<Vec<_>>(),
+            vec![("edit", com.comment.as_str()), ("delete", "Edited")]);
+        assert!(h.revisions.iter().all(|r| r.user_id.as_deref() == Some("user.num1")));
+
+        // Restore original text. Both viewers get the comment back.
+        send_server_cmd!(admin_ws, RestoreComment, RestoreComment{comment_id: com.id.to_string(), revision_id: Some(h.revisions[0].id.clone())});
+        for w in [&mut ws, &mut admin_ws] {
+            expect_client_cmd!(w, DelComment);
+            let m = expect_client_cmd!(w, AddComments);
+            assert_eq!(m.comments[0].comment, com.comment);
+            assert!(m.comments[0].deleted.is_none());
+        }
+        expect_user_msg(&mut admin_ws, proto::user_message::Type::Ok).await;
     }
 }
 
@@ -344,7 +1061,7 @@ async fn test_api_del_comment()
 async fn test_api_list_my_messages()
 {
     api_test! {[ws, ts]
-        send_server_cmd!(ws, ListMyMessages, ListMyMessages{});
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
 
         let m = expect_client_cmd!(&mut ws, ShowMessages);
         assert_eq!(m.msgs.len(), 0);
@@ -356,7 +1073,7 @@ async fn test_api_list_my_messages()
         ];
         let msgs = msgs.iter().map(|m| models::Message::insert(&mut ts.db.conn().unwrap(), &m).unwrap()).collect::<Vec<_>>();
 
-        send_server_cmd!(ws, ListMyMessages, ListMyMessages{});
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
         let sm = expect_client_cmd!(&mut ws, ShowMessages);
         for (i, m) in sm.msgs.iter().enumerate() {
             let mtype = proto::user_message::Type::try_from(m.r#type).unwrap();
@@ -366,7 +1083,7 @@ async fn test_api_list_my_messages()
         }
 
         // List again, this time messages should be marked "seen"
-        send_server_cmd!(ws, ListMyMessages, ListMyMessages{});
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
         let sm = expect_client_cmd!(&mut ws, ShowMessages);
         assert_eq!(sm.msgs.len(), 2);
         for (i, m) in sm.msgs.iter().enumerate() {
@@ -377,6 +1094,605 @@ async fn test_api_list_my_messages()
 }
 
 
+#[tokio::test]
+#[traced_test]
+async fn test_api_manage_my_messages()
+{
+    api_test! {[ws, ts]
+        let conn = &mut ts.db.conn().unwrap();
+        let mut ids = vec![];
+        for (uid, txt) in [("user.num1", "m1"), ("user.num1", "m2"), ("user.num1", "m3"), ("user.num2", "other")] {
+            let m = models::Message::insert(conn, &models::MessageInsert { user_id: uid.into(), message: txt.into(), event_name: "ok".into(), ..Default::default() }).unwrap();
+            ids.push(m.id.to_string());
+        }
+
+        // Paginated listing, without and with marking seen
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages { page_num: 0, page_size: 2, mark_seen: Some(false), ..Default::default() });
+        let sm = expect_client_cmd!(&mut ws, ShowMessages);
+        let li = sm.list_info.unwrap();
+        assert_eq!((sm.msgs.len(), li.total_count, li.unseen_count), (2, 3, 3));
+
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages { page_num: 1, page_size: 2, ..Default::default() });
+        let sm = expect_client_cmd!(&mut ws, ShowMessages);
+        let li = sm.list_info.unwrap();
+        assert_eq!((sm.msgs.len(), li.page_num, li.total_count, li.unseen_count), (1, 1, 3, 2));
+        assert!(!sm.msgs[0].seen);
+
+        // Mark all read, one unread
+        send_server_cmd!(ws, SetMessagesSeen, SetMessagesSeen { all: true, seen: true, ..Default::default() });
+        send_server_cmd!(ws, SetMessagesSeen, SetMessagesSeen { ids: vec![ids[1].clone(), ids[3].clone()], seen: false, ..Default::default() });
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages { unseen_only: true, mark_seen: Some(false), ..Default::default() });
+        let sm = expect_client_cmd!(&mut ws, ShowMessages);
+        assert_eq!(sm.msgs.iter().map(|m| m.message.as_str()).collect::<Vec<_>>(), vec!["m2"]);
+
+        // Delete one, then clear the rest. Other users' messages must survive.
+        send_server_cmd!(ws, DelMessages, DelMessages { ids: vec![ids[0].clone(), ids[3].clone()], ..Default::default() });
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
+        let sm = expect_client_cmd!(&mut ws, ShowMessages);
+        assert_eq!(sm.list_info.unwrap().total_count, 2);
+
+        send_server_cmd!(ws, DelMessages, DelMessages { all: true, ..Default::default() });
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
+        let sm = expect_client_cmd!(&mut ws, ShowMessages);
+        assert_eq!((sm.msgs.len(), sm.list_info.unwrap().total_count), (0, 0));
+        assert!(models::Message::get(conn, &ids[3].parse().unwrap()).is_ok());
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_message_prefs()
+{
+    api_test! {[ws, ts]
+        use proto::user_message::Type;
+        use crate::database::{DBPaging, DbQueryByUser};
+        send_server_cmd!(ws, GetMessagePrefs, GetMessagePrefs {});
+        let mp = expect_client_cmd!(&mut ws, MessagePrefs);
+        assert_eq!(mp.prefs.len(), 5);
+        assert!(mp.prefs.iter().all(|p| p.persist && p.push));
+
+        // Mute OK and PROGRESS, try to mute ERROR too
+        send_server_cmd!(ws, SetMessagePrefs, SetMessagePrefs { prefs: vec![
+            proto::UserMessagePref { r#type: Type::Ok.into(), persist: true, push: false },
+            proto::UserMessagePref { r#type: Type::Progress.into(), persist: false, push: false },
+            proto::UserMessagePref { r#type: Type::Error.into(), persist: false, push: false },
+        ]});
+        let mp = expect_client_cmd!(&mut ws, MessagePrefs);
+        let get = |t: Type| mp.prefs.iter().find(|p| p.r#type == t as i32).map(|p| (p.persist, p.push)).unwrap();
+        assert_eq!(get(Type::Ok), (true, false));
+        assert_eq!(get(Type::Progress), (false, false));
+        assert_eq!(get(Type::Error), (false, true));    // Errors are always pushed
+        assert_eq!(get(Type::MediaFileAdded), (true, true));
+
+        let umsg = |topic, msg: &str| UserMessage {
+            msg: msg.into(), user_id: Some("user.num1".into()), details: None, media_file_id: None,
+            topic, subtitle_id: None, progress: None, webhook_event: None };
+
+        // Muted OK is not pushed, but stored as unseen
+        ts.user_msg_tx.send(umsg(UserMessageTopic::Ok, "muted")).unwrap();
+        expect_no_msg(&mut ws).await;
+        let stored = models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num1", DBPaging::default()).unwrap();
+        assert_eq!(stored.iter().map(|m| (m.message.as_str(), m.seen)).collect::<Vec<_>>(), vec![("muted", false)]);
+
+        // Error is pushed but not stored
+        ts.user_msg_tx.send(umsg(UserMessageTopic::Error, "failed")).unwrap();
+        let m = expect_user_msg(&mut ws, Type::Error).await;
+        assert_eq!(m.message, "failed");
+
+        // Same for messages sent by API handlers
+        send_server_cmd!(ws, SetEmailNotifications, SetEmailNotifications { mode: "none".into() });
+        expect_no_msg(&mut ws).await;
+        assert_eq!(models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num1", DBPaging::default()).unwrap().len(), 1);
+    }
+}
+
+
+fn make_test_jwt(kid: &str, claims: serde_json::Value) -> String
+{
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    let (alg, key) = match kid {
+        "test-rsa" => (Algorithm::RS256, EncodingKey::from_rsa_pem(include_bytes!("../tests/assets/jwt-test-rsa.pem")).unwrap()),
+        _ => (Algorithm::HS256, EncodingKey::from_secret(b"clapshot-test-secret-0123456789ab")),
+    };
+    encode(&Header { kid: Some(kid.into()), ..Header::new(alg) }, &claims, &key).unwrap()
+}
+
+#[test]
+fn test_jwt_key_rotation()
+{
+    use crate::api_server::jwt_auth::{JwtAuth, JwtAuthConfig};
+
+    let all_keys: serde_json::Value = serde_json::from_str(&std::fs::read_to_string("src/tests/assets/jwt-test-jwks.json").unwrap()).unwrap();
+    let only_kid = |kid: &str| serde_json::json!({ "keys": all_keys["keys"].as_array().unwrap().iter().filter(|k| k["kid"] == kid).collect::<Vec<_>>() });
+    let dir = tempfile::tempdir().unwrap();
+    let jwks = dir.path().join("jwks.json");
+    std::fs::write(&jwks, only_kid("test-hs").to_string()).unwrap();
+
+    let auth = JwtAuth::new(JwtAuthConfig { jwks_file: Some(jwks.clone()), ..Default::default() }).unwrap();
+    let exp = chrono::Utc::now().timestamp() + 600;
+    let hs_token = make_test_jwt("test-hs", serde_json::json!({ "sub": "user.num1", "exp": exp }));
+    let rsa_token = make_test_jwt("test-rsa", serde_json::json!({ "sub": "user.num2", "exp": exp }));
+    assert!(auth.validate(&hs_token).is_ok());
+
+    // Empty key set from the source doesn't replace working keys
+    std::fs::write(&jwks, r#"{"keys":[]}"#).unwrap();
+    assert!(auth.refresh_keys().is_err());
+    assert!(auth.validate(&hs_token).is_ok());
+
+    // Unknown key id reloads keys right away...
+    std::fs::write(&jwks, all_keys.to_string()).unwrap();
+    assert_eq!(auth.validate(&rsa_token).unwrap().user_id, "user.num2");
+
+    // ...but not again too soon after
+    std::fs::write(&jwks, only_kid("test-hs").to_string()).unwrap();
+    auth.refresh_keys().unwrap();
+    std::fs::write(&jwks, all_keys.to_string()).unwrap();
+    assert!(auth.validate(&rsa_token).is_err());
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_jwt_auth()
+{
+    use crate::api_server::jwt_auth::JwtAuthConfig;
+    use crate::api_server::test_utils::try_connect_ws;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+    let exp = chrono::Utc::now().timestamp() + 600;
+    let cfg = JwtAuthConfig {
+        jwks_file: Some("src/tests/assets/jwt-test-jwks.json".into()),
+        admin_claim: Some("realm_access.roles".into()),
+        ..Default::default()
+    };
+    let token = make_test_jwt("test-rsa", serde_json::json!({ "sub": "user.num1", "name": "JWT User", "exp": exp }));
+
+    api_test! {[ws, ts, jwt: (Some(cfg), token)]
+        // Connected with RS256 token in Authorization header. Check that it works.
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
+        expect_client_cmd!(&mut ws, ShowMessages);
+
+        // HS256 token in a cookie, with admin role in a nested claim. Proxy headers must be ignored.
+        let admin_token = make_test_jwt("test-hs", serde_json::json!({
+            "sub": "jwt.admin", "name": "Admin From JWT", "exp": exp, "realm_access": { "roles": ["staff", "admin"] } }));
+        let mut ws2 = try_connect_ws(&ts.ws_url, &[
+            ("Cookie", format!("other=1; clapshot_jwt={}", admin_token)),
+            ("X-Remote-User-Id", "user.num2".into())]).await.unwrap();
+        let w = expect_client_cmd!(&mut ws2, Welcome);
+        assert_eq!(w.user.as_ref().map(|u| (u.id.as_str(), u.name.as_str())), Some(("jwt.admin", "Admin From JWT")));
+        assert!(w.is_admin);
+
+        // Refused: no token, expired token, wrong signature, algorithm not matching the key
+        let expired = make_test_jwt("test-hs", serde_json::json!({ "sub": "user.num1", "exp": exp - 3600 }));
+        let hs_header = |kid: &str| Header { kid: Some(kid.into()), ..Header::new(Algorithm::HS256) };
+        let claims = serde_json::json!({ "sub": "user.num1", "exp": exp });
+        let forged = encode(&hs_header("test-hs"), &claims, &EncodingKey::from_secret(b"some other secret")).unwrap();
+        let alg_mismatch = encode(&hs_header("test-rsa"), &claims, &EncodingKey::from_secret(b"clapshot-test-secret-0123456789ab")).unwrap();
+
+        assert!(try_connect_ws(&ts.ws_url, &[("X-Remote-User-Id", "user.num1".into())]).await.is_err());
+        for bad in [expired, forged, alg_mismatch, "not-a-token".to_string()] {
+            assert!(try_connect_ws(&ts.ws_url, &[("Authorization", format!("Bearer {}", bad))]).await.is_err(), "Token was accepted: {}", bad);
+        }
+
+        // Uploads need a token, too
+        let url = format!("http://127.0.0.1:{}/api/upload", ts.port);
+        let form = multipart::Form::new().part("fileupload", multipart::Part::stream("x").file_name("x.mp4").mime_str("video/mp4").unwrap());
+        let response = Client::new().post(url).header("X-Remote-User-Id", "user.num1").multipart(form).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_tokens()
+{
+    use crate::api_server::test_utils::{connect_client_ws_with_headers, try_connect_ws};
+    use crate::database::DbQueryByUser;
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    api_test! {[ws, ts]
+        let bearer = |t: &str| [("Authorization", format!("Bearer {}", t))];
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+
+        // Bad scope and missing name are refused
+        send_server_cmd!(ws, CreateApiToken, CreateApiToken{ name: "x".into(), scopes: vec!["everything".into()], expires_in_days: None });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, CreateApiToken, CreateApiToken{ name: " ".into(), scopes: vec!["upload".into()], expires_in_days: None });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Create two tokens. Only hashes are stored.
+        let mut created = vec![];
+        for (name, scopes) in [("render farm", vec!["upload", "comments:read"]), ("comment bot", vec!["comments:write"])] {
+            send_server_cmd!(ws, CreateApiToken, CreateApiToken{ name: name.into(), scopes: scopes.iter().map(|s| s.to_string()).collect(), expires_in_days: Some(30) });
+            let c = expect_client_cmd!(&mut ws, ApiTokenCreated);
+            expect_client_cmd!(&mut ws, ShowApiTokens);
+            created.push((c.token, c.info.unwrap()));
+        }
+        let [(reader_tok, reader), (writer_tok, writer)] = <[_; 2]>::try_from(created).unwrap();
+        assert!(reader_tok.starts_with("clapshot_"));
+        assert_eq!(reader.scopes, vec!["upload", "comments:read"]);
+        assert!(reader.expires.is_some());
+        let stored = models::ApiToken::get_by_user(&mut ts.db.conn().unwrap(), "user.num1", crate::database::DBPaging::default()).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|t| t.token_hash != reader_tok && t.token_hash != writer_tok));
+
+        send_server_cmd!(ws, ListApiTokens, ListApiTokens{});
+        assert_eq!(expect_client_cmd!(&mut ws, ShowApiTokens).tokens.len(), 2);
+
+        // Token session gets the token owner's identity, and is limited to its scopes
+        let mut tws = try_connect_ws(&ts.ws_url, &bearer(&reader_tok)).await.unwrap();
+        let w = expect_client_cmd!(&mut tws, Welcome);
+        assert_eq!(w.user.unwrap().id, "user.num1");
+        expect_client_cmd!(&mut tws, DefineActions);
+        open_media_file(&mut tws, &mf.id).await;
+        send_server_cmd!(tws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Not allowed".into(), ..Default::default()});
+        expect_user_msg(&mut tws, proto::user_message::Type::Error).await;
+        send_server_cmd!(tws, CreateApiToken, CreateApiToken{ name: "escalate".into(), scopes: vec!["comments:write".into()], expires_in_days: None });
+        expect_user_msg(&mut tws, proto::user_message::Type::Error).await;
+        assert!(models::ApiToken::get(&mut ts.db.conn().unwrap(), &reader.id.parse().unwrap()).unwrap().last_used.is_some());
+        for _ in 0..2 { expect_user_msg(&mut ws, proto::user_message::Type::Error).await; }  // User errors go to all of user's sessions
+
+        let mut wws = connect_client_ws_with_headers(&ts.ws_url, &bearer(&writer_tok)).await;
+        send_server_cmd!(wws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "From CI".into(), ..Default::default()});
+        assert_eq!(expect_client_cmd!(&mut tws, AddComments).comments[0].user_id.as_deref(), Some("user.num1"));
+
+        // Uploads need 'upload' scope
+        let url = format!("http://127.0.0.1:{}/api/upload", ts.port);
+        let upload = |tok: String| {
+            let form = multipart::Form::new().part("fileupload", multipart::Part::stream("x").file_name("x.mp4").mime_str("video/mp4").unwrap());
+            Client::new().post(&url).bearer_auth(tok).multipart(form).send()
+        };
+        assert_eq!(upload(writer_tok.clone()).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(upload(reader_tok.clone()).await.unwrap().status(), reqwest::StatusCode::OK);
+
+        // Unknown, expired and revoked tokens are refused
+        assert!(try_connect_ws(&ts.ws_url, &bearer("clapshot_0123")).await.is_err());
+        let (exp_tok, exp_hash) = crate::api_server::api_tokens::generate_token();
+        models::ApiToken::insert(&mut ts.db.conn().unwrap(), &models::ApiTokenInsert {
+            user_id: "user.num1".into(), name: "old".into(), token_hash: exp_hash, scopes: "comments:read".into(),
+            expires: Some(chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1)) }).unwrap();
+        assert!(try_connect_ws(&ts.ws_url, &bearer(&exp_tok)).await.is_err());
+
+        // Token expiring while connected ends the session on next command
+        let (short_tok, short_hash) = crate::api_server::api_tokens::generate_token();
+        models::ApiToken::insert(&mut ts.db.conn().unwrap(), &models::ApiTokenInsert {
+            user_id: "user.num1".into(), name: "short".into(), token_hash: short_hash, scopes: "comments:read".into(),
+            expires: Some(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1)) }).unwrap();
+        let mut sws = connect_client_ws_with_headers(&ts.ws_url, &bearer(&short_tok)).await;
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        send_server_cmd!(sws, ListMyMessages, ListMyMessages::default());
+        assert_eq!(expect_client_cmd!(&mut sws, Error).msg, "API token expired");
+        assert!(matches!(sws.next().await, Some(Ok(Message::Close(_))) | None | Some(Err(_))));
+
+        // Revoking closes sessions using the token, and refuses new ones
+        send_server_cmd!(ws, RevokeApiToken, RevokeApiToken{ id: reader.id.clone() });
+        expect_client_cmd!(&mut ws, ShowApiTokens);
+        loop {
+            match tws.next().await {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+        assert!(try_connect_ws(&ts.ws_url, &bearer(&reader_tok)).await.is_err());
+
+        // Can't revoke other users' tokens
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws2, RevokeApiToken, RevokeApiToken{ id: writer.id.clone() });
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_share_links()
+{
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::api_server::test_utils::try_connect_ws;
+
+    api_test! {[ws, ts]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        let other = ts.media_files.iter().find(|m| m.user_id == "user.num1" && m.id != mf.id).unwrap().clone();
+        let guest_url = |key: &str, extra: &str| format!("{}?share={}&guest_id=0123456789abcdef&guest_name=Ext{}", ts.ws_url, key, extra);
+        let share_info = |key: String| Client::new().get(format!("http://127.0.0.1:{}/api/share_info?share={}", ts.port, key)).send();
+
+        // Can't share other users' files
+        let user2_mf = ts.media_files.iter().find(|m| m.user_id == "user.num2").unwrap().clone();
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{ media_file_ids: vec![user2_mf.id.clone()], can_comment: true, expires_in_hours: 1, password: None });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // View only link
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{ media_file_ids: vec![mf.id.clone()], can_comment: false, expires_in_hours: 24, password: None });
+        let links = expect_client_cmd!(&mut ws, ShowShareLinks).links;
+        assert_eq!(links.len(), 1);
+        let view_link = links[0].clone();
+        assert!(!view_link.has_password);
+        let view_key = view_link.url.as_deref().unwrap().split("share=").nth(1).unwrap().to_string();
+        let info: serde_json::Value = share_info(view_key.clone()).await.unwrap().json().await.unwrap();
+        assert_eq!(info["password_required"], false);
+
+        // Guest gets a synthetic identity, and can only open the linked file
+        let mut gws = try_connect_ws(&guest_url(&view_key, ""), &[]).await.unwrap();
+        let w = expect_client_cmd!(&mut gws, Welcome);
+        let guest = w.user.unwrap();
+        assert_eq!(guest.id, crate::api_server::share_links::guest_user_id(&models::ShareLink::get(&mut ts.db.conn().unwrap(), &view_link.id.parse().unwrap()).unwrap(), "0123456789abcdef"));
+        assert!(guest.id.starts_with(&format!("guest.{}.", view_link.id)) && !guest.id.contains("0123456789abcdef"));
+        assert_eq!(guest.name, "Ext (guest)");
+        assert_eq!(w.guest_link.unwrap().id, view_link.id);
+        expect_client_cmd!(&mut gws, DefineActions);
+
+        open_media_file(&mut gws, &mf.id).await;
+        send_server_cmd!(gws, OpenMediaFile, OpenMediaFile{ media_file_id: other.id.clone() });
+        expect_user_msg(&mut gws, proto::user_message::Type::Error).await;
+        send_server_cmd!(gws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Not allowed".into(), ..Default::default() });
+        expect_user_msg(&mut gws, proto::user_message::Type::Error).await;
+        send_server_cmd!(gws, ListShareLinks, ListShareLinks{ media_file_id: None });
+        expect_user_msg(&mut gws, proto::user_message::Type::Error).await;
+
+        // Password protected link with commenting
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{ media_file_ids: vec![mf.id.clone()], can_comment: true, expires_in_hours: 24, password: Some("hunter2".into()) });
+        let links = expect_client_cmd!(&mut ws, ShowShareLinks).links;
+        let pw_link = links.iter().find(|l| l.has_password).unwrap().clone();
+        let pw_key = pw_link.url.as_deref().unwrap().split("share=").nth(1).unwrap().to_string();
+        let info: serde_json::Value = share_info(pw_key.clone()).await.unwrap().json().await.unwrap();
+        assert_eq!(info["password_required"], true);
+        assert!(try_connect_ws(&guest_url(&pw_key, ""), &[]).await.is_err());
+        assert!(try_connect_ws(&guest_url(&pw_key, "&share_password=wrong"), &[]).await.is_err());
+
+        let mut cws = try_connect_ws(&guest_url(&pw_key, "&share_password=hunter2"), &[]).await.unwrap();
+        expect_client_cmd!(&mut cws, Welcome);
+        expect_client_cmd!(&mut cws, DefineActions);
+        open_media_file(&mut cws, &mf.id).await;
+        send_server_cmd!(cws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Nice cut".into(), ..Default::default() });
+        let c = expect_client_cmd!(&mut cws, AddComments).comments[0].clone();
+        assert!(c.user_id.as_deref().is_some_and(|u| u.starts_with(&format!("guest.{}.", pw_link.id)) && !u.contains("0123456789abcdef")));
+
+        // Owner can list links per media file
+        send_server_cmd!(ws, ListShareLinks, ListShareLinks{ media_file_id: Some(other.id.clone()) });
+        assert!(expect_client_cmd!(&mut ws, ShowShareLinks).links.is_empty());
+
+        // Unknown and expired links are refused
+        assert!(try_connect_ws(&guest_url("0123", ""), &[]).await.is_err());
+        assert_eq!(share_info("0123".into()).await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+        let expired = models::ShareLink::insert(&mut ts.db.conn().unwrap(), &models::ShareLinkInsert {
+            link_key: "expiredkey".into(), user_id: "user.num1".into(), media_file_ids: mf.id.clone(), can_comment: true,
+            password_hash: None, expires: chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1) }).unwrap();
+        assert!(try_connect_ws(&guest_url(&expired.link_key, ""), &[]).await.is_err());
+
+        // Revoking disconnects guests using the link
+        send_server_cmd!(ws, RevokeShareLink, RevokeShareLink{ id: view_link.id.clone() });
+        expect_client_cmd!(&mut ws, ShowShareLinks);
+        loop {
+            match gws.next().await {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+        assert!(try_connect_ws(&guest_url(&view_key, ""), &[]).await.is_err());
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_share_link_password_throttling()
+{
+    use crate::api_server::rate_limit::RateLimitConfig;
+    use crate::api_server::test_utils::try_connect_ws;
+    use tokio_tungstenite::tungstenite::Error as WsError;
+
+    let mut cfg = RateLimitConfig::default();
+    cfg.set_from_spec("auth_failure:user=3/3600").unwrap();
+    cfg.set_from_spec("auth_failure:ip=5/3600").unwrap();
+
+    api_test! {[ws, ts, rate_limits: cfg]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        let mut keys = vec![];
+        for pw in ["hunter2", "swordfish"] {
+            send_server_cmd!(ws, CreateShareLink, CreateShareLink{ media_file_ids: vec![mf.id.clone()], can_comment: false, expires_in_hours: 1, password: Some(pw.into()) });
+            let links = expect_client_cmd!(&mut ws, ShowShareLinks).links;
+            keys.push(links.last().unwrap().url.as_deref().unwrap().split("share=").nth(1).unwrap().to_string());
+        }
+        let url = |key: &str, pw: &str| format!("{}?share={}&share_password={}", ts.ws_url, key, pw);
+        let status = |res: Result<_, WsError>| match res {
+            Ok(_) => 101,
+            Err(WsError::Http(r)) => r.status().as_u16(),
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        };
+
+        // Per link: after 3 failures, even the right password is refused (without checking it)
+        for _ in 0..3 { assert_eq!(status(try_connect_ws(&url(&keys[0], "wrong"), &[]).await), 401); }
+        assert_eq!(status(try_connect_ws(&url(&keys[0], "hunter2"), &[]).await), 429);
+
+        // Per IP: failures on another link add up
+        assert_eq!(status(try_connect_ws(&url(&keys[1], "swordfish"), &[]).await), 101);
+        for _ in 0..2 { assert_eq!(status(try_connect_ws(&url(&keys[1], "wrong"), &[]).await), 401); }
+        assert_eq!(status(try_connect_ws(&url(&keys[1], "swordfish"), &[]).await), 429);
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_media_file_grants()
+{
+    use proto::client::client_to_server_cmd::grant_media_file_access::Grantee;
+    use proto::media_file_grant::Role;
+    use crate::api_server::test_utils::{connect_client_ws_with_headers, user_auth_headers};
+    use crate::database::{DBPaging, DbQueryByUser};
+
+    api_test! {[ws, ts]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        let grant = |grantee: Grantee, role: Role| GrantMediaFileAccess{ media_file_id: mf.id.clone(), grantee: Some(grantee), role: role.into() };
+        let has_shared_section = |p: &proto::client::server_to_client_cmd::ShowPage| p.page_items.iter().any(|i|
+            matches!(&i.item, Some(proto::page_item::Item::Html(h)) if h.contains("Shared with you")));
+
+        // Without grants, anyone can view
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        open_media_file(&mut ws2, &mf.id).await;
+        send_server_cmd!(ws2, OpenNavigationPage, OpenNavigationPage{ page_id: None });
+        assert!(!has_shared_section(&expect_client_cmd!(&mut ws2, ShowPage)));
+
+        // Grant makes the file restricted, and shows up on grantee's home page
+        send_server_cmd!(ws, GrantMediaFileAccess, grant(Grantee::UserId("user.num2".into()), Role::Viewer));
+        let grants = expect_client_cmd!(&mut ws, ShowMediaFileGrants).grants;
+        assert_eq!(grants.len(), 1);
+        let viewer_grant_id = grants[0].id.clone();
+        send_server_cmd!(ws2, OpenNavigationPage, OpenNavigationPage{ page_id: None });
+        assert!(has_shared_section(&expect_client_cmd!(&mut ws2, ShowPage)));
+
+        let mut ws3 = connect_client_ws(&ts.ws_url, "user.num3").await;
+        send_server_cmd!(ws3, OpenMediaFile, OpenMediaFile{ media_file_id: mf.id.clone() });
+        expect_user_msg(&mut ws3, proto::user_message::Type::Error).await;
+        drop(ws3);
+
+        // Mentions don't leak restricted files to users who can't view them
+        send_server_cmd!(ws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Secret, @user.num3 @user.num2".into(), ..Default::default() });
+        assert!(expect_client_cmd!(&mut ws2, ShowMessages).msgs[0].message.contains("mentioned you"));
+        send_server_cmd!(ws, OpenNavigationPage, OpenNavigationPage{ page_id: None });
+        expect_client_cmd!(&mut ws, ShowPage);  // AddComment handler done
+        let mentions = |user: &str| models::Message::get_by_user(&mut ts.db.conn().unwrap(), user, DBPaging::default()).unwrap()
+            .iter().filter(|m| m.message.contains("mentioned you")).count();
+        assert_eq!(mentions("user.num3"), 0);
+        assert_eq!(mentions("user.num2"), 1);
+
+        // Viewers can't comment
+        open_media_file(&mut ws2, &mf.id).await;
+        send_server_cmd!(ws2, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Not allowed".into(), ..Default::default() });
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws2, OpenNavigationPage, OpenNavigationPage{ page_id: None });
+        expect_client_cmd!(&mut ws2, ShowPage);
+
+        // Group grants, with groups from auth headers
+        send_server_cmd!(ws, GrantMediaFileAccess, grant(Grantee::Group("reviewers".into()), Role::Commenter));
+        assert_eq!(expect_client_cmd!(&mut ws, ShowMediaFileGrants).grants.len(), 2);
+        let mut hdrs = user_auth_headers("user.num3", false);
+        hdrs.push(("HTTP_X_REMOTE_USER_GROUPS", "staff, reviewers".into()));
+        let mut ws4 = connect_client_ws_with_headers(&ts.ws_url, &hdrs).await;
+        open_media_file(&mut ws4, &mf.id).await;
+        send_server_cmd!(ws4, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Reviewed".into(), ..Default::default() });
+        assert_eq!(expect_client_cmd!(&mut ws4, AddComments).comments[0].user_id.as_deref(), Some("user.num3"));
+
+        // Only the owner (or admin) can manage grants
+        send_server_cmd!(ws2, GrantMediaFileAccess, grant(Grantee::UserId("user.num2".into()), Role::Editor));
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws2, ListMediaFileGrants, ListMediaFileGrants{ media_file_id: mf.id.clone() });
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+
+        // Re-granting replaces the earlier role
+        send_server_cmd!(ws, GrantMediaFileAccess, grant(Grantee::Group("reviewers".into()), Role::Editor));
+        let grants = expect_client_cmd!(&mut ws, ShowMediaFileGrants).grants;
+        assert_eq!(grants.len(), 2);
+        assert!(grants.iter().any(|g| g.role() == Role::Editor));
+
+        // Revoked grantee loses access, as the file is still restricted
+        send_server_cmd!(ws, RevokeMediaFileAccess, RevokeMediaFileAccess{ id: viewer_grant_id });
+        assert_eq!(expect_client_cmd!(&mut ws, ShowMediaFileGrants).grants.len(), 1);
+        send_server_cmd!(ws2, OpenMediaFile, OpenMediaFile{ media_file_id: mf.id.clone() });
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_authz_policy()
+{
+    use crate::api_server::authz_policy::AuthzPolicyConfig;
+
+    let dir = assert_fs::TempDir::new().unwrap();
+    let path = dir.path().join("policy.rhai");
+    std::fs::write(&path, r#"
+        fn authorize(req) {
+            if req.op == "rename" && !("leads" in req.groups) { return "Only project leads can rename"; }
+            if req.topic == "comment" && req.comment.author != req.user_id { return false; }
+            if req.topic == "media_file" && req.op == "delete" && req.media_file.media_type == "video" { return false; }
+        }"#).unwrap();
+    let cfg = AuthzPolicyConfig { path, dry_run: false };
+
+    api_test! {[ws, ts, authz_policy: Some(cfg)]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+
+        // Policy denies even the owner, with its own message
+        send_server_cmd!(ws, RenameMediaFile, RenameMediaFile{ media_file_id: mf.id.clone(), new_name: "New name".into() });
+        let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert_eq!(m.message, "Only project leads can rename");
+        send_server_cmd!(ws, DelMediaFile, DelMediaFile{ media_file_id: mf.id.clone() });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(models::MediaFile::get(&mut ts.db.conn().unwrap(), &mf.id).is_ok());
+
+        // No decision = server defaults
+        open_media_file(&mut ws, &mf.id).await;
+
+        // Admin can normally delete others' comments, but the policy overrides that
+        let cmt = ts.comments[0].clone();
+        let mut ws_adm = connect_client_ws_as(&ts.ws_url, "user.admin", true).await;
+        send_server_cmd!(ws_adm, DelComment, DelComment{ comment_id: cmt.id.to_string() });
+        expect_user_msg(&mut ws_adm, proto::user_message::Type::Error).await;
+        assert!(models::Comment::get(&mut ts.db.conn().unwrap(), &cmt.id).is_ok());
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_trusted_proxy()
+{
+    use crate::api_server::trusted_proxy::{parse_network, sign_headers, TrustedProxyConfig};
+    use crate::api_server::test_utils::{try_connect_ws, user_auth_headers};
+
+    // Shared secret
+    let cfg = TrustedProxyConfig {
+        networks: vec![parse_network("127.0.0.0/8").unwrap(), parse_network("::1").unwrap()],
+        secret: Some("proxy-s3cret".into()),
+        ..Default::default()
+    };
+    api_test! {[ws, ts, trusted_proxy: (Some(cfg), vec![("X-Clapshot-Proxy-Secret", "proxy-s3cret".to_string())])]
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
+        expect_client_cmd!(&mut ws, ShowMessages);
+
+        // Auth headers without or with wrong secret are refused
+        let mut hdrs = user_auth_headers("admin", true);
+        assert!(try_connect_ws(&ts.ws_url, &hdrs).await.is_err());
+        hdrs.push(("X-Clapshot-Proxy-Secret", "wrong".into()));
+        assert!(try_connect_ws(&ts.ws_url, &hdrs).await.is_err());
+
+        // No auth headers at all = default user
+        let mut ws2 = try_connect_ws(&ts.ws_url, &[]).await.unwrap();
+        let w = expect_client_cmd!(&mut ws2, Welcome);
+        assert_eq!(w.user.unwrap().id, "anonymous");
+        assert!(!w.is_admin);
+
+        // Same for uploads
+        let url = format!("http://127.0.0.1:{}/api/upload", ts.port);
+        let form = multipart::Form::new().part("fileupload", multipart::Part::stream("x").file_name("x.mp4").mime_str("video/mp4").unwrap());
+        let response = Client::new().post(&url).header("X-Remote-User-Id", "admin").multipart(form).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let form = multipart::Form::new().part("fileupload", multipart::Part::stream("x").file_name("x.mp4").mime_str("video/mp4").unwrap());
+        let response = Client::new().post(&url).header("X-Remote-User-Id", "user.num1").header("X-Clapshot-Proxy-Secret", "proxy-s3cret")
+            .multipart(form).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    // HMAC signed headers
+    let cfg = TrustedProxyConfig { hmac_key: Some("hmac-key".into()), ..Default::default() };
+    let ts_now = chrono::Utc::now().timestamp().to_string();
+    let sig = sign_headers("hmac-key", &ts_now, "user.num1", "Username for user.num1", "", "", None);
+    let sig_hdrs = vec![("X-Clapshot-Proxy-Timestamp", ts_now.clone()), ("X-Clapshot-Proxy-Signature", sig.clone())];
+    api_test! {[ws, ts, trusted_proxy: (Some(cfg), sig_hdrs.clone())]
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages::default());
+        expect_client_cmd!(&mut ws, ShowMessages);
+
+        // Tampered headers (admin flag added) don't match the signature
+        let mut hdrs = user_auth_headers("user.num1", true);
+        hdrs.extend(sig_hdrs.clone());
+        assert!(try_connect_ws(&ts.ws_url, &hdrs).await.is_err());
+    }
+}
+
+
 #[tokio::test]
 #[traced_test]
 async fn test_multipart_upload()
@@ -403,3 +1719,423 @@ async fn test_multipart_upload()
         assert_eq!(contents, file_body);
     }
 }
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_sessions()
+{
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::api_server::test_utils::{connect_client_ws_with_headers, user_auth_headers};
+
+    api_test! {[ws, ts]
+        let mut hdrs = user_auth_headers("user.num1", false);
+        hdrs.push(("User-Agent", "TestBrowser/1.0".into()));
+        hdrs.push(("X-Forwarded-For", "203.0.113.7".into()));
+        let mut ws2 = connect_client_ws_with_headers(&ts.ws_url, &hdrs).await;
+        let mut ws_other = connect_client_ws(&ts.ws_url, "user.num2").await;
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        open_media_file(&mut ws2, &mf.id).await;
+
+        // Users see their own sessions, with random ids
+        send_server_cmd!(ws, ListSessions, ListSessions{ all_users: false });
+        let ss = expect_client_cmd!(&mut ws, ShowSessions);
+        assert_eq!(ss.sessions.len(), 2);
+        assert!(ss.sessions.iter().all(|s| s.user.as_ref().unwrap().id == "user.num1"));
+        assert!(ss.sessions.iter().all(|s| uuid::Uuid::parse_str(&s.sid).is_ok()));
+        let s2 = ss.sessions.iter().find(|s| s.sid != ss.current_sid).unwrap().clone();
+        assert_eq!(s2.user_agent.as_deref(), Some("TestBrowser/1.0"));
+        assert_eq!(s2.media_file_id.as_deref(), Some(mf.id.as_str()));
+        assert_eq!(s2.remote_addr.as_deref(), Some("127.0.0.1"));    // X-Forwarded-For is only read from trusted proxies
+
+        // ...but not others', unless admin
+        send_server_cmd!(ws, ListSessions, ListSessions{ all_users: true });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        let mut ws_adm = connect_client_ws_as(&ts.ws_url, "user.admin", true).await;
+        send_server_cmd!(ws_adm, ListSessions, ListSessions{ all_users: true });
+        let all = expect_client_cmd!(&mut ws_adm, ShowSessions).sessions;
+        assert_eq!(all.len(), 4);
+        let other_sid = all.iter().find(|s| s.user.as_ref().unwrap().id == "user.num2").unwrap().sid.clone();
+
+        send_server_cmd!(ws, DisconnectSession, DisconnectSession{ sid: other_sid.clone(), all_users: false });
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // User can disconnect their own other session
+        send_server_cmd!(ws, DisconnectSession, DisconnectSession{ sid: s2.sid.clone(), all_users: false });
+        let ss = expect_client_cmd!(&mut ws, ShowSessions);
+        assert_eq!(ss.sessions.iter().map(|s| s.sid.clone()).collect::<Vec<_>>(), vec![ss.current_sid.clone()]);
+        loop {
+            match ws2.next().await {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+
+        // Admin can disconnect anyone's
+        send_server_cmd!(ws_adm, DisconnectSession, DisconnectSession{ sid: other_sid.clone(), all_users: true });
+        assert!(expect_client_cmd!(&mut ws_adm, ShowSessions).sessions.iter().all(|s| s.sid != other_sid));
+        loop {
+            match ws_other.next().await {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_rate_limits()
+{
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::api_server::rate_limit::RateLimitConfig;
+    use crate::api_server::test_utils::{try_connect_ws, user_auth_headers};
+
+    let mut cfg = RateLimitConfig { max_drawing_bytes: 1024, violations_before_disconnect: 3, ..Default::default() };
+    cfg.set_from_spec("comment:user=3/3600").unwrap();
+    cfg.set_from_spec("upload:user=1/3600").unwrap();
+
+    api_test! {[ws, ts, rate_limits: cfg]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        open_media_file(&mut ws, &mf.id).await;
+        let add = |comment: &str, drawing: Option<String>| AddComment{ media_file_id: mf.id.clone(), comment: comment.into(), drawing, ..Default::default() };
+
+        // Oversized drawing is refused
+        send_server_cmd!(ws, AddComment, add("Big", Some(format!("data:image/webp;base64,{}", "A".repeat(2000)))));
+        let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(m.message.contains("Drawing too large"));
+
+        for i in 0..3 {
+            send_server_cmd!(ws, AddComment, add(&format!("Comment {}", i), None));
+            expect_client_cmd!(&mut ws, AddComments);
+        }
+        send_server_cmd!(ws, AddComment, add("One too many", None));
+        let m = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(m.message.contains("Too many requests"));
+
+        // Others are not affected
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        open_media_file(&mut ws2, &mf.id).await;
+        send_server_cmd!(ws2, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Hello".into(), ..Default::default() });
+        expect_client_cmd!(&mut ws2, AddComments);
+
+        // Uploads
+        let url = format!("http://127.0.0.1:{}/api/upload", ts.port);
+        let upload = || {
+            let form = multipart::Form::new().part("fileupload", multipart::Part::stream("x").file_name("x.mp4").mime_str("video/mp4").unwrap());
+            Client::new().post(&url).header("X-Remote-User-Id", "user.num2").multipart(form).send()
+        };
+        assert_eq!(upload().await.unwrap().status(), reqwest::StatusCode::OK);
+        assert_eq!(upload().await.unwrap().status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+        // Third violation disconnects, and user can't reconnect for a while
+        send_server_cmd!(ws, AddComment, add("Still going", None));
+        let mut got_error = false;
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(Message::Text(t))) => got_error |= t.contains("Too many requests"),
+                Some(Ok(_)) => continue,
+            }
+        }
+        assert!(got_error);
+        assert!(try_connect_ws(&ts.ws_url, &user_auth_headers("user.num1", false)).await.is_err());
+
+        // The IP gets banned too, so other user ids (e.g. new guest ids) don't help
+        assert!(try_connect_ws(&ts.ws_url, &user_auth_headers("user.num3", false)).await.is_err());
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_rest()
+{
+    use serde_json::Value;
+
+    api_test! {[ws, ts]
+        let api = |path: &str| format!("http://127.0.0.1:{}/api/v1/{}", ts.port, path);
+        let get = |path: &str, user: &str| Client::new().get(api(path)).header("X-Remote-User-Id", user).send();
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        let mf_path = format!("media_files/{}", mf.id);
+
+        // OpenAPI description doesn't need authentication
+        let spec: Value = Client::new().get(api("openapi.json")).send().await.unwrap().json().await.unwrap();
+        assert!(spec["paths"]["/media_files/{id}/comments"]["post"].is_object());
+        assert_eq!(Client::new().put(api(&mf_path)).send().await.unwrap().status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(get("nothing_here", "user.num1").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+
+        // List and get media files
+        let res: Value = get("media_files", "user.num1").await.unwrap().json().await.unwrap();
+        let own = ts.media_files.iter().filter(|m| m.user_id == "user.num1").count();
+        assert_eq!(res["media_files"].as_array().unwrap().len(), own);
+        let res: Value = get(&mf_path, "user.num2").await.unwrap().json().await.unwrap();
+        assert_eq!(res["id"], mf.id);
+
+        // Shared files are listed newest first across pages, regardless of grant order
+        {
+            use crate::database::DbUpdate;
+            let conn = &mut ts.db.conn().unwrap();
+            let mut shared = ts.media_files.iter().filter(|m| m.user_id == "user.num2").cloned().collect::<Vec<_>>();
+            for (i, m) in shared.iter_mut().enumerate() {
+                m.added_time = chrono::NaiveDateTime::default() + chrono::Duration::days(i as i64);
+                models::MediaFile::update_many(conn, &[m.clone()]).unwrap();
+                models::MediaFileGrant::insert(conn, &models::MediaFileGrantInsert { media_file_id: m.id.clone(),
+                    user_id: Some("user.num1".into()), group_name: None, role: "viewer".into(), granted_by: None }).unwrap();
+            }
+            let mut pages = vec![];
+            for page in 0..shared.len() {
+                let res: Value = get(&format!("media_files?shared=true&page_size=1&page={}", page), "user.num1").await.unwrap().json().await.unwrap();
+                pages.push(res["media_files"][0]["id"].as_str().unwrap().to_string());
+            }
+            assert_eq!(pages, shared.iter().rev().map(|m| m.id.clone()).collect::<Vec<_>>());
+        }
+        assert_eq!(get("media_files/nonexistent", "user.num1").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+        let res: Value = get(&format!("{}/status", mf_path), "user.num1").await.unwrap().json().await.unwrap();
+        assert!(res["state"].is_string());
+
+        // Rename: owner only, name validated
+        let rename = |user: &str, title: &str| Client::new().patch(api(&mf_path)).header("X-Remote-User-Id", user)
+            .json(&serde_json::json!({ "title": title })).send();
+        assert_eq!(rename("user.num2", "Hijacked").await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(rename("user.num1", " !! ").await.unwrap().status(), reqwest::StatusCode::BAD_REQUEST);
+        let res: Value = rename("user.num1", "Renamed over REST").await.unwrap().json().await.unwrap();
+        assert_eq!(res["title"], "Renamed over REST");
+
+        // Add comment. Viewers get it over websocket.
+        open_media_file(&mut ws, &mf.id).await;
+        let comments_path = format!("{}/comments", mf_path);
+        let res = Client::new().post(api(&comments_path)).header("X-Remote-User-Id", "user.num2")
+            .json(&serde_json::json!({ "comment": "Hello from REST", "timecode": "00:00:01:00" })).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+        let added: Value = res.json().await.unwrap();
+        assert_eq!(added["userId"], "user.num2");
+        assert_eq!(expect_client_cmd!(&mut ws, AddComments).comments[0].comment, "Hello from REST");
+        let res = Client::new().post(api(&comments_path)).header("X-Remote-User-Id", "user.num2")
+            .json(&serde_json::json!({ "comment": "Orphan", "parent_id": "999999" })).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // Get comments as JSON and exported
+        let res: Value = get(&comments_path, "user.num1").await.unwrap().json().await.unwrap();
+        assert!(res["comments"].as_array().unwrap().iter().any(|c| c["id"] == added["id"]));
+        let csv = get(&format!("{}?format=csv", comments_path), "user.num1").await.unwrap().text().await.unwrap();
+        assert!(csv.starts_with("id,parent_id,timecode,author,comment,created") && csv.contains("Hello from REST"));
+        let edl = get(&format!("{}?format=edl", comments_path), "user.num1").await.unwrap().text().await.unwrap();
+        assert!(edl.contains("* COMMENT: Hello from REST"));
+        assert_eq!(get(&format!("{}?format=doc", comments_path), "user.num1").await.unwrap().status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // Messages
+        let res: Value = get("messages?unseen_only=true", "user.num1").await.unwrap().json().await.unwrap();
+        assert!(res["messages"].is_array() && res["total_count"].is_number());
+
+        // API tokens are limited to their scopes
+        send_server_cmd!(ws, CreateApiToken, CreateApiToken{ name: "reader".into(), scopes: vec!["comments:read".into()], expires_in_days: None });
+        let tok = expect_client_cmd!(&mut ws, ApiTokenCreated).token;
+        assert_eq!(Client::new().get(api(&comments_path)).bearer_auth(&tok).send().await.unwrap().status(), reqwest::StatusCode::OK);
+        let res = Client::new().post(api(&comments_path)).bearer_auth(&tok).json(&serde_json::json!({ "comment": "x" })).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(Client::new().delete(api(&mf_path)).bearer_auth(&tok).send().await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+
+        // Delete: locked files are refused, then owner only
+        models::MediaFile::set_locked(&mut ts.db.conn().unwrap(), &mf.id, true, Some("user.admin")).unwrap();
+        let delete = |user: &str| Client::new().delete(api(&mf_path)).header("X-Remote-User-Id", user).send();
+        assert_eq!(delete("user.num1").await.unwrap().status(), reqwest::StatusCode::CONFLICT);
+        models::MediaFile::set_locked(&mut ts.db.conn().unwrap(), &mf.id, false, Some("user.admin")).unwrap();
+        assert_eq!(delete("user.num2").await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(delete("user.num1").await.unwrap().status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(get(&mf_path, "user.num1").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_protobuf_framing()
+{
+    use futures_util::{SinkExt, StreamExt};
+    use prost::Message as _;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::api_server::test_utils::{try_connect_ws, user_auth_headers};
+    use proto::client::{server_to_client_cmd as s2c, client_to_server_cmd as c2s};
+
+    api_test! {[ws, ts]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        open_media_file(&mut ws, &mf.id).await;
+
+        async fn try_recv_pb(ws: &mut crate::api_server::test_utils::WsClient) -> Option<s2c::Cmd> {
+            match tokio::time::timeout(std::time::Duration::from_millis(500), ws.next()).await.ok()?.unwrap().unwrap() {
+                Message::Binary(data) => Some(proto::client::ServerToClientCmd::decode(&data[..]).unwrap().cmd.unwrap()),
+                m => panic!("Expected binary frame, got {:?}", m),
+            }
+        }
+        async fn recv_pb(ws: &mut crate::api_server::test_utils::WsClient) -> s2c::Cmd {
+            try_recv_pb(ws).await.expect("No message from server")
+        }
+        let send_pb = |cmd: c2s::Cmd| Message::binary(proto::client::ClientToServerCmd { cmd: Some(cmd), ..Default::default() }.encode_to_vec());
+
+        let mut hdrs = user_auth_headers("user.num1", false);
+        hdrs.push(("Sec-WebSocket-Protocol", "clapshot.protobuf, clapshot.json".into()));
+        let mut pws = try_connect_ws(&ts.ws_url, &hdrs).await.unwrap();
+        assert!(matches!(recv_pb(&mut pws).await, s2c::Cmd::Welcome(w) if w.user.as_ref().unwrap().id == "user.num1"));
+        assert!(matches!(recv_pb(&mut pws).await, s2c::Cmd::DefineActions(_)));
+
+        // Commands in binary frames, replies likewise
+        pws.send(send_pb(c2s::Cmd::OpenMediaFile(proto::client::client_to_server_cmd::OpenMediaFile { media_file_id: mf.id.clone() }))).await.unwrap();
+        assert!(matches!(recv_pb(&mut pws).await, s2c::Cmd::OpenMediaFile(o) if o.media_file.as_ref().unwrap().id == mf.id));
+        while let Some(cmd) = try_recv_pb(&mut pws).await {
+            assert!(matches!(cmd, s2c::Cmd::AddComments(_) | s2c::Cmd::MediaFileViewers(_)));
+        }
+        expect_client_cmd!(&mut ws, MediaFileViewerChanged);
+
+        pws.send(send_pb(c2s::Cmd::AddComment(proto::client::client_to_server_cmd::AddComment {
+            media_file_id: mf.id.clone(), comment: "Binary hello".into(), ..Default::default() }))).await.unwrap();
+        match recv_pb(&mut pws).await {
+            s2c::Cmd::AddComments(c) => assert_eq!(c.comments[0].comment, "Binary hello"),
+            c => panic!("Unexpected {:?}", c),
+        }
+        expect_client_cmd!(&mut ws, AddComments);
+
+        // Drawings are raw bytes for binary clients, data URIs for JSON clients
+        let webp = b"RIFF\x10\0\0\0WEBPVP8L\x04\0\0\0\x2f\0\0\0".to_vec();
+        pws.send(send_pb(c2s::Cmd::AddComment(proto::client::client_to_server_cmd::AddComment {
+            media_file_id: mf.id.clone(), comment: "With drawing".into(), drawing_webp: Some(webp.clone()), ..Default::default() }))).await.unwrap();
+        match recv_pb(&mut pws).await {
+            s2c::Cmd::AddComments(c) => {
+                assert_eq!(c.comments[0].drawing_webp.as_ref(), Some(&webp));
+                assert!(c.comments[0].drawing.is_none());
+            },
+            c => panic!("Unexpected {:?}", c),
+        }
+        let c = expect_client_cmd!(&mut ws, AddComments);
+        assert_eq!(c.comments[0].drawing, Some(crate::api_server::webp_data_uri(&webp)));
+
+        // Empty command gets an error message, garbage closes the session
+        pws.send(Message::binary(proto::client::ClientToServerCmd::default().encode_to_vec())).await.unwrap();
+        assert!(matches!(recv_pb(&mut pws).await, s2c::Cmd::ShowMessages(_)));
+        pws.send(Message::binary(vec![0xff, 0xff, 0xff])).await.unwrap();
+        assert!(matches!(recv_pb(&mut pws).await, s2c::Cmd::Error(_)));
+        while let Some(Ok(m)) = pws.next().await {
+            if m.is_close() { break; }
+        }
+        expect_client_cmd!(&mut ws, ShowMessages);     // Error about the empty command goes to all user's sessions
+        expect_client_cmd!(&mut ws, MediaFileViewerChanged);
+
+        // Other sessions are unaffected
+        send_server_cmd!(ws, ListMyMessages, ListMyMessages{ ..Default::default() });
+        expect_client_cmd!(&mut ws, ShowMessages);
+
+        // JSON stays the default, and can be requested explicitly
+        let mut hdrs = user_auth_headers("user.num1", false);
+        hdrs.push(("Sec-WebSocket-Protocol", "clapshot.json".into()));
+        let mut jws = try_connect_ws(&ts.ws_url, &hdrs).await.unwrap();
+        expect_client_cmd!(&mut jws, Welcome);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_ws_resume()
+{
+    use crate::api_server::test_utils::{read, try_connect_ws, user_auth_headers};
+    use crate::database::{DBPaging, DbQueryByUser};
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    api_test! {[ws, ts, ws_resume_grace: std::time::Duration::from_secs(30)]
+        let mf = ts.media_files.iter().find(|m| m.user_id == "user.num1").unwrap().clone();
+        let hdrs = user_auth_headers("user.num1", false);
+
+        // Read until quiet, returning the number of messages received
+        async fn drain(ws: &mut crate::api_server::test_utils::WsClient) -> u64 {
+            let mut n = 0;
+            while read(ws).await.is_some() { n += 1; }
+            n
+        }
+
+        let mut ws2 = try_connect_ws(&ts.ws_url, &hdrs).await.unwrap();
+        let w = expect_client_cmd!(&mut ws2, Welcome);
+        assert!(!w.resumed);
+        let token = w.resume_token.unwrap();
+        let mut received = drain(&mut ws2).await;      // Welcome is not counted
+        send_server_cmd!(ws2, OpenMediaFile, OpenMediaFile{ media_file_id: mf.id.clone() });
+        received += drain(&mut ws2).await;
+
+        // Drop connection without a close frame, and add a comment meanwhile
+        drop(ws2);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        send_server_cmd!(ws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "While away".into(), ..Default::default() });
+        drain(&mut ws).await;
+
+        // Bad tokens and other users get a new session
+        let url = |token: &str, received: u64| format!("{}?resume={}&received={}", ts.ws_url, token, received);
+        let mut other = try_connect_ws(&url("bad", 0), &hdrs).await.unwrap();
+        assert!(!expect_client_cmd!(&mut other, Welcome).resumed);
+        let mut other = try_connect_ws(&url(&token, received), &user_auth_headers("user.num2", false)).await.unwrap();
+        assert!(!expect_client_cmd!(&mut other, Welcome).resumed);
+
+        // Resume: missed comment is replayed, and media file link is still there
+        let mut ws3 = try_connect_ws(&url(&token, received), &hdrs).await.unwrap();
+        let w = expect_client_cmd!(&mut ws3, Welcome);
+        assert!(w.resumed);
+        assert_eq!(w.resume_token.as_deref(), Some(token.as_str()));
+        assert_eq!(expect_client_cmd!(&mut ws3, AddComments).comments[0].comment, "While away");
+        received += 1;
+        send_server_cmd!(ws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Welcome back".into(), ..Default::default() });
+        assert_eq!(expect_client_cmd!(&mut ws3, AddComments).comments[0].comment, "Welcome back");
+        received += 1;
+
+        // Taking over a live connection works too (e.g. server didn't notice the old one died)
+        let mut ws4 = try_connect_ws(&url(&token, received), &hdrs).await.unwrap();
+        assert!(expect_client_cmd!(&mut ws4, Welcome).resumed);
+        assert_eq!(drain(&mut ws4).await, 0);
+
+        // Different privileges get a new session, even for the same user
+        let mut adm = try_connect_ws(&url(&token, received), &user_auth_headers("user.num1", true)).await.unwrap();
+        let w = expect_client_cmd!(&mut adm, Welcome);
+        assert!(!w.resumed && w.is_admin);
+        send_server_cmd!(ws, AddComment, AddComment{ media_file_id: mf.id.clone(), comment: "Still here".into(), ..Default::default() });
+        assert_eq!(expect_client_cmd!(&mut ws4, AddComments).comments[0].comment, "Still here");
+        received += 1;
+
+        // Unknown message count can't be resumed
+        let mut ws5 = try_connect_ws(&url(&token, received + 100), &hdrs).await.unwrap();
+        assert!(!expect_client_cmd!(&mut ws5, Welcome).resumed);
+
+        // Disconnected sessions get a close code telling the client not to reconnect, and can't be resumed
+        send_server_cmd!(ws4, ListSessions, ListSessions{ all_users: false });
+        let sid = expect_client_cmd!(&mut ws4, ShowSessions).current_sid;
+        send_server_cmd!(ws, DisconnectSession, DisconnectSession{ sid, all_users: false });
+        let close = loop {
+            match ws4.next().await {
+                Some(Ok(Message::Close(c))) => break c,
+                Some(Ok(_)) => continue,
+                other => panic!("Expected close frame, got {:?}", other),
+            }
+        };
+        assert_eq!(close.map(|c| u16::from(c.code)), Some(crate::api_server::WS_CLOSE_DISCONNECTED));
+        let mut ws6 = try_connect_ws(&url(&token, received), &hdrs).await.unwrap();
+        assert!(!expect_client_cmd!(&mut ws6, Welcome).resumed);
+
+        // Messages to a user whose only session waits for resume are stored unseen, until delivered live
+        let hdrs3 = user_auth_headers("user.num3", false);
+        let mut ws7 = try_connect_ws(&ts.ws_url, &hdrs3).await.unwrap();
+        let token3 = expect_client_cmd!(&mut ws7, Welcome).resume_token.unwrap();
+        let received3 = drain(&mut ws7).await;
+        drop(ws7);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let umsg = |msg: &str| UserMessage { topic: UserMessageTopic::Ok, user_id: Some("user.num3".into()), msg: msg.into(), ..Default::default() };
+        let stored = || models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num3", DBPaging::default()).unwrap()
+            .into_iter().map(|m| (m.message, m.seen)).collect::<Vec<_>>();
+        ts.user_msg_tx.send(umsg("While away")).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(stored(), vec![("While away".to_string(), false)]);
+
+        let mut ws8 = try_connect_ws(&url(&token3, received3), &hdrs3).await.unwrap();
+        assert!(expect_client_cmd!(&mut ws8, Welcome).resumed);
+        assert_eq!(expect_user_msg(&mut ws8, proto::user_message::Type::Ok).await.message, "While away");
+        ts.user_msg_tx.send(umsg("Back")).unwrap();
+        expect_user_msg(&mut ws8, proto::user_message::Type::Ok).await;
+        assert!(stored().contains(&("Back".to_string(), true)));
+    }
+}
@@ -0,0 +1,160 @@
+//! Trusted reverse proxy checks for `X-Remote-User-*` auth headers.
+//!
+//! Without this, anyone who can reach the server port directly could claim to be
+//! any user. When configured, auth headers are only accepted from peers in the
+//! allowed networks, and optionally only if the proxy also proves itself with
+//! a shared secret header, or an HMAC signature over the auth headers.
+
+use std::net::IpAddr;
+
+use anyhow::{anyhow, bail};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use sha2::Sha256;
+
+type Res<T> = anyhow::Result<T>;
+
+/// Max difference between signature timestamp and server clock
+const MAX_SIGNATURE_AGE_SECS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxyConfig {
+    pub networks: Vec<IpNet>,           // Empty = any peer (then secret or HMAC key should be set)
+    pub secret: Option<String>,         // Must match `X-Clapshot-Proxy-Secret`
+    pub hmac_key: Option<String>,       // Key for `X-Clapshot-Proxy-Signature`
+}
+
+/// Auth related headers of a request, as sent by the proxy
+#[derive(Debug, Default)]
+pub struct ProxyHeaders {
+    pub user_id: Option<String>,
+    pub user_name: Option<String>,
+    pub is_admin: Option<String>,
+    pub groups: Option<String>,
+    pub cookies: Option<String>,
+    pub secret: Option<String>,
+    pub timestamp: Option<String>,
+    pub signature: Option<String>,
+}
+
+impl ProxyHeaders {
+    /// True if request tries to set user identity
+    pub fn has_identity(&self) -> bool {
+        self.user_id.is_some() || self.user_name.is_some() || self.is_admin.is_some() || self.groups.is_some()
+    }
+}
+
+/// Parse network list item. Plain addresses are taken as single host networks.
+pub fn parse_network(s: &str) -> Res<IpNet>
+{
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("Invalid network '{}', expected e.g. '10.0.0.0/8' or '::1'", s))
+}
+
+/// HMAC-SHA256 signature header value for auth headers: `sha256=<hex>`.
+/// Signed message is the timestamp and header values (empty if missing), separated by newlines:
+/// `<timestamp>\n<user id>\n<user name>\n<is admin>\n<clapshot cookies>`,
+/// followed by `\n<user groups>` if the groups header is set.
+pub fn sign_headers(key: &str, timestamp: &str, user_id: &str, user_name: &str, is_admin: &str, cookies: &str, groups: Option<&str>) -> String
+{
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    let mut fields = vec![timestamp, user_id, user_name, is_admin, cookies];
+    fields.extend(groups);
+    mac.update(fields.join("\n").as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+impl TrustedProxyConfig {
+
+    pub fn is_enabled(&self) -> bool {
+        !self.networks.is_empty() || self.secret.is_some() || self.hmac_key.is_some()
+    }
+
+    /// True if peer is in trusted networks (or no networks are configured)
+    pub fn is_trusted_peer(&self, peer: Option<IpAddr>) -> bool {
+        self.networks.is_empty() || peer.is_some_and(|p| self.networks.iter().any(|n| n.contains(&p.to_canonical())))
+    }
+
+    /// True if peer is in explicitly configured trusted networks
+    pub fn is_trusted_network_peer(&self, peer: Option<IpAddr>) -> bool {
+        !self.networks.is_empty() && self.is_trusted_peer(peer)
+    }
+
+    /// Check if auth headers can be trusted. Returns reason if not.
+    ///
+    /// # Arguments
+    /// * `peer` - Address of the connecting peer (proxy)
+    /// * `hdrs` - Auth headers of the request
+    /// * `now` - Current unix time, for signature age check
+    pub fn verify(&self, peer: Option<IpAddr>, hdrs: &ProxyHeaders, now: i64) -> Res<()>
+    {
+        if !self.is_trusted_peer(peer) {
+            match peer {
+                Some(peer) => bail!("peer {} is not a trusted proxy", peer.to_canonical()),
+                None => bail!("unknown peer address"),
+            }
+        }
+        if let Some(secret) = &self.secret {
+            let got = hdrs.secret.as_deref().ok_or_else(|| anyhow!("missing proxy secret header"))?;
+            if !constant_time_eq(got.as_bytes(), secret.as_bytes()) {
+                bail!("wrong proxy secret");
+            }
+        }
+        if let Some(key) = &self.hmac_key {
+            let ts = hdrs.timestamp.as_deref().ok_or_else(|| anyhow!("missing proxy signature timestamp"))?;
+            let sig = hdrs.signature.as_deref().ok_or_else(|| anyhow!("missing proxy signature"))?;
+            let ts_val: i64 = ts.parse().map_err(|_| anyhow!("invalid proxy signature timestamp"))?;
+            if (now - ts_val).abs() > MAX_SIGNATURE_AGE_SECS {
+                bail!("proxy signature timestamp too far from server time");
+            }
+            let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+            let expected = sign_headers(key, ts, &opt(&hdrs.user_id), &opt(&hdrs.user_name), &opt(&hdrs.is_admin), &opt(&hdrs.cookies), hdrs.groups.as_deref());
+            if !constant_time_eq(sig.as_bytes(), expected.as_bytes()) {
+                bail!("invalid proxy signature");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trusted_proxy_verify() {
+        let cfg = TrustedProxyConfig {
+            networks: vec![parse_network("10.1.0.0/16").unwrap(), parse_network("::1").unwrap()],
+            ..Default::default()
+        };
+        let hdrs = ProxyHeaders::default();
+        assert!(cfg.verify(Some("10.1.2.3".parse().unwrap()), &hdrs, 0).is_ok());
+        assert!(cfg.verify(Some("::ffff:10.1.2.3".parse().unwrap()), &hdrs, 0).is_ok());
+        assert!(cfg.verify(Some("::1".parse().unwrap()), &hdrs, 0).is_ok());
+        assert!(cfg.verify(Some("10.2.0.1".parse().unwrap()), &hdrs, 0).is_err());
+        assert!(cfg.verify(None, &hdrs, 0).is_err());
+        assert!(parse_network("10.0.0.0/33").is_err());
+
+        let cfg = TrustedProxyConfig { hmac_key: Some("key".into()), ..Default::default() };
+        let mut hdrs = ProxyHeaders {
+            user_id: Some("alice".into()),
+            timestamp: Some("1000".into()),
+            signature: Some(sign_headers("key", "1000", "alice", "", "", "", None)),
+            ..Default::default()
+        };
+        assert!(cfg.verify(None, &hdrs, 1000 + MAX_SIGNATURE_AGE_SECS).is_ok());
+        assert!(cfg.verify(None, &hdrs, 1001 + MAX_SIGNATURE_AGE_SECS).is_err());
+        hdrs.groups = Some("admins".into());
+        assert!(cfg.verify(None, &hdrs, 1000).is_err());
+        hdrs.signature = Some(sign_headers("key", "1000", "alice", "", "", "", Some("admins")));
+        assert!(cfg.verify(None, &hdrs, 1000).is_ok());
+        hdrs.is_admin = Some("1".into());
+        assert!(cfg.verify(None, &hdrs, 1000).is_err());
+    }
+}
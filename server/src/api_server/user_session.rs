@@ -2,6 +2,8 @@ use std::sync::Arc;
 use crate::{database::models::{self, MediaFile, Comment}, grpc::grpc_client::OrganizerConnection, client_cmd};
 
 use super::{WsMsgSender, server_state::ServerState, SendTo};
+use super::authz_policy::{AuthzPolicy, PolicyDecision, PolicyRequest};
+use crate::database::DbBasicQuery;
 use lib_clapshot_grpc::proto;
 use tracing::{debug, error};
 
@@ -86,6 +88,17 @@ pub struct UserSession {
 
     pub organizer: Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>,
     pub org_session: proto::org::UserSessionData,
+
+    pub token_scopes: Option<Vec<super::api_tokens::ApiTokenScope>>,  // Set if authenticated with an API token
+    pub api_token: Option<models::ApiToken>,                           // Same, for revocation and expiry
+    pub guest_link: Option<models::ShareLink>,                         // Set for guests from a share link
+    pub groups: Vec<String>,                                           // From authentication, for media file grants
+
+    pub remote_addr: Option<String>,
+    pub user_agent: Option<String>,
+    pub connected: chrono::NaiveDateTime,
+    pub rate_limit_violations: u32,
+    pub parked: bool,       // Connection lost, waiting for the client to resume the session
 }
 
 impl UserSession {
@@ -114,12 +127,59 @@ fn try_send_error<'a>(user_id: &str, server: &ServerState, msg: String, details:
 
 
 
+/// Evaluate authz policy script, if one is configured.
+/// Returns None if policy doesn't decide, or if it's in dry run mode.
+/// Policy errors deny the action (fail closed).
+fn policy_authz<'a>(
+    policy: &AuthzPolicy,
+    session: &proto::org::UserSessionData,
+    desc: &str,
+    msg_on_deny: bool,
+    server: &ServerState,
+    op: &AuthzTopic<'a>,
+) -> Option<bool>
+{
+    let user_id = session.user.as_ref().map(|u| u.id.clone()).unwrap_or_default();
+    let comment_mf = match op {
+        AuthzTopic::Comment(c, _) => server.db.conn().ok().and_then(|mut conn| models::MediaFile::get(&mut conn, &c.media_file_id).ok()),
+        _ => None,
+    };
+    let req = PolicyRequest::new(session, op, comment_mf.as_ref());
+    let decision = match policy.evaluate(&req) {
+        Ok(d) => d,
+        Err(e) => {
+            error!(desc, user=user_id, err=%e, "Error in authz policy. Denying by default.");
+            PolicyDecision::Deny(None)
+        }
+    };
+    if policy.is_dry_run() {
+        tracing::info!(desc, user=user_id, topic=req.topic, op=req.op, decision=?decision, "Authz policy dry run");
+        return None;
+    }
+    match decision {
+        PolicyDecision::Allow => {
+            debug!(desc, user=user_id, "Policy: Authorized OK");
+            Some(true)
+        },
+        PolicyDecision::Deny(msg) => {
+            debug!(desc, user=user_id, "Policy: Permission denied");
+            if msg_on_deny {
+                try_send_error(&user_id, server, msg.unwrap_or_else(|| format!("Permission denied: {}", desc)), None, op).ok();
+            }
+            Some(false)
+        },
+        PolicyDecision::NoDecision => None,
+    }
+}
+
 /// Check from Organizer if the user is allowed to perform given action.
 ///
 /// Some(true) = allowed
 /// Some(false) = denied
 /// None = default, as determined by the server - no Organizer or it doesn't support authz
 ///
+/// If an authz policy script is configured, it's evaluated first, and its decision (if any) is final.
+///
 /// If Organizer is not connected, returns None.
 /// If check fails and Organizer is connected, logs an error and denies the action.
 /// If the user is not allowed, an error message is sent to the user if `msg_on_deny` is true.
@@ -140,6 +200,12 @@ pub async fn org_authz<'a>(
         }
     };
 
+    if let Some(policy) = &server.authz_policy {
+        if let Some(res) = policy_authz(policy, session, desc, msg_on_deny, server, &op) {
+            return Some(res);
+        }
+    }
+
     let org = match &organizer {
         Some(org) => org,
         None => { return None; }
@@ -151,7 +217,7 @@ pub async fn org_authz<'a>(
         AuthzTopic::MediaFile(v, op) => authz_op::Op::MediaFileOp(
             authz_op::MediaFileOp {
                 op: op.into(),
-                media_file: Some(v.to_proto3(&server.url_base, vec![])) }), // omit subtitles for authz check
+                media_file: Some(v.to_proto3(&server.url_base, vec![], vec![])) }), // omit subtitles and approvals for authz check
         AuthzTopic::Comment(c, op) => authz_op::Op::CommentOp(
             authz_op::CommentOp {
                 op: op.into(),
@@ -211,6 +277,16 @@ pub async fn org_authz_with_default<'a>(
     if let Some(res) = org_authz(session, desc, msg_on_deny, server, organizer, op.clone()).await {
         if res { Ok(()) } else { Err(AuthzError::Denied) }
     } else {
+        // Built-in media file grants can restrict or extend the default
+        let default = match server.db.conn().map_err(anyhow::Error::from)
+            .and_then(|mut conn| super::media_grants::apply_grants_to_default(&mut conn, session, &op, default))
+        {
+            Ok(d) => d,
+            Err(e) => {
+                error!(desc, err=%e, "Error checking media file grants. Denying by default.");
+                false
+            }
+        };
         if default { Ok(()) } else {
             if msg_on_deny {
                 if let Some(ui) = &session.user {
@@ -2,14 +2,14 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::str::FromStr;
 use lib_clapshot_grpc::proto::client::ClientToServerCmd;
-use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddSubtitle, CollabReport, DelComment, DelMediaFile, DelSubtitle, EditComment, EditSubtitleInfo, JoinCollab, LeaveCollab, OpenMediaFile, OpenNavigationPage, RenameMediaFile, ReorderItems};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddSubtitle, CollabChat, CollabReport, CollabRequestControl, CollabSetPresenter, CreateApiToken, CreateShareLink, DelComment, DelMediaFile, DelMessages, DisconnectSession, DelSubtitle, EditComment, EditSubtitleInfo, ExportReviewReport, GetCommentHistory, GetMessagePrefs, GrantMediaFileAccess, ImportComments, JoinCollab, LeaveCollab, ListApiTokens, ListMediaFileGrants, ListMyMessages, ListSessions, ListShareLinks, OpenMediaFile, OpenNavigationPage, RenameMediaFile, ReorderItems, RestoreComment, RevokeApiToken, RevokeMediaFileAccess, RevokeShareLink, Search, SetEmailNotifications, SetMediaFileApproval, SetMediaFileLocked, SetMessagePrefs, SetMessagesSeen};
 use parking_lot::RwLock;
-type WsMsg = warp::ws::Message;
+use super::WsMsg;
 
 type Res<T> = anyhow::Result<T>;
 type MsgSender = tokio::sync::mpsc::UnboundedSender<WsMsg>;
@@ -32,6 +32,9 @@ use crate::api_server::server_state::ServerState;
 use crate::api_server::user_session::Topic;
 use crate::database::error::DBError;
 use crate::database::{models, DBPaging, DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate, DB};
+use crate::webhooks::WebhookEvent;
+use crate::grpc::grpc_client::OrganizerConnection;
+use crate::grpc::db_models::proto_msg_type_to_event_name;
 use crate::{client_cmd, optional_str_to_i32_or_tonic_error, send_user_error, send_user_ok, str_to_i32_or_tonic_error};
 
 use lib_clapshot_grpc::proto;
@@ -56,17 +59,32 @@ async fn get_media_file_or_send_error(media_file_id: Option<&str>, ses: &Option<
     }
 }
 
+/// If media file is locked (finalized), send an error to non-admin users.
+/// Return true if the operation should be rejected.
+fn reject_if_locked(v: &models::MediaFile, ses: &UserSession, server: &ServerState, action: &str) -> Res<bool> {
+    if v.locked.is_some() && !ses.is_admin {
+        send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("Cannot {}: media file is locked.", action),
+            format!("It was finalized{}. Ask an admin to unlock it.", v.locked_by.as_ref().map(|u| format!(" by {}", u)).unwrap_or_default()), true);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
 // ---------------------------------------------------------------------
 // Command handlers
 // ---------------------------------------------------------------------
 
 /// Send user a navigation page to browse the files / folders they have (and/or something else, if Organizer handles it).
 pub async fn msg_open_navigation_page(data: &OpenNavigationPage , ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    // Navigating away from the player, so the user is no longer viewing any media file
+    server.unlink_session_from_media_file(&ses.sid);
+    ses.cur_media_file_id = None;
+
     org_authz_with_default(&ses.org_session, "list media files", true, server,
         &ses.organizer, true, AuthzTopic::Other(None, authz_req::other_op::Op::ViewHome)).await?;
 
-    // Try to delegate request to Organizer.
-    if let Some(org) = &ses.organizer {
+    // Try to delegate request to Organizer. Guests only get the default listing of the files shared with them.
+    if let Some(org) = ses.organizer.as_ref().filter(|_| ses.guest_link.is_none()) {
         let req = proto::org::NavigatePageRequest {
             ses: Some(ses.org_session.clone()),
             page_id: data.page_id.clone(),
@@ -98,16 +116,39 @@ pub async fn msg_open_navigation_page(data: &OpenNavigationPage , ses: &mut User
 
     // Organizer didn't handle this, so return a default listing.
 
-    let mut media_files: Vec<proto::MediaFile> = Vec::new();
-    for m in models::MediaFile::get_by_user(&mut server.db.conn()?, &ses.user_id, DBPaging::default())? {
-        let subs = models::Subtitle::get_by_media_file(&mut server.db.conn()?, &m.id, DBPaging::default())?;
-        media_files.push(m.to_proto3(&server.url_base, subs));
-    }
+    let items = match &ses.guest_link {
+        Some(link) => models::MediaFile::get_many(&mut server.db.conn()?, &link.media_file_id_list())?,
+        None => models::MediaFile::get_by_user(&mut server.db.conn()?, &ses.user_id, DBPaging::default())?,
+    };
+    let to_proto = |items: Vec<models::MediaFile>| -> Res<Vec<proto::MediaFile>> {
+        let mut media_files: Vec<proto::MediaFile> = Vec::new();
+        for m in items {
+            let subs = models::Subtitle::get_by_media_file(&mut server.db.conn()?, &m.id, DBPaging::default())?;
+            media_files.push(m.to_proto3(&server.url_base, subs, m.get_approvals(&mut server.db.conn()?)?));
+        }
+        Ok(media_files)
+    };
+    let media_files = to_proto(items)?;
 
-    let h_txt = if media_files.is_empty() { "<h2>You have no media yet.</h2>" } else { "<h2>All your media files</h2>" };
+    let h_txt = match (&ses.guest_link, media_files.is_empty()) {
+        (Some(_), _) => "<h2>Shared with you</h2>",
+        (None, true) => "<h2>You have no media yet.</h2>",
+        (None, false) => "<h2>All your media files</h2>",
+    };
     let heading = proto::PageItem{ item: Some(proto::page_item::Item::Html(h_txt.into()))};
     let listing = crate::grpc::folder_listing_for_media_files(&media_files);
-    let page = vec![heading, listing];
+    let mut page = vec![heading, listing];
+
+    // Files that others have granted the user access to
+    if ses.guest_link.is_none() {
+        let conn = &mut server.db.conn()?;
+        let shared_ids = super::media_grants::media_files_shared_with(conn, &ses.user_id, &ses.groups)?;
+        if !shared_ids.is_empty() {
+            let shared = to_proto(models::MediaFile::get_many(conn, &shared_ids)?)?;
+            page.push(proto::PageItem{ item: Some(proto::page_item::Item::Html("<h2>Shared with you</h2>".into()))});
+            page.push(crate::grpc::folder_listing_for_media_files(&shared));
+        }
+    }
 
     server.emit_cmd(
         client_cmd!(ShowPage, { page_items: page, page_id: data.page_id.clone(), page_title: Some("Your Media".to_string())}),
@@ -136,7 +177,7 @@ pub async fn send_open_media_file_cmd(server: &ServerState, session_id: &str, me
     let conn = &mut server.db.conn()?;
     let v_db = models::MediaFile::get(conn, &media_file_id.into())?;
     let subs = models::Subtitle::get_by_media_file(conn, media_file_id, DBPaging::default())?;
-    let v = v_db.to_proto3(&server.url_base, subs);
+    let v = v_db.to_proto3(&server.url_base, subs, v_db.get_approvals(conn)?);
     if v.playback_url.is_none() {
         return Err(anyhow!("No playback file"));
     }
@@ -144,13 +185,17 @@ pub async fn send_open_media_file_cmd(server: &ServerState, session_id: &str, me
         client_cmd!(OpenMediaFile, {media_file: Some(v)}),
         super::SendTo::UserSession(session_id))?;
     let mut cmts = vec![];
-    for mut c in models::Comment::get_by_media_file(conn, media_file_id, DBPaging::default())? {
+    let all_cmts = models::Comment::get_by_media_file(conn, media_file_id, DBPaging::default())?;
+    for mut c in models::Comment::visible_to_users(all_cmts) {
         server.fetch_drawing_data_into_comment(&mut c).await?;
         cmts.push(c.to_proto3());
     }
     server.emit_cmd(
         client_cmd!(AddComments, {comments: cmts}),
         super::SendTo::UserSession(session_id))?;
+    server.emit_cmd(
+        client_cmd!(MediaFileViewers, {media_file_id: media_file_id.to_string(), viewers: server.get_media_file_viewers(media_file_id)}),
+        super::SendTo::UserSession(session_id))?;
     Ok(())
 }
 
@@ -165,53 +210,104 @@ pub async fn del_media_file_and_cleanup(media_file_id: &str, ses: Option<&mut Us
             let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
             org_authz_with_default(&ses.org_session, "delete media file", true, server, &ses.organizer,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Delete)).await?;
+            if reject_if_locked(&v, ses, server, "delete")? { return Ok(()); }
         }
 
-        models::MediaFile::delete(&mut server.db.conn()?, &v.id)?;
-        let mut details = format!("Added by '{}' on {}. Filename was {}.",
-            v.user_id.clone(),
-            v.added_time,
-            v.orig_filename.clone().unwrap_or_default());
+        let (details, cleanup_errors) = trash_media_file(&v, ses.as_ref().map(|s| s.user_id.as_str()), server)?;
 
-        fn backup_media_file_db_row(server: &ServerState, v: &models::MediaFile) -> Res<()> {
-            let backup_file = server.media_files_dir.join(v.id.clone()).join("db_backup.json");
-            if backup_file.exists() {
-                std::fs::remove_file(&backup_file)?;
-            }
-            let json_str = serde_json::to_string_pretty(&v)?;
-            std::fs::write(&backup_file, json_str)?;
-            Ok(())
+        if let Some(ses) = ses {
+            let media_type_str = v.media_type.unwrap_or("file".to_string()).to_title_case();
+            send_user_ok!(&ses.user_id, &server, Topic::MediaFile(&v.id),
+                if !cleanup_errors { format!("{} deleted.", media_type_str) } else { format!("{} deleted, but cleanup had errors.", media_type_str) },
+                details, true);
+        }
+    }
+    Ok(())
+}
+
+/// Delete media file from DB and move its files to trash. Authorization must be checked by caller.
+///
+/// Returns (details for the user, true if cleanup had errors).
+pub(crate) fn trash_media_file(v: &models::MediaFile, by_user: Option<&str>, server: &ServerState) -> Res<(String, bool)> {
+    let webhook_data = match server.webhooks {
+        Some(_) => Some(json!({ "media_file": crate::webhooks::media_file_json(&mut server.db.conn()?, v, &server.url_base)? })),
+        None => None,
+    };
+    models::MediaFile::delete(&mut server.db.conn()?, &v.id)?;
+    if let Some(data) = webhook_data {
+        server.emit_webhook(WebhookEvent::MediaFileDeleted, by_user, data);
+    }
+    let mut details = format!("Added by '{}' on {}. Filename was {}.",
+        v.user_id.clone(),
+        v.added_time,
+        v.orig_filename.clone().unwrap_or_default());
+
+    fn backup_media_file_db_row(server: &ServerState, v: &models::MediaFile) -> Res<()> {
+        let backup_file = server.media_files_dir.join(v.id.clone()).join("db_backup.json");
+        if backup_file.exists() {
+            std::fs::remove_file(&backup_file)?;
         }
+        let json_str = serde_json::to_string_pretty(&v)?;
+        std::fs::write(&backup_file, json_str)?;
+        Ok(())
+    }
 
-        fn move_media_file_to_trash(server: &ServerState, media_file_id: &str) -> Res<()>
-        {
-            let media_file_dir = server.media_files_dir.join(media_file_id);
-            let trash_dir = server.media_files_dir.join("trash");
-            if !trash_dir.exists() {
-                std::fs::create_dir(&trash_dir)?;
-            }
-            let hash_and_datetime = format!("{}_{}", media_file_id, chrono::Utc::now().format("%Y%m%d-%H%M%S"));
-            let media_file_trash_dir = trash_dir.join(hash_and_datetime);
-            std::fs::rename(&media_file_dir, &media_file_trash_dir)?;
-            Ok(())
+    fn move_media_file_to_trash(server: &ServerState, media_file_id: &str) -> Res<()>
+    {
+        let media_file_dir = server.media_files_dir.join(media_file_id);
+        let trash_dir = server.media_files_dir.join("trash");
+        if !trash_dir.exists() {
+            std::fs::create_dir(&trash_dir)?;
         }
+        let hash_and_datetime = format!("{}_{}", media_file_id, chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+        let media_file_trash_dir = trash_dir.join(hash_and_datetime);
+        std::fs::rename(&media_file_dir, &media_file_trash_dir)?;
+        Ok(())
+    }
 
-        let mut cleanup_errors = false;
-        if let Err(e) = backup_media_file_db_row(server, &v) {
-            details.push_str(&format!(" WARNING: DB row backup failed: {:?}.", e));
-            cleanup_errors = true;
+    let mut cleanup_errors = false;
+    if let Err(e) = backup_media_file_db_row(server, v) {
+        details.push_str(&format!(" WARNING: DB row backup failed: {:?}.", e));
+        cleanup_errors = true;
 
-        }
-        if let Err(e) = move_media_file_to_trash(server, &v.id) {
-            details.push_str(&format!(" WARNING: Move to trash failed: {:?}.", e));
-            cleanup_errors = true;
-        }
+    }
+    if let Err(e) = move_media_file_to_trash(server, &v.id) {
+        details.push_str(&format!(" WARNING: Move to trash failed: {:?}.", e));
+        cleanup_errors = true;
+    }
+    Ok((details, cleanup_errors))
+}
 
-        if let Some(ses) = ses {
-            let media_type_str = v.media_type.unwrap_or("file".to_string()).to_title_case();
-            send_user_ok!(&ses.user_id, &server, Topic::MediaFile(&v.id),
-                if !cleanup_errors { format!("{} deleted.", media_type_str) } else { format!("{} deleted, but cleanup had errors.", media_type_str) },
-                details, true);
+
+/// Lock (finalize) or unlock a media file, log it for auditing, and notify viewers.
+/// Used by both client and Organizer requests.
+///
+/// Returns false if the file was already in the requested state.
+pub async fn set_media_file_locked_and_notify(media_file_id: &str, lock: bool, by_user: Option<&str>, server: &ServerState) -> Res<bool> {
+    let conn = &mut server.db.conn()?;
+    if !models::MediaFile::set_locked(conn, media_file_id, lock, by_user)? {
+        return Ok(false);
+    }
+    tracing::info!(media_file_id=media_file_id, user_id=by_user, locked=lock, "Media file lock state changed.");
+    let v = models::MediaFile::get(conn, &media_file_id.into())?;
+    server.emit_cmd(
+        client_cmd!(MediaFileLockChanged, {
+            media_file_id: v.id.clone(),
+            locked: v.locked.map(|t| crate::grpc::datetime_to_proto3(&t)),
+            locked_by: v.locked_by.clone() }),
+        super::SendTo::MediaFileId(&v.id))?;
+    Ok(true)
+}
+
+pub async fn msg_set_media_file_locked(data: &SetMediaFileLocked, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        // Owner can lock, but only admins can unlock
+        let default_perm = ses.is_admin || (data.locked && ses.user_id == v.user_id);
+        org_authz_with_default(&ses.org_session, if data.locked { "lock media file" } else { "unlock media file" }, true, server, &ses.organizer,
+            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Lock)).await?;
+
+        if set_media_file_locked_and_notify(&v.id, data.locked, Some(&ses.user_id), server).await? {
+            send_user_ok!(&ses.user_id, server, Topic::MediaFile(&v.id), if data.locked { "Media file locked." } else { "Media file unlocked." });
         }
     }
     Ok(())
@@ -228,16 +324,15 @@ pub async fn msg_rename_media_file(data: &RenameMediaFile, ses: &mut UserSession
         let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
         org_authz_with_default(&ses.org_session, "rename media file", true, server, &ses.organizer,
             default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Rename)).await?;
+        if reject_if_locked(&v, ses, server, "rename")? { return Ok(()); }
 
-        let new_name = data.new_name.trim();
-        if new_name.is_empty() || !new_name.chars().any(|c| c.is_alphanumeric()) {
-            send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), "Invalid file name (must have letters/numbers)");
-            return Ok(());
-        }
-        if new_name.len() > 160 {
-            send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), "Name too long (max 160)");
-            return Ok(());
-        }
+        let new_name = match validate_media_file_name(&data.new_name) {
+            Ok(n) => n,
+            Err(e) => {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), e);
+                return Ok(());
+            }
+        };
         models::MediaFile::rename(&mut server.db.conn()?, &v.id, new_name)?;
         let media_type_str = v.media_type.unwrap_or("file".to_string()).to_title_case();
         send_user_ok!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("{} renamed.", media_type_str),
@@ -247,68 +342,211 @@ pub async fn msg_rename_media_file(data: &RenameMediaFile, ses: &mut UserSession
 }
 
 
+/// Trim new media file name and check that it's acceptable. Returns error message for the user if not.
+pub(crate) fn validate_media_file_name(name: &str) -> Result<&str, &'static str> {
+    let name = name.trim();
+    if name.is_empty() || !name.chars().any(|c| c.is_alphanumeric()) {
+        return Err("Invalid file name (must have letters/numbers)");
+    }
+    if name.len() > 160 {
+        return Err("Name too long (max 160)");
+    }
+    Ok(name)
+}
+
+
+/// Set the current user's approval state (approved / changes requested / pending) of a media file.
+/// Viewers get the updated approval list, and the owner is notified.
+pub async fn msg_set_media_file_approval(data: &SetMediaFileApproval, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use proto::media_file_approval::State;
+
+    let v = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        Some(v) => {
+            let default_perm = true;    // anyone who can view can also review
+            org_authz_with_default(&ses.org_session, "approve media file", true, server, &ses.organizer,
+                default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Approve)).await?;
+            v
+        },
+        None => return Ok(()),
+    };
+    let note = data.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    let conn = &mut server.db.conn()?;
+    let state = crate::grpc::db_models::proto_approval_state_to_str(data.state());
+    models::MediaFileApproval::set(conn, &v.id, &ses.user_id, state, note)?;
+    let approvals: Vec<proto::MediaFileApproval> = v.get_approvals(conn)?.into_iter().map(|a| a.to_proto3()).collect();
+
+    server.emit_webhook(WebhookEvent::ApprovalChanged, Some(&ses.user_id), json!({
+        "media_file_id": v.id,
+        "approval": approvals.iter().find(|a| a.user_id == ses.user_id),
+        "approvals": approvals }));
+    server.emit_cmd(
+        client_cmd!(MediaFileApprovals, {
+            media_file_id: v.id.clone(),
+            approvals: approvals }),
+        super::SendTo::MediaFileId(&v.id))?;
+
+    if v.user_id != ses.user_id {
+        let what = match data.state() {
+            State::Approved => "approved",
+            State::ChangesRequested => "requested changes to",
+            State::Pending => "reset approval of",
+        };
+        let title = v.title.as_deref().unwrap_or(&v.id);
+        send_user_ok!(&v.user_id, server, Topic::MediaFile(&v.id),
+            format!("{} {} '{}'", ses.user_name, what, title), note.unwrap_or_default().to_string(), true);
+
+        if let Some(email) = &server.email {
+            match models::User::get(conn, &v.user_id) {
+                Ok(owner) => {
+                    if let Err(e) = email.notify_approval(&owner, &ses.user_name, &v, state, note) {
+                        tracing::error!(user=owner.id, details=%e, "Failed to queue approval e-mail.");
+                    }
+                },
+                Err(DBError::NotFound()) => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+
 pub async fn msg_add_comment(data: &proto::client::client_to_server_cmd::AddComment, ses: &mut UserSession, server: &ServerState) -> Res<()> {
 
-    let media_file_id = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+    let mf = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         Some(v) => {
             let default_perm = true;    // anyone can comment on any media file
             org_authz_with_default(&ses.org_session, "comment media file", true, server, &ses.organizer,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Comment)).await?;
-            v.id
+            if reject_if_locked(&v, ses, server, "comment")? { return Ok(()); }
+            v
         },
         None => return Ok(()),
     };
+    store_comment(data, &mf, &ses.user_id, &ses.user_name, &ses.organizer, server).await?;
+    Ok(())
+}
 
-    // Parse drawing data if present and write to file
-    let mut drwn = data.drawing.clone();
-    if let Some(d) = &drwn {
-        if d.starts_with("data:") {
+/// Validate and store a new comment (and its drawing), notify @mentioned users and
+/// send it to everyone watching the media file. Authorization must be checked by caller.
+pub(crate) async fn store_comment(data: &proto::client::client_to_server_cmd::AddComment, mf: &models::MediaFile, user_id: &str, user_name: &str, organizer: &Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>, server: &ServerState) -> Res<models::Comment> {
+    let media_file_id = mf.id.clone();
 
+    // Validate vector annotation, and store it in normalized form
+    let annotation = match data.annotation.as_deref().filter(|a| !a.is_empty()) {
+        Some(a) => Some(super::annotation::Annotation::parse(a)?.to_json()),
+        None => None,
+    };
+
+    // Parse drawing data if present (data URI, or raw WebP from binary clients) and write to file
+    let mut drwn = data.drawing.clone();
+    let img_data = match (&data.drawing_webp, &drwn) {
+        (Some(webp), _) if !webp.is_empty() => {
+            if !(webp.starts_with(b"RIFF") && webp.get(8..12) == Some(b"WEBP")) {
+                bail!("Invalid drawing: not a WebP image");
+            }
+            Some(webp.clone())
+        },
+        (_, Some(d)) if d.starts_with("data:") => {
             // Convert data URI to bytes
             let img_uri = DataUrl::process(&d).map_err(|e| anyhow!("Invalid drawing data URI"))?;
 
             if img_uri.mime_type().type_ != "image" || img_uri.mime_type().subtype != "webp" {
                 bail!("Invalid mimetype in drawing: {:?}", img_uri.mime_type())
             }
-            let img_data = img_uri.decode_to_vec().map_err(|e| anyhow!("Failed to decode drawing data URI: {:?}", e))?;
-
-            // Make up a filename
-            fn sha256hex( data: &[u8] ) -> String {
-                let mut hasher = Sha256::new();
-                hasher.update(data);
-                let result = hasher.finalize();
-                hex::encode(result)
-            }
-            let short_csum = sha256hex(img_data.0.as_ref())[..16].to_string();
-            let fname = format!("{}.webp", short_csum);
-
-            // Write to file
-            let drawing_path = server.media_files_dir.join(&media_file_id).join("drawings").join(&fname);
-            std::fs::create_dir_all(drawing_path.parent().unwrap())
-                .map_err(|e| anyhow!("Failed to create drawings dir: {:?}", e))?;
-            async_std::fs::write(drawing_path, img_data.0).await.map_err(
-                |e| anyhow!("Failed to write drawing file: {:?}", e))?;
-
-            // Replace data URI with filename
-            drwn = Some(fname);
-        }
+            Some(img_uri.decode_to_vec().map_err(|e| anyhow!("Failed to decode drawing data URI: {:?}", e))?.0)
+        },
+        _ => None,
     };
+    if let Some(img_data) = img_data {
+        // Make up a filename
+        fn sha256hex( data: &[u8] ) -> String {
+            let mut hasher = Sha256::new();
+            hasher.update(data);
+            let result = hasher.finalize();
+            hex::encode(result)
+        }
+        let short_csum = sha256hex(&img_data)[..16].to_string();
+        let fname = format!("{}.webp", short_csum);
+
+        // Write to file
+        let drawing_path = server.media_files_dir.join(&media_file_id).join("drawings").join(&fname);
+        std::fs::create_dir_all(drawing_path.parent().unwrap())
+            .map_err(|e| anyhow!("Failed to create drawings dir: {:?}", e))?;
+        async_std::fs::write(drawing_path, img_data).await.map_err(
+            |e| anyhow!("Failed to write drawing file: {:?}", e))?;
+
+        // Replace data URI with filename
+        drwn = Some(fname);
+    }
 
     let c = models::CommentInsert {
         media_file_id: media_file_id.to_string(),
         parent_id: optional_str_to_i32_or_tonic_error!(data.parent_id)?,
-        user_id: Some(ses.user_id.clone()),
-        username_ifnull: ses.user_name.clone(),
+        user_id: Some(user_id.to_string()),
+        username_ifnull: user_name.to_string(),
         comment: data.comment.clone(),
         timecode: data.timecode.clone(),
         drawing: drwn.clone(),
         subtitle_id: optional_str_to_i32_or_tonic_error!(data.subtitle_id)?,
-        subtitle_filename_ifnull: None
+        subtitle_filename_ifnull: None,
+        annotation,
     };
     let c = models::Comment::insert(&mut server.db.conn()?, &c)
         .map_err(|e| anyhow!("Failed to add comment: {:?}", e))?;
+    if let Err(e) = notify_mentioned_users(&c, mf, user_id, user_name, organizer, server).await {
+        tracing::error!(comment=c.id, details=%e, "Failed to notify mentioned users.");
+    }
+    server.emit_webhook(WebhookEvent::CommentAdded, Some(user_id), json!({ "comment": c.to_proto3() }));
+
     // Send to all clients watching this media file
-    ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&media_file_id)).await?;
+    let mut with_drawing = c.clone();
+    server.fetch_drawing_data_into_comment(&mut with_drawing).await?;
+    server.emit_cmd(
+        client_cmd!(AddComments, {comments: vec![with_drawing.to_proto3()]}),
+        super::SendTo::MediaFileId(&media_file_id))?;
+    Ok(c)
+}
+
+/// Notify existing users that were @mentioned in a comment,
+/// with a persisted message and an e-mail (if enabled).
+/// Users who can't view the media file are skipped. E-mail errors are only logged, as the comment is already stored.
+async fn notify_mentioned_users(c: &models::Comment, mf: &models::MediaFile, author_id: &str, author_name: &str, organizer: &Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>, server: &ServerState) -> Res<()> {
+    let mention_re = regex::Regex::new(r"(?:^|[^\w@])@([\w.\-]*\w)").unwrap();
+    let mut mentioned: Vec<&str> = mention_re.captures_iter(&c.comment).filter_map(|cap| cap.get(1)).map(|m| m.as_str()).collect();
+    mentioned.sort();
+    mentioned.dedup();
+
+    let conn = &mut server.db.conn()?;
+    let title = mf.title.as_deref().unwrap_or(&mf.id);
+    for user_id in mentioned.into_iter().filter(|u| *u != author_id) {
+        let user = match models::User::get(conn, &user_id.to_string()) {
+            Ok(u) => u,
+            Err(DBError::NotFound()) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Recipient isn't necessarily connected, so check view permission with a minimal session (no groups, not admin)
+        let recipient_ses = proto::org::UserSessionData {
+            sid: "<mention-check--not-set>".into(),
+            user: Some(proto::UserInfo { id: user.id.clone(), name: user.name.clone() }),
+            ..Default::default()
+        };
+        if org_authz_with_default(&recipient_ses, "view mentioned media file", false, server, organizer,
+            true, AuthzTopic::MediaFile(mf, authz_req::media_file_op::Op::View)).await.is_err()
+        {
+            tracing::debug!(user=user.id, media_file=mf.id, "Not notifying mentioned user who can't view the media file.");
+            continue;
+        }
+
+        send_user_ok!(&user.id, server, Topic::MediaFile(&mf.id),
+            format!("{} mentioned you on '{}'", author_name, title), c.comment.clone(), true);
+        if let Some(email) = &server.email {
+            if let Err(e) = email.notify_mention(&user, author_name, mf, c) {
+                tracing::error!(user=user.id, details=%e, "Failed to queue mention e-mail.");
+            }
+        }
+    }
     Ok(())
 }
 
@@ -324,13 +562,24 @@ pub async fn msg_edit_comment(data: &EditComment, ses: &mut UserSession, server:
                 default_perm, AuthzTopic::Comment(&old, authz_req::comment_op::Op::Edit)).await?;
 
             let vid = &old.media_file_id;
-            models::Comment::edit(conn, id, &data.new_comment)?;
+            if reject_if_locked(&models::MediaFile::get(conn, vid)?, ses, server, "edit comment")? { return Ok(()); }
+            if old.deleted.is_some() {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(vid), "Failed to edit comment.", "Comment has been deleted.", true);
+                return Ok(());
+            }
+            let new_annotation = match data.new_annotation.as_deref() {
+                Some("") => Some(None),
+                Some(a) => Some(Some(super::annotation::Annotation::parse(a)?.to_json())),
+                None => None,
+            };
+            models::Comment::edit(conn, id, &data.new_comment, new_annotation.as_ref().map(|a| a.as_deref()), Some(&ses.user_id))?;
 
             server.emit_cmd(
                 client_cmd!(DelComment, {comment_id: id.to_string()}),
                 super::SendTo::MediaFileId(&vid))?;
 
             let c = models::Comment::get(conn, &id)?;
+            server.emit_webhook(WebhookEvent::CommentEdited, Some(&ses.user_id), json!({ "comment": c.to_proto3() }));
             ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&vid)).await?;
         }
         Err(DBError::NotFound()) => {
@@ -352,19 +601,37 @@ pub async fn msg_del_comment(data: &DelComment, ses: &mut UserSession, server: &
                 default_perm, AuthzTopic::Comment(&cmt, authz_req::comment_op::Op::Delete)).await?;
 
             let vid = cmt.media_file_id;
+            if reject_if_locked(&models::MediaFile::get(conn, &vid)?, ses, server, "delete comment")? { return Ok(()); }
             if Some(&ses.user_id) != cmt.user_id.as_ref() && !ses.is_admin {
                 send_user_error!(&ses.user_id, server, Topic::MediaFile(&vid), "Failed to delete comment.", "You can only delete your own comments", true);
                 return Ok(());
             }
-            let all_comm = models::Comment::get_by_media_file(conn, &vid, DBPaging::default())?;
-            if all_comm.iter().any(|c| c.parent_id.map(|i| i.to_string()) == Some(id.to_string())) {
-                send_user_error!(&ses.user_id, server, Topic::MediaFile(&vid), "Failed to delete comment.", "Comment has replies. Cannot delete.", true);
+            if !models::Comment::soft_delete(conn, id, Some(&ses.user_id))? {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&vid), "Failed to delete comment.", "Comment already deleted.", true);
                 return Ok(());
             }
-            models::Comment::delete(conn, &id)?;
+            server.emit_webhook(WebhookEvent::CommentDeleted, Some(&ses.user_id), json!({ "comment": models::Comment::get(conn, &id)?.to_proto3() }));
             server.emit_cmd(
                 client_cmd!(DelComment, {comment_id: id.to_string()}),
                 super::SendTo::MediaFileId(&vid))?;
+
+            // Keep a placeholder if there are replies, and drop deleted
+            // parents that no longer have any replies to show
+            let visible = models::Comment::visible_to_users(
+                models::Comment::get_by_media_file(conn, &vid, DBPaging::default())?);
+            if let Some(placeholder) = visible.iter().find(|c| c.id == id) {
+                server.emit_cmd(
+                    client_cmd!(AddComments, {comments: vec![placeholder.to_proto3()]}),
+                    super::SendTo::MediaFileId(&vid))?;
+            }
+            let mut parent_id = cmt.parent_id;
+            while let Some(pid) = parent_id {
+                if visible.iter().any(|c| c.id == pid) { break; }
+                server.emit_cmd(
+                    client_cmd!(DelComment, {comment_id: pid.to_string()}),
+                    super::SendTo::MediaFileId(&vid))?;
+                parent_id = models::Comment::get(conn, &pid).ok().and_then(|p| p.parent_id);
+            }
         }
         Err(DBError::NotFound()) => {
             send_user_error!(&ses.user_id, server, Topic::None, "Failed to delete comment.", "No such comment. Cannot delete.", true);
@@ -375,12 +642,249 @@ pub async fn msg_del_comment(data: &DelComment, ses: &mut UserSession, server: &
 }
 
 
+/// Send revision history of a comment to the user (admin only).
+pub async fn msg_get_comment_history(data: &GetCommentHistory, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if !ses.is_admin {
+        send_user_error!(&ses.user_id, server, Topic::None, "Permission denied.", "Only admins can view comment history.", true);
+        return Ok(());
+    }
+    let id = i32::from_str(&data.comment_id)?;
+    let conn = &mut server.db.conn()?;
+    match models::Comment::get(conn, &id) {
+        Ok(cmt) => {
+            let revisions = models::CommentRevision::get_by_comment(conn, id)?.into_iter()
+                .map(|r| r.to_proto3()).collect();
+            server.emit_cmd(
+                client_cmd!(CommentHistory, {comment: Some(cmt.to_proto3()), revisions: revisions}),
+                super::SendTo::UserSession(&ses.sid))?;
+        }
+        Err(DBError::NotFound()) => {
+            send_user_error!(&ses.user_id, server, Topic::None, "Failed to get comment history.", "No such comment.", true);
+        }
+        Err(e) => { bail!(e); }
+    }
+    Ok(())
+}
+
+
+/// Undelete a comment and/or revert it to an earlier revision (admin only).
+pub async fn msg_restore_comment(data: &RestoreComment, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if !ses.is_admin {
+        send_user_error!(&ses.user_id, server, Topic::None, "Permission denied.", "Only admins can restore comments.", true);
+        return Ok(());
+    }
+    let id = i32::from_str(&data.comment_id)?;
+    let revision_id = optional_str_to_i32_or_tonic_error!(data.revision_id)?;
+    let conn = &mut server.db.conn()?;
+    match models::Comment::restore(conn, id, revision_id, Some(&ses.user_id)) {
+        Ok(true) => {
+            let c = models::Comment::get(conn, &id)?;
+            let vid = c.media_file_id.clone();
+            server.emit_cmd(
+                client_cmd!(DelComment, {comment_id: id.to_string()}),
+                super::SendTo::MediaFileId(&vid))?;
+
+            // Restored reply may bring back deleted parents as placeholders
+            let visible = models::Comment::visible_to_users(
+                models::Comment::get_by_media_file(conn, &vid, DBPaging::default())?);
+            let mut cmts = vec![];
+            let mut parent_id = c.parent_id;
+            while let Some(p) = parent_id.and_then(|pid| visible.iter().find(|v| v.id == pid)) {
+                if p.deleted.is_some() { cmts.push(p.to_proto3()); }
+                parent_id = p.parent_id;
+            }
+            if !cmts.is_empty() {
+                cmts.reverse();
+                server.emit_cmd(
+                    client_cmd!(AddComments, {comments: cmts}),
+                    super::SendTo::MediaFileId(&vid))?;
+            }
+            ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&vid)).await?;
+            send_user_ok!(&ses.user_id, server, Topic::Comment(id), "Comment restored.");
+        }
+        Ok(false) | Err(DBError::NotFound()) => {
+            send_user_error!(&ses.user_id, server, Topic::None, "Failed to restore comment.", "No such comment or revision.", true);
+        }
+        Err(e) => { bail!(e); }
+    }
+    Ok(())
+}
+
+
+/// Import comments from a review file (CSV, EDL, SRT/VTT).
+/// Without `commit`, only parse the file and send a preview (incl. rejected lines) back to the user.
+pub async fn msg_import_comments(data: &ImportComments, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use super::comment_import::{ImportFormat, TimecodeMapper, parse_comment_file, decode_contents};
+
+    let mf = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        Some(v) => {
+            let default_perm = true;    // same as for adding a single comment
+            org_authz_with_default(&ses.org_session, "import comments", true, server, &ses.organizer,
+                default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Comment)).await?;
+            if reject_if_locked(&v, ses, server, "import comments")? { return Ok(()); }
+            v
+        },
+        None => return Ok(()),
+    };
+
+    let Some(format) = ImportFormat::from_file_name(&data.file_name) else {
+        send_user_error!(&ses.user_id, server, Topic::MediaFile(&mf.id), "Failed to import comments.", format!("Unsupported file type: '{}'. Use CSV, EDL, SRT or VTT.", data.file_name), true);
+        return Ok(());
+    };
+    let contents = if !data.contents.is_empty() {
+        decode_contents(&data.contents)?
+    } else {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        decode_contents(&STANDARD.decode(&data.contents_base64).context("Failed to base64 decode import file")?)?
+    };
+    let mapper = TimecodeMapper::for_media_file(&mf, data.start_timecode.as_deref())?;
+    let author = data.source_author.as_deref().map(str::trim).filter(|a| !a.is_empty()).unwrap_or(&ses.user_name);
+    let res = parse_comment_file(format, &contents, &mf, &mapper, &ses.user_id, author)?;
+
+    if !data.commit {
+        let comments = res.comments.iter().map(|c| proto::Comment {
+                media_file_id: c.media_file_id.clone(),
+                user_id: c.user_id.clone(),
+                username_ifnull: c.username_ifnull.clone(),
+                comment: c.comment.clone(),
+                timecode: c.timecode.clone(),
+                ..Default::default()
+            }).collect();
+        let rejected = res.rejected.iter().map(|r| proto::client::server_to_client_cmd::comment_import_preview::RejectedLine {
+                line_num: r.line_num as u32,
+                line: r.line.clone(),
+                reason: r.reason.clone(),
+            }).collect();
+        server.emit_cmd(
+            client_cmd!(CommentImportPreview, { media_file_id: mf.id.clone(), file_name: data.file_name.clone(), comments: comments, rejected: rejected }),
+            super::SendTo::UserSession(&ses.sid))?;
+        return Ok(());
+    }
+
+    if res.comments.is_empty() {
+        send_user_error!(&ses.user_id, server, Topic::MediaFile(&mf.id), "No comments to import.", format!("{} line(s) rejected.", res.rejected.len()), true);
+        return Ok(());
+    }
+    let new_comments = models::Comment::insert_many(&mut server.db.conn()?, &res.comments)
+        .map_err(|e| anyhow!("Failed to add comments: {:?}", e))?;
+    let n = new_comments.len();
+    server.emit_cmd(
+        client_cmd!(AddComments, {comments: new_comments.iter().map(|c| c.to_proto3()).collect()}),
+        super::SendTo::MediaFileId(&mf.id))?;
+    send_user_ok!(&ses.user_id, server, Topic::MediaFile(&mf.id), format!("Imported {} comment(s).", n),
+        format!("From '{}'. {} line(s) rejected.", data.file_name, res.rejected.len()), true);
+    Ok(())
+}
+
+
+/// Generate a review report (HTML or PDF) of all comments on a media file.
+/// Frame grabs can take a while, so the report is made in a background thread,
+/// and the user is sent a download link when it's ready.
+pub async fn msg_export_review_report(data: &ExportReviewReport, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use super::review_report::{ReportFormat, write_report};
+
+    let mf = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        Some(v) => {
+            org_authz_with_default(&ses.org_session, "export review report", true, server, &ses.organizer,
+                true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+            v
+        },
+        None => return Ok(()),
+    };
+    let format = match data.format() {
+        proto::client::client_to_server_cmd::export_review_report::Format::Html => ReportFormat::Html,
+        proto::client::client_to_server_cmd::export_review_report::Format::Pdf => ReportFormat::Pdf,
+    };
+    let comments = models::Comment::visible_to_users(
+        models::Comment::get_by_media_file(&mut server.db.conn()?, &mf.id, DBPaging::default())?);
+
+    let server = server.clone();
+    let (sid, user_id) = (ses.sid.clone(), ses.user_id.clone());
+    std::thread::spawn(move || {
+        let _span = tracing::info_span!("review_report", media_file=%mf.id).entered();
+        let res = (|| -> Res<()> {
+            let fname = write_report(&server.media_files_dir.join(&mf.id), &mf, &comments, format)?;
+            let url = format!("{}/videos/{}/reports/{}", server.url_base, mf.id, urlencoding::encode(&fname));
+            server.emit_cmd(
+                client_cmd!(ReviewReportReady, { media_file_id: mf.id.clone(), url: url }),
+                super::SendTo::UserSession(&sid))?;
+            Ok(())
+        })();
+        if let Err(e) = res {
+            tracing::warn!(details=%e, "Review report generation failed.");
+            let send_err = || -> Res<()> {
+                send_user_error!(&user_id, server, Topic::MediaFile(&mf.id), "Failed to generate review report.", format!("{:#}", e), true);
+                Ok(())
+            };
+            send_err().ok();
+        }
+    });
+    Ok(())
+}
+
+
+pub async fn msg_search(data: &Search, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    const DEFAULT_PAGE_SIZE: u32 = 50;
+
+    // Search covers user's own media files and ones shared with them (admins see all).
+    // This is filtered in SQL (for paging), and Organizer gets to veto each media file afterwards.
+    let shared = {
+        let conn = &mut server.db.conn()?;
+        if ses.is_admin { vec![] } else { super::media_grants::media_files_shared_with(conn, &ses.user_id, &ses.groups)? }
+    };
+    let (is_admin, user_id) = (ses.is_admin, ses.user_id.clone());
+    let default_perm = |mf: &models::MediaFile| is_admin || mf.user_id == user_id || shared.contains(&mf.id);
+
+    let media_file_id = data.media_file_id.as_deref().filter(|id| !id.is_empty());
+    if let Some(id) = media_file_id {
+        match get_media_file_or_send_error(Some(id), &Some(ses), server).await? {
+            Some(v) => {
+                org_authz_with_default(&ses.org_session, "search media file", true, server, &ses.organizer,
+                    default_perm(&v), AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+            },
+            None => return Ok(()),
+        }
+    }
+    let pg = DBPaging {
+        page_num: data.page_num,
+        page_size: std::num::NonZeroU32::new(data.page_size).unwrap_or(std::num::NonZeroU32::new(DEFAULT_PAGE_SIZE).unwrap()),
+    };
+
+    let (hits, media_files) = {
+        let conn = &mut server.db.conn()?;
+        let owner = if ses.is_admin || media_file_id.is_some() { None } else { Some(ses.user_id.as_str()) };
+        let hits = crate::database::search::search(conn, &data.query, owner, &shared, media_file_id, pg)?;
+        let mut ids = hits.iter().map(|h| h.media_file_id.clone()).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        (hits, models::MediaFile::get_many(conn, &ids)?)
+    };
+
+    // Authz checks await the Organizer, so they are done after releasing the DB connection
+    let mut allowed: HashSet<String> = HashSet::new();
+    for mf in &media_files {
+        if media_file_id.is_some() || org_authz_with_default(&ses.org_session, "view search hit", false, server, &ses.organizer,
+            default_perm(mf), AuthzTopic::MediaFile(mf, authz_req::media_file_op::Op::View)).await.is_ok()
+        {
+            allowed.insert(mf.id.clone());
+        }
+    }
+    let res = hits.iter().filter(|h| allowed.contains(&h.media_file_id)).map(|h| h.to_proto3()).collect();
+
+    server.emit_cmd(
+        client_cmd!(SearchResults, { query: data.query.clone(), hits: res }),
+        super::SendTo::UserSession(&ses.sid))?;
+    Ok(())
+}
+
+
 pub async fn msg_add_subtitle(data: &AddSubtitle, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     let mf = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         Some(v) => {
             let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
             org_authz_with_default(&ses.org_session, "add subtitle", true, server, &ses.organizer,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Edit)).await?;
+            if reject_if_locked(&v, ses, server, "add subtitle")? { return Ok(()); }
             v
         },
         None => return Ok(()),
@@ -414,7 +918,9 @@ pub async fn msg_add_subtitle(data: &AddSubtitle, ses: &mut UserSession, server:
         return Ok(());
     }
 
-    let file_contents = {
+    let file_contents = if !data.contents.is_empty() {
+        data.contents.clone()
+    } else {
         use base64::{engine::general_purpose::STANDARD, Engine as _};
         STANDARD.decode(&data.contents_base64).context("Failed to base64 decode subtitle file")?
     };
@@ -472,6 +978,10 @@ pub async fn msg_add_subtitle(data: &AddSubtitle, ses: &mut UserSession, server:
         time_offset: 0.0,
     }) .map_err(|e| anyhow!("Failed to add subtitle: {:?}", e))?;
 
+    if let Err(e) = crate::database::search::index_subtitle_cues(conn, &new_sub, &server.media_files_dir) {
+        tracing::warn!(subtitle_id=new_sub.id, details=%e, "Failed to index subtitle for search.");
+    }
+
     let all_subs = models::Subtitle::get_by_media_file(conn, &mf.id, DBPaging::default())?;
     if all_subs.len() == 1 {
         models::MediaFile::set_default_subtitle(conn, &mf.id, Some(new_sub.id))
@@ -493,12 +1003,18 @@ pub async fn msg_edit_subtitle_info(data: &EditSubtitleInfo, ses: &mut UserSessi
     let default_perm = ses.user_id == mf.user_id || ses.is_admin;
     org_authz_with_default(&ses.org_session, "edit subtitle", true, server, &ses.organizer,
         default_perm, AuthzTopic::MediaFile(&mf, authz_req::media_file_op::Op::Edit)).await?;
+    if reject_if_locked(&mf, ses, server, "edit subtitle")? { return Ok(()); }
 
     // Update subtitle in DB
     sub.title = data.title.clone().unwrap_or(sub.title.clone());
     sub.language_code = data.language_code.clone().unwrap_or(sub.language_code.clone());
     sub.time_offset = data.time_offset.clone().unwrap_or(sub.time_offset);
-    models::Subtitle::update_many(conn, &[sub]) .map_err(|e| anyhow!("Failed to update subtitle: {:?}", e))?;
+    let sub = models::Subtitle::update_many(conn, &[sub]) .map_err(|e| anyhow!("Failed to update subtitle: {:?}", e))?.remove(0);
+
+    // Reindex cues, in case the subtitle file has changed on disk
+    if let Err(e) = crate::database::search::index_subtitle_cues(conn, &sub, &server.media_files_dir) {
+        tracing::warn!(subtitle_id=sub.id, details=%e, "Failed to index subtitle for search.");
+    }
 
     // Set/unset default subtitle for media file if requested
     if let Some(is_default) = data.is_default {
@@ -523,6 +1039,7 @@ pub async fn msg_del_subtitle(data: &DelSubtitle, ses: &mut UserSession, server:
     let default_perm = ses.user_id == mf.user_id || ses.is_admin;
     org_authz_with_default(&ses.org_session, "delete subtitle", true, server, &ses.organizer,
         default_perm, AuthzTopic::MediaFile(&mf, authz_req::media_file_op::Op::Edit)).await?;
+    if reject_if_locked(&mf, ses, server, "delete subtitle")? { return Ok(()); }
 
     let subs_dir = server.media_files_dir.join(&mf.id).join("subs");
     tracing::debug!(orig_file=?sub.orig_filename, vtt_file=?sub.filename, "Deleting subtitle files");
@@ -540,15 +1057,269 @@ pub async fn msg_del_subtitle(data: &DelSubtitle, ses: &mut UserSession, server:
     Ok(())
 }
 
-pub async fn msg_list_my_messages(data: &proto::client::client_to_server_cmd::ListMyMessages, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+pub async fn msg_list_my_messages(data: &ListMyMessages, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let pg = match std::num::NonZeroU32::new(data.page_size) {
+        Some(page_size) => DBPaging { page_num: data.page_num, page_size },
+        None => DBPaging::default(),
+    };
     let conn = &mut server.db.conn()?;
-    let msgs = models::Message::get_by_user(conn, &ses.user_id, DBPaging::default())?;
+    let msgs = models::Message::get_by_user_filtered(conn, &ses.user_id, data.unseen_only, pg)?;
+
+    // Mark listed messages seen before counting, but send them with their original status
+    if data.mark_seen.unwrap_or(true) {
+        let unseen_ids = msgs.iter().filter(|m| !m.seen).map(|m| m.id).collect::<Vec<_>>();
+        if !unseen_ids.is_empty() {
+            models::Message::set_seen_by_user(conn, &ses.user_id, Some(&unseen_ids), true)?;
+        }
+    }
+    let (total_count, unseen_count) = models::Message::count_by_user(conn, &ses.user_id)?;
+
     server.emit_cmd(
-        client_cmd!(ShowMessages, { msgs: (&msgs).into_iter().map(|m| m.to_proto3()).collect() }),
+        client_cmd!(ShowMessages, {
+            msgs: msgs.iter().map(|m| m.to_proto3()).collect(),
+            list_info: Some(proto::client::server_to_client_cmd::show_messages::ListInfo {
+                page_num: if data.page_size > 0 { data.page_num } else { 0 },
+                page_size: data.page_size,
+                total_count: total_count as u32,
+                unseen_count: unseen_count as u32,
+            })
+        }),
         super::SendTo::UserSession(&ses.sid)
     )?;
-    for m in msgs {
-        if !m.seen { models::Message::set_seen(conn, m.id, true)?; }
+    Ok(())
+}
+
+fn parse_message_ids(ids: &[String]) -> Res<Vec<i32>> {
+    ids.iter().map(|id| i32::from_str(id).with_context(|| format!("Invalid message ID '{}'", id))).collect()
+}
+
+pub async fn msg_set_messages_seen(data: &SetMessagesSeen, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let ids = if data.all { None } else { Some(parse_message_ids(&data.ids)?) };
+    models::Message::set_seen_by_user(&mut server.db.conn()?, &ses.user_id, ids.as_deref(), data.seen)?;
+    Ok(())
+}
+
+pub async fn msg_del_messages(data: &DelMessages, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let ids = if data.all { None } else { Some(parse_message_ids(&data.ids)?) };
+    let n = models::Message::delete_by_user(&mut server.db.conn()?, &ses.user_id, ids.as_deref())?;
+    tracing::debug!("Deleted {} message(s) of user '{}'", n, ses.user_id);
+    Ok(())
+}
+
+/// Send user's effective preferences for all message types
+fn send_message_prefs(ses: &UserSession, server: &ServerState, send_to: super::SendTo) -> Res<()> {
+    let prefs = (0..).map_while(|i| proto::user_message::Type::try_from(i).ok())
+        .map(|t| {
+            let (persist, push) = server.user_message_prefs(&ses.user_id, proto_msg_type_to_event_name(t));
+            proto::UserMessagePref { r#type: t.into(), persist, push }
+        }).collect();
+    server.emit_cmd(client_cmd!(MessagePrefs, { prefs: prefs }), send_to)?;
+    Ok(())
+}
+
+pub async fn msg_get_message_prefs(data: &GetMessagePrefs, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    send_message_prefs(ses, server, super::SendTo::UserSession(&ses.sid))
+}
+
+pub async fn msg_set_message_prefs(data: &SetMessagePrefs, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let conn = &mut server.db.conn()?;
+    for p in &data.prefs {
+        let t = proto::user_message::Type::try_from(p.r#type).map_err(|_| anyhow!("Invalid message type {}", p.r#type))?;
+        models::UserMessagePref::set(conn, &ses.user_id, proto_msg_type_to_event_name(t), p.persist, p.push)?;
+    }
+    // Update all of user's sessions
+    send_message_prefs(ses, server, super::SendTo::UserId(&ses.user_id))
+}
+
+
+fn send_api_tokens(ses: &UserSession, server: &ServerState, send_to: super::SendTo) -> Res<()> {
+    let tokens = models::ApiToken::get_by_user(&mut server.db.conn()?, &ses.user_id, DBPaging::default())?;
+    server.emit_cmd(client_cmd!(ShowApiTokens, { tokens: tokens.iter().map(|t| t.to_proto3()).collect() }), send_to)?;
+    Ok(())
+}
+
+pub async fn msg_create_api_token(data: &CreateApiToken, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let name = data.name.trim();
+    if name.is_empty() { bail!("API token needs a name"); }
+    let scopes = super::api_tokens::parse_scopes(&data.scopes)?;
+    let expires = match data.expires_in_days {
+        Some(d) if d <= 0 => bail!("Expiry must be at least one day"),
+        Some(d) => Some(chrono::Utc::now().naive_utc() + chrono::Duration::days(d.into())),
+        None => None,
+    };
+    let (token, token_hash) = super::api_tokens::generate_token();
+    let t = models::ApiToken::insert(&mut server.db.conn()?, &models::ApiTokenInsert {
+        user_id: ses.user_id.clone(),
+        name: name.to_string(),
+        token_hash,
+        scopes: scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(","),
+        expires,
+    })?;
+    tracing::info!(user=ses.user_id, token=t.name, scopes=t.scopes, "Created API token.");
+    server.emit_cmd(client_cmd!(ApiTokenCreated, { info: Some(t.to_proto3()), token: token }), super::SendTo::UserSession(&ses.sid))?;
+    send_api_tokens(ses, server, super::SendTo::UserId(&ses.user_id))
+}
+
+pub async fn msg_list_api_tokens(data: &ListApiTokens, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    send_api_tokens(ses, server, super::SendTo::UserSession(&ses.sid))
+}
+
+pub async fn msg_revoke_api_token(data: &RevokeApiToken, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let id = i32::from_str(&data.id).map_err(|_| anyhow!("Invalid token id '{}'", data.id))?;
+    let conn = &mut server.db.conn()?;
+    match models::ApiToken::get(conn, &id) {
+        Ok(t) if t.user_id == ses.user_id => {
+            models::ApiToken::delete(conn, &id)?;
+            let n = server.close_sessions_where(|s| s.api_token.as_ref().is_some_and(|at| at.id == id));
+            tracing::info!(user=ses.user_id, token=t.name, closed_token_sessions=n, "Revoked API token.");
+        },
+        Ok(_) | Err(DBError::NotFound()) => bail!("No such API token"),
+        Err(e) => return Err(e.into()),
+    }
+    send_api_tokens(ses, server, super::SendTo::UserId(&ses.user_id))
+}
+
+
+fn send_share_links(ses: &UserSession, server: &ServerState, media_file_id: Option<&str>, send_to: super::SendTo) -> Res<()> {
+    let links = models::ShareLink::get_by_user(&mut server.db.conn()?, &ses.user_id, DBPaging::default())?;
+    let links = links.iter()
+        .filter(|l| media_file_id.is_none_or(|id| l.allows_media_file(id)))
+        .map(|l| l.to_proto3(Some(&server.url_base))).collect();
+    server.emit_cmd(client_cmd!(ShowShareLinks, { links: links }), send_to)?;
+    Ok(())
+}
+
+pub async fn msg_create_share_link(data: &CreateShareLink, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if data.media_file_ids.is_empty() { bail!("No media files to share"); }
+    if data.expires_in_hours <= 0 { bail!("Expiry must be at least one hour"); }
+    let mut ids: Vec<String> = vec![];
+    for id in &data.media_file_ids {
+        let mf = models::MediaFile::get(&mut server.db.conn()?, id)?;
+        if mf.user_id != ses.user_id && !ses.is_admin { bail!("Can only share your own media files"); }
+        if !ids.contains(&mf.id) { ids.push(mf.id); }
+    }
+    let link = models::ShareLink::insert(&mut server.db.conn()?, &models::ShareLinkInsert {
+        link_key: super::share_links::generate_key(),
+        user_id: ses.user_id.clone(),
+        media_file_ids: ids.join(","),
+        can_comment: data.can_comment,
+        password_hash: data.password.as_deref().filter(|p| !p.is_empty()).map(super::share_links::hash_password),
+        expires: chrono::Utc::now().naive_utc() + chrono::Duration::hours(data.expires_in_hours.into()),
+    })?;
+    tracing::info!(user=ses.user_id, link=link.id, media_files=link.media_file_ids, can_comment=link.can_comment, expires=%link.expires, "Created share link.");
+    send_share_links(ses, server, None, super::SendTo::UserId(&ses.user_id))
+}
+
+pub async fn msg_list_share_links(data: &ListShareLinks, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    send_share_links(ses, server, data.media_file_id.as_deref(), super::SendTo::UserSession(&ses.sid))
+}
+
+pub async fn msg_revoke_share_link(data: &RevokeShareLink, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let id = i32::from_str(&data.id).map_err(|_| anyhow!("Invalid share link id '{}'", data.id))?;
+    let conn = &mut server.db.conn()?;
+    match models::ShareLink::get(conn, &id) {
+        Ok(l) if l.user_id == ses.user_id || ses.is_admin => {
+            models::ShareLink::delete(conn, &id)?;
+            let n = server.close_sessions_where(|s| s.guest_link.as_ref().is_some_and(|gl| gl.id == id));
+            tracing::info!(user=ses.user_id, link=id, closed_guest_sessions=n, "Revoked share link.");
+        },
+        Ok(_) | Err(DBError::NotFound()) => bail!("No such share link"),
+        Err(e) => return Err(e.into()),
+    }
+    send_share_links(ses, server, None, super::SendTo::UserId(&ses.user_id))
+}
+
+
+fn send_media_file_grants(media_file_id: &str, server: &ServerState, send_to: super::SendTo) -> Res<()> {
+    let grants = models::MediaFileGrant::get_by_media_file(&mut server.db.conn()?, media_file_id, DBPaging::default())?;
+    server.emit_cmd(client_cmd!(ShowMediaFileGrants, {
+            media_file_id: media_file_id.to_string(),
+            grants: grants.iter().map(|g| g.to_proto3()).collect()
+        }), send_to)?;
+    Ok(())
+}
+
+/// Get media file for managing its grants. Only owner and admins can do that.
+fn get_media_file_for_grants(media_file_id: &str, ses: &UserSession, server: &ServerState) -> Res<models::MediaFile> {
+    let mf = match models::MediaFile::get(&mut server.db.conn()?, &media_file_id.into()) {
+        Ok(mf) => mf,
+        Err(DBError::NotFound()) => bail!("No such media file"),
+        Err(e) => return Err(e.into()),
+    };
+    if mf.user_id != ses.user_id && !ses.is_admin { bail!("Only the owner can share a media file"); }
+    Ok(mf)
+}
+
+pub async fn msg_grant_media_file_access(data: &GrantMediaFileAccess, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use proto::client::client_to_server_cmd::grant_media_file_access::Grantee;
+    let mf = get_media_file_for_grants(&data.media_file_id, ses, server)?;
+    let (user_id, group_name) = match &data.grantee {
+        Some(Grantee::UserId(u)) if !u.trim().is_empty() => (Some(u.trim().to_string()), None),
+        Some(Grantee::Group(g)) if !g.trim().is_empty() => (None, Some(g.trim().to_string())),
+        _ => bail!("Grant needs a user id or a group"),
+    };
+    let role = super::media_grants::GrantRole::from_proto3(data.role());
+    let conn = &mut server.db.conn()?;
+
+    // Replace any earlier grant to the same user / group
+    let old = models::MediaFileGrant::get_by_media_file(conn, &mf.id, DBPaging::default())?;
+    let old_ids: Vec<i32> = old.iter().filter(|g| g.user_id == user_id && g.group_name == group_name).map(|g| g.id).collect();
+    models::MediaFileGrant::delete_many(conn, &old_ids)?;
+
+    models::MediaFileGrant::insert(conn, &models::MediaFileGrantInsert {
+        media_file_id: mf.id.clone(),
+        user_id: user_id.clone(),
+        group_name: group_name.clone(),
+        role: role.as_str().into(),
+        granted_by: Some(ses.user_id.clone()),
+    })?;
+    tracing::info!(user=ses.user_id, media_file=mf.id, grantee_user=?user_id, grantee_group=?group_name, role=role.as_str(), "Granted media file access.");
+    send_media_file_grants(&mf.id, server, super::SendTo::UserSession(&ses.sid))
+}
+
+pub async fn msg_list_media_file_grants(data: &ListMediaFileGrants, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let mf = get_media_file_for_grants(&data.media_file_id, ses, server)?;
+    send_media_file_grants(&mf.id, server, super::SendTo::UserSession(&ses.sid))
+}
+
+pub async fn msg_revoke_media_file_access(data: &RevokeMediaFileAccess, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let id = i32::from_str(&data.id).map_err(|_| anyhow!("Invalid grant id '{}'", data.id))?;
+    let grant = match models::MediaFileGrant::get(&mut server.db.conn()?, &id) {
+        Ok(g) => g,
+        Err(DBError::NotFound()) => bail!("No such grant"),
+        Err(e) => return Err(e.into()),
+    };
+    let mf = get_media_file_for_grants(&grant.media_file_id, ses, server)?;
+    models::MediaFileGrant::delete(&mut server.db.conn()?, &id)?;
+    tracing::info!(user=ses.user_id, media_file=mf.id, grant=id, "Revoked media file access.");
+    send_media_file_grants(&mf.id, server, super::SendTo::UserSession(&ses.sid))
+}
+
+
+/// Send list of user's own sessions, or everyone's if `all_users` (admin only).
+fn send_sessions(ses: &UserSession, server: &ServerState, all_users: bool, exclude_sid: Option<&str>) -> Res<()> {
+    if all_users && !ses.is_admin { bail!("Only admins can list other users' sessions"); }
+    let sessions = server.list_sessions(|s| (all_users || s.user_id == ses.user_id) && Some(s.sid.as_str()) != exclude_sid);
+    server.emit_cmd(client_cmd!(ShowSessions, { sessions: sessions, current_sid: ses.sid.clone() }), super::SendTo::UserSession(&ses.sid))?;
+    Ok(())
+}
+
+pub async fn msg_list_sessions(data: &ListSessions, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    send_sessions(ses, server, data.all_users, None)
+}
+
+pub async fn msg_disconnect_session(data: &DisconnectSession, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let owner = server.get_session(&data.sid).map(|s| s.user_id.clone());
+    match owner {
+        Some(uid) if uid == ses.user_id || ses.is_admin => {
+            server.close_sessions_where(|s| s.sid == data.sid);
+            tracing::info!(user=ses.user_id, target_user=uid, target_sid=data.sid, "Disconnected session.");
+        },
+        _ => bail!("No such session"),
+    }
+    // Closing is asynchronous, so leave the closed session out of the reply explicitly
+    if data.sid != ses.sid {
+        send_sessions(ses, server, data.all_users, Some(&data.sid))?;
     }
     Ok(())
 }
@@ -561,14 +1332,15 @@ pub async fn msg_join_collab(data: &JoinCollab, ses: &mut UserSession, server: &
             return Ok(());
         }
     }
-    ses.collab_session_guard = None;
-    ses.cur_collab_id = None;
+    leave_collab_and_notify(ses, server)?;
 
     if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        org_authz_with_default(&ses.org_session, "open media file", true, server, &ses.organizer,
+            true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
         org_authz_with_default(&ses.org_session, "join collab", true, server, &ses.organizer,
             true, AuthzTopic::Other(Some(&data.collab_id), authz_req::other_op::Op::JoinCollabSession)).await?;
 
-        match server.link_session_to_collab(&data.collab_id, &v.id, ses.sender.clone()) {
+        match server.link_session_to_collab(&data.collab_id, &v.id, ses) {
             Ok(csg) => {
                 ses.collab_session_guard = Some(csg);
                 ses.cur_collab_id = Some(data.collab_id.clone());
@@ -578,10 +1350,20 @@ pub async fn msg_join_collab(data: &JoinCollab, ses: &mut UserSession, server: &
                             r#type: proto::user_message::Type::Ok as i32,
                             message: format!("'{}' joined collab", &ses.user_name),
                             ..Default::default()
-                        }]
+                        }],
+                        list_info: None
                     }),
                     super::SendTo::Collab(&data.collab_id)
                 )?;
+                emit_collab_participants(&data.collab_id, server)?;
+
+                // Bring the late joiner up to date with the last reported playback state
+                if let Some(state) = server.get_collab_state(&data.collab_id) {
+                    if let Some(evt) = state.last_event {
+                        let evt = proto::client::server_to_client_cmd::CollabEvent { presenter_id: state.presenter_id, ..evt };
+                        server.emit_cmd(proto::client::server_to_client_cmd::Cmd::CollabEvent(evt), super::SendTo::MsgSender(&ses.sender))?;
+                    }
+                }
             }
             Err(e) => {
                 send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("Failed to join collab session: {}", e));
@@ -600,12 +1382,35 @@ pub async fn msg_leave_collab(data: &LeaveCollab, ses: &mut UserSession, server:
                     r#type: proto::user_message::Type::Ok as i32,
                     message: format!("'{}' left collab", &ses.user_name),
                     ..Default::default()
-                }]
+                }],
+                list_info: None
             }),
             super::SendTo::Collab(&collab_id)
         )?;
+    }
+    leave_collab_and_notify(ses, server)
+}
+
+
+/// Send the current participant list of a collab to everyone in it.
+fn emit_collab_participants(collab_id: &str, server: &ServerState) -> Res<()> {
+    let state = server.get_collab_state(collab_id).unwrap_or_default();
+    server.emit_cmd(
+        client_cmd!(CollabParticipants, {
+            collab_id: collab_id.to_string(),
+            participants: state.participants.into_iter().map(|(_sid, u)| u).collect(),
+            presenter_id: state.presenter_id,
+        }),
+        super::SendTo::Collab(collab_id)
+    ).map(|_| ())
+}
+
+/// Remove the session from its current collab (if any) and update the remaining participants.
+/// Also called when the session disconnects.
+pub fn leave_collab_and_notify(ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if let Some(collab_id) = ses.cur_collab_id.take() {
         ses.collab_session_guard = None;
-        ses.cur_collab_id = None;
+        emit_collab_participants(&collab_id, server)?;
     }
     Ok(())
 }
@@ -613,15 +1418,23 @@ pub async fn msg_leave_collab(data: &LeaveCollab, ses: &mut UserSession, server:
 
 pub async fn msg_collab_report(data: &CollabReport, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     if let Some(collab_id) = &ses.cur_collab_id {
-        let ce = client_cmd!(CollabEvent, {
+        let presenter_id = server.get_collab_state(collab_id).and_then(|s| s.presenter_id);
+        if presenter_id.as_ref().is_some_and(|p| p != &ses.user_id) {
+            send_user_error!(&ses.user_id, server, Topic::None, "Report rejected: only the presenter controls playback.");
+            return Ok(());
+        }
+        let evt = proto::client::server_to_client_cmd::CollabEvent {
             paused: data.paused,
-            r#loop: data.paused,
+            r#loop: data.r#loop,
             seek_time_sec: data.seek_time_sec,
             from_user: ses.user_name.clone(),
-            drawing: data.drawing.clone(),
+            drawing: data.drawing_webp.as_ref().filter(|d| !d.is_empty()).map(|d| super::webp_data_uri(d)).or_else(|| data.drawing.clone()),
             subtitle_id: data.subtitle_id.clone(),
-        });
-        server.emit_cmd(ce, super::SendTo::Collab(collab_id)).map(|_| ())
+            presenter_id,
+            drawing_webp: None,     // Set for binary clients when sending
+        };
+        server.set_collab_last_event(collab_id, evt.clone());
+        server.emit_cmd(proto::client::server_to_client_cmd::Cmd::CollabEvent(evt), super::SendTo::Collab(collab_id)).map(|_| ())
     } else {
         send_user_error!(&ses.user_id, server, Topic::None, "Report rejected: no active collab session.");
         return Ok(());
@@ -629,6 +1442,126 @@ pub async fn msg_collab_report(data: &CollabReport, ses: &mut UserSession, serve
 }
 
 
+/// Start or end presenter mode, or hand control over to another participant.
+/// Anyone can start presenter mode. Once it's on, only the presenter (or an admin) can change it.
+pub async fn msg_collab_set_presenter(data: &CollabSetPresenter, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some(state) = ses.cur_collab_id.as_ref().and_then(|id| server.get_collab_state(id)) else {
+        send_user_error!(&ses.user_id, server, Topic::None, "No active collab session.");
+        return Ok(());
+    };
+    let collab_id = ses.cur_collab_id.clone().unwrap_or_default();
+
+    if state.presenter_id.as_ref().is_some_and(|p| p != &ses.user_id) && !ses.is_admin {
+        send_user_error!(&ses.user_id, server, Topic::None, "Only the presenter can hand over control.");
+        return Ok(());
+    }
+    let new_presenter = match &data.user_id {
+        Some(uid) => match state.participants.iter().find(|(_, u)| &u.id == uid) {
+            Some((_, u)) => Some(u.clone()),
+            None => {
+                send_user_error!(&ses.user_id, server, Topic::None, "New presenter is not in this collab session.");
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    server.set_collab_presenter(&collab_id, new_presenter.as_ref().map(|u| u.id.clone()));
+    server.emit_cmd(
+        client_cmd!(ShowMessages, { msgs: vec![
+                proto::UserMessage {
+                r#type: proto::user_message::Type::Ok as i32,
+                message: match &new_presenter {
+                    Some(u) => format!("'{}' is now presenting", u.name),
+                    None => "Presenter mode ended".into(),
+                },
+                ..Default::default()
+            }],
+            list_info: None
+        }),
+        super::SendTo::Collab(&collab_id)
+    )?;
+    emit_collab_participants(&collab_id, server)
+}
+
+
+/// Ask the current presenter to hand over control.
+pub async fn msg_collab_request_control(_data: &CollabRequestControl, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some(collab_id) = ses.cur_collab_id.clone() else {
+        send_user_error!(&ses.user_id, server, Topic::None, "No active collab session.");
+        return Ok(());
+    };
+    if server.get_collab_state(&collab_id).and_then(|s| s.presenter_id).is_none() {
+        send_user_error!(&ses.user_id, server, Topic::None, "Collab session has no presenter.");
+        return Ok(());
+    }
+    server.emit_cmd(
+        client_cmd!(CollabControlRequested, {
+            collab_id: collab_id.clone(),
+            from_user: Some(proto::UserInfo { id: ses.user_id.clone(), name: ses.user_name.clone() })
+        }),
+        super::SendTo::Collab(&collab_id)
+    ).map(|_| ())
+}
+
+
+/// Relay a chat message to collab participants, optionally saving it in the session transcript.
+pub async fn msg_collab_chat(data: &CollabChat, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    const MAX_CHAT_MSG_LEN: usize = 2000;
+
+    let Some(state) = ses.cur_collab_id.as_ref().and_then(|id| server.get_collab_state(id)) else {
+        send_user_error!(&ses.user_id, server, Topic::None, "Chat rejected: no active collab session.");
+        return Ok(());
+    };
+    let collab_id = ses.cur_collab_id.clone().unwrap_or_default();
+    let message = data.message.trim();
+    if message.is_empty() { return Ok(()); }
+    if message.chars().count() > MAX_CHAT_MSG_LEN {
+        send_user_error!(&ses.user_id, server, Topic::None, format!("Chat message too long (max {} characters).", MAX_CHAT_MSG_LEN));
+        return Ok(());
+    }
+
+    let mut created = chrono::Utc::now().naive_utc();
+    let mut saved = false;
+    if data.save_to_transcript {
+        if let Some(db_id) = state.db_session_id {
+            let m = models::CollabChatMessage::insert(&mut server.db.conn()?, &models::CollabChatMessageInsert {
+                collab_session_id: db_id,
+                user_id: ses.user_id.clone(),
+                message: message.to_string(),
+            })?;
+            created = m.created;
+            saved = true;
+        }
+    }
+    server.emit_cmd(
+        client_cmd!(CollabChatMessage, {
+            collab_id: collab_id.clone(),
+            from_user: Some(proto::UserInfo { id: ses.user_id.clone(), name: ses.user_name.clone() }),
+            message: message.to_string(),
+            created: Some(crate::grpc::datetime_to_proto3(&created)),
+            saved: saved,
+        }),
+        super::SendTo::Collab(&collab_id)
+    ).map(|_| ())
+}
+
+
+/// Set current user's e-mail notification preference ("all", "immediate", "digest" or "none").
+pub async fn msg_set_email_notifications(data: &SetEmailNotifications, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    match models::User::set_email_notifications(&mut server.db.conn()?, &ses.user_id, &data.mode) {
+        Ok(_) => {
+            let details = if server.email.is_none() { "Note: e-mail notifications are not enabled on this server." } else { "" };
+            send_user_ok!(&ses.user_id, server, Topic::None, format!("E-mail notifications: {}", data.mode), details.to_string(), false);
+        },
+        Err(DBError::Other(e)) => {
+            send_user_error!(&ses.user_id, server, Topic::None, "Failed to set e-mail notifications.", e.to_string(), false);
+        },
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+
 pub async fn msg_move_to_folder(data: &proto::client::client_to_server_cmd::MoveToFolder, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     if let Some(org) = &ses.organizer {
         let req = proto::org::MoveToFolderRequest {
@@ -701,17 +1634,79 @@ pub async fn msg_organizer_cmd(data: &proto::client::client_to_server_cmd::Organ
 pub enum SessionClose {
     #[error("User logout")]
     Logout,
+    #[error("Share link expired")]
+    ShareLinkExpired,
+    #[error("API token expired")]
+    ApiTokenExpired,
+    #[error("Too many requests, disconnected. Try again in {0} seconds.")]
+    RateLimited(u64),
+}
+
+/// Check if an API token authenticated session is allowed to run the command.
+/// Always true for interactive sessions.
+fn api_token_allows(cmd: &proto::client::client_to_server_cmd::Cmd, ses: &UserSession) -> bool {
+    use proto::client::client_to_server_cmd::Cmd;
+    match (&ses.token_scopes, cmd) {
+        (None, _) | (Some(_), Cmd::Logout(_)) => true,
+        (Some(scopes), cmd) => super::api_tokens::required_scope(cmd).is_some_and(|s| scopes.contains(&s)),
+    }
+}
+
+/// Check if a guest session (from a share link) is allowed to run the command.
+/// Always true for other sessions.
+fn guest_allows(cmd: &proto::client::client_to_server_cmd::Cmd, ses: &UserSession, server: &ServerState) -> bool {
+    match &ses.guest_link {
+        None => true,
+        Some(link) => server.db.conn().map(|mut conn| super::share_links::guest_allows(link, cmd, &mut conn)).unwrap_or(false),
+    }
+}
+
+/// Check command against rate and size limits. Returns reason if refused.
+fn rate_limit_refusal(cmd: &proto::client::client_to_server_cmd::Cmd, ses: &UserSession, server: &ServerState) -> Option<String> {
+    let rl = &server.rate_limiter;
+    if let Some(reason) = rl.check_sizes(cmd) {
+        return Some(reason);
+    }
+    let class = super::rate_limit::RateClass::of_cmd(cmd)?;
+    if rl.check(class, &ses.user_id, ses.remote_addr.as_deref()) { None } else {
+        Some(format!("Too many requests ({}), please slow down", class.as_str()))
+    }
 }
 
 /// Dispatch a message from client to appropriate handler.
 /// Return true if the session should be kept open, or false if it should be closed.
 pub async fn msg_dispatch(req: &ClientToServerCmd, ses: &mut UserSession, server: &ServerState) -> Res<bool> {
     use proto::client::client_to_server_cmd::Cmd;
+    if ses.guest_link.as_ref().is_some_and(|l| l.is_expired(chrono::Utc::now().naive_utc())) {
+        tracing::info!(user=ses.user_id, "Share link has expired. Closing guest session.");
+        return Err(SessionClose::ShareLinkExpired.into());
+    }
+    if ses.api_token.as_ref().is_some_and(|t| t.is_expired(chrono::Utc::now().naive_utc())) {
+        tracing::info!(user=ses.user_id, "API token has expired. Closing token session.");
+        return Err(SessionClose::ApiTokenExpired.into());
+    }
+    if let Some(reason) = req.cmd.as_ref().and_then(|cmd| rate_limit_refusal(cmd, ses, server)) {
+        ses.rate_limit_violations += 1;
+        tracing::info!(user=ses.user_id, addr=?ses.remote_addr, violations=ses.rate_limit_violations, "Refused command: {}", reason);
+        let max_violations = server.rate_limiter.cfg.violations_before_disconnect;
+        if max_violations > 0 && ses.rate_limit_violations >= max_violations {
+            let ban = server.rate_limiter.kick(&ses.user_id, ses.remote_addr.as_deref());
+            tracing::warn!(user=ses.user_id, addr=?ses.remote_addr, ban_secs=ban.as_secs(), "Too many rate limit violations. Disconnecting session.");
+            return Err(SessionClose::RateLimited(ban.as_secs()).into());
+        }
+        send_user_error!(&ses.user_id, server, Topic::None, reason);
+        return Ok(true);
+    }
     let res = match req.cmd.as_ref() {
         None => {
             send_user_error!(&ses.user_id, server, Topic::None, format!("Missing command from client: {:?}", req));
             Ok(())
         }
+        Some(cmd) if !api_token_allows(cmd, ses) || !guest_allows(cmd, ses, server) => {
+            tracing::info!(user=ses.user_id, "Restricted session tried a command it's not allowed to: {:?}", cmd);
+            send_user_error!(&ses.user_id, server, Topic::None, "Command not allowed in this session".to_string());
+            Ok(())
+        }
         Some(cmd) => match cmd {
             Cmd::OpenNavigationPage(data) => msg_open_navigation_page(&data, ses, server).await,
             Cmd::OpenMediaFile(data) => msg_open_media_file(&data, ses, server).await,
@@ -720,13 +1715,39 @@ pub async fn msg_dispatch(req: &ClientToServerCmd, ses: &mut UserSession, server
             Cmd::AddComment(data) => msg_add_comment(&data, ses, server).await,
             Cmd::EditComment(data) => msg_edit_comment(&data, ses, server).await,
             Cmd::DelComment(data) => msg_del_comment(&data, ses, server).await,
+            Cmd::ImportComments(data) => msg_import_comments(&data, ses, server).await,
+            Cmd::ExportReviewReport(data) => msg_export_review_report(&data, ses, server).await,
+            Cmd::Search(data) => msg_search(&data, ses, server).await,
+            Cmd::GetCommentHistory(data) => msg_get_comment_history(&data, ses, server).await,
+            Cmd::RestoreComment(data) => msg_restore_comment(&data, ses, server).await,
+            Cmd::SetMediaFileApproval(data) => msg_set_media_file_approval(&data, ses, server).await,
+            Cmd::SetMediaFileLocked(data) => msg_set_media_file_locked(&data, ses, server).await,
             Cmd::AddSubtitle(data) => msg_add_subtitle(&data, ses, server).await,
             Cmd::EditSubtitleInfo(data) => msg_edit_subtitle_info(&data, ses, server).await,
             Cmd::DelSubtitle(data) => msg_del_subtitle(&data, ses, server).await,
             Cmd::ListMyMessages(data) => msg_list_my_messages(&data, ses, server).await,
+            Cmd::SetMessagesSeen(data) => msg_set_messages_seen(&data, ses, server).await,
+            Cmd::DelMessages(data) => msg_del_messages(&data, ses, server).await,
+            Cmd::GetMessagePrefs(data) => msg_get_message_prefs(&data, ses, server).await,
+            Cmd::SetMessagePrefs(data) => msg_set_message_prefs(&data, ses, server).await,
+            Cmd::CreateApiToken(data) => msg_create_api_token(&data, ses, server).await,
+            Cmd::ListApiTokens(data) => msg_list_api_tokens(&data, ses, server).await,
+            Cmd::RevokeApiToken(data) => msg_revoke_api_token(&data, ses, server).await,
+            Cmd::CreateShareLink(data) => msg_create_share_link(&data, ses, server).await,
+            Cmd::ListShareLinks(data) => msg_list_share_links(&data, ses, server).await,
+            Cmd::RevokeShareLink(data) => msg_revoke_share_link(&data, ses, server).await,
+            Cmd::GrantMediaFileAccess(data) => msg_grant_media_file_access(&data, ses, server).await,
+            Cmd::ListMediaFileGrants(data) => msg_list_media_file_grants(&data, ses, server).await,
+            Cmd::RevokeMediaFileAccess(data) => msg_revoke_media_file_access(&data, ses, server).await,
+            Cmd::ListSessions(data) => msg_list_sessions(&data, ses, server).await,
+            Cmd::DisconnectSession(data) => msg_disconnect_session(&data, ses, server).await,
             Cmd::JoinCollab(data) => msg_join_collab(&data, ses, server).await,
             Cmd::LeaveCollab(data) => msg_leave_collab(&data, ses, server).await,
             Cmd::CollabReport(data) => msg_collab_report(&data, ses, server).await,
+            Cmd::CollabSetPresenter(data) => msg_collab_set_presenter(&data, ses, server).await,
+            Cmd::CollabRequestControl(data) => msg_collab_request_control(&data, ses, server).await,
+            Cmd::CollabChat(data) => msg_collab_chat(&data, ses, server).await,
+            Cmd::SetEmailNotifications(data) => msg_set_email_notifications(&data, ses, server).await,
             Cmd::OrganizerCmd(data) => msg_organizer_cmd(&data, ses, server).await,
             Cmd::MoveToFolder(data) => msg_move_to_folder(&data, ses, server).await,
             Cmd::ReorderItems(data) => msg_reorder_items(&data, ses, server).await,
@@ -0,0 +1,139 @@
+//! Resuming websocket sessions after a dropped connection.
+//!
+//! When a connection drops without a close frame (e.g. a laptop goes to sleep), the session is
+//! kept around for a grace period. It stays linked to its media file and collab, and keeps
+//! collecting events. The client can reconnect with `?resume=<token>&received=<count>`, where
+//! the token is from `Welcome` and count is the number of messages it has received after `Welcome`.
+//! The new connection then takes over the session, and gets the messages it missed replayed
+//! in order from a bounded per-session buffer. If they don't fit in the buffer anymore, or the
+//! new connection authenticated with different privileges (admin flag, groups, API token scopes),
+//! the client gets a fresh session instead (`Welcome.resumed` is false).
+
+use std::collections::VecDeque;
+
+use tokio::sync::{mpsc, oneshot};
+use warp::ws::WebSocket;
+
+use super::{server_state::ServerState, AuthenticatedUser, ClientInfo, WsFraming, WsMsg};
+
+/// URL query parameter names
+pub const QUERY_RESUME_TOKEN: &str = "resume";
+pub const QUERY_RECEIVED_COUNT: &str = "received";
+
+const REPLAY_MAX_MESSAGES: usize = 500;
+const REPLAY_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// A new connection, asking to take over a session
+pub(crate) struct Takeover {
+    pub ws: WebSocket,
+    pub framing: WsFraming,
+    pub client: ClientInfo,
+    pub auth: AuthenticatedUser,
+    pub received: u64,
+    pub reject: oneshot::Sender<WebSocket>,     // Session gives the socket back if it can't be resumed
+}
+
+pub(crate) type TakeoverSender = mpsc::UnboundedSender<Takeover>;
+
+/// Buffer size of a message (JSON size, binary is smaller)
+fn msg_size(msg: &WsMsg) -> usize {
+    msg.json().map_or(0, str::len)
+}
+
+/// Copies of the latest messages sent to a client, numbered from 1, for replaying after a reconnect
+#[derive(Default)]
+pub(crate) struct ReplayBuffer {
+    sent: u64,                  // Number of messages sent (or queued while disconnected)
+    msgs: VecDeque<WsMsg>,      // Latest of them, up to the limits
+    bytes: usize,
+}
+
+impl ReplayBuffer {
+
+    pub fn push(&mut self, msg: WsMsg) {
+        self.sent += 1;
+        self.bytes += msg_size(&msg);
+        self.msgs.push_back(msg);
+        while self.msgs.len() > REPLAY_MAX_MESSAGES || self.bytes > REPLAY_MAX_BYTES {
+            match self.msgs.pop_front() {
+                Some(m) => self.bytes -= msg_size(&m),
+                None => break,
+            }
+        }
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// True if any messages after the first `seq` ones have been dropped from the buffer
+    pub fn lost_after(&self, seq: u64) -> bool {
+        self.sent - (self.msgs.len() as u64) > seq
+    }
+
+    /// Messages after the first `received` ones, or None if some of them are no longer kept
+    pub fn since(&self, received: u64) -> Option<Vec<WsMsg>> {
+        if received > self.sent || self.lost_after(received) {
+            return None;
+        }
+        let skip = self.msgs.len() - (self.sent - received) as usize;
+        Some(self.msgs.iter().skip(skip).cloned().collect())
+    }
+}
+
+/// Hand a new connection over to an existing session, if the token matches one of the user's sessions.
+/// Returns the socket back if there's no such session, or if it can't be resumed.
+pub(crate) async fn try_resume(server: &ServerState, token: &str, auth: &AuthenticatedUser, ws: WebSocket, framing: WsFraming, client: ClientInfo, received: u64) -> Option<WebSocket>
+{
+    let (reject, reject_rx) = oneshot::channel();
+    let takeover = Takeover { ws, framing, client, auth: auth.clone(), received, reject };
+    if let Err(t) = server.take_over_session(token, &auth.user_id, takeover) {
+        return Some(t.ws);
+    }
+    // Sender is dropped without an answer if the session took the connection
+    reject_rx.await.ok()
+}
+
+/// True if the new connection has the same privileges as the session it's resuming
+pub(crate) fn same_privileges(a: &AuthenticatedUser, b: &AuthenticatedUser) -> bool {
+    let sorted = |g: &[String]| { let mut g = g.to_vec(); g.sort(); g };
+    a.is_admin == b.is_admin
+        && a.token_scopes == b.token_scopes
+        && a.api_token.as_ref().map(|t| t.id) == b.api_token.as_ref().map(|t| t.id)
+        && sorted(&a.groups) == sorted(&b.groups)
+        && a.guest_link.as_ref().map(|l| l.id) == b.guest_link.as_ref().map(|l| l.id)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_buffer() {
+        use lib_clapshot_grpc::proto::client::server_to_client_cmd::{Cmd, Error};
+        let msg = |s: String| crate::api_server::server_state::cmd_to_ws_msg(Cmd::Error(Error { msg: s })).unwrap();
+        let mut buf = ReplayBuffer::default();
+        assert_eq!(buf.since(0).unwrap().len(), 0);
+        assert!(buf.since(1).is_none());
+
+        for i in 1..=REPLAY_MAX_MESSAGES + 10 {
+            buf.push(msg(i.to_string()));
+        }
+        let n = buf.sent();
+        assert_eq!(n, REPLAY_MAX_MESSAGES as u64 + 10);
+        assert_eq!(buf.since(n).unwrap().len(), 0);
+        let last3 = buf.since(n - 3).unwrap();
+        assert_eq!(last3.iter().map(|m| m.json().unwrap()).collect::<Vec<_>>(), ["508", "509", "510"].map(|n| format!(r#"{{"error":{{"msg":"{}"}}}}"#, n)));
+        assert_eq!(buf.since(10).unwrap().len(), REPLAY_MAX_MESSAGES);
+        assert!(buf.since(9).is_none());
+        assert!(!buf.lost_after(10) && buf.lost_after(9));
+
+        // Byte limit
+        let mut buf = ReplayBuffer::default();
+        buf.push(msg("x".repeat(REPLAY_MAX_BYTES / 2 + 1)));
+        buf.push(msg("y".repeat(REPLAY_MAX_BYTES / 2 + 1)));
+        assert!(buf.since(0).is_none());
+        assert_eq!(buf.since(1).unwrap().len(), 1);
+    }
+}
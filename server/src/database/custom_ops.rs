@@ -46,11 +46,82 @@ impl models::User {
             Err(e) => { Err(e) }
         }
     }
+
+    /// Set (or clear) user's e-mail address for notifications.
+    pub fn set_email(conn: &mut PooledConnection, uid: &str, new_email: Option<&str>) -> EmptyDBResult
+    {
+        use schema::users::dsl::*;
+        if new_email.is_some_and(|e| !e.contains('@') || e.chars().any(char::is_whitespace)) {
+            return Err(DBError::Other(anyhow::anyhow!("Invalid e-mail address: '{}'", new_email.unwrap_or_default())));
+        }
+        let n = retry_if_db_locked!({
+            diesel::update(users.filter(id.eq(uid)))
+                .set(email.eq(new_email))
+                .execute(conn)
+        })?;
+        if n == 0 { return Err(DBError::NotFound()); }
+        Ok(())
+    }
+
+    /// Set which e-mail notifications the user wants: "all", "immediate", "digest" or "none".
+    pub fn set_email_notifications(conn: &mut PooledConnection, uid: &str, mode: &str) -> EmptyDBResult
+    {
+        use schema::users::dsl::*;
+        if !["all", "immediate", "digest", "none"].contains(&mode) {
+            return Err(DBError::Other(anyhow::anyhow!("Invalid e-mail notification mode: '{}'", mode)));
+        }
+        let n = retry_if_db_locked!({
+            diesel::update(users.filter(id.eq(uid)))
+                .set(email_notifications.eq(mode))
+                .execute(conn)
+        })?;
+        if n == 0 { return Err(DBError::NotFound()); }
+        Ok(())
+    }
+
+    /// Remember when the user was last sent a comment digest.
+    pub fn set_email_digest_sent(conn: &mut PooledConnection, uid: &str, t: chrono::NaiveDateTime) -> EmptyDBResult
+    {
+        use schema::users::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(users.filter(id.eq(uid)))
+                .set(email_digest_sent.eq(t))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Get users that have an e-mail address and want digests.
+    pub fn get_digest_recipients(conn: &mut PooledConnection) -> DBResult<Vec<models::User>>
+    {
+        use schema::users::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            users.filter(email.is_not_null())
+                .filter(email_notifications.eq_any(["all", "digest"]))
+                .load::<models::User>(conn)
+        }))
+    }
 }
 
 
 impl models::MediaFile {
 
+    /// Get media files by ID, newest first (like `get_by_user`), paginated.
+    ///
+    /// # Arguments
+    /// * `ids` - IDs of the media files
+    /// * `pg` - Paging
+    pub fn get_many_paged(conn: &mut PooledConnection, ids: &[String], pg: super::DBPaging) -> DBResult<Vec<models::MediaFile>>
+    {
+        use schema::media_files::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            media_files.filter(id.eq_any(ids))
+                .order((added_time.desc(), id.asc()))
+                .offset(pg.offset()).limit(pg.limit())
+                .load::<models::MediaFile>(conn)
+        }))
+    }
+
     /// Set the recompressed flag for a media file.
     ///
     /// # Arguments
@@ -67,6 +138,39 @@ impl models::MediaFile {
         Ok(())
     }
 
+    /// Lock (finalize) or unlock a media file, and log it for auditing.
+    ///
+    /// # Arguments
+    /// * `vid` - Id of the media file
+    /// * `lock` - True to lock, false to unlock
+    /// * `by_user` - Who did it (None = Organizer or server itself)
+    ///
+    /// # Returns
+    /// * `Res<bool>` - True if the lock state changed, false if it was already as requested
+    pub fn set_locked(conn: &mut PooledConnection, vid: &str, lock: bool, by_user: Option<&str>) -> DBResult<bool>
+    {
+        use schema::media_files::dsl::*;
+        retry_if_db_locked!(conn.transaction(|conn| {
+            let mf = models::MediaFile::get(conn, &vid.to_string())?;
+            if mf.locked.is_some() == lock {
+                return Ok(false);
+            }
+            let (new_locked, new_locked_by) = match lock {
+                true => (Some(Local::now().naive_local()), by_user),
+                false => (None, None),
+            };
+            diesel::update(media_files.filter(id.eq(vid)))
+                .set((locked.eq(new_locked), locked_by.eq(new_locked_by)))
+                .execute(conn)?;
+            models::MediaFileLockLogEntry::insert(conn, &models::MediaFileLockLogEntryInsert {
+                media_file_id: vid.to_string(),
+                user_id: by_user.map(|u| u.to_string()),
+                locked: lock,
+            })?;
+            Ok::<_, DBError>(true)
+        }))
+    }
+
     /// Set default subtitle id for a media file.
     ///
     /// # Arguments
@@ -174,20 +278,326 @@ impl models::MediaFile {
 
 impl models::Comment {
 
-    /// Edit a comment (change text).
+    /// Store current contents of a comment as a revision, before changing it.
+    fn save_revision(conn: &mut PooledConnection, old: &models::Comment, action: &str, user: Option<&str>) -> EmptyDBResult
+    {
+        models::CommentRevision::insert(conn, &models::CommentRevisionInsert {
+            comment_id: old.id,
+            user_id: user.map(|u| u.to_string()),
+            action: action.to_string(),
+            comment: old.comment.clone(),
+            annotation: old.annotation.clone(),
+        })?;
+        Ok(())
+    }
+
+    /// Edit a comment (change text, and optionally vector annotation).
+    /// Previous version is saved in revision history.
     ///
     /// # Arguments
     /// * `comment_id` - ID of the comment
     /// * `new_comment` - New text of the comment
+    /// * `new_annotation` - If Some, replace annotation (Some(None) = remove it)
+    /// * `editor` - User ID of the editor
     ///
     /// # Returns
     /// * `Res<bool>` - True if comment was edited, false if it was not found
-    pub fn edit(conn: &mut PooledConnection, comment_id: i32, new_comment: &str) -> DBResult<bool>
+    pub fn edit(conn: &mut PooledConnection, comment_id: i32, new_comment: &str, new_annotation: Option<Option<&str>>, editor: Option<&str>) -> DBResult<bool>
     {
         use schema::comments::dsl::*;
-        to_db_res(retry_if_db_locked!({
+        retry_if_db_locked!(conn.transaction(|conn| {
+            let old = match models::Comment::get(conn, &comment_id) {
+                Ok(c) => c,
+                Err(DBError::NotFound()) => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            Self::save_revision(conn, &old, "edit", editor)?;
+            let new_ann = new_annotation.unwrap_or(old.annotation.as_deref());
             diesel::update(comments.filter(id.eq(comment_id)))
-                .set((comment.eq(new_comment), edited.eq(diesel::dsl::now))).execute(conn).map(|x| x > 0)
+                .set((comment.eq(new_comment), annotation.eq(new_ann), edited.eq(diesel::dsl::now)))
+                .execute(conn).map(|x| x > 0).map_err(DBError::from)
+        }))
+    }
+
+    /// Soft delete a comment. It remains in the database (as a placeholder for
+    /// reply threads), and can be restored from revision history.
+    ///
+    /// # Returns
+    /// * `Res<bool>` - True if comment was deleted, false if it was not found or already deleted
+    pub fn soft_delete(conn: &mut PooledConnection, comment_id: i32, deleter: Option<&str>) -> DBResult<bool>
+    {
+        use schema::comments::dsl::*;
+        retry_if_db_locked!(conn.transaction(|conn| {
+            let old = match models::Comment::get(conn, &comment_id) {
+                Ok(c) if c.deleted.is_none() => c,
+                Ok(_) | Err(DBError::NotFound()) => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            Self::save_revision(conn, &old, "delete", deleter)?;
+            diesel::update(comments.filter(id.eq(comment_id)))
+                .set(deleted.eq(diesel::dsl::now))
+                .execute(conn).map(|x| x > 0).map_err(DBError::from)
+        }))
+    }
+
+    /// Restore a comment: undelete it, and optionally revert its contents to an earlier revision.
+    /// Current version is saved in revision history.
+    ///
+    /// # Arguments
+    /// * `comment_id` - ID of the comment
+    /// * `revision_id` - If given, revert text and annotation to this revision (must belong to the comment)
+    /// * `restorer` - User ID of the restorer
+    ///
+    /// # Returns
+    /// * `Res<bool>` - True if comment was restored, false if it was not found
+    pub fn restore(conn: &mut PooledConnection, comment_id: i32, revision_id: Option<i32>, restorer: Option<&str>) -> DBResult<bool>
+    {
+        use schema::comments::dsl::*;
+        retry_if_db_locked!(conn.transaction(|conn| {
+            let old = match models::Comment::get(conn, &comment_id) {
+                Ok(c) => c,
+                Err(DBError::NotFound()) => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            let (new_text, new_ann) = match revision_id {
+                Some(rid) => {
+                    let rev = models::CommentRevision::get(conn, &rid)?;
+                    if rev.comment_id != comment_id {
+                        return Err(DBError::Other(anyhow::anyhow!("Revision {} is not of comment {}", rid, comment_id)));
+                    }
+                    (rev.comment, rev.annotation)
+                },
+                None => (old.comment.clone(), old.annotation.clone()),
+            };
+            Self::save_revision(conn, &old, "restore", restorer)?;
+            diesel::update(comments.filter(id.eq(comment_id)))
+                .set((comment.eq(new_text), annotation.eq(new_ann), deleted.eq(None::<chrono::NaiveDateTime>)))
+                .execute(conn).map(|x| x > 0).map_err(DBError::from)
+        }))
+    }
+
+    /// Filter a list of comments (e.g. all comments of a media file) for showing to users.
+    /// Soft deleted comments that still have visible replies are kept as
+    /// placeholders with their contents blanked, other deleted comments are dropped.
+    pub fn visible_to_users(cmts: Vec<models::Comment>) -> Vec<models::Comment>
+    {
+        let mut visible: std::collections::HashSet<i32> = cmts.iter().filter(|c| c.deleted.is_none()).map(|c| c.id).collect();
+        loop {  // Repeat to keep deleted ancestors of deeper replies
+            let parents: Vec<i32> = cmts.iter()
+                .filter(|c| visible.contains(&c.id))
+                .filter_map(|c| c.parent_id)
+                .filter(|p| !visible.contains(p))
+                .collect();
+            if parents.is_empty() { break; }
+            visible.extend(parents);
+        }
+        cmts.into_iter().filter(|c| visible.contains(&c.id)).map(|mut c| {
+            if c.deleted.is_some() {
+                c.comment = String::new();
+                c.drawing = None;
+                c.annotation = None;
+            }
+            c
+        }).collect()
+    }
+}
+
+impl models::MediaFileLockLogEntry {
+
+    /// Get lock / unlock audit log of a media file, oldest first.
+    pub fn get_by_media_file(conn: &mut PooledConnection, mf_id: &str) -> DBResult<Vec<models::MediaFileLockLogEntry>>
+    {
+        use schema::media_file_lock_log::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            media_file_lock_log.filter(media_file_id.eq(mf_id)).order((created.asc(), id.asc())).load::<models::MediaFileLockLogEntry>(conn)
+        }))
+    }
+}
+
+impl models::CollabSession {
+
+    /// Mark a collab session as ended (if it isn't already).
+    pub fn set_ended(conn: &mut PooledConnection, sess_id: i32) -> EmptyDBResult
+    {
+        use schema::collab_sessions::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(collab_sessions.filter(id.eq(sess_id)).filter(ended.is_null()))
+                .set(ended.eq(diesel::dsl::now))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Mark all sessions that are still open as ended. Used on startup, as
+    /// collab sessions don't survive a server restart.
+    ///
+    /// # Returns
+    /// * `Res<usize>` - Number of sessions closed
+    pub fn end_all_open(conn: &mut PooledConnection) -> DBResult<usize>
+    {
+        use schema::collab_sessions::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::update(collab_sessions.filter(ended.is_null()))
+                .set(ended.eq(diesel::dsl::now))
+                .execute(conn)
+        }))
+    }
+}
+
+impl models::CollabSessionParticipant {
+
+    /// Record a user as a participant of a collab session. Does nothing if already recorded.
+    pub fn add(conn: &mut PooledConnection, sess_id: i32, uid: &str) -> EmptyDBResult
+    {
+        use schema::collab_session_participants::dsl::*;
+        retry_if_db_locked!({
+            diesel::insert_into(collab_session_participants)
+                .values(&models::CollabSessionParticipantInsert { collab_session_id: sess_id, user_id: uid.to_string() })
+                .on_conflict((collab_session_id, user_id))
+                .do_nothing()
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Get participants of a collab session, in join order.
+    pub fn get_by_session(conn: &mut PooledConnection, sess_id: i32) -> DBResult<Vec<models::CollabSessionParticipant>>
+    {
+        use schema::collab_session_participants::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            collab_session_participants.filter(collab_session_id.eq(sess_id)).order((joined.asc(), id.asc())).load::<models::CollabSessionParticipant>(conn)
+        }))
+    }
+}
+
+impl models::CollabChatMessage {
+
+    /// Get saved chat transcript of a collab session, oldest first.
+    pub fn get_by_session(conn: &mut PooledConnection, sess_id: i32) -> DBResult<Vec<models::CollabChatMessage>>
+    {
+        use schema::collab_chat_messages::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            collab_chat_messages.filter(collab_session_id.eq(sess_id)).order((created.asc(), id.asc())).load::<models::CollabChatMessage>(conn)
+        }))
+    }
+}
+
+impl models::WebhookDelivery {
+
+    /// Get pending deliveries whose next attempt is due, oldest first.
+    pub fn get_due(conn: &mut PooledConnection, now: chrono::NaiveDateTime, max: i64) -> DBResult<Vec<models::WebhookDelivery>>
+    {
+        use schema::webhook_deliveries::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            webhook_deliveries
+                .filter(status.eq("pending"))
+                .filter(next_attempt.le(now))
+                .order((next_attempt.asc(), id.asc()))
+                .limit(max)
+                .load::<models::WebhookDelivery>(conn)
+        }))
+    }
+
+    /// Get deliveries with given status ("pending", "delivered" or "failed"), newest first.
+    pub fn get_by_status(conn: &mut PooledConnection, st: &str, pg: super::DBPaging) -> DBResult<Vec<models::WebhookDelivery>>
+    {
+        use schema::webhook_deliveries::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            webhook_deliveries
+                .filter(status.eq(st))
+                .order((created.desc(), id.desc()))
+                .offset(pg.offset())
+                .limit(pg.limit())
+                .load::<models::WebhookDelivery>(conn)
+        }))
+    }
+
+    /// Record the result of a delivery attempt.
+    ///
+    /// # Arguments
+    /// * `did` - ID of the delivery
+    /// * `new_status` - "pending" (will be retried), "delivered" or "failed" (gave up)
+    /// * `code` - HTTP status code, if a response was received
+    /// * `err` - Error message, if any
+    /// * `retry_at` - When to try again, if still pending
+    pub fn record_attempt(conn: &mut PooledConnection, did: i32, new_status: &str, code: Option<i32>, err: Option<&str>, retry_at: chrono::NaiveDateTime) -> EmptyDBResult
+    {
+        use schema::webhook_deliveries::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(webhook_deliveries.filter(id.eq(did)))
+                .set((
+                    status.eq(new_status),
+                    attempts.eq(attempts + 1),
+                    last_attempt.eq(diesel::dsl::now),
+                    response_code.eq(code),
+                    last_error.eq(err),
+                    next_attempt.eq(retry_at)))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+}
+
+impl models::MediaFileApproval {
+
+    /// Set (insert or replace) approval state of a media file by a user.
+    ///
+    /// # Arguments
+    /// * `mf_id` - ID of the media file
+    /// * `uid` - ID of the reviewing user
+    /// * `new_state` - "pending", "approved" or "changes_requested"
+    /// * `new_note` - Optional note
+    ///
+    /// # Returns
+    /// * `Res<models::MediaFileApproval>` - The new approval state
+    pub fn set(conn: &mut PooledConnection, mf_id: &str, uid: &str, new_state: &str, new_note: Option<&str>) -> DBResult<models::MediaFileApproval>
+    {
+        use schema::media_file_approvals::dsl::*;
+        let ins = models::MediaFileApprovalInsert {
+            media_file_id: mf_id.to_string(),
+            user_id: uid.to_string(),
+            state: new_state.to_string(),
+            note: new_note.map(|n| n.to_string()),
+        };
+        to_db_res(retry_if_db_locked!({
+            diesel::insert_into(media_file_approvals).values(&ins)
+                .on_conflict((media_file_id, user_id))
+                .do_update()
+                .set((state.eq(new_state), note.eq(new_note), updated.eq(diesel::dsl::now)))
+                .get_result::<models::MediaFileApproval>(conn)
+        }))
+    }
+}
+
+impl models::Comment {
+
+    /// Get comments others have posted on a user's media files after given time,
+    /// for e-mail digests. Deleted comments are excluded. Oldest first.
+    pub fn get_new_on_users_media(conn: &mut PooledConnection, owner_id: &str, since: chrono::NaiveDateTime) -> DBResult<Vec<(models::Comment, Option<String>)>>
+    {
+        use schema::comments::dsl as c;
+        use schema::media_files::dsl as mf;
+        to_db_res(retry_if_db_locked!({
+            c::comments.inner_join(mf::media_files)
+                .filter(mf::user_id.eq(owner_id))
+                .filter(c::user_id.ne(owner_id).or(c::user_id.is_null()))
+                .filter(c::created.gt(since))
+                .filter(c::deleted.is_null())
+                .order((c::created.asc(), c::id.asc()))
+                .select((models::Comment::as_select(), mf::title))
+                .load::<(models::Comment, Option<String>)>(conn)
+        }))
+    }
+}
+
+impl models::CommentRevision {
+
+    /// Get revision history of a comment, oldest first.
+    pub fn get_by_comment(conn: &mut PooledConnection, cid: i32) -> DBResult<Vec<models::CommentRevision>>
+    {
+        use schema::comment_revisions::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            comment_revisions.filter(comment_id.eq(cid)).order((created.asc(), id.asc())).load::<models::CommentRevision>(conn)
         }))
     }
 }
@@ -213,6 +623,79 @@ impl models::Message {
         }))
     }
 
+    /// Get a page of user's messages, newest first.
+    ///
+    /// # Arguments
+    /// * `uid` - ID of the user
+    /// * `unseen_only` - If true, skip messages that have already been seen
+    /// * `pg` - Paging
+    pub fn get_by_user_filtered(conn: &mut PooledConnection, uid: &str, unseen_only: bool, pg: super::DBPaging) -> DBResult<Vec<models::Message>>
+    {
+        use schema::messages::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            let mut q = messages.filter(user_id.eq(uid)).into_boxed();
+            if unseen_only { q = q.filter(seen.eq(false)); }
+            q.order((created.desc(), id.asc())).offset(pg.offset()).limit(pg.limit()).load::<models::Message>(conn)
+        }))
+    }
+
+    /// Count user's messages.
+    ///
+    /// # Returns
+    /// * `DBResult<(i64, i64)>` - Total number of messages, and the number of unseen ones
+    pub fn count_by_user(conn: &mut PooledConnection, uid: &str) -> DBResult<(i64, i64)>
+    {
+        use schema::messages::dsl::*;
+        let total = to_db_res(retry_if_db_locked!({
+            messages.filter(user_id.eq(uid)).count().get_result::<i64>(conn)
+        }))?;
+        let unseen = to_db_res(retry_if_db_locked!({
+            messages.filter(user_id.eq(uid)).filter(seen.eq(false)).count().get_result::<i64>(conn)
+        }))?;
+        Ok((total, unseen))
+    }
+
+    /// Set seen status of user's messages. Messages of other users are left untouched.
+    ///
+    /// # Arguments
+    /// * `uid` - ID of the user
+    /// * `msg_ids` - Messages to update, or None for all of the user's messages
+    /// * `new_status` - New status
+    ///
+    /// # Returns
+    /// * `DBResult<usize>` - Number of messages updated
+    pub fn set_seen_by_user(conn: &mut PooledConnection, uid: &str, msg_ids: Option<&[i32]>, new_status: bool) -> DBResult<usize>
+    {
+        use schema::messages::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            match msg_ids {
+                Some(ids) => diesel::update(messages.filter(user_id.eq(uid)).filter(id.eq_any(ids)))
+                    .set(seen.eq(new_status)).execute(conn),
+                None => diesel::update(messages.filter(user_id.eq(uid)))
+                    .set(seen.eq(new_status)).execute(conn),
+            }
+        }))
+    }
+
+    /// Delete user's messages. Messages of other users are left untouched.
+    ///
+    /// # Arguments
+    /// * `uid` - ID of the user
+    /// * `msg_ids` - Messages to delete, or None to clear all of the user's messages
+    ///
+    /// # Returns
+    /// * `DBResult<usize>` - Number of messages deleted
+    pub fn delete_by_user(conn: &mut PooledConnection, uid: &str, msg_ids: Option<&[i32]>) -> DBResult<usize>
+    {
+        use schema::messages::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            match msg_ids {
+                Some(ids) => diesel::delete(messages.filter(user_id.eq(uid)).filter(id.eq_any(ids))).execute(conn),
+                None => diesel::delete(messages.filter(user_id.eq(uid))).execute(conn),
+            }
+        }))
+    }
+
     /// Get all messages for a given comment.
     ///
     /// # Arguments
@@ -229,3 +712,84 @@ impl models::Message {
         }))
     }
 }
+
+impl models::UserMessagePref {
+
+    /// Get user's preference for a message type, if one has been stored.
+    pub fn get_for(conn: &mut PooledConnection, uid: &str, event: &str) -> DBResult<Option<models::UserMessagePref>>
+    {
+        use schema::user_message_prefs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            user_message_prefs.filter(user_id.eq(uid)).filter(event_name.eq(event))
+                .first::<models::UserMessagePref>(conn).optional()
+        }))
+    }
+
+    /// Insert or update user's preference for a message type.
+    pub fn set(conn: &mut PooledConnection, uid: &str, event: &str, new_persist: bool, new_push: bool) -> DBResult<models::UserMessagePref>
+    {
+        use schema::user_message_prefs::dsl::*;
+        let ins = models::UserMessagePrefInsert {
+            user_id: uid.to_string(),
+            event_name: event.to_string(),
+            persist: new_persist,
+            push: new_push,
+        };
+        to_db_res(retry_if_db_locked!({
+            diesel::insert_into(user_message_prefs).values(&ins)
+                .on_conflict((user_id, event_name))
+                .do_update()
+                .set((persist.eq(new_persist), push.eq(new_push)))
+                .get_result::<models::UserMessagePref>(conn)
+        }))
+    }
+}
+
+impl models::ApiToken {
+
+    /// Find token by the hash of its secret.
+    pub fn get_by_hash(conn: &mut PooledConnection, hash: &str) -> DBResult<Option<models::ApiToken>>
+    {
+        use schema::api_tokens::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            api_tokens.filter(token_hash.eq(hash)).first::<models::ApiToken>(conn).optional()
+        }))
+    }
+
+    /// Record token use.
+    pub fn set_last_used(conn: &mut PooledConnection, tid: i32, ts: chrono::NaiveDateTime) -> DBResult<()>
+    {
+        use schema::api_tokens::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::update(api_tokens.filter(id.eq(tid))).set(last_used.eq(ts)).execute(conn)
+        }))?;
+        Ok(())
+    }
+}
+
+impl models::ShareLink {
+
+    /// Find link by the secret key in its URL.
+    pub fn get_by_key(conn: &mut PooledConnection, key: &str) -> DBResult<Option<models::ShareLink>>
+    {
+        use schema::share_links::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            share_links.filter(link_key.eq(key)).first::<models::ShareLink>(conn).optional()
+        }))
+    }
+}
+
+impl models::MediaFileGrant {
+
+    /// Get grants for given user directly or through any of given groups, on all media files.
+    pub fn get_for_principal(conn: &mut PooledConnection, uid: &str, groups: &[String]) -> DBResult<Vec<models::MediaFileGrant>>
+    {
+        use schema::media_file_grants::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            media_file_grants
+                .filter(user_id.eq(uid).or(group_name.eq_any(groups)))
+                .order_by(created.desc())
+                .load::<models::MediaFileGrant>(conn)
+        }))
+    }
+}
@@ -14,6 +14,7 @@ pub mod models;
 pub mod error;
 pub mod migration_solver;
 pub mod db_backup;
+pub mod search;
 
 #[cfg(test)]
 pub mod tests;
@@ -293,8 +294,19 @@ crate::implement_basic_query_traits!(models::User, models::UserInsert, users, St
 crate::implement_basic_query_traits!(models::MediaType, models::MediaType, media_types, String, id.desc());
 crate::implement_basic_query_traits!(models::MediaFile, models::MediaFileInsert, media_files, String, added_time.desc());
 crate::implement_basic_query_traits!(models::Comment, models::CommentInsert, comments, i32, created.desc());
+crate::implement_basic_query_traits!(models::CommentRevision, models::CommentRevisionInsert, comment_revisions, i32, created.desc());
+crate::implement_basic_query_traits!(models::MediaFileLockLogEntry, models::MediaFileLockLogEntryInsert, media_file_lock_log, i32, created.desc());
+crate::implement_basic_query_traits!(models::CollabSession, models::CollabSessionInsert, collab_sessions, i32, started.desc());
+crate::implement_basic_query_traits!(models::CollabSessionParticipant, models::CollabSessionParticipantInsert, collab_session_participants, i32, joined.desc());
+crate::implement_basic_query_traits!(models::CollabChatMessage, models::CollabChatMessageInsert, collab_chat_messages, i32, created.desc());
+crate::implement_basic_query_traits!(models::MediaFileApproval, models::MediaFileApprovalInsert, media_file_approvals, i32, updated.desc());
 crate::implement_basic_query_traits!(models::Message, models::MessageInsert, messages, i32, created.desc());
 crate::implement_basic_query_traits!(models::Subtitle, models::SubtitleInsert, subtitles, i32, added_time.desc());
+crate::implement_basic_query_traits!(models::WebhookDelivery, models::WebhookDeliveryInsert, webhook_deliveries, i32, created.desc());
+crate::implement_basic_query_traits!(models::UserMessagePref, models::UserMessagePrefInsert, user_message_prefs, i32, id.desc());
+crate::implement_basic_query_traits!(models::ApiToken, models::ApiTokenInsert, api_tokens, i32, created.desc());
+crate::implement_basic_query_traits!(models::ShareLink, models::ShareLinkInsert, share_links, i32, created.desc());
+crate::implement_basic_query_traits!(models::MediaFileGrant, models::MediaFileGrantInsert, media_file_grants, i32, created.desc());
 
 crate::implement_update_traits!(models::User, users, String);
 crate::implement_update_traits!(models::MediaFile, media_files, String);
@@ -312,6 +324,10 @@ crate::implement_query_by_user_traits!(models::User, users, id, created.desc());
 crate::implement_query_by_user_traits!(models::MediaFile, media_files, user_id, added_time.desc());
 crate::implement_query_by_user_traits!(models::Comment, comments, user_id, created.desc());
 crate::implement_query_by_user_traits!(models::Message, messages, user_id, created.desc());
+crate::implement_query_by_user_traits!(models::MediaFileApproval, media_file_approvals, user_id, updated.desc());
+crate::implement_query_by_user_traits!(models::UserMessagePref, user_message_prefs, user_id, event_name.asc());
+crate::implement_query_by_user_traits!(models::ApiToken, api_tokens, user_id, created.desc());
+crate::implement_query_by_user_traits!(models::ShareLink, share_links, user_id, created.desc());
 
 
 
@@ -322,3 +338,6 @@ pub trait DbQueryByMediaFile: Sized {
 crate::implement_query_by_media_file_traits!(models::Comment, comments, media_file_id, created.desc());
 crate::implement_query_by_media_file_traits!(models::Message, messages, media_file_id, created.desc());
 crate::implement_query_by_media_file_traits!(models::Subtitle, subtitles, media_file_id, added_time.desc());
+crate::implement_query_by_media_file_traits!(models::CollabSession, collab_sessions, media_file_id, started.desc());
+crate::implement_query_by_media_file_traits!(models::MediaFileApproval, media_file_approvals, media_file_id, updated.desc());
+crate::implement_query_by_media_file_traits!(models::MediaFileGrant, media_file_grants, media_file_id, created.desc());
@@ -14,6 +14,12 @@ pub struct User {
 
     #[serde(with = "ts_seconds")]
     pub created: chrono::NaiveDateTime,
+
+    pub email: Option<String>,
+    pub email_notifications: String,    // "all", "immediate", "digest" or "none"
+
+    #[serde(with = "ts_seconds_option")]
+    pub email_digest_sent: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -56,6 +62,8 @@ pub struct MediaFile {
     pub fps: Option<String>,
     pub raw_metadata_all: Option<String>,
     pub default_subtitle_id: Option<i32>,
+    pub locked: Option<chrono::NaiveDateTime>,
+    pub locked_by: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -81,6 +89,158 @@ pub struct MediaFileInsert {
 
 // -------------------------------------------------------
 
+/// Audit log entry of locking / unlocking a media file
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(table_name = media_file_lock_log)]
+pub struct MediaFileLockLogEntry {
+    pub id: i32,
+    pub media_file_id: String,
+    pub user_id: Option<String>,
+    pub locked: bool,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = media_file_lock_log)]
+pub struct MediaFileLockLogEntryInsert {
+    pub media_file_id: String,
+    pub user_id: Option<String>,
+    pub locked: bool,
+}
+
+// -------------------------------------------------------
+
+/// Record of a collaborative viewing session
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(table_name = collab_sessions)]
+pub struct CollabSession {
+    pub id: i32,
+    pub collab_id: String,
+    pub media_file_id: String,
+
+    #[serde(with = "ts_seconds")]
+    pub started: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub ended: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = collab_sessions)]
+pub struct CollabSessionInsert {
+    pub collab_id: String,
+    pub media_file_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(belongs_to(CollabSession, foreign_key = collab_session_id))]
+#[diesel(table_name = collab_session_participants)]
+pub struct CollabSessionParticipant {
+    pub id: i32,
+    pub collab_session_id: i32,
+    pub user_id: String,
+
+    #[serde(with = "ts_seconds")]
+    pub joined: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = collab_session_participants)]
+pub struct CollabSessionParticipantInsert {
+    pub collab_session_id: i32,
+    pub user_id: String,
+}
+
+/// Chat message saved into a collab session transcript
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(belongs_to(CollabSession, foreign_key = collab_session_id))]
+#[diesel(table_name = collab_chat_messages)]
+pub struct CollabChatMessage {
+    pub id: i32,
+    pub collab_session_id: i32,
+    pub user_id: String,
+    pub message: String,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = collab_chat_messages)]
+pub struct CollabChatMessageInsert {
+    pub collab_session_id: i32,
+    pub user_id: String,
+    pub message: String,
+}
+
+// -------------------------------------------------------
+
+/// Outgoing webhook call: queued, delivered or given up on
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub url: String,
+    pub event: String,
+    pub payload: String,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    pub status: String,     // "pending", "delivered" or "failed"
+    pub attempts: i32,
+
+    #[serde(with = "ts_seconds")]
+    pub next_attempt: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub last_attempt: Option<chrono::NaiveDateTime>,
+
+    pub response_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDeliveryInsert {
+    pub url: String,
+    pub event: String,
+    pub payload: String,
+}
+
+// -------------------------------------------------------
+
+/// Approval / sign-off state of a media file, by one reviewer
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(treat_none_as_null = true)]
+#[diesel(table_name = media_file_approvals)]
+pub struct MediaFileApproval {
+    pub id: i32,
+    pub media_file_id: String,
+    pub user_id: String,
+    pub state: String,  // "pending", "approved" or "changes_requested"
+    pub note: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub updated: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = media_file_approvals)]
+pub struct MediaFileApprovalInsert {
+    pub media_file_id: String,
+    pub user_id: String,
+    pub state: String,
+    pub note: Option<String>,
+}
+
+// -------------------------------------------------------
+
 #[derive(Serialize, Deserialize, Debug, Default, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
 #[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
 #[diesel(treat_none_as_null = true)]
@@ -135,6 +295,8 @@ pub struct Comment {
     pub drawing: Option<String>,
     pub subtitle_id: Option<i32>,
     pub subtitle_filename_ifnull: Option<String>,
+    pub annotation: Option<String>,
+    pub deleted: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -151,7 +313,37 @@ pub struct CommentInsert {
     pub drawing: Option<String>,
     pub subtitle_id: Option<i32>,
     pub subtitle_filename_ifnull: Option<String>,
+    pub annotation: Option<String>,
+}
 
+// -------------------------------------------------------
+
+/// Earlier version of a comment, stored when it's edited, deleted or restored
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, QueryId, AsChangeset, Clone)]
+#[diesel(belongs_to(Comment, foreign_key = comment_id))]
+#[diesel(treat_none_as_null = true)]
+#[diesel(table_name = comment_revisions)]
+pub struct CommentRevision {
+    pub id: i32,
+    pub comment_id: i32,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    pub user_id: Option<String>,
+    pub action: String,
+    pub comment: String,
+    pub annotation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = comment_revisions)]
+pub struct CommentRevisionInsert {
+    pub comment_id: i32,
+    pub user_id: Option<String>,
+    pub action: String,
+    pub comment: String,
+    pub annotation: Option<String>,
 }
 
 // -------------------------------------------------------
@@ -193,6 +385,115 @@ pub struct MessageInsert {
     pub details: String,
 }
 
+/// Per-user preference for a message type (`event_name`). No row = defaults.
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, AsChangeset, Clone)]
+#[diesel(table_name = user_message_prefs)]
+pub struct UserMessagePref {
+    pub id: i32,
+    pub user_id: String,
+    pub event_name: String,
+    pub persist: bool,      // Store in DB (if the server would normally do so)
+    pub push: bool,         // Send to user's open sessions
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = user_message_prefs)]
+pub struct UserMessagePrefInsert {
+    pub user_id: String,
+    pub event_name: String,
+    pub persist: bool,
+    pub push: bool,
+}
+
+// -------------------------------------------------------
+
+/// Personal API token. Only a hash of the secret token is stored.
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = api_tokens)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,     // Comma-separated
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub expires: Option<chrono::NaiveDateTime>,
+
+    #[serde(with = "ts_seconds_option")]
+    pub last_used: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = api_tokens)]
+pub struct ApiTokenInsert {
+    pub user_id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub expires: Option<chrono::NaiveDateTime>,
+}
+
+// -------------------------------------------------------
+
+/// Guest review link to one or more media files
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = share_links)]
+pub struct ShareLink {
+    pub id: i32,
+    pub link_key: String,
+    pub user_id: String,
+    pub media_file_ids: String,     // Comma-separated
+    pub can_comment: bool,
+    pub password_hash: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds")]
+    pub expires: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = share_links)]
+pub struct ShareLinkInsert {
+    pub link_key: String,
+    pub user_id: String,
+    pub media_file_ids: String,
+    pub can_comment: bool,
+    pub password_hash: Option<String>,
+    pub expires: chrono::NaiveDateTime,
+}
+
+/// Built-in ACL entry: grants a user or a group a role on a media file
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(table_name = media_file_grants)]
+pub struct MediaFileGrant {
+    pub id: i32,
+    pub media_file_id: String,
+    pub user_id: Option<String>,
+    pub group_name: Option<String>,
+    pub role: String,   // "viewer", "commenter" or "editor"
+    pub granted_by: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = media_file_grants)]
+pub struct MediaFileGrantInsert {
+    pub media_file_id: String,
+    pub user_id: Option<String>,
+    pub group_name: Option<String>,
+    pub role: String,
+    pub granted_by: Option<String>,
+}
+
 // -------------------------------------------------------
 // Serialization helpers
 // -------------------------------------------------------
@@ -5,6 +5,9 @@ diesel::table! {
         id -> Text,
         name -> Text,
         created -> Timestamp,
+        email -> Nullable<Text>,
+        email_notifications -> Text,
+        email_digest_sent -> Nullable<Timestamp>,
     }
 }
 
@@ -32,6 +35,8 @@ diesel::table! {
         fps -> Nullable<Text>,
         raw_metadata_all -> Nullable<Text>,
         default_subtitle_id -> Nullable<Integer>,
+        locked -> Nullable<Timestamp>,
+        locked_by -> Nullable<Text>,
     }
 }
 
@@ -49,6 +54,8 @@ diesel::table! {
         drawing -> Nullable<Text>,
         subtitle_id -> Nullable<Integer>,
         subtitle_filename_ifnull -> Nullable<Text>,
+        annotation -> Nullable<Text>,
+        deleted -> Nullable<Timestamp>,
     }
 }
 
@@ -83,13 +90,162 @@ diesel::table! {
     }
 }
 diesel::joinable!(comments -> subtitles (subtitle_id));
+diesel::joinable!(comments -> media_files (media_file_id));
+
+diesel::table! {
+    comment_revisions (id) {
+        id -> Integer,
+        comment_id -> Integer,
+        created -> Timestamp,
+        user_id -> Nullable<Text>,
+        action -> Text,
+        comment -> Text,
+        annotation -> Nullable<Text>,
+    }
+}
+diesel::joinable!(comment_revisions -> comments (comment_id));
+
+diesel::table! {
+    media_file_approvals (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        user_id -> Text,
+        state -> Text,
+        note -> Nullable<Text>,
+        updated -> Timestamp,
+    }
+}
+diesel::joinable!(media_file_approvals -> media_files (media_file_id));
+diesel::joinable!(media_file_approvals -> users (user_id));
+
+
+diesel::table! {
+    media_file_grants (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        user_id -> Nullable<Text>,
+        group_name -> Nullable<Text>,
+        role -> Text,
+        granted_by -> Nullable<Text>,
+        created -> Timestamp,
+    }
+}
+diesel::joinable!(media_file_grants -> media_files (media_file_id));
+
+
+diesel::table! {
+    media_file_lock_log (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        user_id -> Nullable<Text>,
+        locked -> Bool,
+        created -> Timestamp,
+    }
+}
+diesel::joinable!(media_file_lock_log -> media_files (media_file_id));
+
+
+diesel::table! {
+    collab_sessions (id) {
+        id -> Integer,
+        collab_id -> Text,
+        media_file_id -> Text,
+        started -> Timestamp,
+        ended -> Nullable<Timestamp>,
+    }
+}
+diesel::joinable!(collab_sessions -> media_files (media_file_id));
+
+diesel::table! {
+    collab_session_participants (id) {
+        id -> Integer,
+        collab_session_id -> Integer,
+        user_id -> Text,
+        joined -> Timestamp,
+    }
+}
+diesel::joinable!(collab_session_participants -> collab_sessions (collab_session_id));
+
+diesel::table! {
+    collab_chat_messages (id) {
+        id -> Integer,
+        collab_session_id -> Integer,
+        user_id -> Text,
+        message -> Text,
+        created -> Timestamp,
+    }
+}
+diesel::joinable!(collab_chat_messages -> collab_sessions (collab_session_id));
+
+diesel::table! {
+    user_message_prefs (id) {
+        id -> Integer,
+        user_id -> Text,
+        event_name -> Text,
+        persist -> Bool,
+        push -> Bool,
+    }
+}
+
+diesel::table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Text,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created -> Timestamp,
+        expires -> Nullable<Timestamp>,
+        last_used -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    share_links (id) {
+        id -> Integer,
+        link_key -> Text,
+        user_id -> Text,
+        media_file_ids -> Text,
+        can_comment -> Bool,
+        password_hash -> Nullable<Text>,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        url -> Text,
+        event -> Text,
+        payload -> Text,
+        created -> Timestamp,
+        status -> Text,
+        attempts -> Integer,
+        next_attempt -> Timestamp,
+        last_attempt -> Nullable<Timestamp>,
+        response_code -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+    }
+}
 
 
 diesel::allow_tables_to_appear_in_same_query!(
     users,
+    api_tokens,
+    collab_chat_messages,
+    collab_session_participants,
+    collab_sessions,
     comments,
+    comment_revisions,
+    media_file_approvals,
+    media_file_grants,
+    media_file_lock_log,
     messages,
     media_files,
     media_types,
+    share_links,
     subtitles,
+    user_message_prefs,
+    webhook_deliveries,
 );
@@ -0,0 +1,220 @@
+//! Full-text search (SQLite FTS5) over media file titles, comments and subtitle cues.
+//!
+//! Media file and comment indexes are kept up to date by triggers (see migrations
+//! `add_fulltext_search` and `index_fulltext_ids`). Subtitle cues are stored on disk, so they
+//! are indexed here when a subtitle is added or updated (and on startup, for subtitles that are
+//! missing from the index).
+
+use std::path::Path;
+use anyhow::{anyhow, Context};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+
+use super::{models, DBPaging, DbBasicQuery, PooledConnection, DB};
+use super::error::{DBResult, EmptyDBResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchHitKind {
+    MediaFile = 0,
+    Comment = 1,
+    Subtitle = 2,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub kind: SearchHitKind,
+    pub media_file_id: String,
+    pub comment_id: Option<i32>,
+    pub subtitle_id: Option<i32>,
+    pub timecode: Option<String>,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(QueryableByName, Debug)]
+struct SearchHitRow {
+    #[diesel(sql_type = Integer)]
+    kind: i32,
+    #[diesel(sql_type = Text)]
+    media_file_id: String,
+    #[diesel(sql_type = Nullable<Integer>)]
+    comment_id: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    subtitle_id: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    timecode: Option<String>,
+    #[diesel(sql_type = Nullable<Double>)]
+    start_sec: Option<f64>,
+    #[diesel(sql_type = Nullable<Text>)]
+    fps: Option<String>,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+    #[diesel(sql_type = Double)]
+    rank: f64,
+}
+
+const SEARCH_SQL: &str = r#"
+    SELECT h.kind, h.media_file_id, h.comment_id, h.subtitle_id, h.timecode, h.start_sec, m.fps, h.snippet, h.rank FROM (
+        SELECT 0 AS kind, media_file_id, NULL AS comment_id, NULL AS subtitle_id, NULL AS timecode, NULL AS start_sec,
+            snippet(media_files_fts, -1, '[', ']', '...', 16) AS snippet, bm25(media_files_fts) AS rank
+            FROM media_files_fts WHERE media_files_fts MATCH '{title orig_filename} : (' || ?1 || ')'
+        UNION ALL
+        SELECT 1, f.media_file_id, f.rowid, NULL, c.timecode, NULL,
+            snippet(comments_fts, 1, '[', ']', '...', 16), bm25(comments_fts)
+            FROM comments_fts f JOIN comments c ON c.id = f.rowid WHERE comments_fts MATCH ?1 AND c.deleted IS NULL
+        UNION ALL
+        SELECT 2, f.media_file_id, NULL, f.subtitle_id, NULL, f.start_sec + s.time_offset,
+            snippet(subtitle_cues_fts, 3, '[', ']', '...', 16), bm25(subtitle_cues_fts)
+            FROM subtitle_cues_fts f JOIN subtitles s ON s.id = f.subtitle_id WHERE subtitle_cues_fts MATCH 'cue_text : (' || ?1 || ')'
+    ) h JOIN media_files m ON m.id = h.media_file_id
+    WHERE (?2 IS NULL OR m.user_id = ?2 OR m.id IN (SELECT value FROM json_each(?3))) AND (?4 IS NULL OR h.media_file_id = ?4)
+    ORDER BY h.rank, h.media_file_id LIMIT ?5 OFFSET ?6
+"#;
+
+
+/// Convert free-form user input into a safe FTS5 query.
+/// Every word must match (as a prefix), FTS5 operators are not interpreted.
+fn to_fts_query(words: &str) -> Option<String> {
+    let terms = words.split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    if terms.is_empty() { None } else { Some(terms.join(" ")) }
+}
+
+/// Format seconds as SMPTE timecode (HH:MM:SS:FF), or HH:MM:SS:00 if frame rate is unknown.
+fn seconds_to_smpte(secs: f64, fps: Option<f64>) -> String {
+    let secs = secs.max(0.0);
+    let whole = secs.floor() as i64;
+    let frames = fps.filter(|f| *f > 0.0).map(|f| ((secs - whole as f64) * f).floor() as i64).unwrap_or(0);
+    format!("{:02}:{:02}:{:02}:{:02}", whole / 3600, (whole / 60) % 60, whole % 60, frames)
+}
+
+
+/// Search media files, comments and subtitle cues, best matches first.
+///
+/// # Arguments
+/// * `query` - Plain words to search for. All of them must match, as prefixes.
+/// * `user_id` - If given, only search media files owned by this user, or listed in `shared_ids`
+/// * `shared_ids` - Other media files the user can see (ignored if `user_id` is None)
+/// * `media_file_id` - If given, only search this media file
+/// * `pg` - Paging
+pub fn search(conn: &mut PooledConnection, query: &str, user_id: Option<&str>, shared_ids: &[String], media_file_id: Option<&str>, pg: DBPaging) -> DBResult<Vec<SearchHit>>
+{
+    let fts_query = match to_fts_query(query) {
+        Some(q) => q,
+        None => return Ok(vec![]),
+    };
+    let rows: Vec<SearchHitRow> = diesel::sql_query(SEARCH_SQL)
+        .bind::<Text, _>(fts_query)
+        .bind::<Nullable<Text>, _>(user_id)
+        .bind::<Text, _>(serde_json::to_string(shared_ids).context("Failed to serialize media file IDs")?)
+        .bind::<Nullable<Text>, _>(media_file_id)
+        .bind::<BigInt, _>(pg.limit())
+        .bind::<BigInt, _>(pg.offset())
+        .load(conn)?;
+
+    Ok(rows.into_iter().map(|r| {
+        let kind = match r.kind {
+            1 => SearchHitKind::Comment,
+            2 => SearchHitKind::Subtitle,
+            _ => SearchHitKind::MediaFile,
+        };
+        let timecode = r.timecode.or_else(|| r.start_sec.map(|s|
+            seconds_to_smpte(s, r.fps.as_ref().and_then(|f| f.parse::<f64>().ok()))));
+        SearchHit {
+            kind,
+            media_file_id: r.media_file_id,
+            comment_id: r.comment_id,
+            subtitle_id: r.subtitle_id,
+            timecode,
+            snippet: r.snippet,
+            rank: r.rank,
+        }
+    }).collect())
+}
+
+
+/// Read (start_sec, text) cues from a subtitle file, in any format supported by `aspasia`.
+fn read_subtitle_cues(path: &Path) -> anyhow::Result<Vec<(f64, String)>> {
+    use aspasia::{Subtitle, TextEvent, TimedSubtitleFile, WebVttSubtitle};
+    let sub = TimedSubtitleFile::new(path).map_err(|e| anyhow!("Failed to parse subtitle file {:?}: {:?}", path, e))?;
+    let vtt = WebVttSubtitle::from(sub);
+    Ok(vtt.events().iter()
+        .map(|cue| (i64::from(cue.start) as f64 / 1000.0, cue.unformatted_text().replace('\n', " ")))
+        .filter(|(_, text)| !text.trim().is_empty())
+        .collect())
+}
+
+/// (Re)index cues of a subtitle, replacing any old ones.
+///
+/// # Arguments
+/// * `sub` - Subtitle to index
+/// * `media_files_dir` - Base directory of media files (subtitle files are in `<id>/subs/`)
+pub fn index_subtitle_cues(conn: &mut PooledConnection, sub: &models::Subtitle, media_files_dir: &Path) -> EmptyDBResult
+{
+    let subs_dir = media_files_dir.join(&sub.media_file_id).join("subs");
+    let path = match &sub.filename {
+        Some(f) => subs_dir.join(f),
+        None => subs_dir.join("orig").join(&sub.orig_filename),
+    };
+    let cues = read_subtitle_cues(&path)?;
+
+    conn.transaction(|conn| {
+        diesel::sql_query("DELETE FROM subtitle_cues_fts WHERE subtitle_cues_fts MATCH ? AND subtitle_id = ?")
+            .bind::<Text, _>(format!("subtitle_id : \"{}\"", sub.id))
+            .bind::<Integer, _>(sub.id)
+            .execute(conn)?;
+        for (start_sec, text) in &cues {
+            diesel::sql_query("INSERT INTO subtitle_cues_fts (subtitle_id, media_file_id, start_sec, cue_text) VALUES (?, ?, ?, ?)")
+                .bind::<Integer, _>(sub.id)
+                .bind::<Text, _>(&sub.media_file_id)
+                .bind::<Double, _>(start_sec)
+                .bind::<Text, _>(text)
+                .execute(conn)?;
+        }
+        Ok::<_, diesel::result::Error>(())
+    })?;
+    tracing::debug!(subtitle_id=sub.id, n_cues=cues.len(), "Indexed subtitle cues for search.");
+    Ok(())
+}
+
+/// Index cues of all subtitles that have nothing in the search index yet
+/// (e.g. subtitles that were added before full-text search existed).
+pub fn index_missing_subtitle_cues(db: &DB, media_files_dir: &Path) -> EmptyDBResult
+{
+    #[derive(QueryableByName)]
+    struct Id {
+        #[diesel(sql_type = Integer)]
+        id: i32,
+    }
+    let conn = &mut db.conn()?;
+    let ids: Vec<Id> = diesel::sql_query(
+        "SELECT id FROM subtitles WHERE id NOT IN (SELECT DISTINCT subtitle_id FROM subtitle_cues_fts)")
+        .load(conn).context("Failed to list unindexed subtitles")?;
+
+    for id in ids {
+        let sub = models::Subtitle::get(conn, &id.id)?;
+        if let Err(e) = index_subtitle_cues(conn, &sub, media_files_dir) {
+            tracing::warn!(subtitle_id=sub.id, details=%e, "Failed to index subtitle cues for search.");
+        }
+    }
+    Ok(())
+}
+
+
+// Unit tests =====================================================================================
+
+#[test]
+fn test_to_fts_query()
+{
+    assert_eq!(to_fts_query("  "), None);
+    assert_eq!(to_fts_query("foo bar"), Some("\"foo\"* \"bar\"*".to_string()));
+    assert_eq!(to_fts_query("a\"b OR"), Some("\"a\"\"b\"* \"OR\"*".to_string()));
+}
+
+#[test]
+fn test_seconds_to_smpte()
+{
+    assert_eq!(seconds_to_smpte(3723.5, Some(24.0)), "01:02:03:12");
+    assert_eq!(seconds_to_smpte(61.9, None), "00:01:01:00");
+}
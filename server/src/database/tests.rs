@@ -1,7 +1,7 @@
 use tracing_test::traced_test;
 use crate::database::*;
 
-use models::{User, MediaType, MediaFile, MediaFileInsert, Message, MessageInsert, Comment, CommentInsert};
+use models::{User, MediaType, MediaFile, MediaFileInsert, Message, MessageInsert, Comment, CommentInsert, UserMessagePref};
 
 
 fn _dump_db(conn: &mut PooledConnection) {
@@ -104,6 +104,7 @@ pub fn make_test_db() -> (std::sync::Arc<DB>, assert_fs::TempDir, Vec<MediaFile>
             drawing: Some(format!("drawing_{}.webp", i)),
             subtitle_id: None,
             subtitle_filename_ifnull: None,
+            annotation: None,
         };
         let c = Comment::insert(conn, &c).expect("Failed to insert comment");
         let dp = data_dir.join("videos").join(vid).join("drawings");
@@ -130,6 +131,7 @@ pub fn make_test_db() -> (std::sync::Arc<DB>, assert_fs::TempDir, Vec<MediaFile>
         drawing: Some("".into()),
         subtitle_id: None,
         subtitle_filename_ifnull: None,
+        annotation: None,
     };
     let cmt = models::Comment::insert(conn, &c).expect("Failed to insert comment");
     comments.push(cmt);
@@ -255,12 +257,60 @@ fn test_comment_delete() -> anyhow::Result<()> {
         drawing: None,
         subtitle_id: None,
         subtitle_filename_ifnull: None,
+        annotation: None,
     };
     let new_id = models::Comment::insert(conn, &c)?.id;
     assert_ne!(new_id, com[6].id, "Comment ID was re-used after deletion. This would mix up comment threads in the UI.");
     Ok(())
 }
 
+#[test]
+#[traced_test]
+fn test_comment_history_and_soft_delete() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+    let vid0 = &com[0].media_file_id;
+
+    // Edits keep previous versions
+    assert!(models::Comment::edit(conn, com[0].id, "Edit 1", None, Some("user.num1"))?);
+    assert!(models::Comment::edit(conn, com[0].id, "Edit 2", Some(Some("{}")), Some("admin"))?);
+    assert!(!models::Comment::edit(conn, 9999, "Nope", None, None)?);
+    let revs = models::CommentRevision::get_by_comment(conn, com[0].id)?;
+    assert_eq!(revs.iter().map(|r| r.comment.as_str()).collect::<Vec<_>>(), vec![com[0].comment.as_str(), "Edit 1"]);
+    assert_eq!(revs[1].user_id.as_deref(), Some("admin"));
+    assert!(revs.iter().all(|r| r.action == "edit"));
+    let c = models::Comment::get(conn, &com[0].id)?;
+    assert_eq!((c.comment.as_str(), c.annotation.as_deref()), ("Edit 2", Some("{}")));
+    assert!(c.edited.is_some());
+
+    // Soft delete keeps the row. Comment[0] has replies, so it's shown as a blanked placeholder...
+    assert!(models::Comment::soft_delete(conn, com[0].id, Some("user.num1"))?);
+    assert!(!models::Comment::soft_delete(conn, com[0].id, Some("user.num1"))?, "Already deleted");
+    assert!(models::Comment::get(conn, &com[0].id)?.deleted.is_some());
+    let visible = models::Comment::visible_to_users(Comment::get_by_media_file(conn, vid0, DBPaging::default())?);
+    let placeholder = visible.iter().find(|c| c.id == com[0].id).expect("Placeholder missing");
+    assert!(placeholder.comment.is_empty() && placeholder.annotation.is_none());
+
+    // ...until its replies are deleted, too
+    models::Comment::soft_delete(conn, com[5].id, None)?;
+    models::Comment::soft_delete(conn, com[6].id, None)?;
+    let visible = models::Comment::visible_to_users(Comment::get_by_media_file(conn, vid0, DBPaging::default())?);
+    assert!(visible.iter().all(|c| ![com[0].id, com[5].id, com[6].id].contains(&c.id)));
+    assert_eq!(visible.len(), Comment::get_by_media_file(conn, vid0, DBPaging::default())?.len() - 3);
+
+    // Restore to an earlier revision
+    assert!(models::Comment::restore(conn, com[0].id, Some(revs[0].id), Some("admin"))?);
+    let c = models::Comment::get(conn, &com[0].id)?;
+    assert!(c.deleted.is_none());
+    assert_eq!(c.comment, com[0].comment);
+    let revs = models::CommentRevision::get_by_comment(conn, com[0].id)?;
+    assert_eq!(revs.iter().map(|r| r.action.as_str()).collect::<Vec<_>>(), vec!["edit", "edit", "delete", "restore"]);
+
+    // Revision must belong to the comment
+    assert!(models::Comment::restore(conn, com[5].id, Some(revs[0].id), None).is_err());
+    Ok(())
+}
+
 #[test]
 #[traced_test]
 fn test_rename_video() -> anyhow::Result<()> {
@@ -282,6 +332,103 @@ fn test_rename_video() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[traced_test]
+fn test_media_file_lock() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    assert!(MediaFile::set_locked(conn, "11111", true, Some("user.num2"))?);
+    assert!(!MediaFile::set_locked(conn, "11111", true, Some("admin"))?, "Already locked, should be a no-op");
+    let v = MediaFile::get(conn, &"11111".into())?;
+    assert!(v.locked.is_some());
+    assert_eq!(v.locked_by.as_deref(), Some("user.num2"));
+
+    assert!(MediaFile::set_locked(conn, "11111", false, None)?);
+    let v = MediaFile::get(conn, &"11111".into())?;
+    assert!(v.locked.is_none() && v.locked_by.is_none());
+
+    // Both actions are in the audit log
+    let log = models::MediaFileLockLogEntry::get_by_media_file(conn, "11111")?;
+    assert_eq!(log.iter().map(|e| (e.locked, e.user_id.as_deref())).collect::<Vec<_>>(),
+        vec![(true, Some("user.num2")), (false, None)]);
+
+    assert!(matches!(MediaFile::set_locked(conn, "nonexistent", true, None), Err(DBError::NotFound())));
+    Ok(())
+}
+
+
+#[test]
+#[traced_test]
+fn test_collab_session_records() -> anyhow::Result<()> {
+    use models::{CollabSession, CollabSessionInsert, CollabSessionParticipant, CollabChatMessage, CollabChatMessageInsert};
+    let (db, _data_dir, _vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    let s1 = CollabSession::insert(conn, &CollabSessionInsert { collab_id: "c1".into(), media_file_id: "11111".into() })?;
+    let s2 = CollabSession::insert(conn, &CollabSessionInsert { collab_id: "c2".into(), media_file_id: "22222".into() })?;
+    assert!(s1.ended.is_none());
+
+    // Participants are recorded once, in join order
+    for uid in ["user.num2", "user.num1", "user.num2"] {
+        CollabSessionParticipant::add(conn, s1.id, uid)?;
+    }
+    let p = CollabSessionParticipant::get_by_session(conn, s1.id)?;
+    assert_eq!(p.iter().map(|p| p.user_id.as_str()).collect::<Vec<_>>(), vec!["user.num2", "user.num1"]);
+
+    for m in ["hello", "world"] {
+        CollabChatMessage::insert(conn, &CollabChatMessageInsert { collab_session_id: s1.id, user_id: "user.num1".into(), message: m.into() })?;
+    }
+    let t = CollabChatMessage::get_by_session(conn, s1.id)?;
+    assert_eq!(t.iter().map(|m| m.message.as_str()).collect::<Vec<_>>(), vec!["hello", "world"]);
+
+    CollabSession::set_ended(conn, s1.id)?;
+    assert!(CollabSession::get(conn, &s1.id)?.ended.is_some());
+    assert_eq!(CollabSession::end_all_open(conn)?, 1);
+    assert!(CollabSession::get(conn, &s2.id)?.ended.is_some());
+
+    assert_eq!(CollabSession::get_by_media_file(conn, "11111", DBPaging::default())?.len(), 1);
+
+    // Transcript goes away with the media file
+    MediaFile::delete(conn, &"11111".into())?;
+    assert!(CollabChatMessage::get_by_session(conn, s1.id)?.is_empty());
+    Ok(())
+}
+
+
+#[test]
+#[traced_test]
+fn test_user_email_settings_and_digest_query() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    assert_eq!(User::get(conn, &"user.num1".into())?.email_notifications, "all");
+    User::set_email(conn, "user.num1", Some("num1@example.com"))?;
+    User::set_email(conn, "user.num2", Some("num2@example.com"))?;
+    assert!(User::set_email(conn, "user.num1", Some("not an address")).is_err());
+    assert!(matches!(User::set_email(conn, "nobody", Some("x@example.com")), Err(DBError::NotFound())));
+    assert_eq!(User::get(conn, &"user.num1".into())?.email.as_deref(), Some("num1@example.com"));
+
+    assert!(User::set_email_notifications(conn, "user.num1", "sometimes").is_err());
+    User::set_email_notifications(conn, "user.num2", "immediate")?;
+    let r = User::get_digest_recipients(conn)?;
+    assert_eq!(r.iter().map(|u| u.id.as_str()).collect::<Vec<_>>(), vec!["user.num1"]);
+
+    // Others' comments on user.num1's media, oldest first
+    let since = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+    let new_com = Comment::get_new_on_users_media(conn, "user.num1", since)?;
+    let expected = com.iter().filter(|c| c.user_id.as_deref() == Some("user.num2") && ["B1DE0", "22222", "B1DE4"].contains(&c.media_file_id.as_str()));
+    assert_eq!(new_com.iter().map(|(c, _)| c.id).collect::<Vec<_>>(), expected.map(|c| c.id).collect::<Vec<_>>());
+    assert!(new_com.iter().all(|(c, title)| title.as_deref() == Some(if c.media_file_id == "B1DE0" { "test0.mp4" } else { "test2.mp4" })));
+
+    let future = chrono::Utc::now().naive_utc() + chrono::Duration::hours(1);
+    assert!(Comment::get_new_on_users_media(conn, "user.num1", future)?.is_empty());
+
+    User::set_email(conn, "user.num1", None)?;
+    assert!(User::get_digest_recipients(conn)?.is_empty());
+    Ok(())
+}
+
 
 #[test]
 #[traced_test]
@@ -354,6 +501,54 @@ fn test_user_messages() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[traced_test]
+fn test_user_message_management_and_prefs() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    let mut ids = vec![];
+    for i in 0..5 {
+        let uid = if i == 4 { "user.num2" } else { "user.num1" };
+        let m = Message::insert(conn, &MessageInsert { user_id: uid.into(), message: format!("msg{}", i), event_name: "ok".into(), ..Default::default() })?;
+        ids.push(m.id);
+    }
+    assert_eq!(Message::count_by_user(conn, "user.num1")?, (4, 4));
+
+    // Paging and unseen filter
+    let pg = DBPaging { page_num: 1, page_size: 3.try_into()? };
+    assert_eq!(Message::get_by_user_filtered(conn, "user.num1", false, pg)?.len(), 1);
+    assert_eq!(Message::set_seen_by_user(conn, "user.num1", Some(&[ids[0], ids[1]]), true)?, 2);
+    let unseen = Message::get_by_user_filtered(conn, "user.num1", true, DBPaging::default())?;
+    let mut unseen_ids = unseen.iter().map(|m| m.id).collect::<Vec<_>>();
+    unseen_ids.sort();
+    assert_eq!(unseen_ids, vec![ids[2], ids[3]]);
+
+    // Other users' messages are not touched
+    assert_eq!(Message::set_seen_by_user(conn, "user.num1", Some(&[ids[4]]), true)?, 0);
+    assert_eq!(Message::delete_by_user(conn, "user.num1", Some(&[ids[4]]))?, 0);
+    assert!(!Message::get(conn, &ids[4])?.seen);
+
+    // Mark all read / unread, delete, clear
+    assert_eq!(Message::set_seen_by_user(conn, "user.num1", None, true)?, 4);
+    assert_eq!(Message::count_by_user(conn, "user.num1")?, (4, 0));
+    Message::set_seen_by_user(conn, "user.num1", Some(&[ids[3]]), false)?;
+    assert_eq!(Message::count_by_user(conn, "user.num1")?, (4, 1));
+    assert_eq!(Message::delete_by_user(conn, "user.num1", Some(&[ids[0]]))?, 1);
+    assert_eq!(Message::delete_by_user(conn, "user.num1", None)?, 3);
+    assert_eq!(Message::count_by_user(conn, "user.num1")?, (0, 0));
+    assert_eq!(Message::count_by_user(conn, "user.num2")?, (1, 1));
+
+    // Preferences
+    assert!(UserMessagePref::get_for(conn, "user.num1", "progress")?.is_none());
+    UserMessagePref::set(conn, "user.num1", "progress", false, false)?;
+    let p = UserMessagePref::set(conn, "user.num1", "progress", true, false)?;
+    assert!(p.persist && !p.push);
+    assert_eq!(UserMessagePref::get_by_user(conn, "user.num1", DBPaging::default())?.len(), 1);
+    assert!(UserMessagePref::get_for(conn, "user.num2", "progress")?.is_none());
+    Ok(())
+}
+
 #[test]
 #[traced_test]
 fn test_transaction_rollback() -> anyhow::Result<()> {
@@ -424,6 +619,7 @@ fn test_subtitle_add_update_delete() -> anyhow::Result<()> {
         drawing: None,
         subtitle_id: Some(s.id),
         subtitle_filename_ifnull: None,
+        annotation: None,
     };
     let c = models::Comment::insert(conn, &c)?;
     assert_eq!(models::Comment::get(conn, &c.id)?.subtitle_id, Some(s.id));
@@ -442,6 +638,115 @@ fn test_subtitle_add_update_delete() -> anyhow::Result<()> {
 }
 
 
+#[test]
+#[traced_test]
+fn test_media_file_approvals() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // One approval per (media file, user), setting again replaces it
+    let a = models::MediaFileApproval::set(conn, &vid[0].id, "user.num2", "changes_requested", Some("Too dark"))?;
+    let b = models::MediaFileApproval::set(conn, &vid[0].id, "user.num2", "approved", None)?;
+    assert_eq!(a.id, b.id);
+    assert_eq!((b.state.as_str(), b.note.as_deref()), ("approved", None));
+    models::MediaFileApproval::set(conn, &vid[0].id, "user.num1", "pending", None)?;
+    models::MediaFileApproval::set(conn, &vid[1].id, "user.num2", "approved", None)?;
+
+    assert_eq!(models::MediaFileApproval::get_by_media_file(conn, &vid[0].id, DBPaging::default())?.len(), 2);
+    assert_eq!(models::MediaFileApproval::get_by_user(conn, "user.num2", DBPaging::default())?.len(), 2);
+
+    // Deleted along with the media file
+    models::MediaFile::delete(conn, &vid[0].id)?;
+    assert_eq!(models::MediaFileApproval::get_all(conn, DBPaging::default())?.len(), 1);
+    Ok(())
+}
+
+#[test]
+#[traced_test]
+fn test_fulltext_search() -> anyhow::Result<()> {
+    use search::{search, SearchHitKind};
+    let (db, data_dir, vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // Comments (existing rows are indexed by triggers on insert)
+    let hits = search(conn, "comment", None, &[], None, DBPaging::default())?;
+    assert_eq!(hits.len(), com.len());
+    assert!(hits.iter().all(|h| h.kind == SearchHitKind::Comment && h.comment_id.is_some()));
+
+    let hits = search(conn, "Comment 3", None, &[], None, DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].comment_id, Some(com[3].id));
+    assert_eq!(hits[0].media_file_id, com[3].media_file_id);
+    assert_eq!(hits[0].snippet, "[Comment] [3]");
+
+    // Media file titles, filtered by owner and media file
+    let hits = search(conn, "test", Some("user.num1"), &[], None, DBPaging::default())?;
+    assert_eq!(hits.len(), 3);
+    assert!(hits.iter().all(|h| h.kind == SearchHitKind::MediaFile));
+    let other = vid.iter().find(|v| v.user_id != "user.num1").unwrap();
+    let hits = search(conn, "test", Some("user.num1"), &[other.id.clone()], None, DBPaging::default())?;
+    assert_eq!(hits.len(), 4);
+    assert!(hits.iter().any(|h| h.media_file_id == other.id));
+    let hits = search(conn, "test", None, &[], Some(&vid[1].id), DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].media_file_id, vid[1].id);
+
+    // Paging
+    let pg = DBPaging { page_num: 1, page_size: 2.try_into()? };
+    assert_eq!(search(conn, "test", None, &[], None, pg)?.len(), 2);
+
+    // Edits are reflected in the index
+    models::MediaFile::rename(conn, &vid[0].id, "Holiday video")?;
+    assert_eq!(search(conn, "holi", None, &[], None, DBPaging::default())?.len(), 1);
+    models::Comment::edit(conn, com[1].id, "Needs more cowbell", None, None)?;
+    assert_eq!(search(conn, "cowbell", None, &[], None, DBPaging::default())?[0].comment_id, Some(com[1].id));
+    models::Comment::delete(conn, &com[1].id)?;
+    assert!(search(conn, "cowbell", None, &[], None, DBPaging::default())?.is_empty());
+
+    // Bad FTS syntax in user input is not an error
+    assert!(search(conn, "\"unbalanced OR ( NEAR", None, &[], None, DBPaging::default())?.is_empty());
+    assert!(search(conn, "  ", None, &[], None, DBPaging::default())?.is_empty());
+
+    // Subtitle cues (timecode includes subtitle time offset)
+    let subs_dir = data_dir.join("videos").join(&vid[2].id).join("subs");
+    std::fs::create_dir_all(&subs_dir)?;
+    std::fs::write(subs_dir.join("sub.vtt"), "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello there\n\n00:00:03.500 --> 00:00:05.000\nGeneral <i>Kenobi</i>\n")?;
+    let sub = models::Subtitle::insert(conn, &models::SubtitleInsert {
+        media_file_id: vid[2].id.clone(),
+        title: "Sub".to_string(),
+        language_code: "en".to_string(),
+        filename: Some("sub.vtt".to_string()),
+        orig_filename: "sub.srt".to_string(),
+        time_offset: 1.0,
+    })?;
+    search::index_missing_subtitle_cues(&db, &data_dir.join("videos"))?;
+
+    let hits = search(conn, "kenobi", None, &[], None, DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].kind, SearchHitKind::Subtitle);
+    assert_eq!(hits[0].subtitle_id, Some(sub.id));
+    assert_eq!(hits[0].timecode.as_deref(), Some("00:00:04:02"));   // 4.5 sec at 4 fps
+    assert_eq!(hits[0].snippet, "General [Kenobi]");
+
+    // Pointing the subtitle to another file drops stale cues, and reindexing finds the new text
+    std::fs::write(subs_dir.join("sub2.vtt"), "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nObi-Wan\n")?;
+    let sub = models::Subtitle::update_many(conn, &[models::Subtitle { filename: Some("sub2.vtt".to_string()), ..sub }])?.remove(0);
+    assert!(search(conn, "kenobi", None, &[], None, DBPaging::default())?.is_empty());
+    search::index_subtitle_cues(conn, &sub, &data_dir.join("videos"))?;
+    assert_eq!(search(conn, "obi", None, &[], None, DBPaging::default())?[0].subtitle_id, Some(sub.id));
+
+    models::Subtitle::delete(conn, &sub.id)?;
+    assert!(search(conn, "obi", None, &[], None, DBPaging::default())?.is_empty());
+
+    // Deleting a media file removes its hits
+    models::MediaFile::delete(conn, &vid[0].id)?;
+    assert!(search(conn, "holi", None, &[], None, DBPaging::default())?.is_empty());
+    assert!(search(conn, "comment", None, &[], Some(&vid[0].id), DBPaging::default())?.is_empty());
+
+    Ok(())
+}
+
+
 #[test]
 #[traced_test]
 fn test_migrate_existing_v056_db() -> anyhow::Result<()> {
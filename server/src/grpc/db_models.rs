@@ -50,10 +50,12 @@ impl models::MediaFile
             fps: v.duration.as_ref().map(|d| d.fps.clone()),
             raw_metadata_all: v.processing_metadata.as_ref().map(|m| m.ffprobe_metadata_all.clone()).flatten(),
             default_subtitle_id: v.default_subtitle_id.as_ref().map(|id| id.parse().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid default_subtitle_id")))).transpose()?,
+            locked: v.locked.as_ref().and_then(proto3_to_datetime),
+            locked_by: v.locked_by.clone(),
         })
     }
 
-    pub fn to_proto3(&self, url_base: &str, subtitles: Vec<models::Subtitle>) -> proto::MediaFile
+    pub fn to_proto3(&self, url_base: &str, subtitles: Vec<models::Subtitle>, approvals: Vec<models::MediaFileApproval>) -> proto::MediaFile
     {
         let duration = match (self.duration, self.total_frames, &self.fps) {
             (Some(dur), Some(total_frames), Some(fps)) => Some(proto::MediaFileDuration {
@@ -112,6 +114,9 @@ impl models::MediaFile
             processing_metadata,
             subtitles: subtitles.into_iter().map(|s| s.to_proto3(url_base)).collect(),
             default_subtitle_id: self.default_subtitle_id.map(|id| id.to_string()),
+            approvals: approvals.into_iter().map(|a| a.to_proto3()).collect(),
+            locked: self.locked.map(|t| datetime_to_proto3(&t)),
+            locked_by: self.locked_by.clone(),
             playback_url: playback_uri.map(|uri| format!("{}/videos/{}/{}", url_base, &self.id, uri)),
             orig_url: orig_uri.map(|uri| format!("{}/videos/{}/{}", url_base, &self.id, uri))
         }
@@ -120,6 +125,10 @@ impl models::MediaFile
     pub fn get_subtitles(&self, conn: &mut PooledConnection) -> DBResult<Vec<models::Subtitle>> {
         models::Subtitle::get_by_media_file(conn, &self.id, DBPaging::default())
     }
+
+    pub fn get_approvals(&self, conn: &mut PooledConnection) -> DBResult<Vec<models::MediaFileApproval>> {
+        models::MediaFileApproval::get_by_media_file(conn, &self.id, DBPaging::default())
+    }
 }
 
 impl models::MediaFileInsert
@@ -206,6 +215,155 @@ impl models::SubtitleInsert
 }
 
 
+impl models::MediaFileLockLogEntry
+{
+    pub fn to_proto3(&self) -> proto::org::media_file_lock_log::Entry
+    {
+        proto::org::media_file_lock_log::Entry {
+            user_id: self.user_id.clone(),
+            locked: self.locked,
+            created: Some(datetime_to_proto3(&self.created)),
+        }
+    }
+}
+
+
+// ============================ CollabSession ============================
+
+impl models::CollabSession
+{
+    pub fn to_proto3(&self, participants: Vec<models::CollabSessionParticipant>) -> proto::org::collab_session_list::CollabSession
+    {
+        proto::org::collab_session_list::CollabSession {
+            id: self.id.to_string(),
+            collab_id: self.collab_id.clone(),
+            media_file_id: self.media_file_id.clone(),
+            started: Some(datetime_to_proto3(&self.started)),
+            ended: self.ended.map(|t| datetime_to_proto3(&t)),
+            participant_ids: participants.into_iter().map(|p| p.user_id).collect(),
+        }
+    }
+}
+
+impl models::WebhookDelivery
+{
+    pub fn to_proto3(&self) -> proto::org::webhook_delivery_list::Delivery
+    {
+        proto::org::webhook_delivery_list::Delivery {
+            id: self.id.to_string(),
+            url: self.url.clone(),
+            event: self.event.clone(),
+            payload: self.payload.clone(),
+            created: Some(datetime_to_proto3(&self.created)),
+            status: self.status.clone(),
+            attempts: self.attempts,
+            next_attempt: if self.status == "pending" { Some(datetime_to_proto3(&self.next_attempt)) } else { None },
+            last_attempt: self.last_attempt.map(|t| datetime_to_proto3(&t)),
+            response_code: self.response_code,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+impl models::ApiToken
+{
+    pub fn to_proto3(&self) -> proto::ApiToken
+    {
+        proto::ApiToken {
+            id: self.id.to_string(),
+            user_id: self.user_id.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes.split(',').filter(|s| !s.is_empty()).map(String::from).collect(),
+            created: Some(datetime_to_proto3(&self.created)),
+            expires: self.expires.map(|t| datetime_to_proto3(&t)),
+            last_used: self.last_used.map(|t| datetime_to_proto3(&t)),
+        }
+    }
+}
+
+impl models::ShareLink
+{
+    /// Convert to protobuf. URL is only included if `url_base` is given (i.e. for the creator).
+    pub fn to_proto3(&self, url_base: Option<&str>) -> proto::ShareLink {
+        proto::ShareLink {
+            id: self.id.to_string(),
+            user_id: self.user_id.clone(),
+            media_file_ids: self.media_file_id_list(),
+            can_comment: self.can_comment,
+            has_password: self.password_hash.is_some(),
+            created: Some(datetime_to_proto3(&self.created)),
+            expires: Some(datetime_to_proto3(&self.expires)),
+            url: url_base.map(|u| self.url(u)),
+        }
+    }
+}
+
+impl models::MediaFileGrant
+{
+    pub fn to_proto3(&self) -> proto::MediaFileGrant {
+        use proto::media_file_grant::Grantee;
+        proto::MediaFileGrant {
+            id: self.id.to_string(),
+            media_file_id: self.media_file_id.clone(),
+            grantee: match (&self.user_id, &self.group_name) {
+                (Some(u), _) => Some(Grantee::UserId(u.clone())),
+                (None, Some(g)) => Some(Grantee::Group(g.clone())),
+                (None, None) => None,
+            },
+            role: self.grant_role().map(|r| r.to_proto3()).unwrap_or(proto::media_file_grant::Role::Viewer).into(),
+            granted_by: self.granted_by.clone(),
+            created: Some(datetime_to_proto3(&self.created)),
+        }
+    }
+}
+
+impl models::CollabChatMessage
+{
+    pub fn to_proto3(&self) -> proto::org::collab_transcript::Entry
+    {
+        proto::org::collab_transcript::Entry {
+            user_id: self.user_id.clone(),
+            message: self.message.clone(),
+            created: Some(datetime_to_proto3(&self.created)),
+        }
+    }
+}
+
+
+// ============================ MediaFileApproval ============================
+
+pub fn proto_approval_state_to_str(s: proto::media_file_approval::State) -> &'static str {
+    match s {
+        proto::media_file_approval::State::Pending => "pending",
+        proto::media_file_approval::State::Approved => "approved",
+        proto::media_file_approval::State::ChangesRequested => "changes_requested",
+    }
+}
+
+pub fn str_to_proto_approval_state(s: &str) -> proto::media_file_approval::State {
+    match s {
+        "approved" => proto::media_file_approval::State::Approved,
+        "changes_requested" => proto::media_file_approval::State::ChangesRequested,
+        _ => proto::media_file_approval::State::Pending,
+    }
+}
+
+impl models::MediaFileApproval
+{
+    pub fn to_proto3(&self) -> proto::MediaFileApproval
+    {
+        proto::MediaFileApproval {
+            id: self.id.to_string(),
+            media_file_id: self.media_file_id.clone(),
+            user_id: self.user_id.clone(),
+            state: str_to_proto_approval_state(&self.state).into(),
+            note: self.note.clone(),
+            updated: Some(datetime_to_proto3(&self.updated)),
+        }
+    }
+}
+
+
 // ============================ Comment ============================
 
 impl models::Comment
@@ -227,6 +385,8 @@ impl models::Comment
             drawing: c.drawing.clone(),
             subtitle_id: c.subtitle_id.as_ref().map(|id| id.parse()).transpose().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid subtitle ID")))?,
             subtitle_filename_ifnull: c.subtitle_filename_ifnull.clone(),
+            annotation: c.annotation.clone(),
+            deleted: c.deleted.as_ref().and_then(proto3_to_datetime),
         })
     }
 
@@ -248,6 +408,9 @@ impl models::Comment
             drawing: self.drawing.clone(),
             subtitle_id: self.subtitle_id.map(|id| id.to_string()),
             subtitle_filename_ifnull: self.subtitle_filename_ifnull.clone(),
+            annotation: self.annotation.clone(),
+            drawing_webp: None,
+            deleted: self.deleted.map(|d| datetime_to_proto3(&d)),
         }
     }
 }
@@ -269,10 +432,26 @@ impl models::CommentInsert
             drawing: c.drawing.clone(),
             subtitle_id: c.subtitle_id.as_ref().map(|id| id.parse()).transpose().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid subtitle ID")))?,
             subtitle_filename_ifnull: c.subtitle_filename_ifnull.clone(),
+            annotation: c.annotation.clone(),
         })
     }
 }
 
+impl models::CommentRevision
+{
+    pub fn to_proto3(&self) -> proto::client::server_to_client_cmd::comment_history::Revision
+    {
+        proto::client::server_to_client_cmd::comment_history::Revision {
+            id: self.id.to_string(),
+            user_id: self.user_id.clone(),
+            action: self.action.clone(),
+            comment: self.comment.clone(),
+            annotation: self.annotation.clone(),
+            created: Some(datetime_to_proto3(&self.created)),
+        }
+    }
+}
+
 // ============================ Message ============================
 
 impl models::Message
@@ -356,3 +535,26 @@ impl models::MessageInsert
         }
     }
 }
+
+// ============================ SearchHit ============================
+
+impl crate::database::search::SearchHit
+{
+    pub fn to_proto3(&self) -> proto::SearchHit
+    {
+        use crate::database::search::SearchHitKind;
+        proto::SearchHit {
+            kind: match self.kind {
+                SearchHitKind::MediaFile => proto::search_hit::Kind::MediaFile,
+                SearchHitKind::Comment => proto::search_hit::Kind::Comment,
+                SearchHitKind::Subtitle => proto::search_hit::Kind::Subtitle,
+            }.into(),
+            media_file_id: self.media_file_id.clone(),
+            comment_id: self.comment_id.map(|id| id.to_string()),
+            subtitle_id: self.subtitle_id.map(|id| id.to_string()),
+            timecode: self.timecode.clone(),
+            snippet: self.snippet.clone(),
+            rank: self.rank,
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use std::{path::Path, sync::atomic::Ordering::Relaxed};
 use anyhow::Context;
 use tonic::{Request, Response, Status};
-use crate::{api_server::{server_state::ServerState, ws_handers::del_media_file_and_cleanup, SendTo}, client_cmd, database::{DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate}, grpc::grpc_impl_helpers::{paged_vec, rpc_expect_field}, optional_str_to_i32_or_tonic_error, str_to_i32_or_tonic_error};
-use crate::grpc::db_models::proto_msg_type_to_event_name;
+use crate::{api_server::{server_state::ServerState, ws_handers::{del_media_file_and_cleanup, set_media_file_locked_and_notify}, SendTo}, client_cmd, database::{DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate}, grpc::grpc_impl_helpers::{paged_vec, rpc_expect_field}, optional_str_to_i32_or_tonic_error, str_to_i32_or_tonic_error};
+use crate::grpc::db_models::{proto_msg_type_to_event_name, proto_approval_state_to_str};
 use crate::database::models;
+use crate::database::error::DBResult;
 
 use lib_clapshot_grpc::{proto::{self}, run_grpc_server, GrpcBindAddr, RpcResult};
 use lib_clapshot_grpc::proto::org;
@@ -110,6 +111,136 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
         to_rpc_empty(del_media_file_and_cleanup(req.id.as_str(), None, &self.server).await)
     }
 
+    async fn set_media_file_locked(&self, req: Request<org::SetMediaFileLockedRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        to_rpc_empty(set_media_file_locked_and_notify(&req.media_file_id, req.locked, req.user_id.as_deref(), &self.server).await)
+    }
+
+    async fn get_media_file_lock_log(&self, req: Request<org::GetMediaFileLockLogRequest>) -> RpcResult<org::MediaFileLockLog>
+    {
+        let req = req.into_inner();
+        let conn = &mut self.server.db.conn()?;
+        let entries = models::MediaFileLockLogEntry::get_by_media_file(conn, &req.media_file_id)?;
+        Ok(Response::new(org::MediaFileLockLog {
+            entries: entries.into_iter().map(|e| e.to_proto3()).collect(),
+        }))
+    }
+
+    async fn get_media_file_viewers(&self, req: Request<org::GetMediaFileViewersRequest>) -> RpcResult<org::MediaFileViewerList>
+    {
+        let req = req.into_inner();
+        let ids = if req.media_file_ids.is_empty() { self.server.get_viewed_media_file_ids() } else { req.media_file_ids };
+        let entries = ids.into_iter()
+            .map(|id| org::media_file_viewer_list::Entry { viewers: self.server.get_media_file_viewers(&id), media_file_id: id })
+            .filter(|e| !e.viewers.is_empty())
+            .collect();
+        Ok(Response::new(org::MediaFileViewerList { entries }))
+    }
+
+    async fn list_collab_sessions(&self, req: Request<org::ListCollabSessionsRequest>) -> RpcResult<org::CollabSessionList>
+    {
+        let req = req.into_inner();
+        let pg = req.paging.as_ref().try_into()?;
+        let conn = &mut self.server.db.conn()?;
+        let items = match &req.media_file_id {
+            Some(mf_id) => models::CollabSession::get_by_media_file(conn, mf_id, pg)?,
+            None => models::CollabSession::get_all(conn, pg)?,
+        };
+        let mut sessions = Vec::with_capacity(items.len());
+        for s in items { sessions.push(s.to_proto3(models::CollabSessionParticipant::get_by_session(conn, s.id)?)); }
+        Ok(Response::new(org::CollabSessionList { sessions, paging: req.paging }))
+    }
+
+    async fn get_collab_transcript(&self, req: Request<org::GetCollabTranscriptRequest>) -> RpcResult<org::CollabTranscript>
+    {
+        let req = req.into_inner();
+        let sess_id = str_to_i32_or_tonic_error!(req.collab_session_id)?;
+        let conn = &mut self.server.db.conn()?;
+        let entries = models::CollabChatMessage::get_by_session(conn, sess_id)?;
+        Ok(Response::new(org::CollabTranscript {
+            entries: entries.into_iter().map(|e| e.to_proto3()).collect(),
+        }))
+    }
+
+    async fn list_webhook_deliveries(&self, req: Request<org::ListWebhookDeliveriesRequest>) -> RpcResult<org::WebhookDeliveryList>
+    {
+        let req = req.into_inner();
+        let pg = req.paging.as_ref().try_into()?;
+        let conn = &mut self.server.db.conn()?;
+        let items = match &req.status {
+            Some(status) => models::WebhookDelivery::get_by_status(conn, status, pg)?,
+            None => models::WebhookDelivery::get_all(conn, pg)?,
+        };
+        Ok(Response::new(org::WebhookDeliveryList {
+            deliveries: items.into_iter().map(|d| d.to_proto3()).collect(),
+            paging: req.paging,
+        }))
+    }
+
+    async fn list_api_tokens(&self, req: Request<org::ListApiTokensRequest>) -> RpcResult<org::ApiTokenList>
+    {
+        let req = req.into_inner();
+        let pg = req.paging.as_ref().try_into()?;
+        let conn = &mut self.server.db.conn()?;
+        let items = match &req.user_id {
+            Some(uid) => models::ApiToken::get_by_user(conn, uid, pg)?,
+            None => models::ApiToken::get_all(conn, pg)?,
+        };
+        Ok(Response::new(org::ApiTokenList {
+            tokens: items.into_iter().map(|t| t.to_proto3()).collect(),
+            paging: req.paging,
+        }))
+    }
+
+    async fn revoke_api_token(&self, req: Request<org::RevokeApiTokenRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        let id = str_to_i32_or_tonic_error!(req.id)?;
+        let conn = &mut self.server.db.conn()?;
+        let t = models::ApiToken::get(conn, &id)?;
+        models::ApiToken::delete(conn, &id)?;
+        let n = self.server.close_sessions_where(|s| s.api_token.as_ref().is_some_and(|at| at.id == id));
+        tracing::info!(user=t.user_id, token=t.name, closed_token_sessions=n, "Organizer revoked API token.");
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn list_sessions(&self, req: Request<org::ListSessionsRequest>) -> RpcResult<org::ActiveSessionList>
+    {
+        let req = req.into_inner();
+        let mut sessions = self.server.list_sessions(|s| req.user_id.as_ref().is_none_or(|uid| &s.user_id == uid));
+        if let Some(mf_id) = &req.media_file_id {
+            sessions.retain(|s| s.media_file_id.as_ref() == Some(mf_id));
+        }
+        Ok(Response::new(org::ActiveSessionList { sessions }))
+    }
+
+    async fn disconnect_sessions(&self, req: Request<org::DisconnectSessionsRequest>) -> RpcResult<org::DisconnectSessionsResponse>
+    {
+        let req = req.into_inner();
+        if req.sid.is_none() && req.user_id.is_none() {
+            return Err(Status::invalid_argument("Either sid or user_id must be set"));
+        }
+        let n = self.server.close_sessions_where(|s|
+            req.sid.as_ref().is_none_or(|sid| &s.sid == sid) && req.user_id.as_ref().is_none_or(|uid| &s.user_id == uid));
+        tracing::info!(sid=?req.sid, user=?req.user_id, disconnected=n, "Organizer disconnected sessions.");
+        Ok(Response::new(org::DisconnectSessionsResponse { disconnected: n as i32 }))
+    }
+
+    async fn set_user_email(&self, req: Request<org::SetUserEmailRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        let conn = &mut self.server.db.conn()?;
+        models::User::get_or_create(conn, &req.user_id, None)?;
+        if let Some(email) = &req.email {
+            models::User::set_email(conn, &req.user_id, Some(email.trim()).filter(|e| !e.is_empty()))?;
+        }
+        if let Some(mode) = &req.notifications {
+            models::User::set_email_notifications(conn, &req.user_id, mode)?;
+        }
+        Ok(Response::new(proto::Empty {}))
+    }
+
     // ========================================================================
     // Database functions
     // ========================================================================
@@ -131,7 +262,7 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
         };
 
         let mut proto_items = Vec::with_capacity(items.len());
-        for mf in items { proto_items.push(mf.to_proto3(&self.server.url_base, mf.get_subtitles(conn)?)); }
+        for mf in items { proto_items.push(mf.to_proto3(&self.server.url_base, mf.get_subtitles(conn)?, mf.get_approvals(conn)?)); }
 
         Ok(Response::new(org::DbMediaFileList {
             items: proto_items,
@@ -188,6 +319,44 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
     }
 
 
+    async fn db_get_media_file_approvals(&self, req: Request<org::DbGetMediaFileApprovalsRequest>) -> RpcResult<org::DbMediaFileApprovalList>
+    {
+        use org::db_get_media_file_approvals_request::Filter;
+        let req = req.into_inner();
+        let db = self.server.db.clone();
+        let pg = req.paging.as_ref().try_into()?;
+        let conn = &mut db.conn()?;
+        let items = match rpc_expect_field(&req.filter, "filter")? {
+            Filter::All(_) => { models::MediaFileApproval::get_all(conn, pg)? },
+            Filter::Ids(ids) => {
+                let mut approval_ids = Vec::with_capacity(ids.ids.len());
+                for id in &ids.ids { approval_ids.push(str_to_i32_or_tonic_error!(id)?); }
+                paged_vec(models::MediaFileApproval::get_many(conn, &approval_ids)?, pg)
+            },
+            Filter::UserId(user_id) => { models::MediaFileApproval::get_by_user(conn, user_id, pg)? },
+            Filter::MediaFileId(media_file_id) => { models::MediaFileApproval::get_by_media_file(conn, media_file_id, pg)? },
+        };
+        Ok(Response::new(org::DbMediaFileApprovalList {
+            items: items.into_iter().map(|a| a.to_proto3()).collect(),
+            paging: req.paging,
+        }))
+    }
+
+
+    async fn db_search(&self, req: Request<org::DbSearchRequest>) -> RpcResult<org::DbSearchHitList>
+    {
+        let req = req.into_inner();
+        let db = self.server.db.clone();
+        let pg = req.paging.as_ref().try_into()?;
+        let conn = &mut db.conn()?;
+        let items = crate::database::search::search(conn, &req.query, req.user_id.as_deref(), &[], req.media_file_id.as_deref(), pg)?;
+        Ok(Response::new(org::DbSearchHitList {
+            items: items.into_iter().map(|h| h.to_proto3()).collect(),
+            paging: req.paging,
+        }))
+    }
+
+
     async fn db_upsert(&self, req: Request<org::DbUpsertRequest>) -> RpcResult<org::DbUpsertResponse>
     {
         let req = req.into_inner();
@@ -222,16 +391,21 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
                     }).collect::<Vec<_>>();
 
                     // Convert back to proto3
-                    res_comb_orig_order.iter().map(|it| $to_proto(it)).collect::<Result<Vec<_>, tonic::Status>>()
+                    res_comb_orig_order.iter().map(|it| $to_proto(it)).collect::<DBResult<Vec<_>>>()
                 }
             }
         }
         let conn = &mut self.server.db.conn()?;
+        // Approvals are unique per (media file, user), so always set by those instead of ID
+        let media_file_approvals = req.media_file_approvals.iter().map(|a| {
+            models::MediaFileApproval::set(conn, &a.media_file_id, &a.user_id,
+                proto_approval_state_to_str(a.state()), a.note.as_deref()).map(|it| it.to_proto3())
+        }).collect::<DBResult<Vec<_>>>()?;
         Ok(Response::new(org::DbUpsertResponse {
             media_files: upsert_type!([
                 conn, req.media_files, models::MediaFile, models::MediaFileInsert,
                 |it: &proto::MediaFile| it.id.is_empty(),
-                |it: &models::MediaFile| Ok(it.to_proto3(self.server.url_base.as_str(), it.get_subtitles(conn)?))])?,
+                |it: &models::MediaFile| Ok(it.to_proto3(self.server.url_base.as_str(), it.get_subtitles(conn)?, it.get_approvals(conn)?))])?,
             comments: upsert_type!([
                 conn, req.comments, models::Comment, models::CommentInsert,
                 |it: &proto::Comment| it.id.is_empty(),
@@ -243,7 +417,14 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
             subtitles: upsert_type!([
                 conn, req.subtitles, models::Subtitle, models::SubtitleInsert,
                 |it: &proto::Subtitle| it.id.is_empty(),
-                |it: &models::Subtitle| Ok(it.to_proto3(self.server.url_base.as_str()))])?,
+                |it: &models::Subtitle| {
+                    // Organizer may have added or replaced the subtitle file, so (re)index its cues
+                    if let Err(e) = crate::database::search::index_subtitle_cues(conn, it, &self.server.media_files_dir) {
+                        tracing::warn!(subtitle_id=it.id, details=%e, "Failed to index subtitle for search.");
+                    }
+                    Ok(it.to_proto3(self.server.url_base.as_str()))
+                }])?,
+            media_file_approvals,
         }))
     }
 
@@ -267,6 +448,7 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
             subtitles_deleted: delete_type!([conn, req.subtitle_ids, i32, models::Subtitle]),
             comments_deleted: delete_type!([conn, req.comment_ids, i32, models::Comment]),
             user_messages_deleted: delete_type!([conn, req.user_message_ids, i32, models::Message]),
+            media_file_approvals_deleted: delete_type!([conn, req.media_file_approval_ids, i32, models::MediaFileApproval]),
         }))
     }
 }
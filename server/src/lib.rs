@@ -12,6 +12,8 @@ pub mod api_server;
 pub mod database;
 pub mod tests;
 pub mod grpc;
+pub mod email_notifier;
+pub mod webhooks;
 
 pub const PKG_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 pub const PKG_NAME: &'static str = env!("CARGO_PKG_NAME");
@@ -42,6 +44,13 @@ impl ClapshotInit {
         poll_interval: f32,
         default_user: String,
         resubmit_delay: f32,
+        email_config: Option<email_notifier::EmailConfig>,
+        webhook_config: Option<webhooks::WebhookConfig>,
+        jwt_config: Option<api_server::jwt_auth::JwtAuthConfig>,
+        trusted_proxy: Option<api_server::trusted_proxy::TrustedProxyConfig>,
+        authz_policy_config: Option<api_server::authz_policy::AuthzPolicyConfig>,
+        rate_limits: api_server::rate_limit::RateLimitConfig,
+        ws_resume_grace: std::time::Duration,
         terminate_flag: Arc<AtomicBool>)
         -> anyhow::Result<Self>
     {
@@ -72,6 +81,65 @@ impl ClapshotInit {
         let grpc_srv_listening_flag = Arc::new(AtomicBool::new(false));
         let db: Arc<DB> = Arc::new(database::DB::open_db_file(&db_file).unwrap());
 
+        if let Err(e) = database::search::index_missing_subtitle_cues(&db, &data_dir.join("videos")) {
+            tracing::warn!(details=%e, "Failed to index subtitles for search.");
+        }
+        if let Err(e) = db.conn().and_then(|mut conn| database::models::CollabSession::end_all_open(&mut conn)) {
+            tracing::warn!(details=%e, "Failed to close collab sessions left open by previous run.");
+        }
+
+        // Start e-mail notifier and digest sender, if configured
+        let email = match &email_config {
+            Some(cfg) => {
+                let notifier = Arc::new(email_notifier::EmailNotifier::new(cfg, &url_base)?);
+                let (n, db, tf) = (notifier.clone(), db.clone(), terminate_flag.clone());
+                thread::spawn(move || { email_notifier::run_digest_loop(n, db, tf) });
+                tracing::info!(smtp=%cfg.smtp_url.rsplit('@').next().unwrap_or_default(), "E-mail notifications enabled.");
+                Some(notifier)
+            },
+            None => None,
+        };
+
+        // Start webhook delivery, if configured
+        let webhooks = match webhook_config {
+            Some(cfg) => {
+                tracing::info!(urls=?cfg.urls, "Webhooks enabled.");
+                let wh = Arc::new(webhooks::Webhooks::new(cfg, db.clone())?);
+                let (w, tf) = (wh.clone(), terminate_flag.clone());
+                thread::spawn(move || { w.run_delivery_loop(tf) });
+                Some(wh)
+            },
+            None => None,
+        };
+
+        // Load JWT signing keys, if built-in authentication is configured
+        let jwt_auth = match jwt_config {
+            Some(cfg) => {
+                tracing::info!(jwks_file=?cfg.jwks_file, issuer=?cfg.issuer, "JWT authentication enabled. Ignoring X-Remote-User-* headers.");
+                let ja = Arc::new(api_server::jwt_auth::JwtAuth::new(cfg)?);
+                let (j, tf) = (ja.clone(), terminate_flag.clone());
+                thread::spawn(move || { j.run_key_refresh_loop(tf) });
+                Some(ja)
+            },
+            None => None,
+        };
+
+        // Load authorization policy, if configured, and watch it for changes
+        let authz_policy = match authz_policy_config {
+            Some(cfg) => {
+                tracing::info!(path=?cfg.path, dry_run=cfg.dry_run, "Authz policy enabled.");
+                let p = Arc::new(api_server::authz_policy::AuthzPolicy::load(cfg)?);
+                let (p2, tf) = (p.clone(), terminate_flag.clone());
+                thread::spawn(move || { p2.run_reload_loop(tf) });
+                Some(p)
+            },
+            None => None,
+        };
+
+        if let Some(tp) = &trusted_proxy {
+            tracing::info!(networks=?tp.networks, secret=tp.secret.is_some(), hmac=tp.hmac_key.is_some(), "Accepting auth headers only from trusted proxies.");
+        }
+
         // Run API server
         let (user_msg_tx, user_msg_rx) = unbounded::<api_server::UserMessage>();
         let (upload_tx, upload_rx) = unbounded::<video_pipeline::IncomingFile>();
@@ -83,6 +151,13 @@ impl ClapshotInit {
                 organizer_uri.clone(),
                 grpc_srv_listening_flag.clone(),
                 default_user,
+                email,
+                webhooks,
+                jwt_auth,
+                trusted_proxy.map(Arc::new),
+                authz_policy,
+                rate_limits,
+                ws_resume_grace,
                 terminate_flag.clone());
             let grpc_srv = if (&organizer_uri).is_some() { Some(grpc_server_bind.clone()) } else { None };
             let ub = url_base.clone();
@@ -351,7 +426,14 @@ pub fn run_clapshot(
     target_bitrate: u32,
     default_user: String,
     poll_interval: f32,
-    resubmit_delay: f32
+    resubmit_delay: f32,
+    email_config: Option<email_notifier::EmailConfig>,
+    webhook_config: Option<webhooks::WebhookConfig>,
+    jwt_config: Option<api_server::jwt_auth::JwtAuthConfig>,
+    trusted_proxy: Option<api_server::trusted_proxy::TrustedProxyConfig>,
+    authz_policy_config: Option<api_server::authz_policy::AuthzPolicyConfig>,
+    rate_limits: api_server::rate_limit::RateLimitConfig,
+    ws_resume_grace: std::time::Duration,
 ) -> anyhow::Result<()> {
 
     let terminate_flag = Arc::new(AtomicBool::new(false));
@@ -371,6 +453,13 @@ pub fn run_clapshot(
         poll_interval,
         default_user,
         resubmit_delay,
+        email_config,
+        webhook_config,
+        jwt_config,
+        trusted_proxy,
+        authz_policy_config,
+        rate_limits,
+        ws_resume_grace,
         terminate_flag.clone()
     )?;
 
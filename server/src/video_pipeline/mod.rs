@@ -30,6 +30,7 @@ mod ffmpeg_processor;
 
 use metadata_reader::MetadataResult;
 use crate::api_server::{UserMessage, UserMessageTopic};
+use crate::webhooks::WebhookEvent;
 use crate::database::error::DBError;
 use crate::video_pipeline::metadata_reader::MediaType;
 use cleanup_rejected::clean_up_rejected_file;
@@ -258,7 +259,8 @@ fn ingest_media_file(
                     user_id: Some(md.user_id.clone()),
                     media_file_id: Some(media_id.to_string()),
                     subtitle_id: None,
-                    progress: None
+                    progress: None,
+                    webhook_event: None
                 }) { tracing::error!(details=?e, "Failed to send user message") };
         };
     };
@@ -275,6 +277,7 @@ fn ingest_media_file(
                 media_file_id: Some(media_id.to_string()),
                 subtitle_id: None,
                 progress: None,
+                webhook_event: Some(WebhookEvent::MediaFileAdded),
             })?;
             // Tell user in text also
             tracing::debug!(transcode=do_transcode, reason=reason, "Media added to DB. Transcode");
@@ -286,6 +289,7 @@ fn ingest_media_file(
                 media_file_id: Some(media_id.to_string()),
                 subtitle_id: None,
                 progress: if do_transcode { Some(0.0) } else { None },
+                webhook_event: None,
             })?;
             Ok(do_transcode)
         },
@@ -299,6 +303,7 @@ fn ingest_media_file(
                 media_file_id: Some(media_id.to_string()),
                 subtitle_id: None,
                 progress: None,
+                webhook_event: None,
             })?;
             Err(e)
         }
@@ -477,11 +482,12 @@ pub fn run_forever(
                             user_msg_tx.send(UserMessage {
                                     topic: UserMessageTopic::Error,
                                     msg: "Error reading media file metadata.".into(),
-                                    details: Some(format!("'{}': ", e.src_file.file_name().unwrap_or_default().to_string_lossy()) + &e.details + &cleanup_err),
+                                    details: Some(format!("'{}': ", e.src_file.file_name().unwrap_or_default().to_string_lossy()) + e.details.as_str() + cleanup_err.as_str()),
                                     user_id: Some(e.user_id),
                                     media_file_id: vid,
                                     subtitle_id: None,
-                                    progress: None
+                                    progress: None,
+                                    webhook_event: None
                                 }).unwrap_or_else(|e| { tracing::error!("Error sending user message: {:?}", e); });
                         }
                     },
@@ -514,7 +520,8 @@ pub fn run_forever(
                                 user_id: Some(user_id),
                                 media_file_id: Some(vid),
                                 subtitle_id: None,
-                                progress
+                                progress,
+                                webhook_event: None
                             }).unwrap_or_else(|e| { tracing::error!("Error sending user message: {:?}", e); });
                     },
                     Err(e) => { tracing::warn!("Transcoder is dead ('{:?}'). Exit.", e); break; },
@@ -579,7 +586,8 @@ pub fn run_forever(
                                         user_id: Some(user_id),
                                         media_file_id: Some(vid.clone()),
                                         subtitle_id: None,
-                                        progress: None
+                                        progress: None,
+                                        webhook_event: None
                                     }).ok();
                                 }
 
@@ -594,7 +602,8 @@ pub fn run_forever(
                                     user_id: Some(logs.dmsg.user_id.clone()),
                                     media_file_id: Some(logs.media_file_id.clone()),
                                     subtitle_id: None,
-                                    progress: Some(1.0)
+                                    progress: Some(1.0),
+                                    webhook_event: Some(if linked_ok { WebhookEvent::MediaFileTranscoded } else { WebhookEvent::MediaFileTranscodeFailed }),
                                 }).unwrap_or_else(|e| { tracing::error!(details=%e, "Error sending user message"); });
                         },
 
@@ -644,7 +653,8 @@ pub fn run_forever(
                                     user_id: Some(logs.user_id.clone()),
                                     media_file_id: Some(vid.clone()),
                                     subtitle_id: None,
-                                    progress: None
+                                    progress: None,
+                                    webhook_event: None
                                 }).unwrap_or_else(|e| { tracing::error!("Error sending user message: {:?}", e); });
                             }
 
@@ -671,7 +681,8 @@ pub fn run_forever(
                                     user_id: Some(logs.dmsg.user_id),
                                     media_file_id: Some(logs.media_file_id),
                                     subtitle_id: None,
-                                    progress: None
+                                    progress: None,
+                                    webhook_event: if op == "transcoding" { Some(WebhookEvent::MediaFileTranscodeFailed) } else { None },
                                 }).unwrap_or_else(|e| { tracing::error!("Error sending user message: {:?}", e); });
                         },
                     }
@@ -0,0 +1,218 @@
+//! Outgoing webhooks for server events.
+//!
+//! Events are stored in the `webhook_deliveries` table (one row per URL), and a background
+//! thread POSTs them as JSON. Failed calls are retried with exponential backoff, and the
+//! table doubles as a persistent delivery log.
+//!
+//! Payloads contain the same objects as the client API (protobuf messages in JSON form).
+//! Each request is signed with HMAC-SHA256 of the body, in header
+//! `X-Clapshot-Signature: sha256=<hex>`.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::database::{models, DbBasicQuery, DbQueryByMediaFile, DBPaging, PooledConnection, DB};
+
+type Res<T> = anyhow::Result<T>;
+
+const MAX_ATTEMPTS: i32 = 8;
+const FIRST_RETRY_DELAY_SECS: i64 = 10;     // Doubled after every failed attempt...
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;  // ...up to this
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    MediaFileAdded,
+    MediaFileTranscoded,
+    MediaFileTranscodeFailed,
+    MediaFileDeleted,
+    CommentAdded,
+    CommentEdited,
+    CommentDeleted,
+    ApprovalChanged,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 8] = [
+        WebhookEvent::MediaFileAdded, WebhookEvent::MediaFileTranscoded, WebhookEvent::MediaFileTranscodeFailed, WebhookEvent::MediaFileDeleted,
+        WebhookEvent::CommentAdded, WebhookEvent::CommentEdited, WebhookEvent::CommentDeleted, WebhookEvent::ApprovalChanged];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::MediaFileAdded => "media_file.added",
+            WebhookEvent::MediaFileTranscoded => "media_file.transcoded",
+            WebhookEvent::MediaFileTranscodeFailed => "media_file.transcode_failed",
+            WebhookEvent::MediaFileDeleted => "media_file.deleted",
+            WebhookEvent::CommentAdded => "comment.added",
+            WebhookEvent::CommentEdited => "comment.edited",
+            WebhookEvent::CommentDeleted => "comment.deleted",
+            WebhookEvent::ApprovalChanged => "approval.changed",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Res<Self> {
+        WebhookEvent::ALL.into_iter().find(|e| e.as_str() == s)
+            .ok_or_else(|| anyhow!("Unknown webhook event '{}'. Valid ones are: {}", s,
+                WebhookEvent::ALL.map(|e| e.as_str()).join(", ")))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    pub secret: String,                 // HMAC key for signing payloads
+    pub events: Vec<WebhookEvent>,      // Empty = all events
+}
+
+pub struct Webhooks {
+    cfg: WebhookConfig,
+    db: Arc<DB>,
+    wakeup_tx: Sender<()>,
+    wakeup_rx: Receiver<()>,
+}
+
+impl Webhooks {
+
+    pub fn new(cfg: WebhookConfig, db: Arc<DB>) -> Res<Self>
+    {
+        if cfg.secret.is_empty() {
+            anyhow::bail!("Webhook secret must not be empty");
+        }
+        for u in &cfg.urls {
+            let u = reqwest::Url::parse(u).with_context(|| format!("Invalid webhook URL '{}'", u))?;
+            if !["http", "https"].contains(&u.scheme()) {
+                anyhow::bail!("Webhook URL must be http(s): '{}'", u);
+            }
+        }
+        let (wakeup_tx, wakeup_rx) = bounded(1);
+        Ok(Webhooks { cfg, db, wakeup_tx, wakeup_rx })
+    }
+
+    /// Queue an event for delivery to all webhooks.
+    ///
+    /// # Arguments
+    /// * `event` - Event type
+    /// * `user_id` - User who caused the event, if any
+    /// * `data` - JSON object with event-specific fields, merged into the payload
+    pub fn emit(&self, event: WebhookEvent, user_id: Option<&str>, data: serde_json::Value) -> Res<()>
+    {
+        if !self.cfg.events.is_empty() && !self.cfg.events.contains(&event) {
+            return Ok(());
+        }
+        let mut payload = serde_json::json!({
+            "event": event.as_str(),
+            "created": chrono::Utc::now().to_rfc3339(),
+            "user_id": user_id,
+        });
+        if let (Some(p), serde_json::Value::Object(d)) = (payload.as_object_mut(), data) {
+            p.extend(d);
+        }
+        let payload = serde_json::to_string(&payload)?;
+
+        let conn = &mut self.db.conn()?;
+        for url in &self.cfg.urls {
+            models::WebhookDelivery::insert(conn, &models::WebhookDeliveryInsert {
+                url: url.clone(),
+                event: event.as_str().to_string(),
+                payload: payload.clone(),
+            })?;
+        }
+        self.wakeup_tx.try_send(()).ok();   // Full = already woken up
+        Ok(())
+    }
+
+    /// Try to deliver all pending calls that are due. Returns the number of successful deliveries.
+    pub fn deliver_due(&self, now: chrono::NaiveDateTime) -> Res<usize>
+    {
+        let due = models::WebhookDelivery::get_due(&mut self.db.conn()?, now, 100)?;
+        if due.is_empty() {
+            return Ok(0);
+        }
+        // Blocking client must not be dropped inside an async runtime, so don't keep it around
+        let http = reqwest::blocking::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(format!("{}/{}", crate::PKG_NAME, crate::PKG_VERSION))
+            .build()?;
+
+        let mut delivered = 0;
+        for d in due {
+            let (code, err) = match self.post(&http, &d) {
+                Ok(status) if status.is_success() => (Some(status.as_u16() as i32), None),
+                Ok(status) => (Some(status.as_u16() as i32), Some(format!("HTTP status {}", status))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let attempt = d.attempts + 1;
+            let (status, retry_at) = if err.is_none() {
+                delivered += 1;
+                ("delivered", now)
+            } else if attempt >= MAX_ATTEMPTS {
+                tracing::warn!(url=d.url, event=d.event, id=d.id, details=?err, "Webhook delivery failed, giving up.");
+                ("failed", now)
+            } else {
+                let delay = (FIRST_RETRY_DELAY_SECS << (attempt - 1)).min(MAX_RETRY_DELAY_SECS);
+                tracing::debug!(url=d.url, event=d.event, id=d.id, details=?err, "Webhook delivery failed, retrying in {}s.", delay);
+                ("pending", now + chrono::Duration::seconds(delay))
+            };
+            models::WebhookDelivery::record_attempt(&mut self.db.conn()?, d.id, status, code, err.as_deref(), retry_at)?;
+        }
+        Ok(delivered)
+    }
+
+    fn post(&self, http: &reqwest::blocking::Client, d: &models::WebhookDelivery) -> Res<reqwest::StatusCode>
+    {
+        let res = http.post(&d.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Clapshot-Event", &d.event)
+            .header("X-Clapshot-Delivery", d.id.to_string())
+            .header("X-Clapshot-Signature", sign_payload(&self.cfg.secret, &d.payload))
+            .body(d.payload.clone())
+            .send()?;
+        Ok(res.status())
+    }
+
+    /// Deliver queued calls until terminate flag is set.
+    /// Pending deliveries from previous runs are picked up, too.
+    pub fn run_delivery_loop(&self, terminate_flag: Arc<AtomicBool>)
+    {
+        let _span = tracing::info_span!("WEBHOOKS").entered();
+        while !terminate_flag.load(Ordering::Relaxed) {
+            if let Err(e) = self.deliver_due(chrono::Utc::now().naive_utc()) {
+                tracing::error!(details=%e, "Webhook delivery loop error");
+            }
+            self.wakeup_rx.recv_timeout(Duration::from_secs(1)).ok();
+        }
+    }
+}
+
+/// Signature header value for a payload: `sha256=<hex HMAC-SHA256 of body>`
+pub fn sign_payload(secret: &str, payload: &str) -> String
+{
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Media file in the same JSON form as the client API gets it
+pub fn media_file_json(conn: &mut PooledConnection, mf: &models::MediaFile, url_base: &str) -> Res<serde_json::Value>
+{
+    let subs = models::Subtitle::get_by_media_file(conn, &mf.id, DBPaging::default())?;
+    let approvals = mf.get_approvals(conn)?;
+    Ok(serde_json::to_value(mf.to_proto3(url_base, subs, approvals))?)
+}
+
+/// Like `media_file_json`, but look up the media file by id
+pub fn media_file_json_by_id(conn: &mut PooledConnection, media_file_id: &str, url_base: &str) -> Res<serde_json::Value>
+{
+    let mf = models::MediaFile::get(conn, &media_file_id.into())?;
+    media_file_json(conn, &mf, url_base)
+}